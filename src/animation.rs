@@ -0,0 +1,405 @@
+use crate::css::{Value, Color, Keyframes, Separator, Stylesheet};
+use crate::style::StyledNode;
+
+// The animation properties of one animation on an element.
+struct Animation<'a> {
+    keyframes: &'a Keyframes,
+    duration: f32, // In seconds
+    delay: f32,
+    iterations: f32, // Infinite for 'infinite'
+    direction: String,
+    fill_mode: String,
+    timing_function: String,
+}
+
+// Replace the values of animated properties in the tree with their values 'time'
+// seconds after the animations started. The values being animated from are the
+// specified values, so this has to run on a freshly styled tree for every frame.
+pub fn animate(node: &mut StyledNode, stylesheets: &[Stylesheet], time: f32) {
+    for animation in animations(node, stylesheets) {
+        if let Some(progress) = animation.progress(time) {
+            for (name, value) in animation.values_at(node, progress) {
+                node.set_value(name, value);
+            }
+        }
+    }
+    for child in &mut node.children {
+        animate(child, stylesheets, time);
+    }
+}
+
+// The animations that apply to 'node'. 'animation-name' is a comma separated list,
+// and the other animation properties are repeated as needed to match it.
+fn animations<'a>(node: &StyledNode, stylesheets: &'a [Stylesheet]) -> Vec<Animation<'a>> {
+    let names = list(node.value("animation-name"));
+    let property = |name: &str, index: usize| {
+        let values = list(node.value(name));
+        if values.is_empty() { None } else { Some(values[index % values.len()].clone()) }
+    };
+    let keyword = |name: &str, index: usize, default: &str| match property(name, index) {
+        Some(Value::Keyword(k)) => k.to_ascii_lowercase(),
+        _ => default.to_string(),
+    };
+
+    names.iter().enumerate().filter_map(|(index, name)| {
+        let name = match *name {
+            Value::Keyword(ref name) | Value::String(ref name) => name,
+            _ => return None,
+        };
+        // If several '@keyframes' rules have the same name, the last one is used.
+        let keyframes = stylesheets.iter().flat_map(|stylesheet| &stylesheet.keyframes).rev()
+            .find(|keyframes| keyframes.name == *name)?;
+        Some(Animation {
+            keyframes,
+            duration: match property("animation-duration", index) {
+                Some(Value::Time(t)) => t.max(0.0),
+                _ => 0.0,
+            },
+            delay: match property("animation-delay", index) {
+                Some(Value::Time(t)) => t,
+                _ => 0.0,
+            },
+            iterations: match property("animation-iteration-count", index) {
+                Some(Value::Number(n)) => n.max(0.0),
+                Some(Value::Keyword(ref k)) if k == "infinite" => f32::INFINITY,
+                _ => 1.0,
+            },
+            direction: keyword("animation-direction", index, "normal"),
+            fill_mode: keyword("animation-fill-mode", index, "none"),
+            timing_function: keyword("animation-timing-function", index, "ease"),
+        })
+    }).collect()
+}
+
+// The items of a comma separated list, or the value on its own.
+fn list(value: Option<Value>) -> Vec<Value> {
+    match value {
+        Some(Value::List(items, Separator::Comma)) => items,
+        Some(value) => vec![value],
+        None => Vec::new(),
+    }
+}
+
+impl<'a> Animation<'a> {
+    // How far through the keyframes the animation is at 'time', from 0 to 1. Returns
+    // 'None' if the animation has no effect then.
+    fn progress(&self, time: f32) -> Option<f32> {
+        let elapsed = time - self.delay;
+        let active_duration = if self.duration > 0.0 { self.duration * self.iterations } else { 0.0 };
+        let fills = |mode: &str| self.fill_mode == mode || self.fill_mode == "both";
+
+        let (iteration, progress) = if elapsed < 0.0 {
+            if !fills("backwards") { return None }
+            (0.0, 0.0)
+        } else if elapsed >= active_duration {
+            if !fills("forwards") { return None }
+            // Stop where the last iteration ended, which may be part of the way through.
+            let end = self.iterations;
+            if end > 0.0 && end.fract() == 0.0 { (end - 1.0, 1.0) } else { (end.floor(), end.fract()) }
+        } else {
+            let iterations = elapsed / self.duration;
+            (iterations.floor(), iterations.fract())
+        };
+
+        let reversed = match &*self.direction {
+            "reverse" => true,
+            "alternate" => iteration % 2.0 == 1.0,
+            "alternate-reverse" => iteration % 2.0 == 0.0,
+            _ => false,
+        };
+        Some(if reversed { 1.0 - progress } else { progress })
+    }
+
+    // The values of the animated properties at 'progress'. Where the first or last
+    // keyframe doesn't set a property, its value on 'node' is used instead.
+    fn values_at(&self, node: &StyledNode, progress: f32) -> Vec<(String, Value)> {
+        let mut names: Vec<&String> = self.keyframes.keyframes.iter()
+            .flat_map(|keyframe| keyframe.declarations.iter().map(|declaration| &declaration.name))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        names.into_iter().filter_map(|name| {
+            let mut stops: Vec<(f32, Value)> = self.keyframes.keyframes.iter()
+                .filter_map(|keyframe| keyframe.declarations.iter().rev()
+                    .find(|declaration| declaration.name == *name)
+                    .map(|declaration| (keyframe.offset, declaration.value.clone())))
+                .collect();
+            if let Some(value) = node.value(name) {
+                if stops[0].0 > 0.0 {
+                    stops.insert(0, (0.0, value.clone()));
+                }
+                if stops[stops.len() - 1].0 < 1.0 {
+                    stops.push((1.0, value));
+                }
+            }
+
+            // Find the keyframes on either side of 'progress'.
+            let after = stops.iter().position(|&(offset, _)| offset >= progress)?;
+            let (end, ref to) = stops[after];
+            if after == 0 || end == progress {
+                return Some((name.clone(), to.clone()));
+            }
+            let (start, ref from) = stops[after - 1];
+            let t = timing_function(&self.timing_function, (progress - start) / (end - start));
+            Some((name.clone(), interpolate(from, to, t)))
+        }).collect()
+    }
+}
+
+// Apply an easing function to 't', which goes from 0 to 1.
+fn timing_function(name: &str, t: f32) -> f32 {
+    match name {
+        "linear" => t,
+        "ease-in" => cubic_bezier(0.42, 0.0, 1.0, 1.0, t),
+        "ease-out" => cubic_bezier(0.0, 0.0, 0.58, 1.0, t),
+        "ease-in-out" => cubic_bezier(0.42, 0.0, 0.58, 1.0, t),
+        "step-start" => if t > 0.0 { 1.0 } else { 0.0 },
+        "step-end" => if t < 1.0 { 0.0 } else { 1.0 },
+        _ => cubic_bezier(0.25, 0.1, 0.25, 1.0, t), // 'ease'
+    }
+}
+
+// Evaluate the cubic Bézier curve from (0, 0) to (1, 1) with control points (x1, y1)
+// and (x2, y2) at 'x', by finding the point on the curve with that x coordinate.
+fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, x: f32) -> f32 {
+    let bezier = |a: f32, b: f32, s: f32| 3.0 * a * s * (1.0 - s) * (1.0 - s) + 3.0 * b * s * s * (1.0 - s) + s * s * s;
+    // x increases along the curve, so it can be found by bisection.
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..32 {
+        let mid = (low + high) / 2.0;
+        if bezier(x1, x2, mid) < x { low = mid } else { high = mid }
+    }
+    bezier(y1, y2, (low + high) / 2.0)
+}
+
+// The value a fraction 't' of the way from 'from' to 'to'. Values that can't be
+// interpolated, like keywords or lengths in different units, switch halfway through.
+fn interpolate(from: &Value, to: &Value, t: f32) -> Value {
+    let lerp = |a: f32, b: f32| a + (b - a) * t;
+    match (from, to) {
+        (&Value::Length(a, ref unit), &Value::Length(b, ref other)) if unit == other => Value::Length(lerp(a, b), unit.clone()),
+        (&Value::Percentage(a), &Value::Percentage(b)) => Value::Percentage(lerp(a, b)),
+        (&Value::Number(a), &Value::Number(b)) => Value::Number(lerp(a, b)),
+        (&Value::List(ref a, separator), &Value::List(ref b, other)) if separator == other && a.len() == b.len() => {
+            Value::List(a.iter().zip(b).map(|(a, b)| interpolate(a, b, t)).collect(), separator)
+        }
+        _ => match (from.to_color(), to.to_color()) {
+            (Some(a), Some(b)) => {
+                let channel = |a: u8, b: u8| lerp(a as f32, b as f32).round().clamp(0.0, 255.0) as u8;
+                Value::ColorValue(Color { r: channel(a.r, b.r), g: channel(a.g, b.g), b: channel(a.b, b.b), a: channel(a.a, b.a) })
+            }
+            _ => if t < 0.5 { from.clone() } else { to.clone() },
+        },
+    }
+}
+
+// The CSS transitions running in a document. Transitions start when a property
+// with a 'transition-duration' changes between two style resolutions.
+pub struct Transitions {
+    running: Vec<Transition>,
+}
+
+struct Transition {
+    // The node, as the indices of the children leading to it from the root of the style
+    // tree. Pseudo-elements aren't counted, since they come and go with 'content'.
+    path: Vec<usize>,
+    property: String,
+    from: Value,
+    to: Value,
+    start: f32, // In seconds, including the delay
+    duration: f32,
+    timing_function: String,
+}
+
+impl Default for Transitions {
+    fn default() -> Transitions {
+        Transitions::new()
+    }
+}
+
+impl Transitions {
+    pub fn new() -> Transitions {
+        Transitions { running: Vec::new() }
+    }
+
+    // Compare a new style tree with the one rendered before it, and start transitions
+    // for the properties that changed at 'time'. 'old' should have had the running
+    // transitions applied, so that interrupted transitions continue from where they were.
+    // Transitions on nodes that are no longer in the tree are dropped.
+    pub fn update(&mut self, old: &StyledNode, new: &StyledNode, time: f32) {
+        self.running.retain(|transition| {
+            transition.start + transition.duration > time && node_at(new, &transition.path).is_some()
+        });
+        self.update_node(old, new, &mut Vec::new(), time);
+    }
+
+    fn update_node(&mut self, old: &StyledNode, new: &StyledNode, path: &mut Vec<usize>, time: f32) {
+        let properties = list(new.value("transition-property"));
+        for (index, property) in properties.iter().enumerate() {
+            let property = match *property {
+                Value::Keyword(ref property) => property,
+                _ => continue,
+            };
+            let repeated = |name: &str| {
+                let values = list(new.value(name));
+                if values.is_empty() { None } else { Some(values[index % values.len()].clone()) }
+            };
+            let duration = match repeated("transition-duration") {
+                Some(Value::Time(t)) if t > 0.0 => t,
+                _ => continue,
+            };
+            let delay = match repeated("transition-delay") {
+                Some(Value::Time(t)) => t,
+                _ => 0.0,
+            };
+            let timing_function = match repeated("transition-timing-function") {
+                Some(Value::Keyword(k)) => k,
+                _ => "ease".to_string(),
+            };
+
+            // 'all' transitions every property that has a value on both nodes.
+            let names: Vec<String> = if property == "all" {
+                new.specified_values.keys().filter(|name| !name.starts_with("transition")).cloned().collect()
+            } else {
+                vec![property.clone()]
+            };
+            for name in names {
+                let (from, to) = match (old.value(&name), new.value(&name)) {
+                    (Some(from), Some(to)) if from != to => (from, to),
+                    _ => continue,
+                };
+                let existing = self.running.iter().position(|t| t.path == *path && t.property == name);
+                if let Some(i) = existing {
+                    // Already on its way to the new value.
+                    if self.running[i].to == to { continue }
+                    self.running.remove(i);
+                }
+                self.running.push(Transition {
+                    path: path.clone(),
+                    property: name,
+                    from,
+                    to,
+                    start: time + delay,
+                    duration,
+                    timing_function: timing_function.clone(),
+                });
+            }
+        }
+
+        for (i, (old_child, new_child)) in dom_children(old).zip(dom_children(new)).enumerate() {
+            path.push(i);
+            self.update_node(old_child, new_child, path, time);
+            path.pop();
+        }
+    }
+
+    // Replace the values of transitioning properties in the tree with their values at 'time'.
+    pub fn apply(&self, root: &mut StyledNode, time: f32) {
+        for transition in &self.running {
+            let node = transition.path.iter().try_fold(&mut *root, |node, &i| {
+                node.children.iter_mut().filter(|child| child.pseudo_element.is_none()).nth(i)
+            });
+            let node = match node {
+                Some(node) => node,
+                None => continue,
+            };
+            let progress = ((time - transition.start) / transition.duration).clamp(0.0, 1.0);
+            let t = timing_function(&transition.timing_function, progress);
+            node.set_value(transition.property.clone(), interpolate(&transition.from, &transition.to, t));
+        }
+    }
+
+    // Are any transitions still running at 'time'? If so, more frames need rendering.
+    pub fn running(&self, time: f32) -> bool {
+        self.running.iter().any(|transition| transition.start + transition.duration > time)
+    }
+}
+
+// The children of a node that stand for DOM nodes, leaving out its pseudo-elements.
+fn dom_children<'a, 'b>(node: &'b StyledNode<'a>) -> impl Iterator<Item = &'b StyledNode<'a>> {
+    node.children.iter().filter(|child| child.pseudo_element.is_none())
+}
+
+// The node at the end of a transition's path, if there still is one.
+fn node_at<'a, 'b>(root: &'b StyledNode<'a>, path: &[usize]) -> Option<&'b StyledNode<'a>> {
+    path.iter().try_fold(root, |node, &i| dom_children(node).nth(i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+    use crate::testing::{style_tree, stylesheet};
+
+    // The width of the 'p' in '<div><p></p></div>', styled with 'source' and animated to 'time'.
+    fn animated_width(source: &str, time: f32) -> f32 {
+        let root = html::parse("<div><p></p></div>".to_string()).0;
+        let stylesheets = [stylesheet(source)];
+        let mut styled = style_tree(&root, &stylesheets);
+        animate(&mut styled, &stylesheets, time);
+        styled.children[0].value("width").unwrap().to_px()
+    }
+
+    #[test]
+    fn keyframes_are_interpolated() {
+        let stylesheet = "@keyframes grow { from { width: 0px; } 50% { width: 100px; } } \
+                          p { width: 10px; animation-name: grow; animation-duration: 2s; \
+                              animation-timing-function: linear; animation-delay: 500ms; }";
+        assert_eq!(animated_width(stylesheet, 0.25), 10.0);
+        assert_eq!(animated_width(stylesheet, 1.0), 50.0);
+        assert_eq!(animated_width(stylesheet, 2.0), 55.0);
+        assert_eq!(animated_width(stylesheet, 3.0), 10.0);
+    }
+
+    #[test]
+    fn alternate_direction_and_fill_mode() {
+        let stylesheet = "@keyframes grow { to { width: 100px; } } \
+                          p { width: 0px; animation-name: grow; animation-duration: 1s; animation-timing-function: linear; \
+                              animation-iteration-count: infinite; animation-direction: alternate; }";
+        assert_eq!(animated_width(stylesheet, 0.25), 25.0);
+        assert_eq!(animated_width(stylesheet, 1.25), 75.0);
+
+        let stylesheet = "@keyframes grow { from { width: 20px; } to { width: 100px; } } \
+                          p { width: 0px; animation-name: grow; animation-duration: 1s; animation-delay: 1s; \
+                              animation-fill-mode: both; }";
+        assert_eq!(animated_width(stylesheet, 0.0), 20.0);
+        assert_eq!(animated_width(stylesheet, 5.0), 100.0);
+    }
+
+    #[test]
+    fn transitions_follow_dom_children_past_pseudo_elements() {
+        let root = html::parse("<div><p></p></div>".to_string()).0;
+        let (old_stylesheets, new_stylesheets) = (
+            [stylesheet("p { width: 100px; transition: width 2s linear; }")],
+            [stylesheet("p { width: 300px; transition: width 2s linear; } div::before { content: 'x'; }")],
+        );
+        let old = style_tree(&root, &old_stylesheets);
+        let mut new = style_tree(&root, &new_stylesheets);
+        let mut transitions = Transitions::default();
+        transitions.update(&old, &new, 0.0);
+        transitions.apply(&mut new, 1.0);
+        let p = new.children.iter().find(|child| child.pseudo_element.is_none()).unwrap();
+        assert_eq!(p.value("width").unwrap().to_px(), 200.0);
+    }
+
+    #[test]
+    fn transitions_on_removed_nodes_are_dropped() {
+        let root = html::parse("<div><p></p></div>".to_string()).0;
+        let (old_stylesheets, new_stylesheets) = (
+            [stylesheet("p { width: 100px; transition: width 2s; }")],
+            [stylesheet("p { width: 300px; transition: width 2s; }")],
+        );
+        let old = style_tree(&root, &old_stylesheets);
+        let new = style_tree(&root, &new_stylesheets);
+        let mut transitions = Transitions::new();
+        transitions.update(&old, &new, 0.0);
+        assert!(transitions.running(1.0));
+
+        let empty = html::parse("<div></div>".to_string()).0;
+        let mut after = style_tree(&empty, &new_stylesheets);
+        transitions.update(&new, &after, 1.0);
+        transitions.apply(&mut after, 1.5);
+        assert!(!transitions.running(1.5));
+    }
+}
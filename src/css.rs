@@ -1,38 +1,244 @@
 use std::collections::HashMap;
+use std::fmt;
+use crate::css_tokenizer::{self, Token};
 use crate::dom;
+use crate::shorthand;
+use crate::style::{self, FontStyle};
+use crate::url;
 
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    // '@import' rules that haven't been resolved yet.
+    pub imports: Vec<Import>,
+    // Fonts declared with '@font-face', for the text stage to load.
+    pub font_faces: Vec<FontFace>,
+    pub keyframes: Vec<Keyframes>,
+    // At-rules the engine doesn't implement, which are skipped.
+    pub unknown_at_rules: Vec<UnknownAtRule>,
+}
+
+// An '@import' rule. The rules of the stylesheet at 'url' apply as if they came
+// before the importing stylesheet's own rules, but only for media matching 'media'.
+pub struct Import {
+    pub url: String,
+    pub media: Vec<MediaQuery>,
 }
 
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    // The query lists of the '@media' rules this rule is nested in. The rule only
+    // applies if every one of them matches.
+    pub media: Vec<Vec<MediaQuery>>,
+    pub origin: Origin, // Author, unless the stylesheet is registered as something else
+}
+
+// Where a stylesheet comes from. Declarations from later origins override earlier
+// ones, whatever their specificity, and '!important' reverses the order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
+// A '@keyframes' rule: the values that properties go through during the animation called 'name'.
+pub struct Keyframes {
+    pub name: String,
+    pub keyframes: Vec<Keyframe>, // Sorted by offset
+}
+
+// An at-rule the engine doesn't implement, like '@page :first { margin: 1in }', kept so that
+// callers can see what was ignored. The prelude and block are source text.
+pub struct UnknownAtRule {
+    pub name: String,
+    pub prelude: String,       // What comes between the name and the block or ';'
+    pub block: Option<String>, // The contents of the '{}' block, if it has one
+}
+
+pub struct Keyframe {
+    pub offset: f32, // From 0 ('from') to 1 ('to')
+    pub declarations: Vec<Declaration>,
+}
+
+// A '@font-face' rule: a font that can be downloaded for 'family' in the given weight and style.
+pub struct FontFace {
+    pub family: String,
+    pub src: Vec<FontSource>, // Alternatives to try, in order
+    pub weight: u16,
+    pub style: FontStyle,
+}
+
+pub enum FontSource {
+    Url(String, Option<String>), // 'url(...) format(...)', with an optional format hint
+    Local(String),               // 'local(...)', a font installed on the system
+}
+
+// A media query such as 'screen and (min-width: 600px)'.
+#[derive(Clone)]
+pub struct MediaQuery {
+    pub negated: bool,              // 'not screen'
+    pub media_type: Option<String>, // 'None' if the query only tests features
+    pub features: Vec<MediaFeature>,
+}
+
+// A media feature test such as '(min-width: 600px)' or '(color)'.
+#[derive(Clone)]
+pub struct MediaFeature {
+    pub name: String,
+    pub value: Option<Value>,
 }
 
+#[derive(Clone)]
 pub enum Selector {
     Simple(SimpleSelector),
+    Complex(ComplexSelector),
+}
+
+// Simple selectors joined by combinators, such as 'div p'.
+#[derive(Clone)]
+pub struct ComplexSelector {
+    // The rightmost simple selector, which the element itself has to match.
+    pub subject: SimpleSelector,
+    // The rest of the selector from right to left. Each simple selector has to match an
+    // element related by the combinator to the element matched by the one before it.
+    pub combinators: Vec<(Combinator, SimpleSelector)>,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Combinator {
+    Descendant,        // 'a b'
+    Child,             // 'a > b'
+    NextSibling,       // 'a + b'
+    SubsequentSibling, // 'a ~ b'
+}
+
+#[derive(Clone)]
 pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub attributes: Vec<AttributeSelector>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+// An attribute selector such as '[disabled]' or '[type="text"]'.
+#[derive(Clone)]
+pub struct AttributeSelector {
+    pub name: String,
+    pub operator: AttributeOperator,
+}
+
+#[derive(Clone)]
+pub enum PseudoClass {
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    // ':nth-child(an+b)' and ':nth-last-child(an+b)', stored as (a, b).
+    NthChild(i32, i32),
+    NthLastChild(i32, i32),
+    // User interaction, from the state set on the engine.
+    Hover,
+    Focus,
+    Active,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum PseudoElement {
+    Before,
+    After,
+    Marker, // A list item's bullet or number
+}
+
+#[derive(Clone)]
+pub enum AttributeOperator {
+    Exists,            // '[attr]'
+    Equals(String),    // '[attr=value]'
+    Includes(String),  // '[attr~=value]', one of a whitespace separated list of words
+    DashMatch(String), // '[attr|=value]', exactly 'value' or starting with 'value-'
+    Prefix(String),    // '[attr^=value]'
+    Suffix(String),    // '[attr$=value]'
+    Substring(String), // '[attr*=value]'
+}
+
+#[derive(Clone)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    pub important: bool,
 }
 
 #[derive(Clone, PartialEq)]
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
+    Percentage(f32),
+    Number(f32),
+    String(String),
+    Url(String),
     ColorValue(Color),
+    Time(f32), // In seconds
+    Flex(f32), // A share of the room left over in a grid container, in 'fr'
+    Calc(Box<Calc>),
+    Var(String, Option<Box<Value>>), // 'var(--name, fallback)'
+    List(Vec<Value>, Separator),
+    Transform(Vec<TransformFunction>), // The functions in a 'transform' value, applied left to right
     // insert more values here
 }
 
+// The expression in 'calc()', such as '100% - 2 * 10px'.
+#[derive(Clone, PartialEq)]
+pub enum Calc {
+    Leaf(Value), // A length, percentage or number
+    Sum(Box<Calc>, Box<Calc>),
+    Difference(Box<Calc>, Box<Calc>),
+    Product(Box<Calc>, Box<Calc>),
+    Quotient(Box<Calc>, Box<Calc>),
+}
+
+impl Calc {
+    // Evaluate the expression in px, resolving lengths and percentages against 'ctx'.
+    // One side of each product and the right side of each quotient is a number.
+    pub fn resolve(&self, ctx: &LengthContext) -> f32 {
+        match *self {
+            Calc::Leaf(Value::Number(n)) => n,
+            Calc::Leaf(ref value) => value.resolve(ctx),
+            Calc::Sum(ref a, ref b) => a.resolve(ctx) + b.resolve(ctx),
+            Calc::Difference(ref a, ref b) => a.resolve(ctx) - b.resolve(ctx),
+            Calc::Product(ref a, ref b) => a.resolve(ctx) * b.resolve(ctx),
+            Calc::Quotient(ref a, ref b) => a.resolve(ctx) / b.resolve(ctx),
+        }
+    }
+
+    pub fn contains_var(&self) -> bool {
+        match *self {
+            Calc::Leaf(ref value) => value.contains_var(),
+            Calc::Sum(ref a, ref b) | Calc::Difference(ref a, ref b) |
+            Calc::Product(ref a, ref b) | Calc::Quotient(ref a, ref b) => a.contains_var() || b.contains_var(),
+        }
+    }
+}
+
+// A function in a 'transform' value. The single axis forms, like 'translateX()', are stored
+// with the identity value for the other axis.
+#[derive(Clone, PartialEq)]
+pub enum TransformFunction {
+    Translate(Value, Value), // Lengths or percentages of the element's own size
+    Scale(f32, f32),
+    Rotate(f32), // Clockwise, in degrees
+    Skew(f32, f32), // In degrees
+    Matrix([f32; 6]), // 'matrix(a, b, c, d, e, f)'
+}
+
+// What separates the components of a 'Value::List'.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Separator {
+    Space,
+    Comma,
+    Slash,
+}
+
 impl Value {
     pub fn to_px(&self) -> f32 {
         match *self {
@@ -40,14 +246,95 @@ impl Value {
             _ => 0.0,
         }
     }
+
+    // The color this value denotes, if any. Keywords are looked up in the named color table.
+    pub fn to_color(&self) -> Option<Color> {
+        match *self {
+            Value::ColorValue(color) => Some(color),
+            Value::Keyword(ref name) => named_color(name),
+            _ => None,
+        }
+    }
+
+    // Is this one of the keywords that every property accepts: 'inherit', 'initial', 'unset' or 'revert'?
+    pub fn is_css_wide_keyword(&self) -> bool {
+        match *self {
+            Value::Keyword(ref k) => matches!(&*k.to_ascii_lowercase(), "inherit" | "initial" | "unset" | "revert"),
+            _ => false,
+        }
+    }
+
+    // Does this value refer to custom properties with 'var()'?
+    pub fn contains_var(&self) -> bool {
+        match *self {
+            Value::Var(..) => true,
+            Value::Calc(ref calc) => calc.contains_var(),
+            Value::List(ref items, _) => items.iter().any(|item| item.contains_var()),
+            _ => false,
+        }
+    }
+
+    // Convert a length to pixels, resolving relative units against 'ctx'.
+    pub fn resolve(&self, ctx: &LengthContext) -> f32 {
+        match *self {
+            Value::Length(f, Unit::Px) => f,
+            Value::Length(f, Unit::Em) => f * ctx.font_size,
+            Value::Length(f, Unit::Rem) => f * ctx.root_font_size,
+            Value::Length(f, Unit::Vw) => f / 100.0 * ctx.viewport_width,
+            Value::Length(f, Unit::Vh) => f / 100.0 * ctx.viewport_height,
+            Value::Length(f, Unit::Vmin) => f / 100.0 * ctx.viewport_width.min(ctx.viewport_height),
+            Value::Length(f, Unit::Vmax) => f / 100.0 * ctx.viewport_width.max(ctx.viewport_height),
+            Value::Length(f, Unit::In) => f * ctx.dpi,
+            Value::Length(f, Unit::Cm) => f * ctx.dpi / 2.54,
+            Value::Length(f, Unit::Mm) => f * ctx.dpi / 25.4,
+            Value::Length(f, Unit::Pt) => f * ctx.dpi / 72.0,
+            Value::Length(f, Unit::Pc) => f * ctx.dpi / 6.0,
+            Value::Percentage(p) => p / 100.0 * ctx.containing_width,
+            Value::Calc(ref calc) => calc.resolve(ctx),
+            _ => 0.0,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
 pub enum Unit {
     Px,
+    Em,
+    Rem,
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+    In,
+    Cm,
+    Mm,
+    Pt,
+    Pc,
     // insert more units here
 }
 
+// CSS defines one inch as exactly 96px.
+pub const DEFAULT_DPI: f32 = 96.0;
+
+// The reference sizes that relative lengths are resolved against.
+#[derive(Clone, Copy, PartialEq)]
+pub struct LengthContext {
+    // Font size of the element (or of its parent, when resolving 'font-size' itself).
+    pub font_size: f32,
+    // Font size of the root element.
+    pub root_font_size: f32,
+    // Width of the containing block, which percentages are resolved against.
+    pub containing_width: f32,
+    // Height of the containing block, if it's known before its contents are laid out.
+    // Percentage heights are resolved against it, and act like 'auto' without it.
+    pub containing_height: Option<f32>,
+    // Size of the initial viewport, for 'vw', 'vh', 'vmin' and 'vmax'.
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+    // Pixels per inch, for the physical units ('in', 'cm', 'mm', 'pt' and 'pc').
+    pub dpi: f32,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub struct Color {
     pub r: u8,
@@ -56,210 +343,2647 @@ pub struct Color {
     pub a: u8,
 }
 
+impl Color {
+    // Convert a hue (in degrees), saturation and lightness (from 0 to 1) into RGB.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: u8) -> Color {
+        let h = (hue / 360.0).rem_euclid(1.0);
+        let s = saturation.clamp(0.0, 1.0);
+        let l = lightness.clamp(0.0, 1.0);
+
+        let m2 = if l <= 0.5 { l * (s + 1.0) } else { l + s - l * s };
+        let m1 = l * 2.0 - m2;
+        let channel = |h: f32| (hue_to_rgb(m1, m2, h) * 255.0).round() as u8;
+        Color {
+            r: channel(h + 1.0 / 3.0),
+            g: channel(h),
+            b: channel(h - 1.0 / 3.0),
+            a: alpha,
+        }
+    }
+}
+
+// The 'hue to rgb' step of the HSL conversion from the CSS Color specification.
+fn hue_to_rgb(m1: f32, m2: f32, h: f32) -> f32 {
+    let h = h.rem_euclid(1.0);
+    if h * 6.0 < 1.0 {
+        m1 + (m2 - m1) * h * 6.0
+    } else if h * 2.0 < 1.0 {
+        m2
+    } else if h * 3.0 < 2.0 {
+        m1 + (m2 - m1) * (2.0 / 3.0 - h) * 6.0
+    } else {
+        m1
+    }
+}
+
+// A problem found while parsing a stylesheet (or an HTML document). The parser skips
+// the malformed declaration or rule and carries on.
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    // Where the problem is: a byte offset into the source, and the equivalent
+    // line and column (in characters), both counted from 1.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseError {
+    // An error at the byte offset 'offset' into 'source'.
+    pub fn new(source: &str, offset: usize, message: String) -> ParseError {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            message,
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+// Parses the tokens from 'css_tokenizer' into rules.
 struct Parser {
-    pos: usize,
+    tokens: Vec<(Token, usize)>, // Each token with its byte offset in 'input'
+    pos: usize,                  // The index of the next token
     input: String,
+    imports: Vec<Import>,
+    font_faces: Vec<FontFace>,
+    keyframes: Vec<Keyframes>,
+    unknown_at_rules: Vec<UnknownAtRule>,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
+    fn new(source: String) -> Parser {
+        Parser {
+            tokens: css_tokenizer::tokenize(&source),
+            pos: 0,
+            input: source,
+            imports: Vec::new(),
+            font_faces: Vec::new(),
+            keyframes: Vec::new(),
+            unknown_at_rules: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    // Parse rules up to the end of the input, or the '}' that closes an enclosing at-rule.
+    // Malformed rules are skipped.
     fn parse_rules(&mut self) -> Vec<Rule> {
         let mut rules = Vec::new();
         loop {
-            self.consume_whitespace();
-            if self.eof() { break }
-            rules.push(self.parse_rule());
+            // '<!--' and '-->' are ignored, so that stylesheets can be hidden in HTML comments.
+            while matches!(*self.next_token(), Token::Whitespace | Token::Cdo | Token::Cdc) {
+                self.consume_token();
+            }
+            if matches!(*self.next_token(), Token::Eof | Token::CloseCurly) { break }
+            let start = self.pos;
+            let result = if let Token::AtKeyword(_) = *self.next_token() {
+                self.parse_at_rule()
+            } else {
+                self.parse_rule(None)
+            };
+            match result {
+                Ok(new_rules) => rules.extend(new_rules),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.pos = start;
+                    self.skip(true);
+                }
+            }
         }
         rules
     }
 
-    // Read the current char without consuming it.
-    fn next_char(&self) -> char  {
-        self.input[self.pos..].chars().next().unwrap()
+    // Parse an at-rule such as '@media', returning the rules nested inside it.
+    fn parse_at_rule(&mut self) -> ParseResult<Vec<Rule>> {
+        let start = self.offset();
+        let name = match self.consume_token() {
+            Token::AtKeyword(name) => name.to_ascii_lowercase(),
+            token => return self.error_at(start, format!("Expected an at-rule but found {}", token)),
+        };
+        self.consume_whitespace();
+        match &*name {
+            "media" => self.parse_media_rule(),
+            "supports" => self.parse_supports_rule(),
+            "import" => {
+                let import = self.parse_import()?;
+                self.imports.push(import);
+                Ok(Vec::new())
+            }
+            "font-face" => {
+                let font_face = self.parse_font_face()?;
+                self.font_faces.push(font_face);
+                Ok(Vec::new())
+            }
+            "keyframes" => {
+                let keyframes = self.parse_keyframes()?;
+                self.keyframes.push(keyframes);
+                Ok(Vec::new())
+            }
+            _ => {
+                let unknown = self.parse_unknown_at_rule(name);
+                self.unknown_at_rules.push(unknown);
+                Ok(Vec::new())
+            }
+        }
     }
 
-    // Do the next characters start with the given string?
-    fn starts_with(&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
+    // Parse the rest of an at-rule the engine doesn't implement: a prelude up to a ';', or
+    // up to a '{}' block, which is skipped as a whole.
+    fn parse_unknown_at_rule(&mut self, name: String) -> UnknownAtRule {
+        let prelude_start = self.offset();
+        let mut depth = 0;
+        loop {
+            match *self.next_token() {
+                Token::OpenParen | Token::OpenSquare | Token::Function(_) => depth += 1,
+                Token::CloseParen | Token::CloseSquare if depth > 0 => depth -= 1,
+                Token::OpenCurly if depth == 0 => break,
+                // A '}' closing an enclosing block ends the at-rule too, but is left for its parser.
+                Token::Semicolon | Token::CloseCurly | Token::Eof if depth == 0 => {
+                    let prelude = self.input[prelude_start..self.offset()].trim().to_string();
+                    if *self.next_token() == Token::Semicolon {
+                        self.consume_token();
+                    }
+                    return UnknownAtRule { name, prelude, block: None };
+                }
+                Token::Eof => break,
+                _ => {}
+            }
+            self.consume_token();
+        }
+        let prelude = self.input[prelude_start..self.offset()].trim().to_string();
+        self.consume_token();
+        let block_start = self.offset();
+        let mut depth = 0;
+        loop {
+            match *self.next_token() {
+                Token::OpenCurly | Token::OpenParen | Token::OpenSquare | Token::Function(_) => depth += 1,
+                Token::CloseCurly if depth == 0 => break,
+                Token::CloseCurly | Token::CloseParen | Token::CloseSquare if depth > 0 => depth -= 1,
+                Token::Eof => break,
+                _ => {}
+            }
+            self.consume_token();
+        }
+        let block = self.input[block_start..self.offset()].trim().to_string();
+        self.consume_token();
+        UnknownAtRule { name, prelude, block: Some(block) }
     }
 
-    // Return true if all input is consumed.
-    fn eof(&self) -> bool {
-        self.pos >= self.input.len()
+    // Parse the rest of an '@import <url> [<media query list>];' rule.
+    fn parse_import(&mut self) -> ParseResult<Import> {
+        let url = match self.parse_value()? {
+            Value::Url(url) | Value::String(url) => url,
+            _ => return self.error("Expected a URL in @import".to_string())
+        };
+        self.consume_whitespace();
+        let media = self.parse_media_query_list()?;
+        self.expect(Token::Semicolon)?;
+        Ok(Import { url, media })
     }
 
-    // Return the current character, and advance self.pos to the next character.
-    fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += next_pos;
-        return cur_char;
+    // Parse the rest of a '@media <media query list> { <rules> }' rule.
+    fn parse_media_rule(&mut self) -> ParseResult<Vec<Rule>> {
+        let queries = self.parse_media_query_list()?;
+        self.expect(Token::OpenCurly)?;
+        let mut rules = self.parse_rules();
+        self.expect_block_end()?;
+        for rule in &mut rules {
+            rule.media.insert(0, queries.clone());
+        }
+        Ok(rules)
     }
 
-    // Consume characters until 'test' returns false.
-    fn consume_while<F>(&mut self, test: F) -> String
-            where F: Fn(char) -> bool {
-        let mut result = String::new();
-        while !self.eof() && test(self.next_char()) {
-            result.push(self.consume_char());
+    // Parse the rest of a '@supports <condition> { <rules> }' rule. The nested rules are
+    // only kept if the engine supports the condition.
+    fn parse_supports_rule(&mut self) -> ParseResult<Vec<Rule>> {
+        let supported = self.parse_supports_condition()?;
+        self.consume_whitespace();
+        self.expect(Token::OpenCurly)?;
+        let (font_faces, keyframes, unknown) = (self.font_faces.len(), self.keyframes.len(), self.unknown_at_rules.len());
+        let rules = self.parse_rules();
+        self.expect_block_end()?;
+        if !supported {
+            self.font_faces.truncate(font_faces);
+            self.keyframes.truncate(keyframes);
+            self.unknown_at_rules.truncate(unknown);
+            return Ok(Vec::new());
         }
-        return result;
+        Ok(rules)
     }
 
-    // Consume and discard zero or more whitespace characters.
-    fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+    // Parse and evaluate a '@supports' condition: 'not <in-parens>', or '<in-parens>'
+    // joined by either 'and' or 'or'. Mixing them needs parentheses.
+    fn parse_supports_condition(&mut self) -> ParseResult<bool> {
+        if let Token::Ident(ref word) = *self.next_token() {
+            if word.eq_ignore_ascii_case("not") {
+                self.consume_token();
+                self.consume_whitespace();
+                return Ok(!self.parse_supports_in_parens()?);
+            }
+        }
+        let mut result = self.parse_supports_in_parens()?;
+        let mut operator: Option<String> = None;
+        loop {
+            self.consume_whitespace();
+            let word = match *self.next_token() {
+                Token::Ident(ref word) => word.to_ascii_lowercase(),
+                _ => break,
+            };
+            if word != "and" && word != "or" {
+                return self.error(format!("Expected 'and' or 'or' in @supports condition but found {}", word));
+            }
+            if operator.as_ref().is_some_and(|operator| *operator != word) {
+                return self.error("Mixed 'and' and 'or' in @supports condition".to_string());
+            }
+            self.consume_token();
+            self.consume_whitespace();
+            let next = self.parse_supports_in_parens()?;
+            result = if word == "and" { result && next } else { result || next };
+            operator = Some(word);
+        }
+        Ok(result)
     }
 
-    // Parse one simple selector, e.g.: 'type#id.class1.class2.class3'
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
-        let mut selector = SimpleSelector { tag_name: None, id: None, class: Vec::new() };
-        while !self.eof() {
-            match self.next_char() {
-                '#' => {
-                    self.consume_char();
-                    selector.id = Some(self.parse_identifier());
-                }
-                '.' => {
-                    self.consume_char();
-                    selector.class.push(self.parse_identifier());
-                }
-                '*' => {
-                    // Universal selector
-                    self.consume_char();
-                }
-                c if valid_identifier_char(c) => {
-                    selector.tag_name = Some(self.parse_identifier());
-                }
-                _ => break
+    // Parse '(<condition>)' or '(<property>: <value>)'. Anything else in parentheses,
+    // including a declaration the parser rejects, and functions like 'selector()' are false.
+    fn parse_supports_in_parens(&mut self) -> ParseResult<bool> {
+        match *self.next_token() {
+            Token::OpenParen => {}
+            Token::Function(_) => {
+                self.skip_parens();
+                return Ok(false);
+            }
+            ref token => return self.error(format!("Unexpected {} in @supports condition", token)),
+        }
+        let start = self.pos;
+        self.consume_token();
+        self.consume_whitespace();
+        let result = match *self.next_token() {
+            Token::Ident(ref word) if word.eq_ignore_ascii_case("not") => self.parse_supports_condition(),
+            Token::OpenParen | Token::Function(_) => self.parse_supports_condition(),
+            _ => self.parse_supports_declaration(),
+        };
+        self.consume_whitespace();
+        match result {
+            Ok(result) if *self.next_token() == Token::CloseParen => {
+                self.consume_token();
+                Ok(result)
+            }
+            _ => {
+                self.pos = start;
+                self.skip_parens();
+                Ok(false)
             }
         }
-        return selector;
     }
 
-    fn parse_identifier(&mut self) -> String {
-        self.consume_while(valid_identifier_char)
+    // Parse the '<property>: <value>' in a '@supports' condition, and look it up in the
+    // engine's registry of supported properties.
+    fn parse_supports_declaration(&mut self) -> ParseResult<bool> {
+        let mut name = self.parse_identifier()?;
+        if !name.starts_with("--") {
+            name.make_ascii_lowercase();
+        }
+        self.consume_whitespace();
+        self.expect(Token::Colon)?;
+        self.consume_whitespace();
+        let value = self.parse_comma_list()?;
+        Ok(style::supports(&name, &value))
     }
 
-    // Parse a rule set: '<selector> { <declarations> }'
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    // Parse the rest of a '@font-face { <descriptors> }' rule.
+    fn parse_font_face(&mut self) -> ParseResult<FontFace> {
+        let mut font_face = FontFace { family: String::new(), src: Vec::new(), weight: 400, style: FontStyle::Normal };
+        self.expect(Token::OpenCurly)?;
+        loop {
+            self.consume_whitespace();
+            if matches!(*self.next_token(), Token::Eof | Token::CloseCurly) {
+                self.expect_block_end()?;
+                break;
+            }
+            let name = self.parse_identifier()?.to_ascii_lowercase();
+            self.consume_whitespace();
+            self.expect(Token::Colon)?;
+            self.consume_whitespace();
+            if name == "src" {
+                font_face.src = self.parse_font_sources()?;
+            } else {
+                let value = self.parse_comma_list()?;
+                match (&*name, value) {
+                    ("font-family", Value::String(family)) | ("font-family", Value::Keyword(family)) => {
+                        font_face.family = family;
+                    }
+                    // An unquoted family name made of several identifiers.
+                    ("font-family", Value::List(words, Separator::Space)) => {
+                        let mut family = Vec::new();
+                        for word in words {
+                            match word {
+                                Value::Keyword(word) => family.push(word),
+                                _ => return self.error("Invalid font-family in @font-face".to_string()),
+                            }
+                        }
+                        font_face.family = family.join(" ");
+                    }
+                    ("font-weight", Value::Number(n)) => font_face.weight = n.clamp(1.0, 1000.0) as u16,
+                    ("font-weight", Value::Keyword(k)) => font_face.weight = if k == "bold" { 700 } else { 400 },
+                    ("font-style", Value::Keyword(k)) => font_face.style = match &*k {
+                        "italic" => FontStyle::Italic,
+                        "oblique" => FontStyle::Oblique,
+                        _ => FontStyle::Normal,
+                    },
+                    // Other descriptors, like 'font-display', aren't used.
+                    _ => {}
+                }
+            }
+            self.consume_whitespace();
+            self.expect_declaration_end()?;
+        }
+        if font_face.family.is_empty() {
+            return self.error("@font-face without a font-family".to_string());
         }
+        Ok(font_face)
     }
 
-    // Parse a comma separated list of selectors.
-    fn parse_selectors(&mut self) -> Vec<Selector> {
-        let mut selectors = Vec::new();
+    // Parse the 'src' descriptor of '@font-face': a comma separated list of
+    // 'url(<url>) [format(<string>)]' and 'local(<name>)'.
+    fn parse_font_sources(&mut self) -> ParseResult<Vec<FontSource>> {
+        let mut sources = Vec::new();
+        loop {
+            let start = self.offset();
+            let url = match self.consume_token() {
+                Token::Url(url) => Some(url),
+                Token::Function(ref name) if name.eq_ignore_ascii_case("url") => Some(self.parse_function_argument()?),
+                Token::Function(ref name) if name.eq_ignore_ascii_case("local") => None,
+                token => return self.error_at(start, format!("Unexpected {} in @font-face src", token))
+            };
+            match url {
+                Some(url) => {
+                    self.consume_whitespace();
+                    let mut format = None;
+                    if let Token::Function(ref name) = *self.next_token() {
+                        if name.eq_ignore_ascii_case("format") {
+                            self.consume_token();
+                            format = Some(self.parse_function_argument()?);
+                        }
+                    }
+                    sources.push(FontSource::Url(url, format));
+                }
+                None => sources.push(FontSource::Local(self.parse_function_argument()?)),
+            }
+            self.consume_whitespace();
+            if *self.next_token() != Token::Comma {
+                break;
+            }
+            self.consume_token();
+            self.consume_whitespace();
+        }
+        Ok(sources)
+    }
+
+    // Parse the rest of a '@keyframes <name> { <keyframe>* }' rule, where each keyframe is
+    // '<offsets> { <declarations> }' and the offsets are percentages, 'from' or 'to'.
+    fn parse_keyframes(&mut self) -> ParseResult<Keyframes> {
+        let start = self.offset();
+        let name = match self.consume_token() {
+            Token::Ident(name) | Token::String(name) => name,
+            token => return self.error_at(start, format!("Expected a name in @keyframes but found {}", token)),
+        };
+        self.consume_whitespace();
+        self.expect(Token::OpenCurly)?;
+        let mut keyframes = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
             self.consume_whitespace();
-            match self.next_char() {
-                ',' => { self.consume_char(); self.consume_whitespace(); }
-                '{' => break, // start of declarations
-                c   => panic!("Unexpected character {} in selector list", c)
+            if matches!(*self.next_token(), Token::Eof | Token::CloseCurly) {
+                self.expect_block_end()?;
+                break;
+            }
+            let mut offsets = Vec::new();
+            loop {
+                let start = self.offset();
+                offsets.push(match self.consume_token() {
+                    Token::Percentage(p) => p / 100.0,
+                    Token::Ident(ref k) if k.eq_ignore_ascii_case("from") => 0.0,
+                    Token::Ident(ref k) if k.eq_ignore_ascii_case("to") => 1.0,
+                    _ => return self.error_at(start, format!("Invalid keyframe selector in @keyframes {}", name))
+                });
+                self.consume_whitespace();
+                match *self.next_token() {
+                    Token::Comma => { self.consume_token(); self.consume_whitespace(); }
+                    Token::OpenCurly => break,
+                    ref token => return self.error(format!("Unexpected {} in keyframe selector", token))
+                }
+            }
+            let (declarations, _) = self.parse_declarations(None)?;
+            for &offset in &offsets {
+                keyframes.push(Keyframe { offset, declarations: declarations.clone() });
             }
         }
+        keyframes.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap());
+        Ok(Keyframes { name, keyframes })
+    }
 
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        return selectors;
+    // Parse a comma separated list of media queries, up to the '{' or ';' that ends it.
+    fn parse_media_query_list(&mut self) -> ParseResult<Vec<MediaQuery>> {
+        let mut queries = Vec::new();
+        while !matches!(*self.next_token(), Token::OpenCurly | Token::Semicolon) {
+            queries.push(self.parse_media_query()?);
+            match *self.next_token() {
+                Token::Comma => { self.consume_token(); self.consume_whitespace(); }
+                Token::OpenCurly | Token::Semicolon => {}
+                ref token => return self.error(format!("Unexpected {} in media query list", token))
+            }
+        }
+        Ok(queries)
     }
 
-    // Parse a semicolon separated list of declarations
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.consume_char(), '{');
-        let mut declarations = Vec::new();
+    // Parse one media query: '[not | only] <media type> [and <feature>]*' or '<feature> [and <feature>]*'.
+    fn parse_media_query(&mut self) -> ParseResult<MediaQuery> {
+        let mut query = MediaQuery { negated: false, media_type: None, features: Vec::new() };
+        if *self.next_token() == Token::OpenParen {
+            query.features.push(self.parse_media_feature()?);
+        } else {
+            let mut media_type = self.parse_identifier()?.to_ascii_lowercase();
+            if media_type == "not" || media_type == "only" {
+                query.negated = media_type == "not";
+                self.consume_whitespace();
+                media_type = self.parse_identifier()?.to_ascii_lowercase();
+            }
+            query.media_type = Some(media_type);
+        }
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
-                self.consume_char();
-                break;
+            match *self.next_token() {
+                Token::Comma | Token::OpenCurly | Token::Semicolon => break,
+                Token::Ident(ref word) if word.eq_ignore_ascii_case("and") => {
+                    self.consume_token();
+                    self.consume_whitespace();
+                    query.features.push(self.parse_media_feature()?);
+                }
+                _ => return self.error("Expected 'and' in media query".to_string()),
             }
-            declarations.push(self.parse_declaration());
         }
-        declarations
+        Ok(query)
     }
 
-    // Parse a single '<property>: <value>;' declaration.
-    fn parse_declaration(&mut self) -> Declaration {
-        let property_name = self.parse_identifier();
+    // Parse a media feature: '(<name>)' or '(<name>: <value>)'.
+    fn parse_media_feature(&mut self) -> ParseResult<MediaFeature> {
+        self.expect(Token::OpenParen)?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ':');
+        let name = self.parse_identifier()?.to_ascii_lowercase();
         self.consume_whitespace();
-        let value = self.parse_value();
-        self.consume_whitespace();
-        assert_eq!(self.consume_char(), ';');
+        let mut value = None;
+        if *self.next_token() == Token::Colon {
+            self.consume_token();
+            self.consume_whitespace();
+            value = Some(self.parse_value()?);
+            self.consume_whitespace();
+        }
+        self.expect(Token::CloseParen)?;
+        Ok(MediaFeature { name, value })
+    }
+
+    // The next token, without consuming it.
+    fn next_token(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    // The byte offset of the next token in the source.
+    fn offset(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    // Return true if all input is consumed.
+    fn eof(&self) -> bool {
+        *self.next_token() == Token::Eof
+    }
 
-        Declaration {
-            name: property_name,
-            value: value,
+    // Return the next token and move past it. The 'Eof' token is never consumed.
+    fn consume_token(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
         }
+        token
     }
 
-    fn parse_value(&mut self) -> Value {
-        match self.next_char() {
-            '0'..='9' => self.parse_length(),
-            '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier())
+    // Consume any whitespace, returning whether there was some.
+    fn consume_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while *self.next_token() == Token::Whitespace {
+            self.pos += 1;
         }
+        self.pos > start
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    // Consume the token 'expected', or fail if the input continues with something else.
+    fn expect(&mut self, expected: Token) -> ParseResult<()> {
+        if *self.next_token() != expected {
+            return self.error(format!("Expected {} but found {}", expected, self.next_token()));
+        }
+        self.consume_token();
+        Ok(())
     }
 
-    fn parse_float(&mut self) -> f32 {
-        let s = self.consume_while(|c| match c {
-            '0'..='9' | '.' => true,
-            _ => false,
-        });
-        s.parse().unwrap()
+    // Consume the '}' that closes a block. The end of the input closes any open blocks too.
+    fn expect_block_end(&mut self) -> ParseResult<()> {
+        if self.eof() { Ok(()) } else { self.expect(Token::CloseCurly) }
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("Unrecognised unit!")
+    // Consume the ';' after a declaration, which may be left out before the end of the block.
+    fn expect_declaration_end(&mut self) -> ParseResult<()> {
+        match *self.next_token() {
+            Token::CloseCurly | Token::Eof => Ok(()),
+            _ => self.expect(Token::Semicolon),
         }
     }
 
-    fn parse_color(&mut self) -> Value {
-        assert_eq!(self.consume_char(), '#');
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255 })
+    // An error at the next token.
+    fn error<T>(&self, message: String) -> ParseResult<T> {
+        self.error_at(self.offset(), message)
     }
 
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos .. self.pos + 2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    // An error at the byte offset 'pos', such as the start of an invalid value.
+    fn error_at<T>(&self, pos: usize, message: String) -> ParseResult<T> {
+        Err(self.parse_error(pos, message))
     }
-}
 
-fn valid_identifier_char(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true,
-        _ => false,
+    fn parse_error(&self, pos: usize, message: String) -> ParseError {
+        ParseError::new(&self.input, pos, message)
     }
-}
 
-pub type Specificity = (usize, usize, usize);
+    // Skip to the end of a malformed declaration or rule: past the next ';', or past the
+    // end of the next block if 'block' is set. Nested blocks and functions are skipped as
+    // a whole, and a '}' closing an enclosing block is left for its parser.
+    fn skip(&mut self, block: bool) {
+        let mut depth = 0;
+        loop {
+            match *self.next_token() {
+                Token::Eof => return,
+                Token::OpenCurly | Token::OpenParen | Token::OpenSquare | Token::Function(_) => depth += 1,
+                Token::CloseCurly if depth == 0 => return,
+                Token::CloseParen | Token::CloseSquare if depth == 0 => {}
+                Token::CloseCurly if depth == 1 && block => {
+                    self.consume_token();
+                    return;
+                }
+                Token::CloseCurly | Token::CloseParen | Token::CloseSquare => depth -= 1,
+                Token::Semicolon if depth == 0 => {
+                    self.consume_token();
+                    return;
+                }
+                _ => {}
+            }
+            self.consume_token();
+        }
+    }
 
-impl Selector {
-    pub fn specificity(&self) -> Specificity {
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
-        (a, b, c)
+    // Consume a '(' or function token, and everything up to the ')' that closes it.
+    fn skip_parens(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.consume_token() {
+                Token::OpenParen | Token::Function(_) => depth += 1,
+                Token::CloseParen if depth == 1 => return,
+                Token::CloseParen => depth -= 1,
+                Token::Eof => return,
+                _ => {}
+            }
+        }
+    }
+
+    // Parse one simple selector, e.g.: 'type#id.class1.class2.class3'. Also returns
+    // whether it contains the nesting selector '&', which stands for the parent rule's selector.
+    fn parse_simple_selector(&mut self) -> ParseResult<(SimpleSelector, bool)> {
+        let mut selector = SimpleSelector::universal();
+        let mut nesting = false;
+        loop {
+            match self.next_token().clone() {
+                Token::Delim('&') => {
+                    self.consume_token();
+                    nesting = true;
+                }
+                Token::Hash(id, true) => {
+                    self.consume_token();
+                    selector.id = Some(id);
+                }
+                Token::Delim('.') => {
+                    self.consume_token();
+                    selector.class.push(self.parse_identifier()?);
+                }
+                Token::Delim('*') => {
+                    // Universal selector
+                    self.consume_token();
+                }
+                Token::OpenSquare => {
+                    selector.attributes.push(self.parse_attribute_selector()?);
+                }
+                Token::Colon => {
+                    let start = self.offset();
+                    self.consume_token();
+                    // Pseudo-elements start with '::', but ':before' and ':after' are also allowed.
+                    let double_colon = *self.next_token() == Token::Colon;
+                    if double_colon {
+                        self.consume_token();
+                    }
+                    match self.consume_token() {
+                        Token::Ident(ref name) if name.eq_ignore_ascii_case("before") => {
+                            selector.pseudo_element = Some(PseudoElement::Before);
+                        }
+                        Token::Ident(ref name) if name.eq_ignore_ascii_case("after") => {
+                            selector.pseudo_element = Some(PseudoElement::After);
+                        }
+                        Token::Ident(ref name) if double_colon && name.eq_ignore_ascii_case("marker") => {
+                            selector.pseudo_element = Some(PseudoElement::Marker);
+                        }
+                        token if double_colon => return self.error_at(start, format!("Unsupported pseudo-element ::{}", token)),
+                        token => selector.pseudo_classes.push(self.parse_pseudo_class(start, token)?),
+                    }
+                }
+                Token::Ident(name) => {
+                    // HTML tag names are case-insensitive, and the HTML parser lowercases them.
+                    self.consume_token();
+                    selector.tag_name = Some(name.to_ascii_lowercase());
+                }
+                _ => break
+            }
+        }
+        Ok((selector, nesting))
+    }
+
+    // Parse a simple selector that can't be left out, as at the start of a selector and
+    // after a combinator: '{ }', 'a, { }', '> p { }' and 'p > { }' are all invalid, except
+    // that a nested selector can start with a combinator.
+    fn parse_required_simple_selector(&mut self) -> ParseResult<(SimpleSelector, bool)> {
+        let start = self.pos;
+        let selector = self.parse_simple_selector()?;
+        if self.pos == start {
+            return match *self.next_token() {
+                Token::Eof => self.error("Unexpected end of input in selector".to_string()),
+                ref token => self.error(format!("Unexpected {} in selector", token)),
+            };
+        }
+        Ok(selector)
+    }
+
+    // Parse an attribute selector: '[name]' or '[name<operator>value]'.
+    fn parse_attribute_selector(&mut self) -> ParseResult<AttributeSelector> {
+        self.expect(Token::OpenSquare)?;
+        self.consume_whitespace();
+        // HTML attribute names are case-insensitive, and the HTML parser lowercases them.
+        let name = self.parse_identifier()?.to_ascii_lowercase();
+        self.consume_whitespace();
+        if *self.next_token() == Token::CloseSquare {
+            self.consume_token();
+            return Ok(AttributeSelector { name, operator: AttributeOperator::Exists });
+        }
+
+        let operator = match *self.next_token() {
+            Token::Delim('=') => '=',
+            Token::Delim(c @ ('~' | '|' | '^' | '$' | '*')) => {
+                self.consume_token();
+                c
+            }
+            ref token => return self.error(format!("Unexpected {} in attribute selector", token))
+        };
+        self.expect(Token::Delim('='))?;
+        self.consume_whitespace();
+        let value = match self.next_token().clone() {
+            Token::String(value) | Token::Ident(value) => {
+                self.consume_token();
+                value
+            }
+            token => return self.error(format!("Unexpected {} in attribute selector", token))
+        };
+        self.consume_whitespace();
+        self.expect(Token::CloseSquare)?;
+
+        Ok(AttributeSelector {
+            name,
+            operator: match operator {
+                '=' => AttributeOperator::Equals(value),
+                '~' => AttributeOperator::Includes(value),
+                '|' => AttributeOperator::DashMatch(value),
+                '^' => AttributeOperator::Prefix(value),
+                '$' => AttributeOperator::Suffix(value),
+                _ => AttributeOperator::Substring(value),
+            },
+        })
     }
-}
 
-pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser { pos: 0, input: source };
-    Stylesheet { rules: parser.parse_rules() }
+    // Parse a pseudo-class from the token after its ':', which starts at 'start'.
+    // For a functional pseudo-class, its arguments are parsed too.
+    fn parse_pseudo_class(&mut self, start: usize, token: Token) -> ParseResult<PseudoClass> {
+        let name = match token {
+            Token::Ident(ref name) => name.to_ascii_lowercase(),
+            Token::Function(ref name) => format!("{}(", name.to_ascii_lowercase()),
+            token => return self.error_at(start, format!("Unexpected {} in pseudo-class", token)),
+        };
+        match &*name {
+            "first-child" => Ok(PseudoClass::FirstChild),
+            "last-child" => Ok(PseudoClass::LastChild),
+            "only-child" => Ok(PseudoClass::OnlyChild),
+            "hover" => Ok(PseudoClass::Hover),
+            "focus" => Ok(PseudoClass::Focus),
+            "active" => Ok(PseudoClass::Active),
+            "nth-child(" | "nth-last-child(" => {
+                // The an+b syntax doesn't tokenize cleanly ('2n+1' is a dimension and a
+                // number), so it's parsed from the source of the argument instead.
+                let argument_start = self.offset();
+                while !matches!(*self.next_token(), Token::CloseParen | Token::Eof) {
+                    self.consume_token();
+                }
+                let argument = self.input[argument_start..self.offset()].to_string();
+                self.expect(Token::CloseParen)?;
+                match parse_an_plus_b(&argument) {
+                    Some((a, b)) if name == "nth-child(" => Ok(PseudoClass::NthChild(a, b)),
+                    Some((a, b)) => Ok(PseudoClass::NthLastChild(a, b)),
+                    None => self.error_at(argument_start, format!("Invalid an+b expression {}", argument)),
+                }
+            }
+            _ => self.error_at(start, format!("Unsupported pseudo-class :{}", name))
+        }
+    }
+
+    // Consume an identifier, or fail if the next token is something else.
+    fn parse_identifier(&mut self) -> ParseResult<String> {
+        match self.next_token().clone() {
+            Token::Ident(name) => {
+                self.consume_token();
+                Ok(name)
+            }
+            token => self.error(format!("Expected an identifier but found {}", token)),
+        }
+    }
+
+    // Parse a rule set: '<selector> { <declarations> }'. 'parent' holds the selectors of the
+    // rule it's nested in, if any. Rules nested in this one are flattened into the rules
+    // returned after it, with their selectors combined with this rule's.
+    fn parse_rule(&mut self, parent: Option<&[Selector]>) -> ParseResult<Vec<Rule>> {
+        let selectors = self.parse_selectors(parent)?;
+        let (declarations, nested_rules) = self.parse_declarations(Some(&selectors))?;
+        let mut rules = vec![Rule {
+            selectors,
+            declarations,
+            media: Vec::new(),
+            origin: Origin::Author,
+        }];
+        rules.extend(nested_rules);
+        Ok(rules)
+    }
+
+    // Parse a comma separated list of selectors.
+    fn parse_selectors(&mut self, parent: Option<&[Selector]>) -> ParseResult<Vec<Selector>> {
+        let mut selectors = Vec::new();
+        loop {
+            selectors.extend(self.parse_selector(parent)?);
+            match *self.next_token() {
+                Token::Comma => { self.consume_token(); self.consume_whitespace(); }
+                Token::OpenCurly => break, // start of declarations
+                Token::Eof => return self.error("Unexpected end of input in selector".to_string()),
+                ref token => return self.error(format!("Unexpected {} in selector list", token))
+            }
+        }
+
+        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
+        Ok(selectors)
+    }
+
+    // Parse one selector, made of simple selectors joined by combinators: 'div p.note'.
+    // In a nested rule, it's combined with each of the 'parent' selectors in turn.
+    fn parse_selector(&mut self, parent: Option<&[Selector]>) -> ParseResult<Vec<Selector>> {
+        let start = self.pos;
+        // A nested selector can start with a combinator: '> p' is relative to the parent.
+        let empty_start = parent.is_some() && matches!(*self.next_token(), Token::Delim('>' | '+' | '~'));
+        // Simple selectors and the combinators between them, from left to right, and
+        // whether each simple selector contains '&'.
+        let (first, first_nesting) = match empty_start {
+            true => (SimpleSelector::universal(), false),
+            false => self.parse_required_simple_selector()?,
+        };
+        let mut simple_selectors = vec![first];
+        let mut nesting = vec![first_nesting];
+        let mut combinators = Vec::new();
+        loop {
+            let whitespace = self.consume_whitespace();
+            match *self.next_token() {
+                Token::Comma | Token::OpenCurly | Token::Eof => break,
+                Token::Delim(c @ ('>' | '+' | '~')) => {
+                    self.consume_token();
+                    self.consume_whitespace();
+                    combinators.push(match c {
+                        '>' => Combinator::Child,
+                        '+' => Combinator::NextSibling,
+                        _ => Combinator::SubsequentSibling,
+                    });
+                }
+                _ if whitespace => combinators.push(Combinator::Descendant),
+                ref token => return self.error(format!("Unexpected {} in selector", token))
+            }
+            let (simple_selector, has_nesting) = self.parse_required_simple_selector()?;
+            simple_selectors.push(simple_selector);
+            nesting.push(has_nesting);
+        }
+
+        let parent = match parent {
+            Some(parent) => parent,
+            None if nesting.contains(&true) => {
+                let offset = self.tokens[start].1;
+                return self.error_at(offset, "Unexpected & outside a nested rule".to_string());
+            }
+            None => return Ok(vec![build_selector(simple_selectors, combinators)]),
+        };
+        // A nested selector without '&' is relative to the parent: 'p' means '& p', and
+        // '> p' means '& > p'.
+        if !nesting.contains(&true) {
+            if empty_start && !combinators.is_empty() {
+                nesting[0] = true;
+            } else {
+                simple_selectors.insert(0, SimpleSelector::universal());
+                nesting.insert(0, true);
+                combinators.insert(0, Combinator::Descendant);
+            }
+        }
+        Ok(parent.iter().map(|parent| {
+            let (parent_selectors, parent_combinators) = parent.parts();
+            let mut selectors = Vec::new();
+            let mut joined_combinators = Vec::new();
+            for (i, selector) in simple_selectors.iter().enumerate() {
+                if i > 0 {
+                    joined_combinators.push(combinators[i - 1]);
+                }
+                if !nesting[i] {
+                    selectors.push(selector.clone());
+                    continue;
+                }
+                // Replace the '&' with the parent selector, whose subject also has to match
+                // the rest of this simple selector.
+                let (subject, ancestors) = parent_selectors.split_last().unwrap();
+                selectors.extend(ancestors.iter().cloned());
+                joined_combinators.extend(parent_combinators.iter().cloned());
+                selectors.push(subject.merge(selector));
+            }
+            build_selector(selectors, joined_combinators)
+        }).collect())
+    }
+
+    // Parse a semicolon separated list of declarations. Malformed declarations are skipped.
+    // In the block of a style rule, whose selectors are 'parent', there may be nested rules
+    // too, which are returned after the declarations.
+    fn parse_declarations(&mut self, parent: Option<&[Selector]>) -> ParseResult<(Vec<Declaration>, Vec<Rule>)> {
+        self.expect(Token::OpenCurly)?;
+        let mut declarations = Vec::new();
+        let mut rules = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match *self.next_token() {
+                Token::Eof | Token::CloseCurly => {
+                    self.expect_block_end()?;
+                    break;
+                }
+                Token::Semicolon => {
+                    self.consume_token();
+                    continue;
+                }
+                _ => {}
+            }
+            let start = self.pos;
+            let nested_rule = parent.is_some() && self.starts_nested_rule();
+            let result = if nested_rule {
+                self.parse_rule(parent).map(|new_rules| rules.extend(new_rules))
+            } else {
+                self.parse_declaration().map(|new_declarations| declarations.extend(new_declarations))
+            };
+            if let Err(error) = result {
+                self.errors.push(error);
+                self.pos = start;
+                self.skip(nested_rule);
+            }
+        }
+        Ok((declarations, rules))
+    }
+
+    // Does a nested rule start here, rather than a declaration? It does if a '{' comes
+    // before the ';' or '}' that would end a declaration.
+    fn starts_nested_rule(&self) -> bool {
+        if let Token::Ident(ref name) = *self.next_token() {
+            if name.starts_with("--") {
+                return false;
+            }
+        }
+        let mut depth = 0;
+        for (token, _) in &self.tokens[self.pos..] {
+            match *token {
+                Token::OpenParen | Token::OpenSquare | Token::Function(_) => depth += 1,
+                Token::CloseParen | Token::CloseSquare if depth > 0 => depth -= 1,
+                Token::OpenCurly if depth == 0 => return true,
+                Token::Semicolon | Token::CloseCurly | Token::Eof if depth == 0 => return false,
+                Token::Eof => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // Parse a single '<property>: <value> [!important];' declaration.
+    // Shorthand properties are expanded into their longhands, unless they contain
+    // 'var()', in which case that happens once the variables have been substituted.
+    fn parse_declaration(&mut self) -> ParseResult<Vec<Declaration>> {
+        let mut property_name = match *self.next_token() {
+            Token::Ident(ref name) => name.clone(),
+            ref token => return self.error(format!("Unexpected {} in declaration", token)),
+        };
+        self.consume_token();
+        // Property names are case-insensitive, except for custom properties.
+        if !property_name.starts_with("--") {
+            property_name.make_ascii_lowercase();
+        }
+        self.consume_whitespace();
+        self.expect(Token::Colon)?;
+        self.consume_whitespace();
+        let value_start = self.offset();
+        let value = self.parse_comma_list()?;
+        self.consume_whitespace();
+        let important = self.parse_important()?;
+        self.expect_declaration_end()?;
+
+        let mut declarations = if value.contains_var() {
+            vec![Declaration { name: property_name, value, important }]
+        } else {
+            match shorthand::expand(property_name, value) {
+                Ok(declarations) => declarations,
+                Err(message) => return self.error_at(value_start, message),
+            }
+        };
+        for declaration in &mut declarations {
+            declaration.important = important;
+        }
+        Ok(declarations)
+    }
+
+    // Parse an optional '!important' annotation.
+    fn parse_important(&mut self) -> ParseResult<bool> {
+        if *self.next_token() != Token::Delim('!') {
+            return Ok(false);
+        }
+        self.consume_token();
+        self.consume_whitespace();
+        match *self.next_token() {
+            Token::Ident(ref name) if name.eq_ignore_ascii_case("important") => {}
+            _ => return self.error("Expected !important".to_string()),
+        }
+        self.consume_token();
+        self.consume_whitespace();
+        Ok(true)
+    }
+
+    // Parse a full declaration value. Components are separated by commas, whitespace
+    // or '/', in order of increasing precedence, so 'a b/c, d' is '[[a [b/c]], d]'.
+    fn parse_comma_list(&mut self) -> ParseResult<Value> {
+        let mut items = vec![self.parse_space_list()?];
+        while *self.next_token() == Token::Comma {
+            self.consume_token();
+            self.consume_whitespace();
+            items.push(self.parse_space_list()?);
+        }
+        Ok(list_or_single(items, Separator::Comma))
+    }
+
+    fn parse_space_list(&mut self) -> ParseResult<Value> {
+        let mut items = Vec::new();
+        loop {
+            items.push(self.parse_slash_list()?);
+            self.consume_whitespace();
+            match *self.next_token() {
+                Token::Semicolon | Token::Comma | Token::CloseCurly | Token::Delim('!') |
+                Token::CloseParen | Token::Eof => break,
+                _ => {}
+            }
+        }
+        // Transform functions separated by whitespace make up one transform.
+        if items.len() > 1 && items.iter().all(|item| matches!(*item, Value::Transform(_))) {
+            let functions = items.into_iter().flat_map(|item| match item {
+                Value::Transform(functions) => functions,
+                _ => Vec::new(),
+            }).collect();
+            return Ok(Value::Transform(functions));
+        }
+        Ok(list_or_single(items, Separator::Space))
+    }
+
+    fn parse_slash_list(&mut self) -> ParseResult<Value> {
+        let mut items = vec![self.parse_value()?];
+        loop {
+            self.consume_whitespace();
+            if *self.next_token() != Token::Delim('/') {
+                break;
+            }
+            self.consume_token();
+            self.consume_whitespace();
+            items.push(self.parse_value()?);
+        }
+        Ok(list_or_single(items, Separator::Slash))
+    }
+
+    // Parse a single component value.
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        let start = self.offset();
+        let value = match self.next_token().clone() {
+            Token::Ident(name) => Value::Keyword(name),
+            Token::String(value) => Value::String(value),
+            Token::Url(url) => Value::Url(url),
+            Token::Number(n) => Value::Number(n),
+            Token::Percentage(n) => Value::Percentage(n),
+            Token::Dimension(n, unit) => match &*unit.to_ascii_lowercase() {
+                "s" => Value::Time(n),
+                "ms" => Value::Time(n / 1000.0),
+                "fr" => Value::Flex(n),
+                unit => match parse_unit(unit) {
+                    Some(unit) => Value::Length(n, unit),
+                    None => return self.error(format!("Unrecognised unit {}", unit)),
+                },
+            },
+            Token::Hash(digits, _) => self.parse_color(&digits)?,
+            Token::Function(name) => {
+                self.consume_token();
+                return self.parse_function(start, &name);
+            }
+            Token::BadString => return self.error("Unterminated string".to_string()),
+            Token::BadUrl => return self.error("Invalid url()".to_string()),
+            Token::Eof => return self.error("Unexpected end of input in value".to_string()),
+            token => return self.error(format!("Unexpected {} in value", token)),
+        };
+        self.consume_token();
+        Ok(value)
+    }
+
+    // Parse the arguments of a functional notation such as 'rgb(...)', whose name starts at
+    // 'start'. The function token itself has been consumed.
+    fn parse_function(&mut self, start: usize, name: &str) -> ParseResult<Value> {
+        match &*name.to_ascii_lowercase() {
+            "rgb" | "rgba" => self.parse_rgb(),
+            "hsl" | "hsla" => self.parse_hsl(),
+            "translate" | "translatex" | "translatey" | "scale" | "scalex" | "scaley" | "rotate" |
+            "skew" | "skewx" | "skewy" | "matrix" => self.parse_transform_function(start, name),
+            // Unquoted URLs are single tokens; this is 'url("...")'.
+            "url" => Ok(Value::Url(self.parse_function_argument()?)),
+            "var" => {
+                self.consume_whitespace();
+                let name = match *self.next_token() {
+                    Token::Ident(ref name) if name.starts_with("--") => name.clone(),
+                    _ => return self.error("Expected a custom property name in var()".to_string()),
+                };
+                self.consume_token();
+                self.consume_whitespace();
+                let mut fallback = None;
+                if *self.next_token() == Token::Comma {
+                    self.consume_token();
+                    self.consume_whitespace();
+                    fallback = Some(Box::new(self.parse_comma_list()?));
+                }
+                self.expect(Token::CloseParen)?;
+                Ok(Value::Var(name, fallback))
+            }
+            "calc" => {
+                self.consume_whitespace();
+                let calc = self.parse_calc_sum()?;
+                self.expect(Token::CloseParen)?;
+                Ok(Value::Calc(Box::new(calc)))
+            }
+            // 'repeat(3, 1fr 100px)' is the grid tracks in it written out 3 times over.
+            "repeat" => {
+                self.consume_whitespace();
+                let count = match *self.next_token() {
+                    Token::Number(n) if n >= 1.0 && n.fract() == 0.0 => n.min(10000.0) as usize,
+                    _ => return self.error("Expected a repetition count in repeat()".to_string()),
+                };
+                self.consume_token();
+                self.consume_whitespace();
+                self.expect(Token::Comma)?;
+                self.consume_whitespace();
+                let tracks = match self.parse_space_list()? {
+                    Value::List(items, Separator::Space) => items,
+                    track => vec![track],
+                };
+                self.expect(Token::CloseParen)?;
+                let repeated = tracks.iter().cycle().take(tracks.len() * count).cloned().collect();
+                Ok(list_or_single(repeated, Separator::Space))
+            }
+            _ => self.error_at(start, format!("Unrecognised function {}()", name))
+        }
+    }
+
+    // Parse the single argument of a function like 'url("...")' or 'local(...)', up to and
+    // including the closing ')'. It's a string, or identifiers taken as one space separated name.
+    fn parse_function_argument(&mut self) -> ParseResult<String> {
+        self.consume_whitespace();
+        let argument = match self.next_token().clone() {
+            Token::String(value) => {
+                self.consume_token();
+                value
+            }
+            Token::Ident(_) => {
+                let mut words = Vec::new();
+                while let Token::Ident(word) = self.next_token().clone() {
+                    self.consume_token();
+                    words.push(word);
+                    self.consume_whitespace();
+                }
+                words.join(" ")
+            }
+            token => return self.error(format!("Unexpected {} in function argument", token)),
+        };
+        self.consume_whitespace();
+        self.expect(Token::CloseParen)?;
+        Ok(argument)
+    }
+
+    // Parse '<product> [('+' | '-') <product>]*' in a 'calc()' expression. As in CSS,
+    // '+' and '-' need whitespace around them, or they'd be the sign of a number.
+    fn parse_calc_sum(&mut self) -> ParseResult<Calc> {
+        let mut sum = self.parse_calc_product()?;
+        loop {
+            self.consume_whitespace();
+            match *self.next_token() {
+                Token::Delim(op @ ('+' | '-')) => {
+                    self.consume_token();
+                    self.consume_whitespace();
+                    let rhs = Box::new(self.parse_calc_product()?);
+                    sum = if op == '+' { Calc::Sum(Box::new(sum), rhs) } else { Calc::Difference(Box::new(sum), rhs) };
+                }
+                _ => return Ok(sum),
+            }
+        }
+    }
+
+    // Parse '<term> [('*' | '/') <term>]*' in a 'calc()' expression.
+    fn parse_calc_product(&mut self) -> ParseResult<Calc> {
+        let mut product = self.parse_calc_term()?;
+        loop {
+            self.consume_whitespace();
+            match *self.next_token() {
+                Token::Delim(op @ ('*' | '/')) => {
+                    self.consume_token();
+                    self.consume_whitespace();
+                    let rhs = Box::new(self.parse_calc_term()?);
+                    product = if op == '*' { Calc::Product(Box::new(product), rhs) } else { Calc::Quotient(Box::new(product), rhs) };
+                }
+                _ => return Ok(product),
+            }
+        }
+    }
+
+    // Parse a number, length, percentage or nested 'calc()', or a sum in parentheses.
+    fn parse_calc_term(&mut self) -> ParseResult<Calc> {
+        if *self.next_token() == Token::OpenParen {
+            self.consume_token();
+            self.consume_whitespace();
+            let sum = self.parse_calc_sum()?;
+            self.expect(Token::CloseParen)?;
+            return Ok(sum);
+        }
+        let start = self.offset();
+        match self.parse_value()? {
+            Value::Calc(calc) => Ok(*calc),
+            value @ (Value::Length(..) | Value::Percentage(_) | Value::Number(_) | Value::Var(..)) => Ok(Calc::Leaf(value)),
+            _ => self.error_at(start, "Invalid value in calc()".to_string())
+        }
+    }
+
+    // Parse numeric function arguments up to and including the closing ')'.
+    // Arguments may be separated by commas, whitespace or '/', and each one is
+    // returned with its unit suffix: "%", an identifier like "deg", or "" for a plain number.
+    fn parse_numeric_args(&mut self) -> ParseResult<Vec<(f32, String)>> {
+        let mut args = Vec::new();
+        loop {
+            let start = self.offset();
+            match self.consume_token() {
+                Token::Whitespace | Token::Comma | Token::Delim('/') => {}
+                Token::CloseParen => break,
+                Token::Number(n) => args.push((n, String::new())),
+                Token::Percentage(n) => args.push((n, "%".to_string())),
+                Token::Dimension(n, unit) => args.push((n, unit)),
+                token => return self.error_at(start, format!("Unexpected {} in function arguments", token)),
+            }
+        }
+        Ok(args)
+    }
+
+    // Parse 'rgb(r, g, b)' or 'rgba(r, g, b, a)'. Channels are numbers from 0 to 255
+    // or percentages, and the alpha value is a number from 0 to 1 or a percentage.
+    fn parse_rgb(&mut self) -> ParseResult<Value> {
+        let start = self.pos;
+        let args = self.parse_numeric_args()?;
+        if args.len() != 3 && args.len() != 4 {
+            return self.error("rgb() takes 3 or 4 arguments".to_string());
+        }
+        if args.iter().any(|(_, unit)| !unit.is_empty() && unit != "%") {
+            return self.error("rgb() takes numbers and percentages".to_string());
+        }
+        // The older syntax, with commas between the arguments, can't mix numbers and
+        // percentages in the channels.
+        let percentages = args[..3].iter().filter(|(_, unit)| unit == "%").count();
+        let commas = self.tokens[start..self.pos].iter().any(|(token, _)| *token == Token::Comma);
+        if commas && percentages != 0 && percentages != 3 {
+            return self.error("rgb() can't mix numbers and percentages".to_string());
+        }
+        let channel = |(n, unit): (f32, String)| -> u8 {
+            let n = if unit == "%" { n * 2.55 } else { n };
+            n.round().clamp(0.0, 255.0) as u8
+        };
+        let mut args = args.into_iter();
+        Ok(Value::ColorValue(Color {
+            r: channel(args.next().unwrap()),
+            g: channel(args.next().unwrap()),
+            b: channel(args.next().unwrap()),
+            a: args.next().map_or(255, |(n, unit)| alpha_channel(n, &unit)),
+        }))
+    }
+
+    // Parse 'hsl(h, s, l)' or 'hsla(h, s, l, a)'. The hue is an angle (in degrees
+    // if no unit is given), and saturation and lightness are percentages.
+    fn parse_hsl(&mut self) -> ParseResult<Value> {
+        let args = self.parse_numeric_args()?;
+        if args.len() != 3 && args.len() != 4 {
+            return self.error("hsl() takes 3 or 4 arguments".to_string());
+        }
+        let (h, ref hue_unit) = args[0];
+        let degrees = match (hue_unit.as_str(), to_degrees(h, hue_unit)) {
+            ("", _) => h,
+            (_, Some(degrees)) => degrees,
+            (unit, None) => return self.error(format!("Unrecognised angle unit {}", unit))
+        };
+        if args[1].1 != "%" || args[2].1 != "%" {
+            return self.error("hsl() saturation and lightness must be percentages".to_string());
+        }
+        let alpha = args.get(3).map_or(255, |&(n, ref unit)| alpha_channel(n, unit));
+        Ok(Value::ColorValue(Color::from_hsl(degrees, args[1].0 / 100.0, args[2].0 / 100.0, alpha)))
+    }
+
+    // Parse the arguments of the transform function called 'name', which starts at 'start'.
+    fn parse_transform_function(&mut self, start: usize, name: &str) -> ParseResult<Value> {
+        let name = name.to_ascii_lowercase();
+        let args = self.parse_numeric_args()?;
+        let count_error = |expected: &str| self.error_at(start, format!("{}() takes {}", name, expected));
+        let length = |&(n, ref unit): &(f32, String)| match &**unit {
+            "%" => Some(Value::Percentage(n)),
+            "" if n == 0.0 => Some(Value::Length(0.0, Unit::Px)),
+            unit => parse_unit(&unit.to_ascii_lowercase()).map(|unit| Value::Length(n, unit)),
+        };
+        // A unitless angle has to be zero.
+        let angle = |&(n, ref unit): &(f32, String)| if unit.is_empty() && n == 0.0 { Some(0.0) } else { to_degrees(n, unit) };
+        let number = |&(n, ref unit): &(f32, String)| if unit.is_empty() { Some(n) } else { None };
+
+        let function = match &*name {
+            "translate" | "translatex" | "translatey" => {
+                let (x, y) = match (&*name, args.len()) {
+                    ("translate", 1) | ("translatex", 1) => (length(&args[0]), Some(Value::Length(0.0, Unit::Px))),
+                    ("translate", 2) => (length(&args[0]), length(&args[1])),
+                    ("translatey", 1) => (Some(Value::Length(0.0, Unit::Px)), length(&args[0])),
+                    _ => return count_error("1 or 2 lengths"),
+                };
+                x.zip(y).map(|(x, y)| TransformFunction::Translate(x, y))
+            }
+            "scale" | "scalex" | "scaley" => match (&*name, args.len()) {
+                ("scale", 1) => number(&args[0]).map(|s| TransformFunction::Scale(s, s)),
+                ("scale", 2) => number(&args[0]).zip(number(&args[1])).map(|(x, y)| TransformFunction::Scale(x, y)),
+                ("scalex", 1) => number(&args[0]).map(|x| TransformFunction::Scale(x, 1.0)),
+                ("scaley", 1) => number(&args[0]).map(|y| TransformFunction::Scale(1.0, y)),
+                _ => return count_error("1 or 2 numbers"),
+            },
+            "rotate" if args.len() == 1 => angle(&args[0]).map(TransformFunction::Rotate),
+            "skew" | "skewx" | "skewy" => match (&*name, args.len()) {
+                ("skew", 1) | ("skewx", 1) => angle(&args[0]).map(|x| TransformFunction::Skew(x, 0.0)),
+                ("skew", 2) => angle(&args[0]).zip(angle(&args[1])).map(|(x, y)| TransformFunction::Skew(x, y)),
+                ("skewy", 1) => angle(&args[0]).map(|y| TransformFunction::Skew(0.0, y)),
+                _ => return count_error("1 or 2 angles"),
+            },
+            "matrix" if args.len() == 6 => {
+                let numbers: Option<Vec<f32>> = args.iter().map(number).collect();
+                numbers.map(|n| TransformFunction::Matrix([n[0], n[1], n[2], n[3], n[4], n[5]]))
+            }
+            "rotate" => return count_error("1 angle"),
+            _ => return count_error("6 numbers"),
+        };
+        match function {
+            Some(function) => Ok(Value::Transform(vec![function])),
+            None => self.error_at(start, format!("Invalid argument in {}()", name)),
+        }
+    }
+
+    // Parse the digits of a hex color: '#rgb', '#rgba', '#rrggbb' or '#rrggbbaa'.
+    fn parse_color(&self, digits: &str) -> ParseResult<Value> {
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return self.error(format!("Invalid hex color #{}", digits));
+        }
+        let channels: Vec<u8> = match digits.len() {
+            // In the short forms each digit is doubled, so '#fa0' is '#ffaa00'.
+            3 | 4 => digits.chars().map(|c| parse_hex(&c.to_string().repeat(2))).collect(),
+            6 | 8 => (0..digits.len()).step_by(2).map(|i| parse_hex(&digits[i .. i + 2])).collect(),
+            _ => return self.error(format!("Invalid hex color #{}", digits))
+        };
+        Ok(Value::ColorValue(Color {
+            r: channels[0],
+            g: channels[1],
+            b: channels[2],
+            a: channels.get(3).cloned().unwrap_or(255) }))
+    }
+}
+
+// Parse the 'an+b' micro-syntax used by ':nth-child()', such as 'odd', '3' or '-2n + 1',
+// returning (a, b).
+fn parse_an_plus_b(source: &str) -> Option<(i32, i32)> {
+    let source = source.split_whitespace().collect::<String>().to_ascii_lowercase();
+    match &*source {
+        "odd" => Some((2, 1)),
+        "even" => Some((2, 0)),
+        _ => match source.find('n') {
+            Some(i) => {
+                let a = match &source[..i] {
+                    "" | "+" => 1,
+                    "-" => -1,
+                    a => a.parse().ok()?,
+                };
+                let b = if i + 1 == source.len() { 0 } else { source[i + 1..].parse().ok()? };
+                Some((a, b))
+            }
+            None => Some((0, source.parse().ok()?)),
+        }
+    }
+}
+
+// Wrap 'items' in a list, unless there is only one of them.
+pub fn list_or_single(mut items: Vec<Value>, separator: Separator) -> Value {
+    if items.len() == 1 {
+        items.remove(0)
+    } else {
+        Value::List(items, separator)
+    }
+}
+
+// Look up a length unit by its (lowercase) name.
+fn parse_unit(unit: &str) -> Option<Unit> {
+    Some(match unit {
+        "px" => Unit::Px,
+        "em" => Unit::Em,
+        "rem" => Unit::Rem,
+        "vw" => Unit::Vw,
+        "vh" => Unit::Vh,
+        "vmin" => Unit::Vmin,
+        "vmax" => Unit::Vmax,
+        "in" => Unit::In,
+        "cm" => Unit::Cm,
+        "mm" => Unit::Mm,
+        "pt" => Unit::Pt,
+        "pc" => Unit::Pc,
+        _ => return None,
+    })
+}
+
+// Convert an angle with the unit 'deg', 'rad', 'grad' or 'turn' to degrees.
+fn to_degrees(angle: f32, unit: &str) -> Option<f32> {
+    match &*unit.to_ascii_lowercase() {
+        "deg" => Some(angle),
+        "rad" => Some(angle.to_degrees()),
+        "grad" => Some(angle * 0.9),
+        "turn" => Some(angle * 360.0),
+        _ => None,
+    }
+}
+
+fn parse_hex(s: &str) -> u8 {
+    u8::from_str_radix(s, 16).unwrap()
+}
+
+// Convert an alpha value (a number from 0 to 1, or a percentage) to a channel byte.
+fn alpha_channel(value: f32, unit: &str) -> u8 {
+    let alpha = if unit == "%" { value / 100.0 } else { value };
+    (alpha * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// Make a selector from simple selectors and the combinators between them, from left to right.
+fn build_selector(mut simple_selectors: Vec<SimpleSelector>, combinators: Vec<Combinator>) -> Selector {
+    let subject = simple_selectors.pop().unwrap();
+    if combinators.is_empty() {
+        return Selector::Simple(subject);
+    }
+    Selector::Complex(ComplexSelector {
+        subject,
+        combinators: combinators.into_iter().rev().zip(simple_selectors.into_iter().rev()).collect(),
+    })
+}
+
+pub type Specificity = (usize, usize, usize);
+
+impl Selector {
+    pub fn specificity(&self) -> Specificity {
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            // The specificity of a complex selector is the sum of its parts.
+            Selector::Complex(ref complex) => complex.combinators.iter()
+                .map(|(_, simple)| simple.specificity())
+                .fold(complex.subject.specificity(), |(a, b, c), (x, y, z)| (a + x, b + y, c + z)),
+        }
+    }
+
+    // The simple selectors and the combinators between them, from left to right.
+    fn parts(&self) -> (Vec<SimpleSelector>, Vec<Combinator>) {
+        match *self {
+            Selector::Simple(ref simple) => (vec![simple.clone()], Vec::new()),
+            Selector::Complex(ref complex) => {
+                let mut simple_selectors: Vec<SimpleSelector> = complex.combinators.iter().rev()
+                    .map(|(_, simple)| simple.clone()).collect();
+                simple_selectors.push(complex.subject.clone());
+                (simple_selectors, complex.combinators.iter().rev().map(|&(combinator, _)| combinator).collect())
+            }
+        }
+    }
+}
+
+impl SimpleSelector {
+    pub fn specificity(&self) -> Specificity {
+        let a = self.id.iter().count();
+        // Attribute selectors and pseudo-classes count the same as classes.
+        let b = self.class.len() + self.attributes.len() + self.pseudo_classes.len();
+        // Pseudo-elements count the same as type selectors.
+        let c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        (a, b, c)
+    }
+
+    // '*', which matches any element.
+    fn universal() -> SimpleSelector {
+        SimpleSelector {
+            tag_name: None,
+            id: None,
+            class: Vec::new(),
+            attributes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
+        }
+    }
+
+    // A simple selector matching elements that match both this one and 'other'.
+    fn merge(&self, other: &SimpleSelector) -> SimpleSelector {
+        let mut merged = self.clone();
+        merged.tag_name = other.tag_name.clone().or(merged.tag_name);
+        merged.id = other.id.clone().or(merged.id);
+        merged.class.extend(other.class.iter().cloned());
+        merged.attributes.extend(other.attributes.iter().cloned());
+        merged.pseudo_classes.extend(other.pseudo_classes.iter().cloned());
+        merged.pseudo_element = other.pseudo_element.or(merged.pseudo_element);
+        merged
+    }
+}
+
+// Serialization back to CSS, for debugging output. Parsing the result gives back an
+// equal value.
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for queries in &self.media {
+            write!(f, "@media ")?;
+            write_list(f, queries, ", ")?;
+            write!(f, " {{ ")?;
+        }
+        write_list(f, &self.selectors, ", ")?;
+        write!(f, " {{")?;
+        for declaration in &self.declarations {
+            write!(f, " {};", declaration)?;
+        }
+        write!(f, " }}")?;
+        for _ in &self.media {
+            write!(f, " }}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@import url(")?;
+        write_string(f, &self.url)?;
+        write!(f, ")")?;
+        if !self.media.is_empty() {
+            write!(f, " ")?;
+            write_list(f, &self.media, ", ")?;
+        }
+        write!(f, ";")
+    }
+}
+
+impl fmt::Display for FontFace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@font-face {{ font-family: ")?;
+        write_string(f, &self.family)?;
+        write!(f, "; src: ")?;
+        for (i, source) in self.src.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match *source {
+                FontSource::Url(ref url, ref format) => {
+                    write!(f, "url(")?;
+                    write_string(f, url)?;
+                    write!(f, ")")?;
+                    if let Some(ref format) = *format {
+                        write!(f, " format(")?;
+                        write_string(f, format)?;
+                        write!(f, ")")?;
+                    }
+                }
+                FontSource::Local(ref name) => {
+                    write!(f, "local(")?;
+                    write_string(f, name)?;
+                    write!(f, ")")?;
+                }
+            }
+        }
+        write!(f, "; font-weight: {}; font-style: {}; }}", self.weight, match self.style {
+            FontStyle::Normal => "normal",
+            FontStyle::Italic => "italic",
+            FontStyle::Oblique => "oblique",
+        })
+    }
+}
+
+impl fmt::Display for Keyframes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@keyframes ")?;
+        write_string(f, &self.name)?;
+        write!(f, " {{")?;
+        for keyframe in &self.keyframes {
+            write!(f, " {}% {{", keyframe.offset * 100.0)?;
+            for declaration in &keyframe.declarations {
+                write!(f, " {};", declaration)?;
+            }
+            write!(f, " }}")?;
+        }
+        write!(f, " }}")
+    }
+}
+
+impl fmt::Display for UnknownAtRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@")?;
+        write_identifier(f, &self.name)?;
+        if !self.prelude.is_empty() {
+            write!(f, " {}", self.prelude)?;
+        }
+        match self.block {
+            Some(ref block) if block.is_empty() => write!(f, " {{}}"),
+            Some(ref block) => write!(f, " {{ {} }}", block),
+            None => write!(f, ";"),
+        }
+    }
+}
+
+impl fmt::Display for MediaQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated {
+            write!(f, "not ")?;
+        }
+        if let Some(ref media_type) = self.media_type {
+            write!(f, "{}", media_type)?;
+            if !self.features.is_empty() {
+                write!(f, " and ")?;
+            }
+        }
+        for (i, feature) in self.features.iter().enumerate() {
+            if i > 0 {
+                write!(f, " and ")?;
+            }
+            match feature.value {
+                Some(ref value) => write!(f, "({}: {})", feature.name, value)?,
+                None => write!(f, "({})", feature.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Declaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.value)?;
+        if self.important {
+            write!(f, " !important")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Selector::Simple(ref simple) => write!(f, "{}", simple),
+            Selector::Complex(ref complex) => {
+                for &(combinator, ref simple) in complex.combinators.iter().rev() {
+                    write!(f, "{}{}", simple, match combinator {
+                        Combinator::Descendant => " ",
+                        Combinator::Child => " > ",
+                        Combinator::NextSibling => " + ",
+                        Combinator::SubsequentSibling => " ~ ",
+                    })?;
+                }
+                write!(f, "{}", complex.subject)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SimpleSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tag_name {
+            Some(ref tag_name) => write_identifier(f, tag_name)?,
+            None if self.specificity() == (0, 0, 0) => write!(f, "*")?,
+            None => {}
+        }
+        if let Some(ref id) = self.id {
+            write!(f, "#")?;
+            write_identifier(f, id)?;
+        }
+        for class in &self.class {
+            write!(f, ".")?;
+            write_identifier(f, class)?;
+        }
+        for attribute in &self.attributes {
+            write!(f, "[")?;
+            write_identifier(f, &attribute.name)?;
+            let (operator, value) = match attribute.operator {
+                AttributeOperator::Exists => ("", None),
+                AttributeOperator::Equals(ref value) => ("=", Some(value)),
+                AttributeOperator::Includes(ref value) => ("~=", Some(value)),
+                AttributeOperator::DashMatch(ref value) => ("|=", Some(value)),
+                AttributeOperator::Prefix(ref value) => ("^=", Some(value)),
+                AttributeOperator::Suffix(ref value) => ("$=", Some(value)),
+                AttributeOperator::Substring(ref value) => ("*=", Some(value)),
+            };
+            if let Some(value) = value {
+                write!(f, "{}", operator)?;
+                write_string(f, value)?;
+            }
+            write!(f, "]")?;
+        }
+        for pseudo_class in &self.pseudo_classes {
+            match *pseudo_class {
+                PseudoClass::FirstChild => write!(f, ":first-child")?,
+                PseudoClass::LastChild => write!(f, ":last-child")?,
+                PseudoClass::OnlyChild => write!(f, ":only-child")?,
+                PseudoClass::NthChild(a, b) => write!(f, ":nth-child({})", AnPlusB(a, b))?,
+                PseudoClass::NthLastChild(a, b) => write!(f, ":nth-last-child({})", AnPlusB(a, b))?,
+                PseudoClass::Hover => write!(f, ":hover")?,
+                PseudoClass::Focus => write!(f, ":focus")?,
+                PseudoClass::Active => write!(f, ":active")?,
+            }
+        }
+        match self.pseudo_element {
+            Some(pseudo_element) => write!(f, "{}", pseudo_element),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for PseudoElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PseudoElement::Before => write!(f, "::before"),
+            PseudoElement::After => write!(f, "::after"),
+            PseudoElement::Marker => write!(f, "::marker"),
+        }
+    }
+}
+
+// The argument of ':nth-child()', written as 'an+b'.
+struct AnPlusB(i32, i32);
+
+impl fmt::Display for AnPlusB {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let AnPlusB(a, b) = *self;
+        match a {
+            0 => return write!(f, "{}", b),
+            1 => write!(f, "n")?,
+            -1 => write!(f, "-n")?,
+            _ => write!(f, "{}n", a)?,
+        }
+        match b {
+            0 => Ok(()),
+            _ if b > 0 => write!(f, "+{}", b),
+            _ => write!(f, "{}", b),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Keyword(ref keyword) => write_identifier(f, keyword),
+            Value::Length(n, ref unit) => write!(f, "{}{}", n, unit),
+            Value::Percentage(n) => write!(f, "{}%", n),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(ref s) => write_string(f, s),
+            Value::Url(ref url) => {
+                write!(f, "url(")?;
+                write_string(f, url)?;
+                write!(f, ")")
+            }
+            Value::ColorValue(color) => {
+                write!(f, "#{:02x}{:02x}{:02x}", color.r, color.g, color.b)?;
+                if color.a != 255 {
+                    write!(f, "{:02x}", color.a)?;
+                }
+                Ok(())
+            }
+            Value::Time(t) => write!(f, "{}s", t),
+            Value::Flex(n) => write!(f, "{}fr", n),
+            Value::Calc(ref calc) => write!(f, "calc({})", calc),
+            Value::Var(ref name, ref fallback) => {
+                write!(f, "var({}", name)?;
+                if let Some(ref fallback) = *fallback {
+                    write!(f, ", {}", fallback)?;
+                }
+                write!(f, ")")
+            }
+            Value::List(ref items, separator) => write_list(f, items, match separator {
+                Separator::Space => " ",
+                Separator::Comma => ", ",
+                Separator::Slash => " / ",
+            }),
+            Value::Transform(ref functions) => write_list(f, functions, " "),
+        }
+    }
+}
+
+impl fmt::Display for TransformFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransformFunction::Translate(ref x, ref y) => write!(f, "translate({}, {})", x, y),
+            TransformFunction::Scale(x, y) => write!(f, "scale({}, {})", x, y),
+            TransformFunction::Rotate(angle) => write!(f, "rotate({}deg)", angle),
+            TransformFunction::Skew(x, y) => write!(f, "skew({}deg, {}deg)", x, y),
+            TransformFunction::Matrix(ref m) => {
+                write!(f, "matrix({}, {}, {}, {}, {}, {})", m[0], m[1], m[2], m[3], m[4], m[5])
+            }
+        }
+    }
+}
+
+impl fmt::Display for Calc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Sums inside products need parentheses to keep their precedence.
+        let operand = |f: &mut fmt::Formatter, calc: &Calc| match *calc {
+            Calc::Sum(..) | Calc::Difference(..) => write!(f, "({})", calc),
+            _ => write!(f, "{}", calc),
+        };
+        match *self {
+            Calc::Leaf(ref value) => write!(f, "{}", value),
+            Calc::Sum(ref a, ref b) => write!(f, "{} + {}", a, b),
+            Calc::Difference(ref a, ref b) => match **b {
+                Calc::Sum(..) | Calc::Difference(..) => write!(f, "{} - ({})", a, b),
+                _ => write!(f, "{} - {}", a, b),
+            },
+            Calc::Product(ref a, ref b) | Calc::Quotient(ref a, ref b) => {
+                operand(f, a)?;
+                write!(f, "{}", if let Calc::Product(..) = *self { " * " } else { " / " })?;
+                match **b {
+                    // The right side of a quotient is grouped too, so 'a / (b * c)' stays that way.
+                    Calc::Product(..) | Calc::Quotient(..) => write!(f, "({})", b),
+                    _ => operand(f, b),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Unit::Px => "px",
+            Unit::Em => "em",
+            Unit::Rem => "rem",
+            Unit::Vw => "vw",
+            Unit::Vh => "vh",
+            Unit::Vmin => "vmin",
+            Unit::Vmax => "vmax",
+            Unit::In => "in",
+            Unit::Cm => "cm",
+            Unit::Mm => "mm",
+            Unit::Pt => "pt",
+            Unit::Pc => "pc",
+        })
+    }
+}
+
+// Write the items of a list with 'separator' between them.
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter, items: &[T], separator: &str) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+// Write a double quoted string, escaping the characters that can't appear in it as they are.
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => write!(f, "\\{}", c)?,
+            '\n' => write!(f, "\\a ")?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// Write an identifier, escaping the characters that would otherwise end it.
+fn write_identifier(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    for (i, c) in name.chars().enumerate() {
+        let starts_number = i == 0 || (i == 1 && name.starts_with('-'));
+        match c {
+            // A digit can't start an identifier, so it's written as a code point.
+            '0'..='9' if starts_number => write!(f, "\\{:x} ", c as u32)?,
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => write!(f, "{}", c)?,
+            _ if !c.is_ascii() => write!(f, "{}", c)?,
+            _ => write!(f, "\\{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+impl Stylesheet {
+    // Write the stylesheet back out as CSS, one rule per line. '@import' rules come first, as
+    // they have to, then '@font-face', '@keyframes' and unknown at-rules, then the style rules in order.
+    pub fn to_css(&self) -> String {
+        let imports = self.imports.iter().map(|import| import.to_string());
+        let font_faces = self.font_faces.iter().map(|font_face| font_face.to_string());
+        let keyframes = self.keyframes.iter().map(|keyframes| keyframes.to_string());
+        let unknown = self.unknown_at_rules.iter().map(|rule| rule.to_string());
+        let rules = self.rules.iter().map(|rule| rule.to_string());
+        imports.chain(font_faces).chain(keyframes).chain(unknown).chain(rules).map(|line| line + "\n").collect()
+    }
+
+    // Replace the '@import' rules with the rules of the stylesheets they import, which
+    // 'fetch' loads given their URL. Imports that fail to load are skipped. Returns the
+    // errors found while parsing the imported stylesheets, with the URL of each one.
+    pub fn resolve_imports<F>(&mut self, fetch: &mut F) -> Vec<(String, ParseError)>
+            where F: FnMut(&str) -> Option<String> {
+        let mut errors = Vec::new();
+        self.resolve_imports_from(fetch, &mut Vec::new(), &mut errors);
+        errors
+    }
+
+    // Resolve the relative URLs in the stylesheet (in '@import' rules, '@font-face' sources
+    // and 'url()' values) against 'base', the URL the stylesheet was loaded from.
+    pub fn resolve_urls(&mut self, base: &str) {
+        for import in &mut self.imports {
+            import.url = url::resolve(base, &import.url);
+        }
+        for font_face in &mut self.font_faces {
+            for source in &mut font_face.src {
+                if let FontSource::Url(ref mut url, _) = *source {
+                    *url = url::resolve(base, url);
+                }
+            }
+        }
+        let declarations = self.rules.iter_mut().flat_map(|rule| &mut rule.declarations)
+            .chain(self.keyframes.iter_mut().flat_map(|keyframes| &mut keyframes.keyframes)
+                .flat_map(|keyframe| &mut keyframe.declarations));
+        for declaration in declarations {
+            resolve_value_urls(&mut declaration.value, base);
+        }
+    }
+
+    // Replace declarations of aliased properties, such as '-webkit-transition', with the
+    // properties they stand for in 'aliases' (see 'default_property_aliases').
+    pub fn apply_property_aliases(&mut self, aliases: &HashMap<String, String>) {
+        for rule in &mut self.rules {
+            apply_property_aliases(&mut rule.declarations, aliases);
+        }
+        for keyframes in &mut self.keyframes {
+            for keyframe in &mut keyframes.keyframes {
+                apply_property_aliases(&mut keyframe.declarations, aliases);
+            }
+        }
+    }
+
+    // 'pending' holds the URLs of the stylesheets being imported, so that cycles are ignored.
+    fn resolve_imports_from<F>(&mut self, fetch: &mut F, pending: &mut Vec<String>, errors: &mut Vec<(String, ParseError)>)
+            where F: FnMut(&str) -> Option<String> {
+        let mut rules = Vec::new();
+        let mut font_faces = Vec::new();
+        let mut keyframes = Vec::new();
+        let mut unknown_at_rules = Vec::new();
+        for import in std::mem::take(&mut self.imports) {
+            if pending.contains(&import.url) { continue }
+            let source = match fetch(&import.url) {
+                Some(source) => source,
+                None => continue,
+            };
+            let (mut stylesheet, import_errors) = parse(source);
+            errors.extend(import_errors.into_iter().map(|error| (import.url.clone(), error)));
+            stylesheet.resolve_urls(&import.url);
+            pending.push(import.url);
+            stylesheet.resolve_imports_from(fetch, pending, errors);
+            pending.pop();
+
+            font_faces.append(&mut stylesheet.font_faces);
+            keyframes.append(&mut stylesheet.keyframes);
+            unknown_at_rules.append(&mut stylesheet.unknown_at_rules);
+            for mut rule in stylesheet.rules {
+                if !import.media.is_empty() {
+                    rule.media.insert(0, import.media.clone());
+                }
+                rules.push(rule);
+            }
+        }
+        // Imported rules come first in the cascade, so the importing stylesheet wins ties.
+        rules.append(&mut self.rules);
+        self.rules = rules;
+        font_faces.append(&mut self.font_faces);
+        self.font_faces = font_faces;
+        keyframes.append(&mut self.keyframes);
+        self.keyframes = keyframes;
+        unknown_at_rules.append(&mut self.unknown_at_rules);
+        self.unknown_at_rules = unknown_at_rules;
+    }
+}
+
+fn resolve_value_urls(value: &mut Value, base: &str) {
+    match *value {
+        Value::Url(ref mut url) => *url = url::resolve(base, url),
+        Value::List(ref mut items, _) => {
+            for item in items {
+                resolve_value_urls(item, base);
+            }
+        }
+        Value::Var(_, Some(ref mut fallback)) => resolve_value_urls(fallback, base),
+        _ => {}
+    }
+}
+
+// The standard properties that legacy stylesheets often use vendor-prefixed versions of.
+const PREFIXED_PROPERTIES: [&str; 17] = [
+    "transition", "transition-property", "transition-duration", "transition-timing-function",
+    "transition-delay", "animation-name", "animation-duration", "animation-delay",
+    "animation-iteration-count", "animation-direction", "animation-fill-mode",
+    "animation-timing-function", "border-radius", "box-shadow", "box-sizing", "transform", "user-select",
+];
+
+// The default property alias table, mapping each vendor-prefixed version of the properties
+// above (like '-webkit-transition') to the standard property.
+pub fn default_property_aliases() -> HashMap<String, String> {
+    let mut aliases = HashMap::new();
+    for prefix in &["-webkit-", "-moz-", "-ms-", "-o-"] {
+        for property in &PREFIXED_PROPERTIES {
+            aliases.insert(format!("{}{}", prefix, property), property.to_string());
+        }
+    }
+    aliases
+}
+
+// Rename declarations of properties in 'aliases' to the properties they stand for, expanding
+// them if those are shorthands. Ones whose values the standard property doesn't accept are left alone.
+fn apply_property_aliases(declarations: &mut Vec<Declaration>, aliases: &HashMap<String, String>) {
+    *declarations = std::mem::take(declarations).into_iter().flat_map(|declaration| {
+        let name = match aliases.get(&declaration.name) {
+            Some(name) => name.clone(),
+            None => return vec![declaration],
+        };
+        // Shorthands with 'var()' are expanded once the variables have been substituted.
+        if declaration.value.contains_var() {
+            return vec![Declaration { name, ..declaration }];
+        }
+        match shorthand::expand(name, declaration.value.clone()) {
+            Ok(mut expanded) => {
+                for longhand in &mut expanded {
+                    longhand.important = declaration.important;
+                }
+                expanded
+            }
+            Err(_) => vec![declaration],
+        }
+    }).collect();
+}
+
+// Parse a stylesheet, along with any errors in it. The rules and declarations with
+// errors are left out, as browsers do.
+pub fn parse(source: String) -> (Stylesheet, Vec<ParseError>) {
+    let mut parser = Parser::new(source);
+    let mut rules = parser.parse_rules();
+    while !parser.eof() {
+        // 'parse_rules' stops at a '}' that doesn't close anything.
+        let error = parser.parse_error(parser.offset(), "Unexpected } in stylesheet".to_string());
+        parser.errors.push(error);
+        parser.consume_token();
+        rules.extend(parser.parse_rules());
+    }
+    let stylesheet = Stylesheet {
+        rules,
+        imports: parser.imports,
+        font_faces: parser.font_faces,
+        keyframes: parser.keyframes,
+        unknown_at_rules: parser.unknown_at_rules,
+    };
+    (stylesheet, parser.errors)
+}
+
+// Parse the declarations in an element's 'style' attribute, like 'color: red; margin: 0',
+// along with any errors in them. Invalid declarations are left out.
+pub fn parse_style_attribute(source: String) -> (Vec<Declaration>, Vec<ParseError>) {
+    let mut parser = Parser::new(source);
+    let mut declarations = Vec::new();
+    loop {
+        parser.consume_whitespace();
+        match *parser.next_token() {
+            Token::Eof => break,
+            Token::Semicolon => {
+                parser.consume_token();
+                continue;
+            }
+            Token::CloseCurly => {
+                let error = parser.parse_error(parser.offset(), "Unexpected } in style attribute".to_string());
+                parser.errors.push(error);
+                parser.consume_token();
+                continue;
+            }
+            _ => {}
+        }
+        let start = parser.pos;
+        match parser.parse_declaration() {
+            Ok(new_declarations) => declarations.extend(new_declarations),
+            Err(error) => {
+                parser.errors.push(error);
+                parser.pos = start;
+                parser.skip(false);
+            }
+        }
+    }
+    (declarations, parser.errors)
+}
+
+// Parse a comma separated list of selectors on its own, like the argument to 'querySelector'.
+pub fn parse_selector_list(source: String) -> ParseResult<Vec<Selector>> {
+    let mut parser = Parser::new(source);
+    parser.consume_whitespace();
+    let mut selectors = Vec::new();
+    loop {
+        if matches!(*parser.next_token(), Token::Comma | Token::Eof) {
+            return parser.error("Expected a selector".to_string());
+        }
+        selectors.extend(parser.parse_selector(None)?);
+        match *parser.next_token() {
+            Token::Comma => { parser.consume_token(); parser.consume_whitespace(); }
+            Token::Eof => break,
+            ref token => return parser.error(format!("Unexpected {} in selector list", token)),
+        }
+    }
+    Ok(selectors)
+}
+
+// Look up a CSS named color such as 'rebeccapurple' (case-insensitively).
+pub fn named_color(name: &str) -> Option<Color> {
+    let name = name.to_ascii_lowercase();
+    if name == "transparent" {
+        return Some(Color { r: 0, g: 0, b: 0, a: 0 });
+    }
+    NAMED_COLORS.binary_search_by(|&(n, _)| n.cmp(&*name)).ok().map(|i| {
+        let rgb = NAMED_COLORS[i].1;
+        Color { r: (rgb >> 16) as u8, g: (rgb >> 8) as u8, b: rgb as u8, a: 255 }
+    })
+}
+
+// The named colors from CSS Color Level 4, sorted by name for binary search.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse 'source', which has to be valid CSS.
+    fn parsed(source: &str) -> Stylesheet {
+        let (stylesheet, errors) = parse(source.to_string());
+        assert!(errors.is_empty(), "{:?}", errors);
+        stylesheet
+    }
+
+    // The messages of the errors in 'source'.
+    fn errors(source: &str) -> Vec<String> {
+        parse(source.to_string()).1.into_iter().map(|error| error.message).collect()
+    }
+
+    // The value of 'name: value' parsed in a rule.
+    fn declared(name: &str, value: &str) -> Value {
+        parsed(&format!("a {{ {}: {}; }}", name, value)).rules[0].declarations[0].value.clone()
+    }
+
+    fn rgba(r: u8, g: u8, b: u8, a: u8) -> Value {
+        Value::ColorValue(Color { r, g, b, a })
+    }
+
+    #[test]
+    fn malformed_declarations_and_rules_are_dropped() {
+        let (stylesheet, errors) = parse("a { color: red; width: 5zz; height: 3px } b { color: blue } } \
+                                          c { margin: 1px 2px 3px 4px 5px; padding: (1px; x); top: 1px; } \
+                                          @bogus x { y { } } d { width: 1px".to_string());
+        let names: Vec<Vec<&str>> = stylesheet.rules.iter()
+            .map(|rule| rule.declarations.iter().map(|declaration| &*declaration.name).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["color", "height"], vec!["color"], vec!["top"], vec!["width"]]);
+        assert_eq!(stylesheet.unknown_at_rules.len(), 1);
+        assert_eq!(errors.len(), 4);
+    }
+
+    #[test]
+    fn parse_errors_have_positions() {
+        let (_, errors) = parse("a { color: red; }\n  b { width: 5zz; }\nc:visited {}\nd { content: \"é\"; x: #12; }".to_string());
+        let errors: Vec<String> = errors.iter().map(|error| format!("{} at {}", error, error.offset)).collect();
+        assert_eq!(errors, vec![
+            "2:14: Unrecognised unit zz at 31",
+            "3:2: Unsupported pseudo-class :visited at 39",
+            "4:22: Invalid hex color #12 at 73",
+        ]);
+    }
+
+    #[test]
+    fn rgb_colors() {
+        assert!(declared("color", "rgb(255, 0, 10)") == rgba(255, 0, 10, 255));
+        assert!(declared("color", "RGBA(100%, 50%, 0%, 0.5)") == rgba(255, 128, 0, 128));
+        assert!(declared("color", "rgb(0 300 5 / 25%)") == rgba(0, 255, 5, 64));
+        assert!(declared("color", "rgb(10% 20 30)") == rgba(26, 20, 30, 255));
+    }
+
+    #[test]
+    fn rgb_channels_have_no_units() {
+        assert_eq!(errors("a { color: rgb(1px, 2, 3); }"), vec!["rgb() takes numbers and percentages"]);
+    }
+
+    #[test]
+    fn legacy_rgb_does_not_mix_numbers_and_percentages() {
+        assert_eq!(errors("a { color: rgb(10%, 20, 30); }"), vec!["rgb() can't mix numbers and percentages"]);
+    }
+
+    #[test]
+    fn hsl_colors() {
+        assert!(declared("color", "hsl(0, 100%, 50%)") == rgba(255, 0, 0, 255));
+        assert!(declared("color", "HSLA(120deg, 100%, 25%, 0.5)") == rgba(0, 128, 0, 128));
+        assert!(declared("color", "hsl(0.5turn 100% 50% / 50%)") == rgba(0, 255, 255, 128));
+    }
+
+    #[test]
+    fn hsl_saturation_and_lightness_are_percentages() {
+        assert_eq!(errors("a { color: hsl(120, 100, 50%); }"), vec!["hsl() saturation and lightness must be percentages"]);
+    }
+
+    #[test]
+    fn named_colors() {
+        assert!(NAMED_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(named_color("RebeccaPurple") == Some(Color { r: 0x66, g: 0x33, b: 0x99, a: 255 }));
+        assert!(named_color("transparent") == Some(Color { r: 0, g: 0, b: 0, a: 0 }));
+        assert!(named_color("bogus").is_none());
+        assert!(declared("color", "navy").to_color() == Some(Color { r: 0, g: 0, b: 0x80, a: 255 }));
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert!(declared("color", "#fa0") == rgba(255, 170, 0, 255));
+        assert!(declared("color", "#fa08") == rgba(255, 170, 0, 136));
+        assert!(declared("color", "#0A1b2C") == rgba(10, 27, 44, 255));
+        assert!(declared("color", "#0a1b2c80") == rgba(10, 27, 44, 128));
+    }
+
+    #[test]
+    fn hex_colors_have_3_4_6_or_8_digits() {
+        assert_eq!(errors("a { color: #abcde; }"), vec!["Invalid hex color #abcde"]);
+    }
+
+    #[test]
+    fn complex_selector_specificity_is_the_sum_of_its_parts() {
+        let stylesheet = parsed("#a .b c, p {}");
+        let specificities: Vec<Specificity> = stylesheet.rules[0].selectors.iter().map(Selector::specificity).collect();
+        assert_eq!(specificities, vec![(1, 1, 1), (0, 0, 1)]);
+    }
+
+    #[test]
+    fn selectors_cannot_start_with_a_combinator() {
+        let (stylesheet, errors) = parse("> p { color: red; } b {}".to_string());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(stylesheet.rules.len(), 1);
+    }
+
+    #[test]
+    fn combinators_need_a_selector_after_them() {
+        let (stylesheet, errors) = parse("p > { color: red; } b {}".to_string());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(stylesheet.rules.len(), 1);
+    }
+
+    #[test]
+    fn selectors_cannot_be_empty() {
+        for source in &["{ color: red; } b {}", "a, { color: red; } b {}", "a,, b { color: red; } b {}"] {
+            let (stylesheet, errors) = parse(source.to_string());
+            assert_eq!(errors.len(), 1, "{}", source);
+            assert_eq!(stylesheet.rules.len(), 1, "{}", source);
+        }
+    }
+
+    #[test]
+    fn attribute_selectors_count_as_classes() {
+        let stylesheet = parsed("a[href][target=_blank] {}");
+        assert_eq!(stylesheet.rules[0].selectors[0].specificity(), (0, 2, 1));
+    }
+
+    #[test]
+    fn an_plus_b() {
+        assert_eq!(parse_an_plus_b("odd"), Some((2, 1)));
+        assert_eq!(parse_an_plus_b("EVEN"), Some((2, 0)));
+        assert_eq!(parse_an_plus_b("3"), Some((0, 3)));
+        assert_eq!(parse_an_plus_b("-n + 3"), Some((-1, 3)));
+        assert_eq!(parse_an_plus_b("+2n-1"), Some((2, -1)));
+        assert_eq!(parse_an_plus_b("4n"), Some((4, 0)));
+        assert_eq!(parse_an_plus_b("n+"), None);
+    }
+
+    #[test]
+    fn pseudo_elements_count_as_type_selectors() {
+        let stylesheet = parsed("p:first-child::after {}");
+        assert_eq!(stylesheet.rules[0].selectors[0].specificity(), (0, 1, 2));
+    }
+
+    #[test]
+    fn imports_come_first_and_skip_cycles() {
+        let mut stylesheet = parsed("@import url(a.css) print; @import 'missing.css'; p { width: 1px; }");
+        let mut fetched = Vec::new();
+        stylesheet.resolve_imports(&mut |url| {
+            fetched.push(url.to_string());
+            match url {
+                "a.css" => Some("@import \"b.css\"; a {}".to_string()),
+                "b.css" => Some("@import url( a.css ); b {}".to_string()),
+                _ => None,
+            }
+        });
+        assert_eq!(fetched, vec!["a.css", "b.css", "missing.css"]);
+        let tags: Vec<Option<String>> = stylesheet.rules.iter().map(|rule| match rule.selectors[0] {
+            Selector::Simple(ref simple) => simple.tag_name.clone(),
+            _ => None,
+        }).collect();
+        assert_eq!(tags, vec![Some("b".to_string()), Some("a".to_string()), Some("p".to_string())]);
+        assert_eq!(stylesheet.rules[0].media.len(), 1);
+        assert!(stylesheet.rules[2].media.is_empty());
+        assert!(stylesheet.imports.is_empty());
+    }
+
+    #[test]
+    fn font_face_rules() {
+        let stylesheet = parsed("@font-face { font-family: Open Sans; font-weight: bold; font-style: italic; \
+                                 src: local(\"Open Sans\"), url(open-sans.woff2) format('woff2'), url(\"open-sans.ttf\"); }");
+        let font_face = &stylesheet.font_faces[0];
+        assert_eq!(font_face.family, "Open Sans");
+        assert_eq!(font_face.weight, 700);
+        assert!(font_face.style == crate::style::FontStyle::Italic);
+        let sources: Vec<String> = font_face.src.iter().map(|source| match *source {
+            FontSource::Url(ref url, ref format) => format!("url {} {:?}", url, format),
+            FontSource::Local(ref name) => format!("local {}", name),
+        }).collect();
+        assert_eq!(sources, vec!["local Open Sans", "url open-sans.woff2 Some(\"woff2\")", "url open-sans.ttf None"]);
+    }
+
+    #[test]
+    fn signed_numbers() {
+        let stylesheet = parsed("a { margin-left: -.5em; z-index: +3; text-indent: -x; }");
+        let values: Vec<&Value> = stylesheet.rules[0].declarations.iter().map(|declaration| &declaration.value).collect();
+        assert!(*values[0] == Value::Length(-0.5, Unit::Em));
+        assert!(*values[1] == Value::Number(3.0));
+        assert!(*values[2] == Value::Keyword("-x".to_string()));
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert!(declared("content", "\"a\\\"b\\26 c\\\nd\"") == Value::String("a\"b&cd".to_string()));
+        assert!(declared("content", "'it\\'s'") == Value::String("it's".to_string()));
+        assert!(declared("content", "\"\\1F600x\\0\"") == Value::String("\u{1F600}x\u{FFFD}".to_string()));
+    }
+
+    #[test]
+    fn strings_cannot_contain_newlines() {
+        let (stylesheet, errors) = parse("a { content: \"bad\n; width: 1px; } b { x: 'sk\\';ip'; y: 2px; }".to_string());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(stylesheet.rules[0].declarations.len(), 1);
+        assert_eq!(stylesheet.rules[0].declarations[0].name, "width");
+        assert!(stylesheet.rules[1].declarations[0].value == Value::String("sk';ip".to_string()));
+        assert_eq!(stylesheet.rules[1].declarations[1].name, "y");
+    }
+
+    #[test]
+    fn value_lists() {
+        let px = |n| Value::Length(n, Unit::Px);
+        let list = |items, separator| Value::List(items, separator);
+        assert!(declared("x", "1px 2px / 3px, 4px") == list(vec![
+            list(vec![px(1.0), list(vec![px(2.0), px(3.0)], Separator::Slash)], Separator::Space),
+            px(4.0),
+        ], Separator::Comma));
+        assert!(declared("x", "a,b") == list(vec![Value::Keyword("a".to_string()), Value::Keyword("b".to_string())], Separator::Comma));
+    }
+
+    #[test]
+    fn url_values() {
+        let (stylesheet, errors) = parse("a { w: url( a\\(1\\).png ); x: url('b c.png'); y: url(\"d\\\"e\"); z: url(a\"b); } \
+                                          @font-face { font-family: F; src: url(f\\20 g.woff); }".to_string());
+        assert_eq!(errors.len(), 1);
+        let urls: Vec<String> = stylesheet.rules[0].declarations.iter().map(|declaration| match declaration.value {
+            Value::Url(ref url) => url.clone(),
+            _ => panic!("{} isn't a URL", declaration.name),
+        }).collect();
+        assert_eq!(urls, vec!["a(1).png", "b c.png", "d\"e"]);
+        assert!(matches!(stylesheet.font_faces[0].src[0], FontSource::Url(ref url, None) if url == "f g.woff"));
+    }
+
+    #[test]
+    fn urls_are_resolved_against_the_stylesheet() {
+        let mut stylesheet = parsed("@import 'x.css'; a { background: url(a.png) red; b: var(--c, url(../c.png)); } \
+                                     @font-face { font-family: F; src: url(/f.woff); }");
+        stylesheet.resolve_urls("http://a.com/css/main.css");
+        assert_eq!(stylesheet.imports[0].url, "http://a.com/css/x.css");
+        let backgrounds: Vec<&str> = stylesheet.rules[0].declarations.iter().filter_map(|declaration| match declaration.value {
+            Value::Url(ref url) => Some(&**url),
+            _ => None,
+        }).collect();
+        assert_eq!(backgrounds, ["http://a.com/css/a.png"]);
+        assert!(matches!(stylesheet.rules[0].declarations.last().unwrap().value,
+                         Value::Var(_, Some(ref fallback)) if **fallback == Value::Url("http://a.com/c.png".to_string())));
+        assert!(matches!(stylesheet.font_faces[0].src[0], FontSource::Url(ref url, None) if url == "http://a.com/f.woff"));
+    }
+
+    #[test]
+    fn supports_rules() {
+        let (stylesheet, errors) = parse("@supports (display: block) { a { width: 1px; } } \
+                                          @supports (display: flex) { b { width: 2px; } } \
+                                          @supports not (display: flex) { c { width: 3px; } } \
+                                          @supports (margin: 1px 2px) and ((width: 5zz) or (--x: y)) { d { x: y; } } \
+                                          @supports (display: block) and (display: block) or (a: b) { e {} } \
+                                          @supports (width: bogus) or (a: b) or (width: 5%) { f {} } g {}".to_string());
+        assert_eq!(errors.len(), 1);
+        let tags: Vec<String> = stylesheet.rules.iter().map(|rule| match rule.selectors[0] {
+            Selector::Simple(ref simple) => simple.tag_name.clone().unwrap(),
+            _ => panic!("Expected a simple selector"),
+        }).collect();
+        assert_eq!(tags, vec!["a", "b", "d", "f", "g"]);
+    }
+
+    #[test]
+    fn nested_rules() {
+        let (stylesheet, errors) = parse(".card, #x { width: 1px; & > p { height: 2px; .big & { margin-top: 3px; } } \
+                                          &.wide { padding-top: 4px; } + div { margin-left: 6px; } } & { } p { width: 9px; }".to_string());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(stylesheet.rules.len(), 6);
+        assert_eq!(stylesheet.rules[1].selectors.len(), 2);
+        assert_eq!(stylesheet.rules[1].selectors[0].specificity(), (1, 0, 1));
+        assert_eq!(stylesheet.rules[2].selectors[1].specificity(), (0, 2, 1));
+    }
+
+    #[test]
+    fn nested_selectors_can_start_but_not_end_with_a_combinator() {
+        let (stylesheet, errors) = parse("a { > p { width: 1px; } p > { width: 2px; } }".to_string());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert_eq!(stylesheet.rules[1].selectors[0].specificity(), (0, 0, 2));
+    }
+
+    #[test]
+    fn rules_serialize_to_css_that_parses_back() {
+        let stylesheet = parsed("@media screen and (min-width: 600px), not print { \
+                                     .a\\:b > p + #\\31 x ~ [lang|=en]:nth-child(-2n+3)::before, * { \
+                                         width: calc((100% - 2 * (10px + 1em)) / 2) !important; \
+                                         x: \"q\\\"\\\\\\a \" url(\"u v\") #ff000080 1.5s var(--a, 1px 2px); y: a b/c, d; } }");
+        let rule = &stylesheet.rules[0];
+        assert_eq!(rule.selectors[0].to_string(), ".a\\:b > p + #\\31 x ~ [lang|=\"en\"]:nth-child(-2n+3)::before");
+        let serialized = rule.to_string();
+        let again = parsed(&serialized);
+        assert_eq!(again.rules[0].to_string(), serialized);
+        for (a, b) in rule.declarations.iter().zip(&again.rules[0].declarations) {
+            assert!(a.value == b.value, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn stylesheets_serialize_to_css_that_parses_back() {
+        let mut stylesheet = parsed("@import 'x.css' screen; a { color: red; } \
+                                     @font-face { font-family: My Font; src: local(Foo Bar), url(f.woff) format('woff'); font-weight: bold; } \
+                                     @keyframes spin { from { width: 0px; } 50%, to { width: 10px; } } \
+                                     @media print { b { margin: 1px 2px; } }");
+        stylesheet.rules[0].declarations[0].value = Value::Keyword("blue".to_string());
+        let css = stylesheet.to_css();
+        assert_eq!(css.lines().count(), 5);
+        assert!(css.contains("blue"));
+        assert_eq!(parsed(&css).to_css(), css);
+    }
+
+    #[test]
+    fn transform_functions() {
+        use TransformFunction::*;
+        let (stylesheet, errors) = parse("a { transform: translate(10px) translateY(50%) rotate(0.25turn) scale(2) skewX(10deg) \
+                                                     matrix(1, 0, 0, 1, 5, 6); x: scaleY(3); y: rotate(5); z: matrix(1, 2); }".to_string());
+        assert_eq!(errors.len(), 2);
+        let declarations = &stylesheet.rules[0].declarations;
+        let px = |n| Value::Length(n, Unit::Px);
+        assert!(declarations[0].value == Value::Transform(vec![
+            Translate(px(10.0), px(0.0)), Translate(px(0.0), Value::Percentage(50.0)), Rotate(90.0),
+            Scale(2.0, 2.0), Skew(10.0, 0.0), Matrix([1.0, 0.0, 0.0, 1.0, 5.0, 6.0]),
+        ]));
+        assert!(declarations[1].value == Value::Transform(vec![Scale(1.0, 3.0)]));
+        assert!(parsed(&format!("a {{ {}; }}", declarations[0])).rules[0].declarations[0].value == declarations[0].value);
+    }
+
+    #[test]
+    fn unitless_numbers() {
+        assert!(declared("line-height", "1.5") == Value::Number(1.5));
+        assert!(declared("margin", "0") == Value::Number(0.0));
+        let font_face = &parsed("@font-face { font-family: F; font-weight: 300; }").font_faces[0];
+        assert_eq!(font_face.weight, 300);
+    }
+
+    #[test]
+    fn unknown_at_rules_are_kept_aside() {
+        let stylesheet = parsed("@page :first { margin: 1in; @top { x: f(1) } } @charset \"utf-8\"; a { color: red; } \
+                                 @foo [x;y] ; b { c { } } @bar");
+        assert_eq!(stylesheet.rules.len(), 3);
+        let unknown: Vec<String> = stylesheet.unknown_at_rules.iter().map(|rule| rule.to_string()).collect();
+        assert_eq!(unknown, vec!["@page :first { margin: 1in; @top { x: f(1) } }", "@charset \"utf-8\";", "@foo [x;y];", "@bar;"]);
+        let css = stylesheet.to_css();
+        assert_eq!(parsed(&css).to_css(), css);
+    }
+
+    #[test]
+    fn style_attributes() {
+        let (declarations, errors) = parse_style_attribute("color: red; ; margin: 1px 2px; width: 5zz } height: 3px".to_string());
+        assert_eq!(errors.len(), 2);
+        let names: Vec<&str> = declarations.iter().map(|declaration| &*declaration.name).collect();
+        assert_eq!(names, vec!["color", "margin-top", "margin-right", "margin-bottom", "margin-left", "height"]);
+    }
+
+    #[test]
+    fn dynamic_pseudo_classes() {
+        let stylesheet = parsed("div:hover { width: 1px; } a:FOCUS, :active {}");
+        assert_eq!(stylesheet.rules[0].selectors[0].to_string(), "div:hover");
+        assert_eq!(stylesheet.rules[1].selectors[0].specificity(), (0, 1, 1));
+    }
 }
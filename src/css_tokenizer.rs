@@ -0,0 +1,449 @@
+use std::fmt;
+
+// The tokens of CSS Syntax Level 3. Comments aren't tokens; they're dropped.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    Ident(String),
+    Function(String), // A name followed by '(', which is part of the token
+    AtKeyword(String),
+    Hash(String, bool), // Set if the name is an identifier, as an ID selector needs
+    String(String),
+    BadString, // A string broken by an unescaped newline
+    Url(String), // An unquoted 'url(...)'
+    BadUrl,
+    Number(f32),
+    Percentage(f32),
+    Dimension(f32, String),
+    Delim(char),
+    Whitespace,
+    Cdo, // '<!--'
+    Cdc, // '-->'
+    Colon,
+    Semicolon,
+    Comma,
+    OpenSquare,
+    CloseSquare,
+    OpenParen,
+    CloseParen,
+    OpenCurly,
+    CloseCurly,
+    Eof,
+}
+
+// How tokens are shown in parse errors.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Ident(ref name) => write!(f, "{}", name),
+            Token::Function(ref name) => write!(f, "{}(", name),
+            Token::AtKeyword(ref name) => write!(f, "@{}", name),
+            Token::Hash(ref name, _) => write!(f, "#{}", name),
+            Token::String(ref value) => write!(f, "{:?}", value),
+            Token::BadString => write!(f, "unterminated string"),
+            Token::Url(ref url) => write!(f, "url({})", url),
+            Token::BadUrl => write!(f, "invalid url()"),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Percentage(n) => write!(f, "{}%", n),
+            Token::Dimension(n, ref unit) => write!(f, "{}{}", n, unit),
+            Token::Delim(c) => write!(f, "{}", c),
+            Token::Whitespace => write!(f, "whitespace"),
+            Token::Cdo => write!(f, "<!--"),
+            Token::Cdc => write!(f, "-->"),
+            Token::Colon => write!(f, ":"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Comma => write!(f, ","),
+            Token::OpenSquare => write!(f, "["),
+            Token::CloseSquare => write!(f, "]"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::OpenCurly => write!(f, "{{"),
+            Token::CloseCurly => write!(f, "}}"),
+            Token::Eof => write!(f, "the end of the input"),
+        }
+    }
+}
+
+// Split CSS source into tokens, each with the byte offset it starts at.
+// The last token is always 'Eof'.
+pub fn tokenize(input: &str) -> Vec<(Token, usize)> {
+    let mut tokenizer = Tokenizer { input, pos: 0 };
+    let mut tokens = Vec::new();
+    loop {
+        tokenizer.consume_comments();
+        let pos = tokenizer.pos;
+        let token = tokenizer.consume_token();
+        let eof = token == Token::Eof;
+        tokens.push((token, pos));
+        if eof {
+            return tokens;
+        }
+    }
+}
+
+struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn consume_token(&mut self) -> Token {
+        if self.eof() {
+            return Token::Eof;
+        }
+        let c = self.peek(0);
+        match c {
+            c if is_whitespace(c) => {
+                self.consume_while(is_whitespace);
+                Token::Whitespace
+            }
+            '"' | '\'' => self.consume_string(),
+            '#' => {
+                self.consume_char();
+                if is_name_char(self.peek(0)) || is_valid_escape(self.peek(0), self.peek(1)) {
+                    let id = self.starts_identifier();
+                    Token::Hash(self.consume_name(), id)
+                } else {
+                    Token::Delim('#')
+                }
+            }
+            '+' | '.' if self.starts_number() => self.consume_numeric(),
+            '-' if self.starts_number() => self.consume_numeric(),
+            '-' if self.input[self.pos..].starts_with("-->") => {
+                self.pos += 3;
+                Token::Cdc
+            }
+            '-' if self.starts_identifier() => self.consume_ident_like(),
+            '<' if self.input[self.pos..].starts_with("<!--") => {
+                self.pos += 4;
+                Token::Cdo
+            }
+            '@' => {
+                self.consume_char();
+                if self.starts_identifier() {
+                    Token::AtKeyword(self.consume_name())
+                } else {
+                    Token::Delim('@')
+                }
+            }
+            '\\' if is_valid_escape(c, self.peek(1)) => self.consume_ident_like(),
+            '0'..='9' => self.consume_numeric(),
+            c if is_name_start(c) => self.consume_ident_like(),
+            _ => {
+                self.consume_char();
+                match c {
+                    ':' => Token::Colon,
+                    ';' => Token::Semicolon,
+                    ',' => Token::Comma,
+                    '[' => Token::OpenSquare,
+                    ']' => Token::CloseSquare,
+                    '(' => Token::OpenParen,
+                    ')' => Token::CloseParen,
+                    '{' => Token::OpenCurly,
+                    '}' => Token::CloseCurly,
+                    _ => Token::Delim(c),
+                }
+            }
+        }
+    }
+
+    // Skip any '/* ... */' comments. An unterminated comment runs to the end of the input.
+    fn consume_comments(&mut self) {
+        while self.input[self.pos..].starts_with("/*") {
+            match self.input[self.pos + 2..].find("*/") {
+                Some(end) => self.pos += end + 4,
+                None => self.pos = self.input.len(),
+            }
+        }
+    }
+
+    // Consume a string, up to and including the closing quote. A newline ends the string
+    // early, and makes it a bad string; the newline itself is left for the next token.
+    fn consume_string(&mut self) -> Token {
+        let quote = self.consume_char();
+        let mut value = String::new();
+        loop {
+            if self.eof() {
+                return Token::String(value);
+            }
+            match self.peek(0) {
+                c if c == quote => {
+                    self.consume_char();
+                    return Token::String(value);
+                }
+                c if is_newline(c) => return Token::BadString,
+                '\\' => {
+                    self.consume_char();
+                    match self.peek(0) {
+                        // An escaped newline continues the string on the next line.
+                        c if is_newline(c) => self.consume_newline(),
+                        _ if self.eof() => {}
+                        _ => value.push(self.consume_escape()),
+                    }
+                }
+                _ => value.push(self.consume_char()),
+            }
+        }
+    }
+
+    // Consume a number, followed by a '%' or a unit if there is one.
+    fn consume_numeric(&mut self) -> Token {
+        let number = self.consume_number();
+        if self.starts_identifier() {
+            Token::Dimension(number, self.consume_name())
+        } else if self.peek(0) == '%' {
+            self.consume_char();
+            Token::Percentage(number)
+        } else {
+            Token::Number(number)
+        }
+    }
+
+    // Consume a number with an optional sign, fraction and exponent, such as '-1.5e3'.
+    fn consume_number(&mut self) -> f32 {
+        let start = self.pos;
+        if matches!(self.peek(0), '+' | '-') {
+            self.consume_char();
+        }
+        self.consume_while(|c| c.is_ascii_digit());
+        if self.peek(0) == '.' && self.peek(1).is_ascii_digit() {
+            self.consume_char();
+            self.consume_while(|c| c.is_ascii_digit());
+        }
+        let exponent_digit = match (self.peek(1), self.peek(2)) {
+            ('+' | '-', c) => c.is_ascii_digit(),
+            (c, _) => c.is_ascii_digit(),
+        };
+        if matches!(self.peek(0), 'e' | 'E') && exponent_digit {
+            self.consume_char();
+            if matches!(self.peek(0), '+' | '-') {
+                self.consume_char();
+            }
+            self.consume_while(|c| c.is_ascii_digit());
+        }
+        self.input[start..self.pos].parse().unwrap()
+    }
+
+    // Consume an identifier, a function name or an unquoted 'url()'.
+    fn consume_ident_like(&mut self) -> Token {
+        let name = self.consume_name();
+        if self.peek(0) != '(' {
+            return Token::Ident(name);
+        }
+        self.consume_char();
+        if name.eq_ignore_ascii_case("url") {
+            self.consume_while(is_whitespace);
+            // A quoted URL is a function with a string argument.
+            if !matches!(self.peek(0), '"' | '\'') {
+                return self.consume_url();
+            }
+        }
+        Token::Function(name)
+    }
+
+    // Consume the rest of an unquoted 'url(', which must not contain quotes, parentheses or
+    // whitespace unless they're escaped. Whitespace before the closing ')' is allowed.
+    fn consume_url(&mut self) -> Token {
+        let mut url = String::new();
+        loop {
+            if self.eof() {
+                return Token::Url(url);
+            }
+            match self.peek(0) {
+                ')' => {
+                    self.consume_char();
+                    return Token::Url(url);
+                }
+                c if is_whitespace(c) => {
+                    self.consume_while(is_whitespace);
+                    if self.peek(0) == ')' || self.eof() {
+                        continue;
+                    }
+                    self.consume_bad_url();
+                    return Token::BadUrl;
+                }
+                '\\' if is_valid_escape('\\', self.peek(1)) => {
+                    self.consume_char();
+                    url.push(self.consume_escape());
+                }
+                '"' | '\'' | '(' | '\\' => {
+                    self.consume_bad_url();
+                    return Token::BadUrl;
+                }
+                c if c.is_control() => {
+                    self.consume_bad_url();
+                    return Token::BadUrl;
+                }
+                _ => url.push(self.consume_char()),
+            }
+        }
+    }
+
+    // Skip the rest of a malformed URL, up to and including the ')' that ends it.
+    fn consume_bad_url(&mut self) {
+        while !self.eof() {
+            match self.consume_char() {
+                ')' => return,
+                '\\' if !self.eof() => { self.consume_escape(); }
+                _ => {}
+            }
+        }
+    }
+
+    // Consume a name made of name characters and escapes.
+    fn consume_name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            let c = self.peek(0);
+            if is_name_char(c) && !self.eof() {
+                name.push(self.consume_char());
+            } else if is_valid_escape(c, self.peek(1)) {
+                self.consume_char();
+                name.push(self.consume_escape());
+            } else {
+                return name;
+            }
+        }
+    }
+
+    // Consume what follows a backslash: up to 6 hex digits giving a code point, which may
+    // be followed by a whitespace character, or any other character standing for itself.
+    fn consume_escape(&mut self) -> char {
+        if self.eof() {
+            return '\u{FFFD}';
+        }
+        if !self.peek(0).is_ascii_hexdigit() {
+            return self.consume_char();
+        }
+        let start = self.pos;
+        while self.pos - start < 6 && self.peek(0).is_ascii_hexdigit() && !self.eof() {
+            self.consume_char();
+        }
+        let code_point = u32::from_str_radix(&self.input[start..self.pos], 16).unwrap();
+        if is_newline(self.peek(0)) {
+            self.consume_newline();
+        } else if is_whitespace(self.peek(0)) {
+            self.consume_char();
+        }
+        // Null, surrogates and values out of range become the replacement character.
+        match code_point {
+            0 => '\u{FFFD}',
+            _ => char::from_u32(code_point).unwrap_or('\u{FFFD}'),
+        }
+    }
+
+    // Consume a newline, which is two characters for '\r\n'.
+    fn consume_newline(&mut self) {
+        if self.consume_char() == '\r' && self.peek(0) == '\n' {
+            self.consume_char();
+        }
+    }
+
+    // Do the next characters start an identifier?
+    fn starts_identifier(&self) -> bool {
+        match self.peek(0) {
+            '-' => is_name_start(self.peek(1)) || self.peek(1) == '-' || is_valid_escape(self.peek(1), self.peek(2)),
+            '\\' => is_valid_escape('\\', self.peek(1)),
+            c => is_name_start(c),
+        }
+    }
+
+    // Do the next characters start a number? A sign or '.' has to be followed by a digit.
+    fn starts_number(&self) -> bool {
+        match self.peek(0) {
+            '+' | '-' => self.peek(1).is_ascii_digit() || (self.peek(1) == '.' && self.peek(2).is_ascii_digit()),
+            '.' => self.peek(1).is_ascii_digit(),
+            c => c.is_ascii_digit(),
+        }
+    }
+
+    // The character 'n' places ahead, or '\0' past the end of the input.
+    fn peek(&self, n: usize) -> char {
+        self.input[self.pos..].chars().nth(n).unwrap_or('\0')
+    }
+
+    fn eof(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn consume_char(&mut self) -> char {
+        let c = self.peek(0);
+        self.pos += c.len_utf8();
+        c
+    }
+
+    fn consume_while<F>(&mut self, test: F) where F: Fn(char) -> bool {
+        while !self.eof() && test(self.peek(0)) {
+            self.consume_char();
+        }
+    }
+}
+
+fn is_newline(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\x0C')
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t') || is_newline(c)
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+// Do these two characters start an escape? A backslash can't escape a newline, except in a string.
+fn is_valid_escape(first: char, second: char) -> bool {
+    first == '\\' && !is_newline(second) && second != '\0'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token> {
+        tokenize(input).into_iter().map(|(token, _)| token).collect()
+    }
+
+    #[test]
+    fn newline_in_string_makes_a_bad_string() {
+        assert_eq!(tokens("'ab\nc'"), vec![
+            Token::BadString, Token::Whitespace, Token::Ident("c".to_string()), Token::String(String::new()), Token::Eof,
+        ]);
+        // A string left open at the end of the input is just closed.
+        assert_eq!(tokens("\"ab"), vec![Token::String("ab".to_string()), Token::Eof]);
+    }
+
+    #[test]
+    fn numbers_and_urls() {
+        assert_eq!(tokens("1e2px -.5% +3"), vec![
+            Token::Dimension(100.0, "px".to_string()), Token::Whitespace, Token::Percentage(-0.5),
+            Token::Whitespace, Token::Number(3.0), Token::Eof,
+        ]);
+        assert_eq!(tokens("url( a\\(1\\).png )"), vec![Token::Url("a(1).png".to_string()), Token::Eof]);
+        assert_eq!(tokens("url(a\"b) x"), vec![Token::BadUrl, Token::Whitespace, Token::Ident("x".to_string()), Token::Eof]);
+        assert_eq!(tokens("url('b c')"), vec![Token::Function("url".to_string()), Token::String("b c".to_string()),
+                                              Token::CloseParen, Token::Eof]);
+    }
+
+    #[test]
+    fn comments_are_dropped_and_cdo_cdc_kept() {
+        assert_eq!(tokens("<!--a/* x */#b-1 -->"), vec![
+            Token::Cdo, Token::Ident("a".to_string()), Token::Hash("b-1".to_string(), true), Token::Whitespace,
+            Token::Cdc, Token::Eof,
+        ]);
+        assert_eq!(tokens("#1a"), vec![Token::Hash("1a".to_string(), false), Token::Eof]);
+    }
+
+    #[test]
+    fn escapes_in_names_and_strings() {
+        assert_eq!(tokens("a\\31 b"), vec![Token::Ident("a1b".to_string()), Token::Eof]);
+        assert_eq!(tokens(".a\\:b"), vec![Token::Delim('.'), Token::Ident("a:b".to_string()), Token::Eof]);
+        assert_eq!(tokens("\"a\\\"b\\26 c\\\nd\""), vec![Token::String("a\"b&cd".to_string()), Token::Eof]);
+        assert_eq!(tokens("'\\1F600x'"), vec![Token::String("\u{1F600}x".to_string()), Token::Eof]);
+        // Zero, surrogates and code points past the last one become U+FFFD.
+        assert_eq!(tokens("'\\0\\D800\\110000'"), vec![Token::String("\u{FFFD}\u{FFFD}\u{FFFD}".to_string()), Token::Eof]);
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use crate::url;
 
+#[derive(Clone)]
 pub struct Node {
     // data common to all nodes:
     pub children: Vec<Node>,
@@ -8,28 +10,335 @@ pub struct Node {
     pub node_type: NodeType,
 }
 
+#[derive(Clone)]
 pub enum NodeType {
     Text(String),
+    Comment(String),
     Element(ElementData),
 }
 
+#[derive(Clone)]
 pub struct ElementData {
     pub tag_name: String,
+    pub namespace: Namespace,
     pub attributes: AttrMap,
 }
 
 pub type AttrMap = HashMap<String, String>;
 
+// The namespaces elements can be in. An 'a' in an SVG image isn't the same element as an
+// 'a' in HTML, and SVG elements are drawn rather than laid out as boxes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Namespace {
+    Html,
+    Svg,
+}
+
+pub const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+// The SVG attribute names with capitals in them, which HTML is written without.
+const SVG_ATTRIBUTE_NAMES: [&str; 5] = ["viewBox", "preserveAspectRatio", "gradientUnits", "gradientTransform", "pathLength"];
+
+// The SVG attribute name that 'name' is a differently cased version of, if it's one with
+// capitals in it.
+pub fn svg_attribute_name(name: String) -> String {
+    match SVG_ATTRIBUTE_NAMES.iter().find(|svg_name| svg_name.eq_ignore_ascii_case(&name)) {
+        Some(svg_name) => svg_name.to_string(),
+        None => name,
+    }
+}
+
 pub fn text(data: String) -> Node {
     Node { children: Vec::new(), node_type: NodeType::Text(data) }
 }
 
+pub fn comment(data: String) -> Node {
+    Node { children: Vec::new(), node_type: NodeType::Comment(data) }
+}
+
 pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
+    elem_ns(name, Namespace::Html, attrs, children)
+}
+
+pub fn elem_ns(name: String, namespace: Namespace, attrs: AttrMap, children: Vec<Node>) -> Node {
     Node {
         children,
         node_type: NodeType::Element(ElementData {
             tag_name: name,
+            namespace,
             attributes: attrs,
         })
     }
 }
+
+// Changing documents. Nodes are found by their path: the child indices leading to them from
+// the root, which is how the style engine refers to them too. After changing a document
+// that has been styled, tell the engine what changed with 'Engine::invalidate_attributes'
+// or 'Engine::invalidate_subtree', and restyle it.
+impl Node {
+    // The node at 'path' below this one, if there is one.
+    pub fn node_at(&self, path: &[usize]) -> Option<&Node> {
+        match path.split_first() {
+            Some((&index, rest)) => self.children.get(index)?.node_at(rest),
+            None => Some(self),
+        }
+    }
+
+    pub fn node_at_mut(&mut self, path: &[usize]) -> Option<&mut Node> {
+        match path.split_first() {
+            Some((&index, rest)) => self.children.get_mut(index)?.node_at_mut(rest),
+            None => Some(self),
+        }
+    }
+
+    // The path to the first element in this subtree (in document order) with the id 'id'.
+    pub fn get_element_by_id(&self, id: &str) -> Option<Vec<usize>> {
+        self.find_all(&|elem| elem.attributes.get("id").is_some_and(|value| value == id)).into_iter().next()
+    }
+
+    // The paths to the elements in this subtree that have the class 'class', in document order.
+    pub fn get_elements_by_class_name(&self, class: &str) -> Vec<Vec<usize>> {
+        self.find_all(&|elem| elem.attributes.get("class").is_some_and(|classes| {
+            classes.split_whitespace().any(|name| name == class)
+        }))
+    }
+
+    // The paths to the elements in this subtree that pass 'test', in document order.
+    pub fn find_all(&self, test: &dyn Fn(&ElementData) -> bool) -> Vec<Vec<usize>> {
+        let mut found = Vec::new();
+        self.find_all_into(test, &mut Vec::new(), &mut found);
+        found
+    }
+
+    fn find_all_into(&self, test: &dyn Fn(&ElementData) -> bool, path: &mut Vec<usize>, found: &mut Vec<Vec<usize>>) {
+        if let NodeType::Element(ref elem) = self.node_type {
+            if test(elem) {
+                found.push(path.clone());
+            }
+        }
+        for (i, child) in self.children.iter().enumerate() {
+            path.push(i);
+            child.find_all_into(test, path, found);
+            path.pop();
+        }
+    }
+
+    // The URL relative URLs in the document are resolved against: the 'href' of its first
+    // '<base>' element that has one, or 'document_url', where the document was loaded from.
+    pub fn base_url(&self, document_url: &str) -> String {
+        let bases = self.find_all(&|elem| elem.tag_name == "base" && elem.attributes.contains_key("href"));
+        match bases.first().and_then(|path| self.node_at(path)) {
+            Some(&Node { node_type: NodeType::Element(ref elem), .. }) => url::resolve(document_url, &elem.attributes["href"]),
+            _ => document_url.to_string(),
+        }
+    }
+
+    // The text of this node and its descendants, like the contents of a '<style>' element.
+    pub fn text_content(&self) -> String {
+        match self.node_type {
+            NodeType::Text(ref text) => text.clone(),
+            NodeType::Comment(_) => String::new(),
+            NodeType::Element(_) => self.children.iter().map(|child| child.text_content()).collect(),
+        }
+    }
+
+    // Add 'child' after this node's other children.
+    pub fn append_child(&mut self, child: Node) {
+        self.children.push(child);
+    }
+
+    // Add 'child' before the 'index'th child, or at the end if 'index' is the number of children.
+    pub fn insert_before(&mut self, index: usize, child: Node) {
+        self.children.insert(index, child);
+    }
+
+    // Take out the 'index'th child, returning it.
+    pub fn remove_child(&mut self, index: usize) -> Node {
+        self.children.remove(index)
+    }
+
+    // Set an attribute of an element. Text and comments have no attributes, so this
+    // does nothing to them. Like the parser, this lowercases the names of attributes on
+    // HTML elements, and gives SVG attributes the capitals they should have.
+    pub fn set_attribute(&mut self, name: &str, value: String) {
+        if let NodeType::Element(ref mut elem) = self.node_type {
+            let name = match elem.namespace {
+                Namespace::Html => name.to_ascii_lowercase(),
+                Namespace::Svg => svg_attribute_name(name.to_string()),
+            };
+            elem.attributes.insert(name, value);
+        }
+    }
+
+    // Replace the text of a text node or comment. An element's children are all replaced
+    // by one text node instead.
+    pub fn set_text_content(&mut self, text: String) {
+        match self.node_type {
+            NodeType::Text(ref mut data) | NodeType::Comment(ref mut data) => *data = text,
+            NodeType::Element(_) => self.children = vec![self::text(text)],
+        }
+    }
+
+    // A short description of the node for debugging output, like 'div#main.wide' for an
+    // element, or the start of its text for a text node.
+    pub fn label(&self) -> String {
+        match self.node_type {
+            NodeType::Element(ref elem) => {
+                let mut label = elem.tag_name.clone();
+                if let Some(id) = elem.attributes.get("id") {
+                    label.push('#');
+                    label.push_str(id);
+                }
+                for class in elem.attributes.get("class").iter().flat_map(|classes| classes.split_whitespace()) {
+                    label.push('.');
+                    label.push_str(class);
+                }
+                label
+            }
+            NodeType::Text(ref text) => format!("{:?}", shortened(text)),
+            NodeType::Comment(ref data) => format!("<!--{}-->", shortened(data)),
+        }
+    }
+}
+
+// 'text', cut off after 40 characters.
+fn shortened(text: &str) -> String {
+    match text.char_indices().nth(40) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+// An indented outline of the tree under 'node', one node per line, with each element's
+// attributes in name order. For debugging.
+pub fn dump(node: &Node) -> String {
+    let mut output = String::new();
+    dump_node(node, 0, &mut output);
+    output
+}
+
+fn dump_node(node: &Node, depth: usize, output: &mut String) {
+    output.push_str(&"  ".repeat(depth));
+    match node.node_type {
+        NodeType::Element(ref elem) => {
+            output.push('<');
+            output.push_str(&elem.tag_name);
+            let mut attributes: Vec<_> = elem.attributes.iter().collect();
+            attributes.sort();
+            for (name, value) in attributes {
+                output.push_str(&format!(" {}={:?}", name, value));
+            }
+            if elem.namespace == Namespace::Svg {
+                output.push_str(" (svg)");
+            }
+            output.push('>');
+        }
+        NodeType::Text(ref text) => output.push_str(&format!("{:?}", text)),
+        NodeType::Comment(ref data) => output.push_str(&format!("<!--{}-->", data)),
+    }
+    output.push('\n');
+    for child in &node.children {
+        dump_node(child, depth + 1, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute_names(node: &Node) -> Vec<String> {
+        let mut names: Vec<String> = match node.node_type {
+            NodeType::Element(ref elem) => elem.attributes.keys().cloned().collect(),
+            _ => Vec::new(),
+        };
+        names.sort();
+        names
+    }
+
+    fn tag_name(node: &Node) -> &str {
+        match node.node_type {
+            NodeType::Element(ref elem) => &elem.tag_name,
+            _ => "#",
+        }
+    }
+
+    #[test]
+    fn nodes_are_found_and_changed_by_path() {
+        let mut root = elem("div".to_string(), AttrMap::new(), vec![
+            elem("p".to_string(), AttrMap::new(), vec![text("a".to_string())]),
+        ]);
+        assert_eq!(tag_name(root.node_at(&[0]).unwrap()), "p");
+        assert!(root.node_at(&[0, 1]).is_none());
+
+        root.append_child(elem("ul".to_string(), AttrMap::new(), Vec::new()));
+        root.insert_before(0, comment("c".to_string()));
+        let names: Vec<&str> = root.children.iter().map(tag_name).collect();
+        assert_eq!(names, ["#", "p", "ul"]);
+        assert_eq!(tag_name(&root.remove_child(2)), "ul");
+
+        root.node_at_mut(&[1, 0]).unwrap().set_text_content("b".to_string());
+        match root.children[1].children[0].node_type {
+            NodeType::Text(ref data) => assert_eq!(data, "b"),
+            _ => panic!("expected a text node"),
+        }
+        root.set_text_content("x".to_string());
+        assert_eq!(root.children.len(), 1);
+    }
+
+    #[test]
+    fn set_attribute_cases_names_by_namespace() {
+        let mut div = elem("div".to_string(), AttrMap::new(), Vec::new());
+        div.set_attribute("Data-Count", "1".to_string());
+        assert_eq!(attribute_names(&div), ["data-count"]);
+
+        let mut svg = elem_ns("svg".to_string(), Namespace::Svg, AttrMap::new(), Vec::new());
+        svg.set_attribute("viewbox", "0 0 10 10".to_string());
+        svg.set_attribute("fill", "red".to_string());
+        svg.set_attribute("dataValue", "1".to_string());
+        assert_eq!(attribute_names(&svg), ["dataValue", "fill", "viewBox"]);
+    }
+
+    #[test]
+    fn elements_by_id_and_class() {
+        let mut first = AttrMap::new();
+        first.insert("class".to_string(), "a b".to_string());
+        let mut second = AttrMap::new();
+        second.insert("id".to_string(), "x".to_string());
+        second.insert("class".to_string(), "ab b".to_string());
+        let root = elem("div".to_string(), AttrMap::new(), vec![
+            elem("p".to_string(), first, Vec::new()),
+            elem("p".to_string(), AttrMap::new(), vec![elem("span".to_string(), second, Vec::new())]),
+        ]);
+        assert_eq!(root.get_element_by_id("x"), Some(vec![1, 0]));
+        assert_eq!(root.get_element_by_id("y"), None);
+        assert_eq!(root.get_elements_by_class_name("b"), [vec![0], vec![1, 0]]);
+        assert_eq!(root.get_elements_by_class_name("a"), [vec![0]]);
+    }
+
+    #[test]
+    fn base_url_comes_from_the_first_base_element_with_an_href() {
+        let mut base = AttrMap::new();
+        base.insert("href".to_string(), "../assets/".to_string());
+        let root = elem("html".to_string(), AttrMap::new(), vec![
+            elem("base".to_string(), AttrMap::new(), Vec::new()),
+            elem("base".to_string(), base, Vec::new()),
+        ]);
+        assert_eq!(root.base_url("http://a.com/docs/index.html"), "http://a.com/assets/");
+        assert_eq!(root.children[0].base_url("docs/index.html"), "docs/index.html");
+    }
+
+    #[test]
+    fn dump_and_labels() {
+        let mut attributes = AttrMap::new();
+        attributes.insert("id".to_string(), "main".to_string());
+        attributes.insert("class".to_string(), "a  b".to_string());
+        let root = elem("div".to_string(), attributes, vec![
+            text("x".repeat(45)),
+            comment(" c ".to_string()),
+            elem_ns("svg".to_string(), Namespace::Svg, AttrMap::new(), Vec::new()),
+        ]);
+        assert_eq!(root.label(), "div#main.a.b");
+        assert_eq!(root.children[0].label(), format!("{:?}", "x".repeat(40) + "..."));
+        assert_eq!(dump(&root), format!("<div class=\"a  b\" id=\"main\">\n  {:?}\n  <!-- c -->\n  <svg (svg)>\n", "x".repeat(45)));
+    }
+}
@@ -0,0 +1,92 @@
+// The character encodings documents can be decoded from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // Also used for 'iso-8859-1' and 'us-ascii', like browsers do, since pages labeled
+    // with those are usually really windows-1252.
+    Windows1252,
+}
+
+// The characters windows-1252 has in 0x80-0x9F, where ISO-8859-1 has control codes.
+// Bytes that aren't assigned decode to the control code with the same value.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+impl Encoding {
+    // The encoding with the name 'label', as in '<meta charset="...">', if it's supported.
+    pub fn from_label(label: &str) -> Option<Encoding> {
+        match &*label.trim().to_ascii_lowercase() {
+            "utf-8" | "utf8" | "unicode-1-1-utf-8" => Some(Encoding::Utf8),
+            "utf-16" | "utf-16le" => Some(Encoding::Utf16Le),
+            "utf-16be" => Some(Encoding::Utf16Be),
+            "windows-1252" | "cp1252" | "x-cp1252" | "iso-8859-1" | "iso8859-1" | "iso_8859-1" |
+            "latin1" | "l1" | "us-ascii" | "ascii" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    // The encoding given by a byte order mark at the start of 'bytes', if there is one.
+    pub fn from_bom(bytes: &[u8]) -> Option<Encoding> {
+        match bytes {
+            [0xEF, 0xBB, 0xBF, ..] => Some(Encoding::Utf8),
+            [0xFF, 0xFE, ..] => Some(Encoding::Utf16Le),
+            [0xFE, 0xFF, ..] => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    // Decode 'bytes' into a string, skipping a byte order mark for this encoding. Bytes
+    // that aren't valid in the encoding become U+FFFD, the replacement character.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let bytes = match Encoding::from_bom(bytes) {
+            Some(encoding) if encoding == self => &bytes[if self == Encoding::Utf8 { 3 } else { 2 }..],
+            _ => bytes,
+        };
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| match self {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                }).collect();
+                let mut text = String::from_utf16_lossy(&units);
+                // A trailing odd byte is half a code unit.
+                if bytes.len() % 2 == 1 {
+                    text.push('\u{FFFD}');
+                }
+                text
+            }
+            Encoding::Windows1252 => bytes.iter().map(|&byte| match byte {
+                0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+                _ => byte as char,
+            }).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_and_byte_order_marks() {
+        assert_eq!(Encoding::from_label(" Latin1 "), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::from_label("shift_jis"), None);
+        assert_eq!(Encoding::from_bom(b"\xfe\xff\x00a"), Some(Encoding::Utf16Be));
+        assert_eq!(Encoding::from_bom(b"abc"), None);
+    }
+
+    #[test]
+    fn decode_each_encoding() {
+        assert_eq!(Encoding::Utf8.decode(b"\xef\xbb\xbfa\xc3\xa9\xff"), "a\u{e9}\u{fffd}");
+        assert_eq!(Encoding::Utf16Le.decode(b"\xff\xfea\x00\xe9\x00b"), "a\u{e9}\u{fffd}");
+        assert_eq!(Encoding::Utf16Be.decode(b"\x00a\x20\x14"), "a\u{2014}");
+        assert_eq!(Encoding::Windows1252.decode(b"\x80\x81\xe9"), "\u{20ac}\u{81}\u{e9}");
+    }
+}
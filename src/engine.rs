@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::css::{self, Origin, ParseError, PseudoElement, Stylesheet};
+use crate::dom::{Node, NodeType};
+use crate::style::{self, ElementStates, Invalidations, Media, StyledNode};
+use crate::url;
+
+// Styles documents for some media, with stylesheets registered from each cascade origin.
+pub struct Engine {
+    media: Media,
+    // Properties to rename in stylesheets as they're registered, like '-webkit-transition' to
+    // 'transition'. Starts as 'css::default_property_aliases()'.
+    pub property_aliases: HashMap<String, String>,
+    // Every registered stylesheet, in the order they were registered.
+    stylesheets: Vec<Stylesheet>,
+    // The hovered, focused and active elements, for ':hover', ':focus' and ':active'.
+    states: ElementStates,
+    // What has changed since the last 'restyle'.
+    invalidations: Invalidations,
+}
+
+impl Engine {
+    pub fn new(media: Media) -> Engine {
+        Engine {
+            media,
+            property_aliases: css::default_property_aliases(),
+            stylesheets: Vec::new(),
+            states: ElementStates::default(),
+            invalidations: Invalidations::default(),
+        }
+    }
+
+    // Register a stylesheet from 'origin'. Its '@import' rules should already be resolved.
+    // Among stylesheets from the same origin, ones registered later win ties in the cascade.
+    pub fn add_stylesheet(&mut self, mut stylesheet: Stylesheet, origin: Origin) {
+        stylesheet.apply_property_aliases(&self.property_aliases);
+        for rule in &mut stylesheet.rules {
+            rule.origin = origin;
+            self.invalidations.selectors.extend(rule.selectors.iter().cloned());
+        }
+        self.stylesheets.push(stylesheet);
+    }
+
+    // Register the stylesheets that come with a document, from its '<style>' elements and
+    // '<link rel="stylesheet">' elements, in document order, as author stylesheets. 'fetch'
+    // loads linked and imported stylesheets given their URL; ones that fail to load are
+    // skipped. Returns the errors found while parsing them, with the URL of each one
+    // ('None' for the contents of a '<style>' element).
+    pub fn add_document_stylesheets<F>(&mut self, root: &Node, document_url: &str, fetch: &mut F) -> Vec<(Option<String>, ParseError)>
+            where F: FnMut(&str) -> Option<String> {
+        let base = root.base_url(document_url);
+        let mut sources = Vec::new();
+        document_stylesheets(root, &mut sources);
+        let mut errors = Vec::new();
+        for source in sources {
+            let (url, text) = match source {
+                StylesheetSource::Embedded(text) => (None, text),
+                StylesheetSource::Linked(href) => {
+                    let url = url::resolve(&base, &href);
+                    match fetch(&url) {
+                        Some(text) => (Some(url), text),
+                        None => continue,
+                    }
+                }
+            };
+            let (mut stylesheet, parse_errors) = css::parse(text);
+            // URLs in a '<style>' element are relative to the document, and URLs in a linked
+            // stylesheet to the stylesheet.
+            stylesheet.resolve_urls(url.as_ref().unwrap_or(&base));
+            errors.extend(parse_errors.into_iter().map(|error| (url.clone(), error)));
+            errors.extend(stylesheet.resolve_imports(fetch).into_iter().map(|(url, error)| (Some(url), error)));
+            self.add_stylesheet(stylesheet, Origin::Author);
+        }
+        errors
+    }
+
+    // Unregister the 'index'th registered stylesheet, returning it.
+    pub fn remove_stylesheet(&mut self, index: usize) -> Stylesheet {
+        let stylesheet = self.stylesheets.remove(index);
+        for rule in &stylesheet.rules {
+            self.invalidations.selectors.extend(rule.selectors.iter().cloned());
+        }
+        stylesheet
+    }
+
+    // All the registered stylesheets, in the order they were registered.
+    pub fn stylesheets(&self) -> &[Stylesheet] {
+        &self.stylesheets
+    }
+
+    pub fn media(&self) -> &Media {
+        &self.media
+    }
+
+    // Style for 'media' from now on, after the viewport is resized, say. Any rule's media
+    // queries can match differently, so the next 'restyle' restyles the whole tree.
+    pub fn set_media(&mut self, media: Media) {
+        self.media = media;
+        self.invalidations.subtrees.push(Vec::new());
+    }
+
+    // Apply the registered stylesheets to a DOM tree.
+    pub fn style<'a>(&self, root: &'a Node) -> StyledNode<'a> {
+        style::style_tree(root, &self.stylesheets, &self.media, &self.states)
+    }
+
+    // Note that the attributes of the element at 'path' (child indices from the root) have
+    // changed, for the next 'restyle'.
+    pub fn invalidate_attributes(&mut self, path: Vec<usize>) {
+        self.invalidations.attributes.push(path);
+    }
+
+    // Note that the children of the element at 'path' have changed, for the next 'restyle'.
+    pub fn invalidate_subtree(&mut self, path: Vec<usize>) {
+        self.invalidations.subtrees.push(path);
+    }
+
+    // Style 'root' again, where 'old' is its previous version styled by this engine, only
+    // restyling what the stylesheets registered or removed and the elements invalidated
+    // since the last restyle can affect. Every registered stylesheet counts as a change
+    // the first time.
+    pub fn restyle<'a>(&mut self, root: &'a Node, old: &StyledNode) -> StyledNode<'a> {
+        let styled = style::restyle_tree(root, old, &self.invalidations, &self.stylesheets, &self.media, &self.states);
+        self.invalidations = Invalidations::default();
+        styled
+    }
+
+    // The computed values of the element at 'path' (child indices from the root), or of its
+    // 'pseudo_element', like 'getComputedStyle' in the DOM. 'styled' is the document
+    // styled by this engine. See 'StyledNode::computed_values'.
+    pub fn computed_style(&self, styled: &StyledNode, path: &[usize],
+                          pseudo_element: Option<PseudoElement>) -> Option<BTreeMap<String, String>> {
+        styled.descendant(path, pseudo_element).map(|node| node.computed_values())
+    }
+
+    pub fn states(&self) -> &ElementStates {
+        &self.states
+    }
+
+    // Mark the element at 'path' (child indices from the root of 'root') as hovered, or
+    // no element if it's 'None'. 'styled' is 'root' styled by this engine, and the parts
+    // of it that the change can affect are restyled.
+    pub fn set_hover<'a>(&mut self, root: &'a Node, styled: &mut StyledNode<'a>, path: Option<Vec<usize>>) {
+        let old = std::mem::replace(&mut self.states.hover, path);
+        self.restyle_state_change(root, styled, old, self.states.hover.clone());
+    }
+
+    // Like 'set_hover', for the focused element.
+    pub fn set_focus<'a>(&mut self, root: &'a Node, styled: &mut StyledNode<'a>, path: Option<Vec<usize>>) {
+        let old = std::mem::replace(&mut self.states.focus, path);
+        self.restyle_state_change(root, styled, old, self.states.focus.clone());
+    }
+
+    // Like 'set_hover', for the active element (one being clicked, say).
+    pub fn set_active<'a>(&mut self, root: &'a Node, styled: &mut StyledNode<'a>, path: Option<Vec<usize>>) {
+        let old = std::mem::replace(&mut self.states.active, path);
+        self.restyle_state_change(root, styled, old, self.states.active.clone());
+    }
+
+    // Restyle what can change when a state moves from the element at 'old' to the one at
+    // 'new'. The elements that change are below the deepest node on both paths, but sibling
+    // combinators can reach their siblings, so that node's parent is restyled.
+    fn restyle_state_change<'a>(&self, root: &'a Node, styled: &mut StyledNode<'a>,
+                                old: Option<Vec<usize>>, new: Option<Vec<usize>>) {
+        if old == new {
+            return;
+        }
+        let mut common: Vec<usize> = match (old, new) {
+            (Some(old), Some(new)) => old.iter().zip(&new).take_while(|(a, b)| a == b).map(|(&a, _)| a).collect(),
+            _ => Vec::new(),
+        };
+        common.pop();
+        style::restyle_subtree(root, styled, &common, &self.stylesheets, &self.media, &self.states);
+    }
+}
+
+// Where one of a document's stylesheets comes from.
+enum StylesheetSource {
+    Embedded(String), // The contents of a '<style>' element
+    Linked(String), // The URL of a '<link rel="stylesheet">' element
+}
+
+// Add the stylesheets of 'node' and its descendants to 'sources', in document order.
+fn document_stylesheets(node: &Node, sources: &mut Vec<StylesheetSource>) {
+    if let NodeType::Element(ref elem) = node.node_type {
+        match &*elem.tag_name {
+            "style" => sources.push(StylesheetSource::Embedded(node.text_content())),
+            "link" => {
+                // 'rel' is a list of keywords, and alternate stylesheets aren't used by default.
+                let rel: Vec<String> = elem.attributes.get("rel").map_or(Vec::new(), |rel| {
+                    rel.split_whitespace().map(|keyword| keyword.to_ascii_lowercase()).collect()
+                });
+                if let Some(href) = elem.attributes.get("href") {
+                    if rel.iter().any(|keyword| keyword == "stylesheet") && !rel.iter().any(|keyword| keyword == "alternate") {
+                        sources.push(StylesheetSource::Linked(href.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for child in &node.children {
+        document_stylesheets(child, sources);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::{Unit, Value};
+    use crate::html;
+    use crate::testing::{media, same_styles, stylesheet};
+
+    #[test]
+    fn origins_cascade_in_order_and_revert_rolls_back() {
+        let root = html::parse("<div id=\"x\" class=\"a\"></div>".to_string()).0;
+        let mut engine = Engine::new(media());
+        engine.add_stylesheet(stylesheet("#x { width: 1px; height: 1px !important; margin: 1px; padding: 1px; } \
+                                          div { border-width: 1px; }"), Origin::UserAgent);
+        engine.add_stylesheet(stylesheet("#x { width: 2px; padding: 2px; } \
+                                          div { height: 2px !important; margin: 2px; border-width: 2px; }"), Origin::User);
+        engine.add_stylesheet(stylesheet("div { width: 3px; height: 3px !important; margin: revert; } \
+                                          .a { padding: revert; border-width: revert; }"), Origin::Author);
+        let div = engine.style(&root);
+        let px = |n| Some(Value::Length(n, Unit::Px));
+        assert!(div.value("width") == px(3.0));
+        assert!(div.value("height") == px(1.0));
+        assert!(div.value("margin-top") == px(2.0));
+        assert!(div.value("padding-top") == px(2.0));
+        assert!(div.value("border-top-width") == px(2.0));
+    }
+
+    #[test]
+    fn vendor_prefixed_properties_are_aliased() {
+        let mut engine = Engine::new(media());
+        engine.property_aliases.insert("-webkit-unknown".to_string(), "width".to_string());
+        engine.add_stylesheet(stylesheet("a { -webkit-transition: width 2s !important; -moz-box-sizing: border-box; \
+                                              -webkit-transition-delay: var(--d); -webkit-unknown: 1px; }"), Origin::Author);
+        let declarations = &engine.stylesheets()[0].rules[0].declarations;
+        let names: Vec<&str> = declarations.iter().map(|declaration| &*declaration.name).collect();
+        assert_eq!(names, vec!["transition-property", "transition-duration", "transition-timing-function",
+                               "transition-delay", "box-sizing", "transition-delay", "width"]);
+        assert!(declarations[1].important);
+    }
+
+    #[test]
+    fn element_states_restyle_what_they_match() {
+        let root = html::parse("<html><div><p></p><p></p></div><div><p></p></div></html>".to_string()).0;
+        let mut engine = Engine::new(media());
+        engine.add_stylesheet(stylesheet("div:hover { width: 1px; } div:hover > p { height: 2px; } p:focus { width: 3px; } \
+                                          p:focus + p { width: 4px; } :active { margin-top: 5px; } html:hover { height: 9px; }"),
+                              Origin::Author);
+        let px = |n| Some(Value::Length(n, Unit::Px));
+        let mut html = engine.style(&root);
+        assert!(html.children[0].value("width").is_none() && html.value("height").is_none());
+
+        engine.set_hover(&root, &mut html, Some(vec![0, 1]));
+        assert!(html.value("height") == px(9.0));
+        assert!(html.children[0].value("width") == px(1.0) && html.children[1].value("width").is_none());
+        assert!(html.children[0].children[0].value("height") == px(2.0));
+
+        engine.set_focus(&root, &mut html, Some(vec![0, 0]));
+        assert!(html.children[0].children[0].value("width") == px(3.0));
+        assert!(html.children[0].children[1].value("width") == px(4.0));
+
+        engine.set_hover(&root, &mut html, Some(vec![1]));
+        assert!(html.children[0].value("width").is_none() && html.children[1].value("width") == px(1.0));
+        assert!(html.children[0].children[0].value("height").is_none());
+        assert!(html.children[1].children[0].value("height") == px(2.0));
+        assert!(html.children[0].children[0].value("width") == px(3.0));
+
+        engine.set_focus(&root, &mut html, None);
+        assert!(html.children[0].children[0].value("width").is_none());
+        assert!(html.children[0].children[1].value("width").is_none());
+
+        engine.set_active(&root, &mut html, Some(vec![1, 0]));
+        assert!(html.value("margin-top") == px(5.0) && html.children[1].children[0].value("margin-top") == px(5.0));
+        assert!(html.children[0].value("margin-top").is_none());
+        assert!(engine.style(&root).children[1].value("width") == px(1.0));
+    }
+
+    #[test]
+    fn set_media_restyles_the_whole_tree() {
+        let root = html::parse("<div><p>text</p></div>".to_string()).0;
+        let mut engine = Engine::new(media());
+        engine.add_stylesheet(stylesheet("@media (min-width: 700px) { p { color: red; } }"), Origin::Author);
+        let old = engine.restyle(&root, &engine.style(&root));
+
+        engine.set_media(Media { width: 600.0, ..media() });
+        let restyled = engine.restyle(&root, &old);
+        assert!(same_styles(&restyled, &engine.style(&root)));
+        assert!(!same_styles(&restyled, &old));
+    }
+
+    #[test]
+    fn computed_style_of_elements_and_pseudo_elements() {
+        let root = html::parse("<div><p></p><span><i></i></span></div>".to_string()).0;
+        let mut engine = Engine::new(media());
+        engine.add_stylesheet(stylesheet("div { font-size: 10px; } p::before { content: 'x'; } i { width: 2em; } \
+                                          span::after { content: 'y'; color: #ff0000; }"), Origin::Author);
+        let div = engine.style(&root);
+        let i = engine.computed_style(&div, &[1, 0], None).unwrap();
+        assert_eq!(i.get("width").map(String::as_str), Some("20px"));
+        let after = engine.computed_style(&div, &[1], Some(css::PseudoElement::After)).unwrap();
+        assert!(after.contains_key("color"));
+        assert!(engine.computed_style(&div, &[2], None).is_none());
+        assert!(engine.computed_style(&div, &[1], Some(css::PseudoElement::Before)).is_none());
+    }
+
+    #[test]
+    fn document_stylesheets_are_added_in_order() {
+        let root = html::parse("<html><link rel='Stylesheet' href='a.css'><style>p { width: 2px; } 5 {}</style>\
+                                <link rel='alternate stylesheet' href='b.css'><link rel=stylesheet href=missing.css>\
+                                <p></p></html>".to_string()).0;
+        let mut engine = Engine::new(media());
+        let mut fetched = Vec::new();
+        let errors = engine.add_document_stylesheets(&root, "index.html", &mut |url| {
+            fetched.push(url.to_string());
+            match url {
+                "a.css" => Some("@import 'c.css'; p { width: 1px; height: 1px; }".to_string()),
+                "c.css" => Some("p { margin-top: 3px; }".to_string()),
+                _ => None,
+            }
+        });
+        assert_eq!(fetched, ["a.css", "c.css", "missing.css"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].0.is_none());
+        let px = |n| Some(Value::Length(n, Unit::Px));
+        let p = &engine.style(&root).children[4];
+        assert!(p.value("width") == px(2.0));
+        assert!(p.value("height") == px(1.0));
+        assert!(p.value("margin-top") == px(3.0));
+    }
+}
@@ -0,0 +1,295 @@
+use crate::css::{FontSource, Stylesheet};
+use crate::style::{self, FontStyle};
+
+// The measurements of a font's glyphs that text layout needs, read from a TrueType or
+// OpenType font file. Sizes are in font units, 'units_per_em' to the em.
+pub struct Metrics {
+    units_per_em: f32,
+    ascent: f32, // Above the baseline
+    descent: f32, // Below the baseline, as a positive number
+    line_gap: f32,
+    glyphs: Option<GlyphTables>, // 'None' for the fallback metrics
+}
+
+// Where the character map and advance widths are in the font file.
+struct GlyphTables {
+    data: Vec<u8>,
+    cmap: usize, // The offset of the Unicode character map subtable
+    cmap_format: u16, // 4 (for the Basic Multilingual Plane) or 12 (for all of Unicode)
+    hmtx: usize,
+    num_h_metrics: usize,
+}
+
+// Read big-endian numbers out of font data, or 'None' past the end.
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+fn i16_at(data: &[u8], offset: usize) -> Option<i16> {
+    u16_at(data, offset).map(|n| n as i16)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(((u16_at(data, offset)? as u32) << 16) | u16_at(data, offset + 2)? as u32)
+}
+
+impl Metrics {
+    // Read the metrics out of the data of a TrueType or OpenType ('.ttf' or '.otf') font.
+    pub fn parse(data: Vec<u8>) -> Result<Metrics, String> {
+        let table = |tag: &[u8]| -> Option<usize> {
+            let num_tables = u16_at(&data, 4)? as usize;
+            (0..num_tables).map(|i| 12 + 16 * i)
+                .find(|&record| data.get(record..record + 4) == Some(tag))
+                .and_then(|record| u32_at(&data, record + 8))
+                .map(|offset| offset as usize)
+        };
+        match u32_at(&data, 0) {
+            Some(0x00010000) | Some(0x4F54544F) => {} // TrueType, or 'OTTO' for OpenType
+            Some(0x774F4646) | Some(0x774F4632) => return Err("WOFF fonts aren't supported".to_string()),
+            _ => return Err("Not a TrueType or OpenType font".to_string()),
+        }
+        let missing = |name: &str| format!("The font has no usable '{}' table", name);
+        let head = table(b"head").ok_or_else(|| missing("head"))?;
+        let hhea = table(b"hhea").ok_or_else(|| missing("hhea"))?;
+        let hmtx = table(b"hmtx").ok_or_else(|| missing("hmtx"))?;
+        let cmap = table(b"cmap").ok_or_else(|| missing("cmap"))?;
+
+        // Use the character map for all of Unicode if there is one, or for the BMP if not.
+        let subtables: Vec<(u16, u16, usize)> = (0..u16_at(&data, cmap + 2).unwrap_or(0) as usize)
+            .filter_map(|i| {
+                let record = cmap + 4 + 8 * i;
+                let offset = cmap + u32_at(&data, record + 4)? as usize;
+                Some((u16_at(&data, record)?, u16_at(&data, offset)?, offset))
+            })
+            .collect();
+        let unicode = |format: u16| subtables.iter()
+            .find(|&&(platform, subtable_format, _)| (platform == 0 || platform == 3) && subtable_format == format)
+            .map(|&(_, _, offset)| (offset, format));
+        let (cmap, cmap_format) = unicode(12).or_else(|| unicode(4)).ok_or_else(|| missing("cmap"))?;
+
+        let read = || -> Option<Metrics> {
+            Some(Metrics {
+                units_per_em: u16_at(&data, head + 18)? as f32,
+                ascent: i16_at(&data, hhea + 4)? as f32,
+                descent: -i16_at(&data, hhea + 6)? as f32,
+                line_gap: i16_at(&data, hhea + 8)? as f32,
+                glyphs: Some(GlyphTables { data: Vec::new(), cmap, cmap_format, hmtx, num_h_metrics: u16_at(&data, hhea + 34)? as usize }),
+            })
+        };
+        let mut metrics = read().filter(|metrics| metrics.units_per_em > 0.0).ok_or_else(|| missing("head"))?;
+        metrics.glyphs.as_mut().unwrap().data = data;
+        Ok(metrics)
+    }
+
+    // Metrics for when there's no font to measure text with, which are roughly those of
+    // a typical proportional font.
+    pub fn fallback() -> Metrics {
+        Metrics { units_per_em: 1000.0, ascent: 800.0, descent: 200.0, line_gap: 0.0, glyphs: None }
+    }
+
+    // The distance from the baseline to the top of the font, at the font size 'size'.
+    pub fn ascent(&self, size: f32) -> f32 {
+        self.ascent * size / self.units_per_em
+    }
+
+    // The distance from the baseline to the bottom of the font.
+    pub fn descent(&self, size: f32) -> f32 {
+        self.descent * size / self.units_per_em
+    }
+
+    // The space the font wants between lines, added to the ascent and descent.
+    pub fn line_gap(&self, size: f32) -> f32 {
+        self.line_gap * size / self.units_per_em
+    }
+
+    // How far the character 'c' moves the pen along. Characters that aren't in the font
+    // get the width of its missing-glyph box.
+    pub fn advance(&self, c: char, size: f32) -> f32 {
+        let units = match self.glyphs {
+            Some(ref glyphs) => glyphs.advance(glyphs.glyph(c).unwrap_or(0)).unwrap_or(0) as f32,
+            None => if c == ' ' { self.units_per_em / 4.0 } else { self.units_per_em / 2.0 },
+        };
+        units * size / self.units_per_em
+    }
+
+    // The width of 'text', set in this font at 'size'.
+    pub fn measure(&self, text: &str, size: f32) -> f32 {
+        text.chars().map(|c| self.advance(c, size)).sum()
+    }
+}
+
+impl GlyphTables {
+    // The glyph for the character 'c', if the font has one.
+    fn glyph(&self, c: char) -> Option<u16> {
+        let (data, cmap, c) = (&self.data, self.cmap, c as u32);
+        let glyph = match self.cmap_format {
+            // Groups of consecutive characters mapped to consecutive glyphs.
+            12 => {
+                let groups = u32_at(data, cmap + 12)? as usize;
+                (0..groups).map(|i| cmap + 16 + 12 * i).find_map(|group| {
+                    let (start, end) = (u32_at(data, group)?, u32_at(data, group + 4)?);
+                    if (start..=end).contains(&c) { Some(u32_at(data, group + 8)? + c - start) } else { None }
+                })? as u16
+            }
+            // Segments of the BMP, with each segment mapped by adding a delta to the
+            // character, or through an array of glyphs.
+            _ => {
+                if c > 0xFFFF {
+                    return None;
+                }
+                let segments = u16_at(data, cmap + 6)? as usize / 2;
+                let end_codes = cmap + 14;
+                let start_codes = end_codes + 2 * segments + 2;
+                let deltas = start_codes + 2 * segments;
+                let range_offsets = deltas + 2 * segments;
+                let segment = (0..segments).find(|&i| u16_at(data, end_codes + 2 * i).is_some_and(|end| end as u32 >= c))?;
+                let start = u16_at(data, start_codes + 2 * segment)? as u32;
+                if c < start {
+                    return None;
+                }
+                let delta = u16_at(data, deltas + 2 * segment)?;
+                let range_offset = u16_at(data, range_offsets + 2 * segment)? as usize;
+                if range_offset == 0 {
+                    (c as u16).wrapping_add(delta)
+                } else {
+                    let glyph = u16_at(data, range_offsets + 2 * segment + range_offset + 2 * (c - start) as usize)?;
+                    if glyph == 0 { return None } else { glyph.wrapping_add(delta) }
+                }
+            }
+        };
+        Some(glyph).filter(|&glyph| glyph != 0)
+    }
+
+    // The advance width of 'glyph'. Glyphs after the last one listed have its width.
+    fn advance(&self, glyph: u16) -> Option<u16> {
+        let index = (glyph as usize).min(self.num_h_metrics.checked_sub(1)?);
+        u16_at(&self.data, self.hmtx + 4 * index)
+    }
+}
+
+// A font for one family, weight and style.
+struct Face {
+    family: String,
+    weight: u16,
+    style: FontStyle,
+    metrics: Metrics,
+}
+
+// The fonts loaded for a document, which text is laid out in.
+pub struct Fonts {
+    faces: Vec<Face>,
+    fallback: Metrics,
+}
+
+impl Default for Fonts {
+    fn default() -> Fonts {
+        Fonts::new()
+    }
+}
+
+impl Fonts {
+    pub fn new() -> Fonts {
+        Fonts { faces: Vec::new(), fallback: Metrics::fallback() }
+    }
+
+    // Make 'metrics' the font for 'family' in the given weight and style.
+    pub fn add(&mut self, family: &str, weight: u16, style: FontStyle, metrics: Metrics) {
+        self.faces.push(Face { family: family.to_string(), weight, style, metrics });
+    }
+
+    // Load the fonts the stylesheets' '@font-face' rules give, getting the files with
+    // 'fetch'. Each rule uses the first of its sources that can be fetched and read.
+    // Returns the problems with fonts that were fetched, along with their URLs.
+    pub fn load_font_faces<F>(&mut self, stylesheets: &[Stylesheet], fetch: &mut F) -> Vec<(String, String)>
+            where F: FnMut(&str) -> Option<Vec<u8>> {
+        let mut errors = Vec::new();
+        for font_face in stylesheets.iter().flat_map(|stylesheet| &stylesheet.font_faces) {
+            // Fonts installed on the system ('local()') aren't looked for.
+            let urls = font_face.src.iter().filter_map(|source| match *source {
+                FontSource::Url(ref url, _) => Some(url),
+                FontSource::Local(_) => None,
+            });
+            for url in urls {
+                let metrics = match fetch(url).map(Metrics::parse) {
+                    Some(Ok(metrics)) => metrics,
+                    Some(Err(error)) => {
+                        errors.push((url.clone(), error));
+                        continue;
+                    }
+                    None => continue,
+                };
+                self.add(&font_face.family, font_face.weight, font_face.style, metrics);
+                break;
+            }
+        }
+        errors
+    }
+
+    // The font to lay out text in 'font' with: the first family in its list that has a
+    // font loaded, in the closest style and weight there is, like the CSS font matching
+    // algorithm picks (roughly). If none of them do, the fallback metrics are used.
+    pub fn select(&self, font: &style::Font) -> &Metrics {
+        for family in &font.family {
+            let candidates = self.faces.iter().filter(|face| face.family.eq_ignore_ascii_case(family));
+            let best = candidates.min_by_key(|face| {
+                let style_distance = match (face.style, font.style) {
+                    (a, b) if a == b => 0,
+                    (FontStyle::Normal, _) | (_, FontStyle::Normal) => 2,
+                    _ => 1, // Italic and oblique can stand in for each other
+                };
+                (style_distance, (face.weight as i32 - font.weight as i32).abs())
+            });
+            if let Some(face) = best {
+                return &face.metrics;
+            }
+        }
+        &self.fallback
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+    use crate::testing::test_font;
+
+    fn font(weight: u16, family: &str) -> style::Font {
+        style::Font { size: 10.0, style: FontStyle::Italic, weight, family: vec!["Nope".to_string(), family.to_string()] }
+    }
+
+    #[test]
+    fn metrics_from_font_files() {
+        let metrics = Metrics::parse(test_font()).unwrap();
+        assert_eq!(metrics.measure("AB", 10.0), 13.0);
+        assert_eq!(metrics.measure("Cz", 10.0), 12.0);
+        assert_eq!((metrics.ascent(10.0), metrics.descent(10.0), metrics.line_gap(10.0)), (9.0, 3.0, 1.0));
+        assert!(Metrics::parse(b"wOF2xxxxxxxx".to_vec()).is_err());
+        assert!(Metrics::parse(vec![0, 1, 0, 0, 0, 0]).is_err());
+        assert_eq!(Metrics::fallback().measure("ab c", 16.0), 28.0);
+    }
+
+    #[test]
+    fn fonts_are_selected_by_family_and_weight() {
+        let mut fonts = Fonts::new();
+        fonts.add("Test", 400, FontStyle::Normal, Metrics::parse(test_font()).unwrap());
+        fonts.add("Test", 700, FontStyle::Normal, Metrics::fallback());
+        assert_eq!(fonts.select(&font(500, "test")).measure("A", 10.0), 6.0);
+        assert_eq!(fonts.select(&font(600, "test")).measure("A", 10.0), 5.0);
+        assert_eq!(fonts.select(&font(400, "other")).measure("A", 10.0), 5.0);
+    }
+
+    #[test]
+    fn font_faces_are_loaded_from_their_first_usable_source() {
+        let (stylesheet, _) = css::parse("@font-face { font-family: Test; src: url(a.woff2) format('woff2'), url(b.ttf), url(t.ttf) }".to_string());
+        let mut fonts = Fonts::new();
+        let errors = fonts.load_font_faces(&[stylesheet], &mut |url| match url {
+            "a.woff2" => Some(b"wOF2....".to_vec()),
+            "t.ttf" => Some(test_font()),
+            _ => None,
+        });
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "a.woff2");
+        assert_eq!(fonts.select(&font(400, "Test")).measure("A", 10.0), 6.0);
+    }
+
+}
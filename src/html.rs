@@ -1,12 +1,114 @@
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use crate::css::ParseError;
 use crate::dom;
+use crate::encoding::Encoding;
 
-struct Parser {
+// Elements that can't have any contents, so they have no end tag.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+];
+
+// Elements whose contents are raw text, which runs until their end tag with no tags or
+// character references in it.
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+// Elements whose end tag can be left out, since it's implied by what comes after them.
+const OPTIONAL_END_TAGS: [&str; 8] = ["p", "li", "tr", "td", "th", "tbody", "thead", "tfoot"];
+
+// Elements whose start tag ends an open '<p>'.
+const CLOSES_P: [&str; 31] = [
+    "address", "article", "aside", "blockquote", "dd", "details", "div", "dl", "dt", "fieldset",
+    "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr",
+    "li", "main", "menu", "nav", "ol", "p", "pre", "section", "ul",
+];
+
+// The SVG tag names with capitals in them, which HTML is written without.
+const SVG_TAG_NAMES: [&str; 5] = ["clipPath", "foreignObject", "linearGradient", "radialGradient", "textPath"];
+
+// A piece of markup, as read by 'Tokenizer'.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Doctype,
+    StartTag { name: String, attributes: dom::AttrMap, self_closing: bool },
+    EndTag { name: String },
+    Text(String), // With character references decoded, except in raw text elements
+    Comment(String),
+    ProcessingInstruction(String), // '<?target data?>', only read in XML
+}
+
+// Splits an HTML document into tokens, which it returns one at a time as an iterator,
+// each with its byte offset in the source. 'parse' builds a tree out of them, but they
+// can also be used as they come, without building a tree of the whole document.
+pub struct Tokenizer {
     pos: usize,
     input: String,
+    raw_text_element: Option<String>, // Set after the start tag of a raw text element
+    xml: bool, // Whether the source is XML, which is case-sensitive and stricter
+    errors: Vec<ParseError>,
+}
+
+impl Iterator for Tokenizer {
+    type Item = (Token, usize);
+
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let start = self.pos;
+        if self.eof() {
+            return None;
+        }
+        if let Some(name) = self.raw_text_element.take() {
+            let text = self.parse_raw_text(&name);
+            if !text.is_empty() {
+                return Some((Token::Text(text), start));
+            }
+        }
+        let token = if self.starts_with("<!--") {
+            self.parse_comment()
+        } else if self.xml && self.starts_with("<![CDATA[") {
+            self.parse_cdata()
+        } else if self.xml && self.starts_with("<?") {
+            self.parse_processing_instruction()
+        } else if self.starts_with_ignore_case("<!doctype") {
+            self.skip_tag();
+            Token::Doctype
+        } else if self.starts_with("<!") {
+            // Other markup starting with '<!', like '<![CDATA[ ... ]]>', is treated as a
+            // comment up to the next '>'.
+            self.pos += "<!".len();
+            let data = self.consume_while(|c| c != '>');
+            self.skip_tag();
+            self.error_at(start, "Unexpected <! markup, treated as a comment".to_string());
+            Token::Comment(data)
+        } else if self.starts_with("</") {
+            self.pos += "</".len();
+            let name = self.parse_tag_name();
+            self.skip_tag();
+            Token::EndTag { name }
+        } else if self.at_tag() {
+            self.parse_start_tag()?
+        } else {
+            self.parse_text()
+        };
+        Some((token, start))
+    }
 }
 
-impl Parser {
+impl Tokenizer {
+    pub fn new(source: String) -> Tokenizer {
+        Tokenizer { pos: 0, input: source, raw_text_element: None, xml: false, errors: Vec::new() }
+    }
+
+    // A tokenizer for XML, like XHTML and SVG documents. Names keep their case (and any
+    // namespace prefix), there are no raw text elements, and CDATA sections are text.
+    pub fn new_xml(source: String) -> Tokenizer {
+        Tokenizer { xml: true, ..Tokenizer::new(source) }
+    }
+
+    // The problems found in the tokens read so far.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
     // Read the current char without consuming it.
     fn next_char(&self) -> char  {
         self.input[self.pos..].chars().next().unwrap()
@@ -26,7 +128,7 @@ impl Parser {
     fn consume_char(&mut self) -> char {
         let mut iter = self.input[self.pos..].char_indices();
         let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
         self.pos += next_pos;
         return cur_char;
     }
@@ -46,100 +148,730 @@ impl Parser {
         self.consume_while(char::is_whitespace);
     }
 
-    // Parse a tag or attribute name.
+    // Note a problem at the byte offset 'pos'. Parsing carries on either way.
+    fn error_at(&mut self, pos: usize, message: String) {
+        self.errors.push(ParseError::new(&self.input, pos, message));
+    }
+
+    // Parse a tag name. These are case-insensitive in HTML, so they're lowercased. XML
+    // names can also have namespace prefixes, like 'svg:rect'.
     fn parse_tag_name(&mut self) -> String {
+        if self.xml {
+            return self.consume_while(|c| c.is_alphanumeric() || matches!(c, ':' | '-' | '_' | '.'));
+        }
         self.consume_while(|c| match c {
             'a'..='z' | 'A'..='Z' | '0'..='9' => true,
             _ => false
-        })
+        }).to_ascii_lowercase()
+    }
+
+    // Do the next characters start with the given ASCII string, ignoring case?
+    fn starts_with_ignore_case(&self, s: &str) -> bool {
+        self.input.as_bytes()[self.pos..].get(..s.len()).is_some_and(|next| next.eq_ignore_ascii_case(s.as_bytes()))
     }
 
-    // Parse a single node.
-    fn parse_node(&mut self) -> dom::Node {
-        match self.next_char() {
-            '<' => self.parse_element(),
-            _   => self.parse_text(),
+    // Is the next thing a tag or comment? A '<' followed by anything else is just text.
+    fn at_tag(&self) -> bool {
+        let mut chars = self.input[self.pos..].chars();
+        chars.next() == Some('<') && matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '/' || c == '!' || (self.xml && c == '?'))
+    }
+
+    // Parse a '<!-- ... -->' comment.
+    fn parse_comment(&mut self) -> Token {
+        let start = self.pos;
+        self.pos += "<!--".len();
+        let data = match self.input[self.pos..].find("-->") {
+            Some(end) => {
+                let data = self.input[self.pos..self.pos + end].to_string();
+                self.pos += end + "-->".len();
+                data
+            }
+            None => {
+                self.error_at(start, "Unterminated comment".to_string());
+                let data = self.input[self.pos..].to_string();
+                self.pos = self.input.len();
+                data
+            }
+        };
+        Token::Comment(data)
+    }
+
+    // Parse a '<![CDATA[ ... ]]>' section, whose contents are text that isn't decoded.
+    fn parse_cdata(&mut self) -> Token {
+        let start = self.pos;
+        self.pos += "<![CDATA[".len();
+        let end = self.input[self.pos..].find("]]>").map_or(self.input.len(), |end| self.pos + end);
+        let text = self.input[self.pos..end].to_string();
+        if end == self.input.len() {
+            self.error_at(start, "Unterminated CDATA section".to_string());
+            self.pos = end;
+        } else {
+            self.pos = end + "]]>".len();
         }
+        Token::Text(text)
     }
 
-    // Parse a text node.
-    fn parse_text(&mut self) -> dom::Node {
-        dom::text(self.consume_while(|c| c != '<'))
+    // Parse a '<?target data?>' processing instruction, like the '<?xml ...?>' declaration.
+    fn parse_processing_instruction(&mut self) -> Token {
+        let start = self.pos;
+        self.pos += "<?".len();
+        let end = self.input[self.pos..].find("?>").map_or(self.input.len(), |end| self.pos + end);
+        let data = self.input[self.pos..end].to_string();
+        if end == self.input.len() {
+            self.error_at(start, "Unterminated processing instruction".to_string());
+            self.pos = end;
+        } else {
+            self.pos = end + "?>".len();
+        }
+        Token::ProcessingInstruction(data)
     }
 
-    // Parse a single element, including its open tag, contents and closing tag.
-    fn parse_element(&mut self) -> dom::Node {
-        // Opening tag.
-        assert!(self.consume_char() == '<');
-        let tag_name = self.parse_tag_name();
-        let attrs = self.parse_attributes();
-        assert!(self.consume_char() == '>');
+    // Parse the text up to the next tag.
+    fn parse_text(&mut self) -> Token {
+        let start = self.pos;
+        let mut text = String::new();
+        while !self.eof() && !self.at_tag() {
+            if self.xml && self.next_char() == '<' {
+                self.error_at(self.pos, "Unescaped < in text".to_string());
+            }
+            text.push(self.consume_char());
+        }
+        self.check_references(start, &text);
+        Token::Text(decode_entities(&text))
+    }
 
-        // Contents
-        let children = self.parse_nodes();
+    // In XML, every '&' has to start a character reference, which the parser has to know.
+    fn check_references(&mut self, start: usize, text: &str) {
+        if !self.xml {
+            return;
+        }
+        for (i, _) in text.match_indices('&') {
+            let name = text[i + 1..].find(';').map(|end| &text[i + 1..i + 1 + end]);
+            if name.and_then(character_reference).is_none() {
+                self.error_at(start + i, "Unescaped & or unknown character reference".to_string());
+            }
+        }
+    }
 
-        // Closing tag
+    // Parse a start tag, ending in '>' or '/>'. A tag cut off by the end of the document
+    // is left out.
+    fn parse_start_tag(&mut self) -> Option<Token> {
+        let start = self.pos;
         assert!(self.consume_char() == '<');
-        assert!(self.consume_char() == '/');
-        assert!(self.parse_tag_name() == tag_name);
+        let name = self.parse_tag_name();
+        let attributes = self.parse_attributes();
+        if self.eof() {
+            self.error_at(start, format!("Unterminated <{}> tag", name));
+            return None;
+        }
+        let self_closing = self.starts_with("/>");
+        if self_closing {
+            self.consume_char();
+        }
         assert!(self.consume_char() == '>');
+        if !self_closing && !self.xml && RAW_TEXT_ELEMENTS.contains(&&*name) {
+            self.raw_text_element = Some(name.clone());
+        }
+        Some(Token::StartTag { name, attributes, self_closing })
+    }
 
-        return dom::elem(tag_name, attrs, children);
+    // Parse the contents of the raw text element 'name', up to its end tag.
+    fn parse_raw_text(&mut self, name: &str) -> String {
+        let end_tag = format!("</{}", name);
+        let mut text = String::new();
+        while !self.eof() && !self.starts_with_ignore_case(&end_tag) {
+            text.push(self.consume_char());
+        }
+        text
     }
 
-    // Parse a single name="value" pair.
+    // Consume the rest of a tag, up to and including its '>'.
+    fn skip_tag(&mut self) {
+        self.consume_while(|c| c != '>');
+        if !self.eof() {
+            self.consume_char();
+        }
+    }
+
+    // Parse an attribute name. Like tag names, these are lowercased in HTML.
+    fn parse_attr_name(&mut self) -> String {
+        let name = self.consume_while(|c| !c.is_whitespace() && !matches!(c, '>' | '/' | '=' | '"' | '\''));
+        if self.xml { name } else { name.to_ascii_lowercase() }
+    }
+
+    // Parse a single name="value" pair. The value can be left out, which is the same as
+    // an empty one.
     fn parse_attr(&mut self) -> (String, String) {
-        let name = self.parse_tag_name();
-        assert!(self.consume_char() == '=');
+        let start = self.pos;
+        let name = self.parse_attr_name();
+        self.consume_whitespace();
+        if self.eof() || self.next_char() != '=' {
+            if self.xml {
+                self.error_at(start, format!("Attribute {} has no value", name));
+            }
+            return (name, String::new());
+        }
+        self.consume_char();
+        self.consume_whitespace();
         let value = self.parse_attr_value();
         return (name, value);
     }
 
-    // Parse a quoted value.
+    // Parse a double-quoted, single-quoted or unquoted value, decoding any character
+    // references in it.
     fn parse_attr_value(&mut self) -> String {
-        let open_quote = self.consume_char();
-        assert!(open_quote == '"' || open_quote == '\'');
+        if self.eof() {
+            return String::new();
+        }
+        let start = self.pos;
+        let open_quote = self.next_char();
+        if open_quote != '"' && open_quote != '\'' {
+            if self.xml {
+                self.error_at(start, "Attribute values have to be quoted".to_string());
+            }
+            let value = self.consume_while(|c| !c.is_whitespace() && c != '>');
+            self.check_references(start, &value);
+            return decode_entities(&value);
+        }
+        self.consume_char();
         let value = self.consume_while(|c| c != open_quote);
-        assert!(self.consume_char() == open_quote);
-        return value;
+        if self.eof() {
+            self.error_at(start, "Unterminated attribute value".to_string());
+        } else {
+            self.consume_char();
+        }
+        self.check_references(start + 1, &value);
+        return decode_entities(&value);
     }
 
-    // Parse a list of name="value" pairs, separated by whitespace.
+    // Parse a list of name="value" pairs, separated by whitespace. If an attribute is
+    // repeated, the first value is used.
     fn parse_attributes(&mut self) -> dom::AttrMap {
         let mut attributes = HashMap::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '>' {
+            if self.eof() || self.next_char() == '>' || self.starts_with("/>") {
                 break;
             }
+            let start = self.pos;
+            if matches!(self.next_char(), '/' | '=' | '"' | '\'') {
+                let c = self.consume_char();
+                self.error_at(start, format!("Unexpected {:?} in tag", c));
+                continue;
+            }
             let (name, value) = self.parse_attr();
-            attributes.insert(name, value);
+            match attributes.entry(name) {
+                Entry::Occupied(entry) => self.error_at(start, format!("Duplicate attribute {}", entry.key())),
+                Entry::Vacant(entry) => { entry.insert(value); }
+            }
         }
         return attributes;
     }
+}
 
-    // Parse a sequence of sibling nodes.
+// Builds a DOM tree out of the tokens of a document.
+struct TreeBuilder {
+    tokenizer: Tokenizer,
+    next: Option<(Token, usize)>, // The next token, once it's been looked at
+    open_elements: Vec<String>, // The tag names of the elements being parsed, outermost first
+    namespace: dom::Namespace, // The namespace of the innermost element being parsed
+    errors: Vec<ParseError>,
+}
+
+impl TreeBuilder {
+    fn new(tokenizer: Tokenizer) -> TreeBuilder {
+        TreeBuilder { tokenizer, next: None, open_elements: Vec::new(), namespace: dom::Namespace::Html, errors: Vec::new() }
+    }
+
+    // All the problems found, from the tokenizer and the tree builder. Elements report
+    // being unclosed after their contents, so the errors are put in source order.
+    fn into_errors(mut self) -> Vec<ParseError> {
+        self.errors.append(&mut self.tokenizer.errors);
+        self.errors.sort_by_key(|error| error.offset);
+        self.errors
+    }
+
+    // Look at the next token without consuming it.
+    fn peek(&mut self) -> Option<&Token> {
+        if self.next.is_none() {
+            self.next = self.tokenizer.next();
+        }
+        self.next.as_ref().map(|(token, _)| token)
+    }
+
+    fn consume_token(&mut self) -> Option<(Token, usize)> {
+        self.peek();
+        self.next.take()
+    }
+
+    // Note a problem at the byte offset 'pos'. Parsing carries on either way.
+    fn error_at(&mut self, pos: usize, message: String) {
+        self.errors.push(ParseError::new(&self.tokenizer.input, pos, message));
+    }
+
+    // Parse a single node, or 'None' for a token that doesn't add one.
+    fn parse_node(&mut self) -> Option<dom::Node> {
+        match self.consume_token()? {
+            // Text is kept whitespace and all, since what happens to whitespace is up to the
+            // 'white-space' property.
+            (Token::Text(text), _) => if text.is_empty() { None } else { Some(dom::text(text)) },
+            (Token::Comment(data), _) => Some(dom::comment(data)),
+            // Processing instructions are for other programs, and don't add a node.
+            (Token::ProcessingInstruction(_), _) => None,
+            (Token::StartTag { name, attributes, self_closing }, start) => {
+                Some(self.parse_element(name, attributes, self_closing, start))
+            }
+            (Token::Doctype, _) | (Token::EndTag { .. }, _) => unreachable!(),
+        }
+    }
+
+    // Parse the contents and end tag of an element whose start tag has been read. Void
+    // elements and ones whose start tag ends in '/>' are empty, and have no end tag. In
+    // XML, there are no void elements, and every other element needs its end tag.
+    fn parse_element(&mut self, tag_name: String, mut attrs: dom::AttrMap, self_closing: bool, start: usize) -> dom::Node {
+        let xml = self.tokenizer.xml;
+        // An 'svg' element (with or without a prefix) starts an SVG image, and everything
+        // inside it is SVG too. In XML, the namespace can also be given with 'xmlns'.
+        let namespace = match attrs.get("xmlns") {
+            Some(namespace) if xml => if namespace == dom::SVG_NAMESPACE { dom::Namespace::Svg } else { dom::Namespace::Html },
+            _ if tag_name.rsplit(':').next() == Some("svg") => dom::Namespace::Svg,
+            _ => self.namespace,
+        };
+        // SVG names have capitals in them, which the HTML tokenizer has lowercased.
+        let node_name = if namespace == dom::Namespace::Svg && !xml {
+            attrs = attrs.into_iter().map(|(name, value)| (dom::svg_attribute_name(name), value)).collect();
+            svg_name(&SVG_TAG_NAMES, tag_name.clone())
+        } else {
+            tag_name.clone()
+        };
+        if self_closing || (!xml && namespace == dom::Namespace::Html && VOID_ELEMENTS.contains(&&*tag_name)) {
+            return dom::elem_ns(node_name, namespace, attrs, Vec::new());
+        }
+
+        // Contents. A newline straight after the start tag of a 'pre', 'listing' or
+        // 'textarea' is left out, so what's in it can start on the next line of the source.
+        self.open_elements.push(tag_name.clone());
+        if !xml && matches!(&*tag_name, "pre" | "listing" | "textarea") {
+            self.peek();
+            if let Some((Token::Text(ref mut text), ref mut offset)) = self.next {
+                if text.starts_with('\n') {
+                    text.remove(0);
+                    *offset += 1;
+                }
+            }
+        }
+        let parent_namespace = std::mem::replace(&mut self.namespace, namespace);
+        let mut children = self.parse_nodes();
+        self.namespace = parent_namespace;
+        self.open_elements.pop();
+        if tag_name == "table" && !xml {
+            children = wrap_table_rows(children);
+        }
+
+        // Closing tag. Without one, the element ends where its parent does (at the
+        // parent's closing tag or the end of the document), or where a start tag that
+        // implies its end is.
+        if matches!(self.peek(), Some(Token::EndTag { name }) if *name == tag_name) {
+            self.consume_token();
+        } else if xml || !OPTIONAL_END_TAGS.contains(&&*tag_name) {
+            self.error_at(start, format!("Unclosed <{}>", tag_name));
+        }
+
+        return dom::elem_ns(node_name, namespace, attrs, children);
+    }
+
+    // Does the start tag 'name' end one of the elements being parsed? A new list item ends
+    // the one before it, along with anything left open inside it, but not one in an outer
+    // list, so only elements up to the nearest boundary are looked at.
+    fn closed_by_start_tag(&self, name: &str) -> bool {
+        if self.tokenizer.xml {
+            return false;
+        }
+        let (closes, boundaries): (&[&str], &[&str]) = match name {
+            "li" => (&["li", "p"], &["ul", "ol", "table", "td", "th"]),
+            "td" | "th" => (&["td", "th"], &["tr", "table"]),
+            "tr" => (&["tr", "td", "th"], &["table"]),
+            "tbody" | "thead" | "tfoot" => (&["tbody", "thead", "tfoot", "tr", "td", "th"], &["table"]),
+            _ if CLOSES_P.contains(&name) => (&["p"], &["table", "td", "th", "button"]),
+            _ => return false,
+        };
+        for open in self.open_elements.iter().rev() {
+            if closes.contains(&&**open) {
+                return true;
+            }
+            if boundaries.contains(&&**open) {
+                return false;
+            }
+        }
+        false
+    }
+
+    // Parse a sequence of sibling nodes, up to the end tag of an element being parsed.
+    // End tags that don't close any of them are ignored.
     fn parse_nodes(&mut self) -> Vec<dom::Node> {
         let mut nodes = Vec::new();
         loop {
-            self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
-                break;
+            match self.peek() {
+                None => break,
+                Some(Token::EndTag { name }) => {
+                    let name = name.clone();
+                    if self.open_elements.contains(&name) {
+                        break;
+                    }
+                    let (_, start) = self.consume_token().unwrap();
+                    self.error_at(start, format!("Unexpected </{}>", name));
+                }
+                Some(Token::StartTag { name, .. }) => {
+                    let name = name.clone();
+                    if self.closed_by_start_tag(&name) {
+                        break;
+                    }
+                    nodes.extend(self.parse_node());
+                }
+                Some(Token::Doctype) => {
+                    // The DOCTYPE just says the document is HTML, and doesn't add a node.
+                    let (_, start) = self.consume_token().unwrap();
+                    if !self.open_elements.is_empty() || !nodes.is_empty() {
+                        self.error_at(start, "DOCTYPE should come before anything else".to_string());
+                    }
+                }
+                Some(_) => nodes.extend(self.parse_node()),
             }
-            nodes.push(self.parse_node());
         }
         return nodes;
     }
 }
 
-// Parse an HTML document and return the root element.
-pub fn parse(source: String) -> dom::Node {
-    let mut nodes = Parser { pos: 0, input: source }.parse_nodes();
-    
+// Replace character references like '&amp;' and '&#x2014;' with the characters they stand
+// for. Ones that aren't recognized are left as they are.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let c = reference.and_then(|(name, _)| character_reference(name));
+        match (c, reference) {
+            (Some(c), Some((_, end))) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// The character the reference '&name;' stands for, if it's one the parser knows.
+fn character_reference(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => name.strip_prefix('#')?.parse().ok(),
+            };
+            // Numbers that aren't characters stand for the replacement character.
+            Some(code.and_then(char::from_u32).filter(|&c| c != '\0').unwrap_or('\u{fffd}'))
+        }
+    }
+}
+
+// The SVG name that 'name' is a lowercased version of, if it's one with capitals in it.
+fn svg_name(names: &[&str], name: String) -> String {
+    match names.iter().find(|svg_name| svg_name.eq_ignore_ascii_case(&name)) {
+        Some(svg_name) => svg_name.to_string(),
+        None => name,
+    }
+}
+
+// Put rows that are directly inside a table, and the whitespace between them, into a
+// 'tbody', which their start tag implies.
+fn wrap_table_rows(children: Vec<dom::Node>) -> Vec<dom::Node> {
+    let is_row = |node: &dom::Node| matches!(node.node_type, dom::NodeType::Element(ref elem) if elem.tag_name == "tr");
+    let mut wrapped: Vec<dom::Node> = Vec::new();
+    let mut in_body = false; // Whether the last child is a 'tbody' made here
+    for child in children {
+        if is_row(&child) || in_body && is_whitespace(&child) {
+            if !in_body {
+                wrapped.push(dom::elem("tbody".to_string(), HashMap::new(), Vec::new()));
+                in_body = true;
+            }
+            wrapped.last_mut().unwrap().children.push(child);
+        } else {
+            wrapped.push(child);
+            in_body = false;
+        }
+    }
+    wrapped
+}
+
+// Is 'node' text that's only whitespace, like the indentation between elements?
+fn is_whitespace(node: &dom::Node) -> bool {
+    matches!(node.node_type, dom::NodeType::Text(ref text) if text.chars().all(|c| c.is_ascii_whitespace()))
+}
+
+// Parse an HTML document and return the root element, along with any problems found
+// in it. Malformed markup never stops the parser: unclosed elements are closed where
+// their parent ends, and stray end tags are ignored, so there's always a document.
+pub fn parse(source: String) -> (dom::Node, Vec<ParseError>) {
+    let mut builder = TreeBuilder::new(Tokenizer::new(source));
+    let mut nodes = builder.parse_nodes();
+
     // If the document contains a root element, just return it. Otherwise, create one.
-    if nodes.len() == 1 {
+    // Comments and whitespace around the root element are dropped, since they'd need
+    // somewhere to go.
+    let is_element = |node: &dom::Node| matches!(node.node_type, dom::NodeType::Element(_));
+    if nodes.iter().filter(|node| is_element(node)).count() == 1 &&
+       nodes.iter().all(|node| is_element(node) || is_whitespace(node) || matches!(node.node_type, dom::NodeType::Comment(_))) {
+        nodes.retain(is_element);
+    }
+    let root = if nodes.len() == 1 {
         nodes.swap_remove(0)
     } else {
         dom::elem("html".to_string(), HashMap::new(), nodes)
+    };
+    (root, builder.into_errors())
+}
+
+// Parse an XML document, like an XHTML or SVG one, and return its root element. Unlike
+// HTML, XML has to be well-formed, so the first problem in it is returned instead of a
+// repaired document.
+pub fn parse_xml(source: String) -> Result<dom::Node, ParseError> {
+    let mut builder = TreeBuilder::new(Tokenizer::new_xml(source));
+    let nodes = builder.parse_nodes();
+
+    // There has to be exactly one root element, with only comments and whitespace around it.
+    let mut root = None;
+    for node in nodes {
+        match node.node_type {
+            dom::NodeType::Element(_) if root.is_none() => root = Some(node),
+            dom::NodeType::Comment(_) => {}
+            _ if is_whitespace(&node) => {}
+            _ => {
+                let message = if root.is_none() { "Text before the root element" } else { "Content after the root element" };
+                builder.error_at(builder.tokenizer.input.len(), message.to_string());
+                break;
+            }
+        }
+    }
+    if root.is_none() {
+        builder.error_at(builder.tokenizer.input.len(), "No root element".to_string());
+    }
+    match builder.into_errors().into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(root.unwrap()),
+    }
+}
+
+// Parse an HTML document that hasn't been decoded yet, in the encoding given by its byte
+// order mark or '<meta charset>' (see 'sniff_encoding').
+pub fn parse_bytes(bytes: &[u8]) -> (dom::Node, Vec<ParseError>) {
+    parse(sniff_encoding(bytes).decode(bytes))
+}
+
+// Work out the encoding of an HTML document, like browsers do: a byte order mark wins,
+// then a '<meta charset>' (or '<meta http-equiv="Content-Type">') in the first 1024 bytes.
+// Without either, the document is UTF-8 if it's valid UTF-8, and windows-1252 if it isn't.
+pub fn sniff_encoding(bytes: &[u8]) -> Encoding {
+    if let Some(encoding) = Encoding::from_bom(bytes) {
+        return encoding;
+    }
+    // The declaration is ASCII in any encoding it can be read in, so the prefix can be
+    // tokenized without knowing the encoding yet.
+    let prefix = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]).into_owned();
+    for (token, _) in Tokenizer::new(prefix) {
+        if let Token::StartTag { ref name, ref attributes, .. } = token {
+            if name != "meta" { continue }
+            let label = attributes.get("charset").cloned().or_else(|| {
+                let is_content_type = attributes.get("http-equiv").is_some_and(|value| value.eq_ignore_ascii_case("content-type"));
+                attributes.get("content").filter(|_| is_content_type).and_then(|content| charset_from_content(content))
+            });
+            if let Some(encoding) = label.as_deref().and_then(Encoding::from_label) {
+                // A page that could declare itself UTF-16 in ASCII can't really be UTF-16.
+                return match encoding {
+                    Encoding::Utf16Le | Encoding::Utf16Be => Encoding::Utf8,
+                    encoding => encoding,
+                };
+            }
+        }
+    }
+    if std::str::from_utf8(bytes).is_ok() { Encoding::Utf8 } else { Encoding::Windows1252 }
+}
+
+// The charset in a Content-Type value, like 'text/html; charset=windows-1252'.
+fn charset_from_content(content: &str) -> Option<String> {
+    let start = content.to_ascii_lowercase().find("charset")? + "charset".len();
+    let value = content[start..].trim_start().strip_prefix('=')?.trim_start();
+    let value = value.trim_start_matches(['"', '\'']);
+    let end = value.find(|c: char| c == ';' || c == '"' || c == '\'' || c.is_whitespace()).unwrap_or(value.len());
+    Some(value[..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::NodeType;
+
+    // The shape of a DOM tree, like 'div(p(a),p(b))'.
+    fn outline(node: &dom::Node) -> String {
+        match node.node_type {
+            NodeType::Element(ref elem) => {
+                let children: Vec<String> = node.children.iter().map(outline).collect();
+                format!("{}({})", elem.tag_name, children.join(","))
+            }
+            NodeType::Text(ref text) => text.clone(),
+            NodeType::Comment(_) => "#".to_string(),
+        }
+    }
+
+    #[test]
+    fn implied_end_tags() {
+        let cases = [
+            ("<div><p>a<p>b<div>c</div></div>", "div(p(a),p(b),div(c))"),
+            ("<ul><li>a<li>b<ul><li>c<li>d</ul><li>e</ul>", "ul(li(a),li(b,ul(li(c),li(d))),li(e))"),
+            ("<table><tr><td>a<td>b<tr><td><p>c<td>d</table>", "table(tbody(tr(td(a),td(b)),tr(td(p(c)),td(d))))"),
+            ("<table><thead><tr><th>h<tbody><tr><td>x</table>", "table(thead(tr(th(h))),tbody(tr(td(x))))"),
+        ];
+        for &(source, expected) in cases.iter() {
+            let (root, errors) = parse(source.to_string());
+            assert_eq!(outline(&root), expected);
+            assert!(errors.is_empty(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn misnested_tags_are_closed_with_an_error() {
+        let (root, errors) = parse("<div><p><span>a<div>b</div></div>".to_string());
+        assert_eq!(outline(&root), "div(p(span(a)),div(b))");
+        assert_eq!(errors.len(), 1);
+        let (root, errors) = parse("<div><b><i>x</b></i> y</div>".to_string());
+        assert_eq!(outline(&root), "div(b(i(x)), y)");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn duplicate_and_malformed_attributes() {
+        let (root, errors) = parse("<div a=1 b c='x' a=2 =\"q\">< 3</div>".to_string());
+        let elem = match root.node_type {
+            NodeType::Element(ref elem) => elem,
+            _ => panic!("expected an element"),
+        };
+        assert_eq!(elem.attributes.get("a").map(String::as_str), Some("1"));
+        assert_eq!(elem.attributes.get("b").map(String::as_str), Some(""));
+        assert_eq!(elem.attributes.get("c").map(String::as_str), Some("x"));
+        assert_eq!(outline(&root.children[0]), "< 3");
+        assert_eq!(errors.len(), 4);
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let (_, errors) = parse("<div>\n  <span>".to_string());
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[1].line, errors[1].column), (2, 3));
+    }
+
+    #[test]
+    fn comments_and_doctype() {
+        let (root, errors) = parse("<!DOCTYPE html><div><!-- a -- b -->x<!---->y</div>".to_string());
+        assert_eq!(outline(&root), "div(#,x,#,y)");
+        match root.children[0].node_type {
+            NodeType::Comment(ref text) => assert_eq!(text, " a -- b "),
+            _ => panic!("expected a comment"),
+        }
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn void_elements_have_no_children() {
+        let (root, errors) = parse("<p>a<br>b<img src=x.png><wbr/><input disabled/></p>".to_string());
+        assert_eq!(outline(&root), "p(a,br(),b,img(),wbr(),input())");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn character_references() {
+        assert_eq!(decode_entities("a &amp; b &lt;&gt; &quot;&apos;"), "a & b <> \"'");
+        assert_eq!(decode_entities("&#65;&#x42;&#X43;&#0;&#x110000;"), "ABC\u{fffd}\u{fffd}");
+        assert_eq!(decode_entities("&bogus; & &amp"), "&bogus; & &amp");
+        let (root, _) = parse("<a title='x &amp; y'>&lt;p&gt;</a>".to_string());
+        match root.node_type {
+            NodeType::Element(ref elem) => assert_eq!(elem.attributes["title"], "x & y"),
+            _ => panic!("expected an element"),
+        }
+        assert_eq!(outline(&root), "a(<p>)");
+    }
+
+    #[test]
+    fn tokens_come_with_their_offsets() {
+        let tokens: Vec<(Token, usize)> = Tokenizer::new("<!doctype html><P Class=x>a &amp; b</p><!--c-->".to_string()).collect();
+        let mut attributes = dom::AttrMap::new();
+        attributes.insert("class".to_string(), "x".to_string());
+        assert_eq!(tokens, [
+            (Token::Doctype, 0),
+            (Token::StartTag { name: "p".to_string(), attributes, self_closing: false }, 15),
+            (Token::Text("a & b".to_string()), 26),
+            (Token::EndTag { name: "p".to_string() }, 35),
+            (Token::Comment("c".to_string()), 39),
+        ]);
+    }
+
+    #[test]
+    fn script_and_style_contents_are_raw_text() {
+        let (root, errors) = parse("<div><style>p > a { content: '&amp;' }</style><script>if (a<b) {}</SCRIPT>x</div>".to_string());
+        assert_eq!(outline(&root), "div(style(p > a { content: '&amp;' }),script(if (a<b) {}),x)");
+        assert!(errors.is_empty());
+        assert_eq!(root.children[0].text_content(), "p > a { content: '&amp;' }");
+    }
+
+    #[test]
+    fn encoding_from_meta_tag() {
+        assert_eq!(sniff_encoding(b"<meta charset=\"windows-1252\">"), Encoding::Windows1252);
+        assert_eq!(sniff_encoding(b"<meta http-equiv=Content-Type content='text/html; charset=\"utf-16\"'>"), Encoding::Utf8);
+        assert_eq!(sniff_encoding(b"<p>\xe9</p>"), Encoding::Windows1252);
+    }
+
+    #[test]
+    fn xml_keeps_case_and_needs_well_formed_markup() {
+        let root = parse_xml("<?xml version='1.0'?><Doc a='1'><Br/><![CDATA[<x> & y]]><p>&lt;</p></Doc>".to_string()).unwrap();
+        assert_eq!(outline(&root), "Doc(Br(),<x> & y,p(<))");
+        let errors = [
+            "<a><b></a>",
+            "<a>x < y</a>",
+            "<a>&bogus;</a>",
+            "<a b=1/>",
+            "<a b/>",
+            "<a/><b/>",
+            "text<a/>",
+            "<!-- only a comment -->",
+        ];
+        for source in errors.iter() {
+            assert!(parse_xml(source.to_string()).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn svg_elements_get_their_namespace_and_capitals() {
+        let (root, errors) = parse("<div><svg VIEWBOX='0 0 1 1'><lineargradient/><rect/></svg><p></p></div>".to_string());
+        assert!(errors.is_empty());
+        let elem = |node: &dom::Node| match node.node_type {
+            NodeType::Element(ref elem) => (elem.tag_name.clone(), elem.namespace, elem.attributes.keys().cloned().collect::<Vec<_>>()),
+            _ => panic!("expected an element"),
+        };
+        let svg = &root.children[0];
+        assert_eq!(elem(svg), ("svg".to_string(), dom::Namespace::Svg, vec!["viewBox".to_string()]));
+        assert_eq!(elem(&svg.children[0]).0, "linearGradient");
+        assert_eq!(elem(&svg.children[1]).1, dom::Namespace::Svg);
+        assert_eq!(elem(&root.children[1]).1, dom::Namespace::Html);
     }
 }
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use image::GenericImage;
+
+use crate::dom::{Node, NodeType};
+use crate::url;
+
+// The images loaded for a document's '<img>' elements, by the 'src' they're given as.
+// Only their sizes are kept, since what's in them isn't painted yet.
+#[derive(Default)]
+pub struct Images {
+    sizes: HashMap<String, (u32, u32)>,
+}
+
+impl Images {
+    pub fn new() -> Images {
+        Default::default()
+    }
+
+    // Decode 'data' as the image for 'src'. Returns why if it can't be decoded.
+    pub fn add(&mut self, src: &str, data: &[u8]) -> Result<(), String> {
+        let image = image::load_from_memory(data).map_err(|error| error.to_string())?;
+        self.sizes.insert(src.to_string(), image.dimensions());
+        Ok(())
+    }
+
+    // Load the images of the '<img>' elements in the document 'root', getting the files
+    // with 'fetch'. Their URLs are relative to 'document_url', or to its '<base href>'.
+    // Returns the problems with images that were fetched, along with their URLs.
+    pub fn load_images<F>(&mut self, root: &Node, document_url: &str, fetch: &mut F) -> Vec<(String, String)>
+            where F: FnMut(&str) -> Option<Vec<u8>> {
+        let base = root.base_url(document_url);
+        let mut errors = Vec::new();
+        for path in root.find_all(&|elem| elem.tag_name == "img" && elem.attributes.contains_key("src")) {
+            let src = match root.node_at(&path) {
+                Some(&Node { node_type: NodeType::Element(ref elem), .. }) => &elem.attributes["src"],
+                _ => continue,
+            };
+            if self.sizes.contains_key(src) {
+                continue;
+            }
+            let url = url::resolve(&base, src);
+            if let Some(data) = fetch(&url) {
+                if let Err(error) = self.add(src, &data) {
+                    errors.push((url, error));
+                }
+            }
+        }
+        errors
+    }
+
+    // The width and height of the image for 'src' in pixels, which are CSS 'px', if it's
+    // been loaded.
+    pub fn size(&self, src: &str) -> Option<(f32, f32)> {
+        self.sizes.get(src).map(|&(width, height)| (width as f32, height as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+    use crate::testing::test_png;
+
+    #[test]
+    fn images_are_loaded_once_relative_to_the_document() {
+        let root = html::parse("<img src=a.png><img src=a.png><img src=b.png><img src=missing.png><img>".to_string()).0;
+        let mut images = Images::new();
+        let mut fetched = Vec::new();
+        let errors = images.load_images(&root, "dir/page.html", &mut |url| {
+            fetched.push(url.to_string());
+            match url {
+                "dir/a.png" => Some(test_png(20, 10)),
+                "dir/b.png" => Some(b"nope".to_vec()),
+                _ => None,
+            }
+        });
+        assert_eq!(fetched, ["dir/a.png", "dir/b.png", "dir/missing.png"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "dir/b.png");
+        assert_eq!(images.size("a.png"), Some((20.0, 10.0)));
+        assert_eq!(images.size("b.png"), None);
+    }
+}
@@ -1,5 +1,13 @@
-use crate::css::{Value, Unit};
-use crate::style::{StyledNode};
+use crate::css::{Value, Unit, LengthContext, PseudoElement};
+use crate::style::{StyledNode, Display, Float, Clear, Position, Overflow, Sides, FlexDirection, FlexWrap, Alignment,
+                   FlexBasis, TrackSize, GridLine, TextAlign, OverflowWrap, VerticalAlign, LineHeight, ListStylePosition,
+                   Font, Media};
+use crate::dom::{NodeType, Namespace};
+use crate::svg::{self, IntrinsicSize};
+use crate::font::Fonts;
+use crate::images::Images;
+use std::collections::HashMap;
+use std::ops::Range;
 
 #[derive(Default, Clone, Copy)]
 pub struct Dimensions {
@@ -28,47 +36,269 @@ pub struct EdgeSizes {
     pub bottom: f32,
 }
 
+// The window a document is shown in: 'width' by 'height' device pixels, zoomed in by
+// 'zoom', so that a CSS px is 'zoom' device pixels across. Physical units like 'in' are
+// 'dpi' px to the inch.
+#[derive(Clone, Copy)]
+pub struct Viewport {
+    pub width: f32,
+    pub height: f32,
+    pub dpi: f32,
+    pub zoom: f32,
+}
+
+impl Viewport {
+    // The initial containing block, which the root box is laid out in, and which viewport
+    // units and media queries are relative to: the viewport, in px.
+    pub fn initial_containing_block(&self) -> Rect {
+        Rect { x: 0.0, y: 0.0, width: self.width / self.zoom, height: self.height / self.zoom }
+    }
+
+    // The media to style a document for in the viewport, like 'screen' or 'print'.
+    pub fn media(&self, media_type: &str) -> Media {
+        let block = self.initial_containing_block();
+        Media { media_type: media_type.to_string(), width: block.width, height: block.height, dpi: self.dpi }
+    }
+}
+
+#[derive(Clone)]
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
+    // How far the box's contents reach, which is how far it could be scrolled: its padding
+    // box, and what's in it that isn't clipped by a box in between. Set after layout.
+    pub scrollable_overflow: Rect,
+    baseline: f32, // How far a line box's baseline is below its top. Set during layout.
+    // Whether the box itself, or anything in it, has changed since the layout it's being
+    // laid out again after. Set by 'relayout'.
+    dirty: bool,
+    children_dirty: bool,
+    // The box's layout from before, for it to reuse if it's laid out in the same room, when
+    // nothing in it has changed. Set by 'relayout'.
+    previous: Option<Box<LayoutBox<'a>>>,
+    input: Option<LayoutInput>, // What the box was last laid out in
+}
+
+// What a box was given to lay it out in, and the dimensions it got, before anything it's
+// in moved it.
+#[derive(Clone, Copy)]
+struct LayoutInput {
+    containing_block: Rect,
+    ctx: LengthContext,
+    // Whether there were floats in its block formatting context that it goes around, or
+    // 'None' if it isn't in one.
+    floats: Option<bool>,
+    dimensions: Dimensions,
 }
 
 #[derive(Copy, Clone)]
 pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
     InlineNode(&'a StyledNode<'a>),
-    AnonymousBlock,
+    // An inline box that's put on a line whole, with its contents laid out like a block's.
+    InlineBlockNode(&'a StyledNode<'a>),
+    SvgNode(&'a StyledNode<'a>), // An SVG image, which is drawn rather than laid out inside
+    // An '<img>', with the size of the image it shows, or with none if that isn't loaded.
+    ImageNode(&'a StyledNode<'a>, IntrinsicSize),
+    // A block holding a run of the inline-level children of the box it's in, whose node it
+    // takes inherited properties like 'text-align' from.
+    AnonymousBlock(&'a StyledNode<'a>),
+    LineBox, // A line of an anonymous block's inline content, made during layout
+    // The bytes 'start..end' of a text node's text, on one line. Made during layout.
+    TextRun(&'a StyledNode<'a>, usize, usize),
+    // The parts of a table. Those without a node are anonymous, made to put table parts
+    // that aren't in the table, row or cell they go in into one.
+    TableNode(Option<&'a StyledNode<'a>>), // A 'table' or an 'inline-table'
+    TableRowGroupNode(&'a StyledNode<'a>),
+    TableRowNode(Option<&'a StyledNode<'a>>),
+    TableCellNode(Option<&'a StyledNode<'a>>),
+    FlexNode(&'a StyledNode<'a>), // A block whose children are laid out as flex items
+    GridNode(&'a StyledNode<'a>), // A block whose children are placed in the areas of a grid
+}
+
+// Lay out a styled tree in 'viewport'. Text is measured with the font metrics in 'fonts',
+// and images are the size they are in 'images'.
+pub fn layout_tree<'a>(node: &'a StyledNode<'a>, viewport: &Viewport, fonts: &Fonts, images: &Images) -> LayoutBox<'a> {
+    lay_out_root(node, build_layout_tree(node, false, images), viewport, fonts)
+}
+
+// Lay out a styled tree again after a style or DOM change, where 'old' is its layout from
+// before, in the same viewport and with the same fonts and images. The tree should be
+// restyled from the one 'old' was laid out from (with 'Engine::restyle'), since that's how
+// the elements that haven't changed are told apart: they keep their computed styles. Boxes
+// with nothing changed in them keep their layout from before, moved to where they go now,
+// if they're laid out in the same room, and only the rest are laid out from scratch.
+pub fn relayout<'a>(old: &LayoutBox, node: &'a StyledNode<'a>, viewport: &Viewport, fonts: &Fonts,
+                    images: &Images) -> LayoutBox<'a> {
+    let mut root_box = build_layout_tree(node, false, images);
+    // A root with 'display: none' was laid out as an anonymous block, which keeps its node.
+    let old_node = match old.box_type {
+        BoxType::AnonymousBlock(old_node) => old_node,
+        _ => old.get_style_node(),
+    };
+    let pairs = StylePairs::new(old_node, node);
+    root_box.mark_changes(old, &pairs);
+    root_box.take_previous(old, &pairs);
+    lay_out_root(node, root_box, viewport, fonts)
 }
 
-pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions) -> LayoutBox<'a> {
-    containing_block.content.height = 0.0;
+fn lay_out_root<'a>(node: &StyledNode, mut root_box: LayoutBox<'a>, viewport: &Viewport, fonts: &Fonts) -> LayoutBox<'a> {
+    // The root element's font size is the reference for 'rem' units.
+    let viewport_rect = viewport.initial_containing_block();
+    let ctx = LengthContext {
+        font_size: node.font.size,
+        root_font_size: node.font.size,
+        containing_width: viewport_rect.width,
+        containing_height: Some(viewport_rect.height),
+        viewport_width: viewport_rect.width,
+        viewport_height: viewport_rect.height,
+        dpi: viewport.dpi,
+    };
+
+    // The root box goes at the top of the initial containing block, which has no content
+    // above it yet.
+    let containing_block = Dimensions { content: Rect { height: 0.0, ..viewport_rect }, ..Default::default() };
 
-    let mut root_box = build_layout_tree(node);
-    root_box.layout(containing_block);
+    // The root box isn't in a block formatting context, and makes its own.
+    root_box.layout(containing_block, ctx, fonts, None);
+    root_box.apply_relative_offset(&ctx);
+    root_box.layout_positioned(viewport_rect, viewport_rect, &ctx, fonts);
+    root_box.calculate_scrollable_overflow();
     root_box
 }
 
-// Build the tree of LayoutBoxes, but don't perform any layout calculations yet.
-fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
-    // Create the root box
+// The nodes of a styled tree paired with those of an earlier styling of it, by where they
+// are in the tree. The children of nodes that have a different number of them now aren't
+// paired.
+struct StylePairs<'a, 'o> {
+    new_by_old: HashMap<*const StyledNode<'o>, &'a StyledNode<'a>>,
+    old_by_new: HashMap<*const StyledNode<'a>, &'o StyledNode<'o>>,
+}
+
+impl<'a, 'o> StylePairs<'a, 'o> {
+    fn new(old: &'o StyledNode<'o>, new: &'a StyledNode<'a>) -> StylePairs<'a, 'o> {
+        fn pair<'a, 'o>(pairs: &mut StylePairs<'a, 'o>, old: &'o StyledNode<'o>, new: &'a StyledNode<'a>) {
+            pairs.new_by_old.insert(old, new);
+            pairs.old_by_new.insert(new, old);
+            if old.children.len() == new.children.len() {
+                for (old, new) in old.children.iter().zip(&new.children) {
+                    pair(pairs, old, new);
+                }
+            }
+        }
+        let mut pairs = StylePairs { new_by_old: HashMap::new(), old_by_new: HashMap::new() };
+        pair(&mut pairs, old, new);
+        pairs
+    }
+
+    // Is 'new' the same as 'old' was, with the same style and text, and as many children?
+    // Restyling keeps the computed styles of elements that haven't changed.
+    fn unchanged(&self, old: &StyledNode<'o>, new: &StyledNode<'a>) -> bool {
+        self.old_by_new.get(&(new as *const _)).is_some_and(|&paired| std::ptr::eq(paired, old))
+            && std::ptr::eq(&*old.computed, &*new.computed)
+            && old.children.len() == new.children.len()
+            && old.text() == new.text()
+    }
+
+    // Is 'new' the same as the node it's paired with was?
+    fn unchanged_since(&self, new: &StyledNode<'a>) -> bool {
+        self.old_by_new.get(&(new as *const _)).is_some_and(|old| self.unchanged(old, new))
+    }
+}
+
+// Is the node taken out of the normal flow and positioned on its own?
+fn is_absolute(style_node: &StyledNode) -> bool {
+    matches!(style_node.computed.position, Position::Absolute | Position::Fixed)
+}
+
+// Build the tree of LayoutBoxes, but don't perform any layout calculations yet. A
+// 'blockified' node is a flex or grid item, which is block-level whatever its 'display' says.
+fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>, blockified: bool, images: &Images) -> LayoutBox<'a> {
+    // Create the root box. List items are laid out as blocks for now, and so are table
+    // captions. The root element always has a box, so 'display: contents' is treated as
+    // 'block' there, and so are table columns. Floats and absolutely positioned boxes are
+    // always blocks, even tables, table parts, and flex and grid containers. A list item's
+    // marker is inline, unless it's outside the item's box, where it's laid out on its own.
     let mut root = LayoutBox::new(match style_node.display() {
-        Block => BoxType::BlockNode(style_node),
-        Inline => BoxType::InlineNode(style_node),
-        DisplayNone => panic!("Root node has display: none.")
+        // A root that generates no boxes is laid out as an empty block.
+        Display::None => return LayoutBox::new(BoxType::AnonymousBlock(style_node)),
+        _ if svg::is_svg_root(style_node.node()) => BoxType::SvgNode(style_node),
+        _ if is_image(style_node) => BoxType::ImageNode(style_node, image_size(style_node, images)),
+        _ if style_node.pseudo_element == Some(PseudoElement::Marker) => match style_node.computed.list_style_position {
+            ListStylePosition::Outside => BoxType::InlineBlockNode(style_node),
+            ListStylePosition::Inside => BoxType::InlineNode(style_node),
+        },
+        _ if style_node.computed.float != Float::None || is_absolute(style_node) => BoxType::BlockNode(style_node),
+        display if blockified && (display.is_table_part() || matches!(display, Display::Inline | Display::InlineBlock)) => {
+            BoxType::BlockNode(style_node)
+        }
+        Display::Flex => BoxType::FlexNode(style_node),
+        Display::Grid => BoxType::GridNode(style_node),
+        Display::Block | Display::ListItem | Display::TableCaption |
+        Display::TableColumnGroup | Display::TableColumn | Display::Contents => BoxType::BlockNode(style_node),
+        Display::Inline => BoxType::InlineNode(style_node),
+        Display::InlineBlock => BoxType::InlineBlockNode(style_node),
+        Display::Table | Display::InlineTable => BoxType::TableNode(Some(style_node)),
+        Display::TableRowGroup | Display::TableHeaderGroup |
+        Display::TableFooterGroup => BoxType::TableRowGroupNode(style_node),
+        Display::TableRow => BoxType::TableRowNode(Some(style_node)),
+        Display::TableCell => BoxType::TableCellNode(Some(style_node)),
     });
 
-    // Create the descendant boxes.
-    for child in &style_node.children {
-        match child.display() {
-            Block => root.children.push(build_layout_tree(child)),
-            Inline => root.get_inline_container().children.push(build_layout_tree(child)),
-            DisplayNone => {} // Skip nodes with 'display: none'
-        }
+    // Create the descendant boxes. An SVG image's contents are part of the image, and an
+    // '<img>' has none.
+    if let BoxType::SvgNode(_) | BoxType::ImageNode(..) = root.box_type {
+        return root;
+    }
+    root.add_children(style_node, images);
+    // The text of generated content goes inside its box, unless that's an inline box, whose
+    // text it is.
+    if style_node.pseudo_element.is_some() && !matches!(root.box_type, BoxType::InlineNode(_)) {
+        root.get_inline_container().children.push(LayoutBox::new(BoxType::InlineNode(style_node)));
+    }
+    root.fix_table_structure();
+    root.move_marker_down();
+    if let BoxType::FlexNode(_) | BoxType::GridNode(_) = root.box_type {
+        // Flex and grid items are laid out and painted in 'order', and in the order they're in
+        // within that.
+        root.children.sort_by_key(|child| if child.is_anonymous() { 0 } else { child.get_style_node().computed.order });
     }
     return root;
 }
 
+// Is 'style_node' an HTML '<img>' element (rather than generated content for one)?
+fn is_image(style_node: &StyledNode) -> bool {
+    style_node.pseudo_element.is_none() && match style_node.node().node_type {
+        NodeType::Element(ref elem) => elem.namespace == Namespace::Html && elem.tag_name == "img",
+        _ => false,
+    }
+}
+
+// The size an '<img>' has of its own, which is its image's. An image that isn't loaded has
+// nothing to show, and takes no room unless it's given a size.
+fn image_size(style_node: &StyledNode, images: &Images) -> IntrinsicSize {
+    let size = match style_node.node().node_type {
+        NodeType::Element(ref elem) => elem.attributes.get("src").and_then(|src| images.size(src)),
+        _ => None,
+    };
+    match size {
+        Some((width, height)) => IntrinsicSize {
+            width: Some(width),
+            height: Some(height),
+            ratio: Some(width / height).filter(|_| height > 0.0),
+        },
+        None => IntrinsicSize { width: Some(0.0), height: Some(0.0), ratio: None },
+    }
+}
+
+// Is 'style_node' text that's only whitespace that collapses?
+fn is_collapsible_whitespace(style_node: &StyledNode) -> bool {
+    style_node.computed.white_space.collapses() && style_node.text().is_some_and(|text| {
+        text.chars().all(|c| c.is_ascii_whitespace() && (c != '\n' || !style_node.computed.white_space.keeps_newlines()))
+    })
+}
+
 impl<'a> LayoutBox<'a> {
     // Constructor function
     fn new(box_type: BoxType) -> LayoutBox {
@@ -76,185 +306,4082 @@ impl<'a> LayoutBox<'a> {
             box_type,
             dimensions: Default::default(), // Initially set all fields to 0.0
             children: Vec::new(),
+            scrollable_overflow: Default::default(),
+            baseline: 0.0,
+            dirty: false,
+            children_dirty: false,
+            previous: None,
+            input: None,
+        }
+    }
+
+    // Add boxes for the children of 'style_node'. An element with 'display: contents'
+    // has no box of its own, and its children's boxes go where its box would have been.
+    // A float or absolutely positioned box goes with the inline content it's in, if there
+    // is any. Each element in a flex or grid container is an item of its own, and runs of
+    // text go in anonymous blocks, which are items too. Whitespace that would collapse
+    // away, like the indentation between blocks, doesn't start a run of its own.
+    fn add_children(&mut self, style_node: &'a StyledNode<'a>, images: &Images) {
+        let items = matches!(self.box_type, BoxType::FlexNode(_) | BoxType::GridNode(_));
+        for child in &style_node.children {
+            let in_run = matches!(self.box_type, BoxType::InlineNode(_))
+                || matches!(self.children.last(), Some(&LayoutBox { box_type: BoxType::AnonymousBlock(_), .. }));
+            match child.display() {
+                Display::None => {} // Skip nodes with 'display: none'
+                _ if !in_run && is_collapsible_whitespace(child) => {}
+                // Columns would only set the widths of the cells in them, and don't have boxes.
+                Display::TableColumnGroup | Display::TableColumn => {}
+                Display::Contents => self.add_children(child, images),
+                _ if items && child.text().is_none() => self.children.push(build_layout_tree(child, true, images)),
+                _ if child.computed.float != Float::None || is_absolute(child) => match self.children.last() {
+                    Some(&LayoutBox { box_type: BoxType::AnonymousBlock(_), .. }) => {
+                        self.children.last_mut().unwrap().children.push(build_layout_tree(child, false, images));
+                    }
+                    _ => self.children.push(build_layout_tree(child, false, images)),
+                },
+                display if display.is_block_level() || display.is_table_part() => {
+                    self.children.push(build_layout_tree(child, false, images))
+                }
+                _ => self.get_inline_container().children.push(build_layout_tree(child, false, images)),
+            }
+        }
+    }
+
+    // An outside marker goes beside its list item's first line, which is in the item's first
+    // child if that's a block, rather than on a line of its own before it.
+    fn move_marker_down(&mut self) {
+        fn insert_marker<'a>(layout_box: &mut LayoutBox<'a>, marker: LayoutBox<'a>) {
+            match layout_box.children.first_mut() {
+                Some(first) if matches!(first.box_type, BoxType::AnonymousBlock(_)) => first.children.insert(0, marker),
+                Some(first) if first.is_in_flow_block() => insert_marker(first, marker),
+                _ => {
+                    let mut anonymous = LayoutBox::new(BoxType::AnonymousBlock(layout_box.get_style_node()));
+                    anonymous.children.push(marker);
+                    layout_box.children.insert(0, anonymous);
+                }
+            }
         }
+        let alone = match self.children.first() {
+            Some(first) => matches!(first.box_type, BoxType::AnonymousBlock(_))
+                && first.children.len() == 1 && first.children[0].is_outside_marker(),
+            None => false,
+        };
+        if alone && self.children.get(1).is_some_and(|next| next.is_in_flow_block()) {
+            let marker = self.children.remove(0).children.remove(0);
+            insert_marker(&mut self.children[0], marker);
+        }
+    }
+
+    // Is the box a block in the normal flow, with lines of its own inside it?
+    fn is_in_flow_block(&self) -> bool {
+        matches!(self.box_type, BoxType::BlockNode(_)) && self.float() == Float::None && !self.is_absolute()
+    }
+
+    // Is the box a list item's marker that goes outside the item's box?
+    fn is_outside_marker(&self) -> bool {
+        matches!(self.box_type, BoxType::InlineBlockNode(node) if node.pseudo_element == Some(PseudoElement::Marker))
     }
 
     // Where a new inline child should go.
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
-            BoxType::BlockNode(_) => {
+            BoxType::InlineNode(_) | BoxType::SvgNode(_) | BoxType::ImageNode(..) | BoxType::AnonymousBlock(_) |
+            BoxType::LineBox | BoxType::TextRun(..) => self,
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) | BoxType::TableNode(_) |
+            BoxType::TableRowGroupNode(_) | BoxType::TableRowNode(_) | BoxType::TableCellNode(_) |
+            BoxType::FlexNode(_) | BoxType::GridNode(_) => {
                 // If we've just generated an anonymous block box, keep using it.
                 // Otherwise, create a new one.
                 match self.children.last() {
-                    Some(&LayoutBox { box_type: AnonymousBlock,..}) => {}
-                    _ => self.children.push(LayoutBox::new(BoxType::AnonymousBlock))
+                    Some(&LayoutBox { box_type: BoxType::AnonymousBlock(_),..}) => {}
+                    _ => self.children.push(LayoutBox::new(BoxType::AnonymousBlock(self.get_style_node())))
                 }
                 self.children.last_mut().unwrap()
             }
         }
     }
 
+    // Put each run of children that 'wrapped' picks out into a new box of type 'box_type',
+    // and fix up the structure of what's in those in turn.
+    fn wrap_children(&mut self, wrapped: impl Fn(&LayoutBox) -> bool, box_type: BoxType<'a>) {
+        let mut children: Vec<LayoutBox<'a>> = Vec::new();
+        let mut wrappers = Vec::new(); // Where the new boxes are among the children
+        for child in std::mem::take(&mut self.children) {
+            if !wrapped(&child) {
+                children.push(child);
+                continue;
+            }
+            if wrappers.last().map(|&i| i + 1) != Some(children.len()) {
+                wrappers.push(children.len());
+                children.push(LayoutBox::new(box_type));
+            }
+            children.last_mut().unwrap().children.push(child);
+        }
+        for i in wrappers {
+            children[i].fix_table_structure();
+        }
+        self.children = children;
+    }
+
+    // Give the table parts among the box's children the structure a table needs: a row's
+    // contents go in cells, a table's or row group's contents go in rows, and table parts
+    // anywhere else go in a table, with the cells in rows. A table's first header group
+    // goes above its other rows, and its first footer group below them.
+    fn fix_table_structure(&mut self) {
+        let is_row = |child: &LayoutBox| matches!(child.box_type, BoxType::TableRowNode(_));
+        let is_table_child = |child: &LayoutBox| match child.box_type {
+            BoxType::TableRowGroupNode(_) | BoxType::TableRowNode(_) => true,
+            BoxType::BlockNode(node) => node.display() == Display::TableCaption && child.float() == Float::None && !child.is_absolute(),
+            _ => false,
+        };
+        match self.box_type {
+            BoxType::TableNode(_) => {
+                self.wrap_children(|child| !is_table_child(child), BoxType::TableRowNode(None));
+                let group = |children: &[LayoutBox], display: Display| children.iter().position(|child| {
+                    matches!(child.box_type, BoxType::TableRowGroupNode(node) if node.display() == display)
+                });
+                if let Some(i) = group(&self.children, Display::TableHeaderGroup) {
+                    let header = self.children.remove(i);
+                    self.children.insert(0, header);
+                }
+                if let Some(i) = group(&self.children, Display::TableFooterGroup) {
+                    let footer = self.children.remove(i);
+                    self.children.push(footer);
+                }
+            }
+            BoxType::TableRowGroupNode(_) => self.wrap_children(|child| !is_row(child), BoxType::TableRowNode(None)),
+            BoxType::TableRowNode(_) => {
+                self.wrap_children(|child| !matches!(child.box_type, BoxType::TableCellNode(_)), BoxType::TableCellNode(None))
+            }
+            _ => {
+                self.wrap_children(|child| matches!(child.box_type, BoxType::TableCellNode(_)), BoxType::TableRowNode(None));
+                self.wrap_children(is_table_child, BoxType::TableNode(None));
+            }
+        }
+    }
+
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
-            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
-            BoxType::AnonymousBlock => panic!("Anonymous block box has no style node")
+            BoxType::BlockNode(node) | BoxType::InlineNode(node) | BoxType::InlineBlockNode(node) |
+            BoxType::SvgNode(node) | BoxType::ImageNode(node, _) | BoxType::TextRun(node, ..) | BoxType::TableNode(Some(node)) |
+            BoxType::TableRowGroupNode(node) | BoxType::TableRowNode(Some(node)) | BoxType::TableCellNode(Some(node)) |
+            BoxType::FlexNode(node) | BoxType::GridNode(node) => node,
+            BoxType::AnonymousBlock(_) | BoxType::LineBox | BoxType::TableNode(None) | BoxType::TableRowNode(None) |
+            BoxType::TableCellNode(None) => panic!("Anonymous box has no style node")
         }
     }
 
-    // Lay out a box and its descendants.
-    fn layout(&mut self, containing_block: Dimensions) {
+    // Is the box anonymous, and so without a node of its own?
+    fn is_anonymous(&self) -> bool {
+        matches!(self.box_type, BoxType::AnonymousBlock(_) | BoxType::LineBox | BoxType::TableNode(None) |
+                 BoxType::TableRowNode(None) | BoxType::TableCellNode(None))
+    }
+
+    // Does the box take up a line of its own when it's in an inline box?
+    fn is_block_in_inline(&self) -> bool {
+        matches!(self.box_type, BoxType::BlockNode(_) | BoxType::TableNode(_) | BoxType::FlexNode(_) | BoxType::GridNode(_))
+            && !self.is_inline_table()
+    }
+
+    // Is the box a table that goes on a line, like an inline block?
+    fn is_inline_table(&self) -> bool {
+        matches!(self.box_type, BoxType::TableNode(Some(node)) if node.display() == Display::InlineTable)
+    }
+
+    // How the box floats, if it's a float.
+    // An absolutely positioned box doesn't float.
+    pub fn float(&self) -> Float {
         match self.box_type {
-            BoxType::BlockNode(_) => self.layout_block(containing_block),
-            BoxType::InlineNode(_) => {} // Todo
-            BoxType::AnonymousBlock => {} // Todo
+            BoxType::BlockNode(node) if !is_absolute(node) => node.computed.float,
+            _ => Float::None,
         }
     }
 
-    fn layout_block(&mut self, containing_block: Dimensions) {
-        // Child width can depend on parent width so we need to calculate
-        // this box's width before laying out its children.
-        self.calculate_block_width(containing_block);
+    pub fn position(&self) -> Position {
+        match self.box_type {
+            _ if self.is_anonymous() => Position::Static,
+            _ => self.get_style_node().computed.position,
+        }
+    }
 
-        // Determine where the box is located within its container.
-        self.calculate_block_position(containing_block);
+    // The 'z-index' of the box if it makes a stacking context, which positioned boxes with
+    // a 'z-index' other than 'auto' do.
+    pub fn z_index(&self) -> Option<i32> {
+        match self.position() {
+            Position::Static => None,
+            _ => self.get_style_node().computed.z_index,
+        }
+    }
 
-        // Recursively lay out the children of this box.
-        self.layout_block_children();
+    fn is_absolute(&self) -> bool {
+        matches!(self.position(), Position::Absolute | Position::Fixed)
+    }
 
-        // Parent height can depend on child height, so 'calculate_height'
-        // must be called *after* the children are laid out.
-        self.calculate_block_height();
+    // What the box does with contents that go outside it. Only block containers clip them.
+    pub fn overflow(&self) -> Overflow {
+        match self.box_type {
+            BoxType::BlockNode(node) | BoxType::InlineBlockNode(node) | BoxType::FlexNode(node) |
+            BoxType::GridNode(node) | BoxType::TableCellNode(Some(node)) => node.computed.overflow,
+            _ => Overflow::Visible,
+        }
+    }
+
+    // Does the box lay out its contents around floats of its own, rather than around
+    // those of the block formatting context it's in?
+    fn establishes_formatting_context(&self) -> bool {
+        matches!(self.box_type, BoxType::InlineBlockNode(_)) || self.float() != Float::None || self.is_absolute() ||
+            !matches!(self.overflow(), Overflow::Visible | Overflow::Clip)
     }
 
-    fn calculate_block_width(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+    // Lay out a box and its descendants, around the floats placed so far in 'floats', those
+    // of the block formatting context it's in ('None' if it isn't in one).
+    fn layout(&mut self, containing_block: Dimensions, ctx: LengthContext, fonts: &Fonts, floats: Option<&mut Floats>) {
+        // A box that hasn't changed since it was laid out before keeps that layout if it's
+        // in the same room, unless there are floats in the way that it goes around.
+        let beside_floats = floats.as_ref().map(|floats| self.goes_around_floats() && !floats.boxes.is_empty());
+        if let Some(previous) = self.previous.take() {
+            let same_room = previous.input.is_some_and(|input| {
+                input.containing_block.width == containing_block.content.width && input.ctx == ctx
+                    && input.floats == beside_floats
+            });
+            if same_room && beside_floats != Some(true) {
+                self.reuse(*previous, containing_block.content);
+                return;
+            }
+        }
+        match self.box_type {
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) => self.layout_block(containing_block, ctx, fonts, floats),
+            BoxType::InlineNode(_) => {} // Laid out by the anonymous block it's in
+            BoxType::SvgNode(_) | BoxType::ImageNode(..) => self.layout_replaced(containing_block, ctx),
+            BoxType::AnonymousBlock(_) => {
+                self.layout_inline_children(containing_block, ctx, fonts, floats.unwrap_or(&mut Floats::default()))
+            }
+            BoxType::LineBox | BoxType::TextRun(..) => {} // Made by laying out an anonymous block
+            BoxType::TableNode(_) => self.layout_table(containing_block, ctx, fonts),
+            BoxType::FlexNode(_) | BoxType::GridNode(_) => self.layout_container(containing_block, ctx, fonts),
+            // Laid out by the table they're in
+            BoxType::TableRowGroupNode(_) | BoxType::TableRowNode(_) | BoxType::TableCellNode(_) => {}
+        }
+        if let BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) | BoxType::SvgNode(_) | BoxType::ImageNode(..) |
+               BoxType::AnonymousBlock(_) | BoxType::TableNode(_) | BoxType::FlexNode(_) | BoxType::GridNode(_) = self.box_type {
+            self.input = Some(LayoutInput {
+                containing_block: containing_block.content,
+                ctx,
+                floats: beside_floats,
+                dimensions: self.dimensions,
+            });
+        }
+    }
 
-        // 'width' has initial value 'auto'.
-        let auto = Value::Keyword("auto".to_string());
-        let mut width = style.value("width").unwrap_or(auto.clone());
+    // How many boxes there are in the box, counting itself, if there are no floats or
+    // absolutely positioned boxes in it, which would make its layout depend on what's
+    // around it.
+    #[cfg(feature = "parallel")]
+    fn self_contained_size(&self) -> Option<usize> {
+        if self.float() != Float::None || self.is_absolute() {
+            return None;
+        }
+        self.children.iter().try_fold(1, |size, child| Some(size + child.self_contained_size()?))
+    }
 
-        // Margin, border and padding have initial value 0.
-        let zero = Value::Length(0.0, Unit::Px);
+    // Does where the box's contents go depend on the floats in the block formatting context
+    // it's in?
+    fn goes_around_floats(&self) -> bool {
+        matches!(self.box_type, BoxType::BlockNode(_) | BoxType::AnonymousBlock(_)) && !self.establishes_formatting_context()
+    }
 
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
+    // Lay out an anonymous block's inline-level children in line boxes, which replace them
+    // as its children, followed by the floats among them. The block goes below the previous
+    // content and takes the full width of its containing block, and the lines wrap at that
+    // width, less the room floats take, and stack inside it.
+    fn layout_inline_children(&mut self, containing_block: Dimensions, ctx: LengthContext, fonts: &Fonts,
+                              floats: &mut Floats) {
+        let ctx = LengthContext { containing_width: containing_block.content.width, ..ctx };
+        let d = &mut self.dimensions;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+        d.content.width = containing_block.content.width;
 
-        let border_left = style.lookup("border-left-width", "border-width", &zero);
-        let border_right = style.lookup("border-right-width", "border-width", &zero);
+        let container = match self.box_type {
+            BoxType::AnonymousBlock(node) => node,
+            _ => return, // Only anonymous blocks have inline-level children of their own
+        };
+        let mut lines = LineBuilder::new(d.content, container, ctx, fonts, floats);
+        for child in std::mem::take(&mut self.children) {
+            lines.place(child);
+        }
+        self.children = lines.finish();
+        for child in &mut self.children {
+            child.apply_line_offsets(&ctx);
+        }
+        d.content.height = self.children.iter()
+            .filter(|child| matches!(child.box_type, BoxType::LineBox))
+            .map(|line| line.dimensions.content.height)
+            .sum();
+    }
 
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
+    // Move a relatively positioned box by its offsets from where the normal flow put it,
+    // without moving anything else. 'left' wins over 'right', and 'top' over 'bottom'.
+    // Percentages of the containing block's height are taken as 'auto' if it isn't known
+    // before its contents are laid out.
+    fn apply_relative_offset(&mut self, ctx: &LengthContext) {
+        let style_node = match self.box_type {
+            _ if self.is_anonymous() => return,
+            _ => self.get_style_node(),
+        };
+        let style = &style_node.computed;
+        if style.position != Position::Relative {
+            return;
+        }
+        let ctx = LengthContext { font_size: style_node.font.size, ..*ctx };
+        let offset = |value: &Option<Value>| value.as_ref().map(|value| value.resolve(&ctx));
+        let vertical = |value: &Option<Value>| match *value {
+            Some(ref value @ Value::Length(..)) => Some(value.resolve(&ctx)),
+            Some(ref value) => ctx.containing_height.map(|containing_height| {
+                value.resolve(&LengthContext { containing_width: containing_height, ..ctx })
+            }),
+            None => None,
+        };
+        let offsets = &style.offsets;
+        let dx = offset(&offsets.left).or(offset(&offsets.right).map(|right| -right)).unwrap_or(0.0);
+        let dy = vertical(&offsets.top).or(vertical(&offsets.bottom).map(|bottom| -bottom)).unwrap_or(0.0);
+        self.translate(dx, dy);
+    }
 
-        let total: f32 = [&margin_left, &margin_right, &border_left, &border_right,
-                     &padding_left, &padding_right, &width].iter().map(|v| v.to_px()).sum();
+    // Where the baseline of the box's last line is, if it has one in the flow: of the last
+    // line it holds that has anything on it, or of the last block in it that has one.
+    fn last_baseline(&self) -> Option<f32> {
+        match self.box_type {
+            BoxType::LineBox if self.children.iter().any(|child| !child.is_absolute()) => {
+                Some(self.dimensions.content.y + self.baseline)
+            }
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) | BoxType::AnonymousBlock(_) => self.children.iter().rev()
+                .filter(|child| child.float() == Float::None && !child.is_absolute())
+                .find_map(|child| child.last_baseline()),
+            _ => None,
+        }
+    }
 
-        if width != auto && total > containing_block.content.width {
-            if margin_left == auto {
-                margin_left = Value::Length(0.0, Unit::Px);
+    // Apply the relative offsets of the boxes on a line (or of a float beside it), including
+    // the inline boxes they're in.
+    fn apply_line_offsets(&mut self, ctx: &LengthContext) {
+        self.apply_relative_offset(ctx);
+        if let BoxType::LineBox | BoxType::InlineNode(_) = self.box_type {
+            for child in &mut self.children {
+                child.apply_line_offsets(ctx);
             }
-            if margin_right == auto {
-                margin_right = Value::Length(0.0, Unit::Px);
+        }
+    }
+
+    // Lay out the absolutely positioned boxes inside this one, now that the boxes they're
+    // positioned against have been laid out. 'containing_block' is the padding box of the
+    // nearest positioned box this one is in, or the viewport, which fixed boxes go in.
+    fn layout_positioned(&mut self, containing_block: Rect, viewport: Rect, ctx: &LengthContext, fonts: &Fonts) {
+        let containing_block = match self.position() {
+            Position::Static => containing_block,
+            _ => self.dimensions.padding_box(),
+        };
+        for child in &mut self.children {
+            match child.position() {
+                Position::Absolute => child.layout_absolute(containing_block, *ctx, fonts),
+                Position::Fixed => child.layout_absolute(viewport, *ctx, fonts),
+                Position::Static | Position::Relative => {}
             }
+            child.layout_positioned(containing_block, viewport, ctx, fonts);
         }
+    }
 
-        let underflow = containing_block.content.width - total;
+    // Lay out an absolutely positioned box in 'containing_block', from its offsets, size and
+    // margins. If both its offsets on an axis are 'auto', it goes where it would have been
+    // in the normal flow. An 'auto' width is the rest of the room if both horizontal offsets
+    // are set, and shrinks to fit if not; an 'auto' height is its contents' height unless
+    // both vertical offsets are set. If every size and offset on an axis is set, 'auto'
+    // margins share what room there's left over.
+    fn layout_absolute(&mut self, containing_block: Rect, ctx: LengthContext, fonts: &Fonts) {
+        let style_node = self.get_style_node();
+        let style = &style_node.computed;
+        let ctx = LengthContext {
+            font_size: style_node.font.size,
+            containing_width: containing_block.width,
+            containing_height: Some(containing_block.height),
+            ..ctx
+        };
+        // Vertical percentages are of the containing block's height.
+        let vertical_ctx = LengthContext { containing_width: containing_block.height, ..ctx };
+        let resolve = |value: &Option<Value>, ctx: &LengthContext| value.as_ref().map(|value| value.resolve(ctx));
+        let replaced = match self.box_type {
+            BoxType::SvgNode(_) | BoxType::ImageNode(..) => Some(self.replaced_size(&ctx)),
+            _ => None,
+        };
+        let static_position = self.dimensions.content;
+
+        let d = &mut self.dimensions;
+        d.border.left = style.border_width.left.resolve(&ctx);
+        d.border.right = style.border_width.right.resolve(&ctx);
+        d.border.top = style.border_width.top.resolve(&ctx);
+        d.border.bottom = style.border_width.bottom.resolve(&ctx);
+        d.padding.left = style.padding.left.resolve(&ctx);
+        d.padding.right = style.padding.right.resolve(&ctx);
+        d.padding.top = style.padding.top.resolve(&ctx);
+        d.padding.bottom = style.padding.bottom.resolve(&ctx);
+
+        // The horizontal axis.
+        let edges = d.border.left + d.border.right + d.padding.left + d.padding.right;
+        let (mut margin_left, mut margin_right) = (resolve(&style.margin.left, &ctx), resolve(&style.margin.right, &ctx));
+        let mut left = resolve(&style.offsets.left, &ctx);
+        let right = resolve(&style.offsets.right, &ctx);
+        if left.is_none() && right.is_none() {
+            left = Some(static_position.x - containing_block.x);
+        }
+        let margins = margin_left.unwrap_or(0.0) + margin_right.unwrap_or(0.0);
+        let width = match (left, resolve(&style.width, &ctx).or(replaced.map(|size| size.0)), right) {
+            (_, Some(width), _) => width,
+            (Some(left), None, Some(right)) => (containing_block.width - left - right - edges - margins).max(0.0),
+            (left, None, right) => {
+                let widths = self.content_widths(&ctx, fonts);
+                let available = containing_block.width - left.unwrap_or(0.0) - right.unwrap_or(0.0) - edges - margins;
+                widths.max.min(widths.min.max(available))
+            }
+        };
+        if let (Some(left), Some(right)) = (left, right) {
+            let extra = containing_block.width - left - right - width - edges;
+            match (margin_left, margin_right) {
+                (None, None) if extra < 0.0 => margin_right = Some(extra),
+                (None, None) => {
+                    margin_left = Some(extra / 2.0);
+                    margin_right = Some(extra / 2.0);
+                }
+                (None, Some(margin_right)) => margin_left = Some(extra - margin_right),
+                (Some(margin_left), None) => margin_right = Some(extra - margin_left),
+                (Some(_), Some(_)) => {} // Overconstrained, so 'right' is ignored
+            }
+        }
+
+        let d = &mut self.dimensions;
+        d.margin.left = margin_left.unwrap_or(0.0);
+        d.margin.right = margin_right.unwrap_or(0.0);
+        d.content.width = width;
+        let margin_box_width = width + edges + d.margin.left + d.margin.right;
+        d.content.x = match left {
+            Some(left) => containing_block.x + left,
+            None => containing_block.x + containing_block.width - right.unwrap_or(0.0) - margin_box_width,
+        } + d.margin.left + d.border.left + d.padding.left;
 
-        match (width == auto, margin_left == auto, margin_right == auto) {
-            // If the values are overconstrained, calculate margin_right.
-            (false, false, false) => {
-                margin_right = Value::Length(margin_right.to_px() + underflow, Unit::Px);
+        // The vertical axis. The contents are laid out from the top, if it's known yet, and
+        // moved down afterwards if not.
+        let edges = d.border.top + d.border.bottom + d.padding.top + d.padding.bottom;
+        let (mut margin_top, mut margin_bottom) = (resolve(&style.margin.top, &vertical_ctx), resolve(&style.margin.bottom, &vertical_ctx));
+        let mut top = resolve(&style.offsets.top, &vertical_ctx);
+        let bottom = resolve(&style.offsets.bottom, &vertical_ctx);
+        if top.is_none() && bottom.is_none() {
+            top = Some(static_position.y - containing_block.y);
+        }
+        let mut height = resolve(&style.height, &vertical_ctx).or(replaced.map(|size| size.1));
+        if let (Some(top), None, Some(bottom)) = (top, height, bottom) {
+            let margins = margin_top.unwrap_or(0.0) + margin_bottom.unwrap_or(0.0);
+            height = Some((containing_block.height - top - bottom - edges - margins).max(0.0));
+        }
+        d.margin.top = margin_top.unwrap_or(0.0);
+        d.content.y = containing_block.y + top.unwrap_or(0.0) + d.margin.top + d.border.top + d.padding.top;
+        d.content.height = 0.0;
+        if replaced.is_none() {
+            let mut floats = Floats::default();
+            self.layout_block_children(LengthContext { containing_height: height, ..ctx }, fonts, &mut floats, (false, false));
+            let d = &mut self.dimensions;
+            d.content.height = d.content.height.max(floats.bottom() - d.content.y);
+        }
+        let d = &mut self.dimensions;
+        let height = height.unwrap_or(d.content.height);
+        d.content.height = height;
+        if let (Some(top), Some(bottom)) = (top, bottom) {
+            let extra = containing_block.height - top - bottom - height - edges;
+            match (margin_top, margin_bottom) {
+                (None, None) if extra < 0.0 => margin_bottom = Some(extra),
+                (None, None) => {
+                    margin_top = Some(extra / 2.0);
+                    margin_bottom = Some(extra / 2.0);
+                }
+                (None, Some(margin_bottom)) => margin_top = Some(extra - margin_bottom),
+                (Some(margin_top), None) => margin_bottom = Some(extra - margin_top),
+                (Some(_), Some(_)) => {} // Overconstrained, so 'bottom' is ignored
             }
+        }
+        d.margin.top = margin_top.unwrap_or(0.0);
+        d.margin.bottom = margin_bottom.unwrap_or(0.0);
+        let y = match top {
+            Some(top) => containing_block.y + top + d.margin.top + d.border.top + d.padding.top,
+            None => containing_block.y + containing_block.height - bottom.unwrap_or(0.0)
+                - d.margin.bottom - d.border.bottom - d.padding.bottom - height,
+        };
+        let dy = y - d.content.y;
+        self.translate(0.0, dy);
+    }
 
-            (false, false, true) => { margin_right = Value::Length(0.0, Unit::Px); }
-            (false, true, false) => { margin_left  = Value::Length(0.0, Unit::Px); }
+    // Put a float that's been laid out as high up in 'area' as it can go, and as far to the
+    // left or right.
+    fn place_float(&mut self, floats: &mut Floats, area: Rect) {
+        let margin_box = self.dimensions.margin_box();
+        let style = &self.get_style_node().computed;
+        let (x, y) = floats.place(style.float, style.clear, margin_box.width, margin_box.height, area);
+        self.translate(x - margin_box.x, y - margin_box.y);
+    }
 
-            (true, _, _) => {
-                if margin_left == auto { margin_left = Value::Length(0.0, Unit::Px); }
-                if margin_right == auto { margin_right = Value::Length(0.0, Unit::Px); }
+    // Work out the scrollable overflow of the box and everything in it. Returns how far it
+    // makes its parent's reach: its border box, and its own overflow unless it clips that.
+    fn calculate_scrollable_overflow(&mut self) -> Rect {
+        let padding_box = self.dimensions.padding_box();
+        let mut overflow = padding_box;
+        for child in &mut self.children {
+            overflow = overflow.union(child.calculate_scrollable_overflow());
+        }
+        let border_box = self.dimensions.border_box();
+        let reach = if self.overflow() == Overflow::Visible { border_box.union(overflow) } else { border_box };
+        // Nothing can be scrolled to above or to the left of the padding box.
+        self.scrollable_overflow = Rect {
+            x: padding_box.x,
+            y: padding_box.y,
+            width: overflow.x + overflow.width - padding_box.x,
+            height: overflow.y + overflow.height - padding_box.y,
+        };
+        reach
+    }
 
-                if underflow >= 0.0 {
-                    // Expand width to fill the underflow.
-                    width = Value::Length(underflow, Unit::Px);
-                } else {
-                    width = Value::Length(0.0, Unit::Px);
-                    margin_right = Value::Length(margin_right.to_px() + underflow, Unit::Px);
+    // Move the box and everything in it.
+    fn translate(&mut self, dx: f32, dy: f32) {
+        self.dimensions.content.x += dx;
+        self.dimensions.content.y += dy;
+        for child in &mut self.children {
+            child.translate(dx, dy);
+        }
+    }
+
+    // The node the box was made from, or for an anonymous block, the node it's in. Other
+    // anonymous boxes, and lines, don't have one.
+    fn node(&self) -> Option<&'a StyledNode<'a>> {
+        match self.box_type {
+            BoxType::AnonymousBlock(node) => Some(node),
+            BoxType::LineBox | BoxType::TableNode(None) | BoxType::TableRowNode(None) | BoxType::TableCellNode(None) => None,
+            _ => Some(self.get_style_node()),
+        }
+    }
+
+    // Mark the box dirty if it's changed since 'old', the box it was in the layout from
+    // before, and its children dirty if anything in it has. Floats and absolutely
+    // positioned boxes always count as changed, since where they go depends on what's
+    // around them. An anonymous block's inline-level boxes have been replaced by lines in
+    // 'old', so they're only compared with the nodes they're made from.
+    fn mark_changes<'o>(&mut self, old: &LayoutBox<'o>, pairs: &StylePairs<'a, 'o>) {
+        self.dirty = std::mem::discriminant(&self.box_type) != std::mem::discriminant(&old.box_type)
+            || match (self.node(), old.node()) {
+                (Some(new), Some(old)) => !pairs.unchanged(old, new),
+                (None, None) => false,
+                _ => true,
+            }
+            || self.float() != Float::None || self.is_absolute();
+        self.children_dirty = match self.box_type {
+            BoxType::AnonymousBlock(_) => !self.children.iter().all(|child| child.is_unchanged_inline(pairs)),
+            _ if self.children.len() != old.children.len() => true,
+            _ => {
+                for (child, old_child) in self.children.iter_mut().zip(&old.children) {
+                    child.mark_changes(old_child, pairs);
                 }
+                self.children.iter().any(|child| child.dirty || child.children_dirty)
             }
+        };
+    }
+
+    // Is the inline-level box, and everything in it, made from nodes that haven't changed?
+    fn is_unchanged_inline(&self, pairs: &StylePairs<'a, '_>) -> bool {
+        self.node().is_none_or(|node| pairs.unchanged_since(node)) && self.float() == Float::None && !self.is_absolute()
+            && self.children.iter().all(|child| child.is_unchanged_inline(pairs))
+    }
 
-            (false, true, true) => {
-                margin_left = Value::Length(underflow / 2.0, Unit::Px);
-                margin_right = Value::Length(underflow / 2.0, Unit::Px);
+    // Give the boxes that haven't changed, and don't have anything changed in them, their
+    // layout from 'old' to reuse.
+    fn take_previous<'o>(&mut self, old: &LayoutBox<'o>, pairs: &StylePairs<'a, 'o>) {
+        if !self.dirty && !self.children_dirty && old.input.is_some() {
+            self.previous = old.rebind(pairs).map(Box::new);
+        }
+        if self.previous.is_none() && !matches!(self.box_type, BoxType::AnonymousBlock(_))
+            && self.children.len() == old.children.len() {
+            for (child, old_child) in self.children.iter_mut().zip(&old.children) {
+                child.take_previous(old_child, pairs);
             }
         }
     }
 
-    fn calculate_block_position(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+    // A copy of the box, which was laid out from an earlier styling of the tree, made from
+    // the nodes 'pairs' has in place of the ones it was made from, or 'None' if any of them
+    // aren't paired.
+    fn rebind<'n>(&self, pairs: &StylePairs<'n, 'a>) -> Option<LayoutBox<'n>> {
+        let node = |node: &'a StyledNode<'a>| pairs.new_by_old.get(&(node as *const _)).copied();
+        let anonymous = |node: Option<&'a StyledNode<'a>>| match node {
+            Some(node) => pairs.new_by_old.get(&(node as *const _)).map(|&node| Some(node)),
+            None => Some(None),
+        };
+        let box_type = match self.box_type {
+            BoxType::BlockNode(n) => BoxType::BlockNode(node(n)?),
+            BoxType::InlineNode(n) => BoxType::InlineNode(node(n)?),
+            BoxType::InlineBlockNode(n) => BoxType::InlineBlockNode(node(n)?),
+            BoxType::SvgNode(n) => BoxType::SvgNode(node(n)?),
+            BoxType::ImageNode(n, intrinsic) => BoxType::ImageNode(node(n)?, intrinsic),
+            BoxType::AnonymousBlock(n) => BoxType::AnonymousBlock(node(n)?),
+            BoxType::LineBox => BoxType::LineBox,
+            BoxType::TextRun(n, start, end) => BoxType::TextRun(node(n)?, start, end),
+            BoxType::TableNode(n) => BoxType::TableNode(anonymous(n)?),
+            BoxType::TableRowGroupNode(n) => BoxType::TableRowGroupNode(node(n)?),
+            BoxType::TableRowNode(n) => BoxType::TableRowNode(anonymous(n)?),
+            BoxType::TableCellNode(n) => BoxType::TableCellNode(anonymous(n)?),
+            BoxType::FlexNode(n) => BoxType::FlexNode(node(n)?),
+            BoxType::GridNode(n) => BoxType::GridNode(node(n)?),
+        };
+        let mut layout_box = LayoutBox::new(box_type);
+        layout_box.dimensions = self.dimensions;
+        layout_box.scrollable_overflow = self.scrollable_overflow;
+        layout_box.baseline = self.baseline;
+        layout_box.input = self.input;
+        layout_box.children = self.children.iter().map(|child| child.rebind(pairs)).collect::<Option<_>>()?;
+        Some(layout_box)
+    }
+
+    // Take on the layout 'previous' had, from being laid out before in a containing block
+    // as wide as 'containing_block', moved along with the containing block. It's put back
+    // where it was laid out first, since what moved it after that, like its relative
+    // offset, is done again.
+    fn reuse(&mut self, mut previous: LayoutBox<'a>, containing_block: Rect) {
+        let input = match previous.input {
+            Some(input) => input,
+            None => return,
+        };
+        let before = input.containing_block;
+        let dx = containing_block.x - before.x;
+        let dy = containing_block.y + containing_block.height - before.y - before.height;
+        let (laid_out, moved) = (input.dimensions.content, previous.dimensions.content);
+        previous.translate(laid_out.x + dx - moved.x, laid_out.y + dy - moved.y);
+        previous.dimensions = input.dimensions;
+        previous.dimensions.content.x += dx;
+        previous.dimensions.content.y += dy;
+        for child in &mut previous.children {
+            child.move_inputs(dx, dy);
+        }
+        previous.input = Some(LayoutInput { containing_block, dimensions: previous.dimensions, ..input });
+        *self = previous;
+    }
+
+    // Move where the box and everything in it were laid out.
+    fn move_inputs(&mut self, dx: f32, dy: f32) {
+        if let Some(ref mut input) = self.input {
+            input.containing_block.x += dx;
+            input.containing_block.y += dy;
+            input.dimensions.content.x += dx;
+            input.dimensions.content.y += dy;
+        }
+        for child in &mut self.children {
+            child.move_inputs(dx, dy);
+        }
+    }
+
+    // Lay out a replaced element, like an image, whose size comes from the element itself
+    // when CSS doesn't give one. It goes below the previous content, like a block.
+    fn layout_replaced(&mut self, containing_block: Dimensions, ctx: LengthContext) {
+        let ctx = LengthContext {
+            font_size: self.get_style_node().font.size,
+            containing_width: containing_block.content.width,
+            ..ctx
+        };
+        let (width, height) = self.replaced_size(&ctx);
+
+        // 'auto' horizontal margins are zero.
+        let style = &self.get_style_node().computed;
         let d = &mut self.dimensions;
+        d.margin.left = style.margin.left.as_ref().map_or(0.0, |margin| margin.resolve(&ctx));
+        d.margin.right = style.margin.right.as_ref().map_or(0.0, |margin| margin.resolve(&ctx));
+        d.border.left = style.border_width.left.resolve(&ctx);
+        d.border.right = style.border_width.right.resolve(&ctx);
+        d.padding.left = style.padding.left.resolve(&ctx);
+        d.padding.right = style.padding.right.resolve(&ctx);
+        self.calculate_block_position(containing_block, &ctx);
+        self.dimensions.content.width = width;
+        self.dimensions.content.height = height;
+    }
 
-        // Margin, border and padding have initial value 0.
-        let zero = Value::Length(0.0, Unit::Px);
+    // The content size of a replaced element.
+    fn replaced_size(&self, ctx: &LengthContext) -> (f32, f32) {
+        let style_node = self.get_style_node();
+        let style = &style_node.computed;
+        let intrinsic = match (self.box_type, &style_node.node().node_type) {
+            (BoxType::ImageNode(_, intrinsic), _) => intrinsic,
+            (_, NodeType::Element(ref elem)) => svg::intrinsic_size(elem),
+            _ => Default::default(),
+        };
 
-        // If margin-top or margin-bottom is 'auto', the used value is zero.
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+        // 'aspect-ratio' is used instead of the element's own ratio, unless it's 'auto' too,
+        // when it's only used if the element doesn't have one. Then the element's own size
+        // only gives its width, or its height if it has no width, and the ratio the other.
+        let aspect_ratio = style.aspect_ratio;
+        let intrinsic = match aspect_ratio.ratio {
+            Some(ratio) if !aspect_ratio.auto || intrinsic.ratio.is_none() => IntrinsicSize {
+                height: intrinsic.height.filter(|_| intrinsic.width.is_none()),
+                ratio: Some(ratio),
+                ..intrinsic
+            },
+            _ => intrinsic,
+        };
 
-        d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
-        d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
+        // An 'auto' width or height is the intrinsic one, or is worked out from the other
+        // one and the aspect ratio, with a default size for when there's nothing to go on.
+        let width = style.width.as_ref().map(|width| width.resolve(ctx));
+        let height = self.specified_height(ctx);
+        match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, intrinsic.ratio.map(|ratio| width / ratio).or(intrinsic.height).unwrap_or(svg::DEFAULT_HEIGHT)),
+            (None, Some(height)) => (intrinsic.ratio.map(|ratio| height * ratio).or(intrinsic.width).unwrap_or(svg::DEFAULT_WIDTH), height),
+            (None, None) => match (intrinsic.width, intrinsic.height, intrinsic.ratio) {
+                (Some(width), Some(height), _) => (width, height),
+                (Some(width), None, Some(ratio)) => (width, width / ratio),
+                (None, Some(height), Some(ratio)) => (height * ratio, height),
+                (width, height, _) => (width.unwrap_or(svg::DEFAULT_WIDTH), height.unwrap_or(svg::DEFAULT_HEIGHT)),
+            },
+        }
+    }
 
-        d.padding.top = style.lookup("padding-top-width", "padding-width", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom-width", "padding-width", &zero).to_px();
+    // Lay out a table: its captions above it, like blocks, and its cells in a grid of rows
+    // and columns, with 'border-spacing' between them and around them. The columns share
+    // out the table's width in proportion to how wide their cells would be, and each row is
+    // as tall as the tallest cell in it. Row groups and rows go around their cells.
+    fn layout_table(&mut self, containing_block: Dimensions, ctx: LengthContext, fonts: &Fonts) {
+        let node = match self.box_type {
+            BoxType::TableNode(node) => node,
+            _ => return,
+        };
+        let ctx = LengthContext {
+            font_size: node.map_or(ctx.font_size, |node| node.font.size),
+            containing_width: containing_block.content.width,
+            ..ctx
+        };
+        if node.is_some() {
+            self.calculate_block_width(containing_block, &ctx, fonts);
+            self.calculate_block_position(containing_block, &ctx);
+        } else {
+            // An anonymous table has no margins, borders or padding, and shrinks to fit.
+            let widths = self.content_widths(&ctx, fonts);
+            self.dimensions = Dimensions {
+                content: Rect {
+                    x: containing_block.content.x,
+                    y: containing_block.content.y + containing_block.content.height,
+                    width: widths.max.min(containing_block.content.width).max(widths.min),
+                    height: 0.0,
+                },
+                ..Default::default()
+            };
+        }
+        let parts_ctx = LengthContext { containing_width: self.dimensions.content.width, containing_height: None, ..ctx };
 
-        d.content.x = containing_block.content.x +
-            d.margin.left + d.border.left + d.padding.left;
+        // The captions go where the table's border box would, which moves down below them,
+        // with its top margin taking in the room they take up.
+        let table = self.dimensions;
+        let top = table.border_box().y;
+        let mut captions_height = 0.0;
+        for caption in self.children.iter_mut().filter(|child| matches!(child.box_type, BoxType::BlockNode(_))) {
+            let containing_block = Dimensions { content: Rect { y: top, height: captions_height, ..table.content }, ..Default::default() };
+            caption.layout(containing_block, parts_ctx, fonts, None);
+            let margin_box = caption.dimensions.margin_box();
+            captions_height = margin_box.y + margin_box.height - top;
+        }
+        self.dimensions.margin.top += captions_height;
+        self.dimensions.content.y += captions_height;
 
-        d.content.y = containing_block.content.height + containing_block.content.y +
-            d.margin.top + d.border.top + d.padding.top;
+        // Share the width out between the columns. If there's more than they need to be
+        // unbroken, they get the rest in proportion to how wide that is, and if not, they
+        // get what there is above the least they can be in proportion to how much wider
+        // they'd like to be than that.
+        let grid = TableGrid::new(self);
+        let columns = self.column_widths(&grid, &parts_ctx, fonts);
+        let (spacing_x, spacing_y) = self.border_spacing(&ctx);
+        let d = self.dimensions;
+        let available = d.content.width - if columns.is_empty() { 0.0 } else { spacing_x * (columns.len() + 1) as f32 };
+        let min: f32 = columns.iter().map(|column| column.min).sum();
+        let max: f32 = columns.iter().map(|column| column.max).sum();
+        let widths: Vec<f32> = if available >= max {
+            let weights: Vec<f32> = columns.iter().map(|column| column.max).collect();
+            columns.iter().zip(share(available - max, &weights)).map(|(column, extra)| column.max + extra).collect()
+        } else {
+            let weights: Vec<f32> = columns.iter().map(|column| column.max - column.min).collect();
+            columns.iter().zip(share(available - min, &weights)).map(|(column, extra)| column.min + extra).collect()
+        };
+        let mut xs = Vec::new();
+        let mut x = d.content.x + spacing_x;
+        for width in &widths {
+            xs.push(x);
+            x += width + spacing_x;
+        }
+        for (cell, place) in self.table_cells_mut().zip(&grid.cells) {
+            let width = widths[place.column..place.column + place.columns].iter().sum::<f32>() + spacing_x * (place.columns - 1) as f32;
+            cell.layout_table_cell(xs[place.column], width, parts_ctx, fonts);
+        }
+
+        // Each row is as tall as its cells, and at least as tall as its own height. A cell
+        // that spans rows makes the last of them taller if they aren't tall enough for it
+        // between them.
+        let mut heights: Vec<f32> = self.table_rows().map(|row| match row.box_type {
+            BoxType::TableRowNode(Some(_)) => row.specified_height(&parts_ctx).unwrap_or(0.0),
+            _ => 0.0,
+        }).collect();
+        let cells: Vec<(f32, GridCell)> = self.table_cells()
+            .map(|cell| cell.dimensions.border_box().height)
+            .zip(grid.cells.iter().copied())
+            .collect();
+        for &(height, place) in cells.iter().filter(|(_, place)| place.rows == 1) {
+            heights[place.row] = heights[place.row].max(height);
+        }
+        for &(height, place) in cells.iter().filter(|(_, place)| place.rows > 1) {
+            let spanned = heights[place.row..place.row + place.rows].iter().sum::<f32>() + spacing_y * (place.rows - 1) as f32;
+            if height > spanned {
+                heights[place.row + place.rows - 1] += height - spanned;
+            }
+        }
+
+        // A table whose height is set taller than its rows shares the extra height out
+        // between them equally.
+        let spacing = if heights.is_empty() { 0.0 } else { spacing_y * (heights.len() + 1) as f32 };
+        let rows_height = heights.iter().sum::<f32>() + spacing;
+        let height = node.and_then(|_| self.specified_height(&ctx)).unwrap_or(0.0);
+        if height > rows_height && !heights.is_empty() {
+            let extra = (height - rows_height) / heights.len() as f32;
+            for row_height in &mut heights {
+                *row_height += extra;
+            }
+        }
+        self.dimensions.content.height = rows_height.max(height);
+
+        // Put the rows in place, and the cells in them, which are as tall as the rows they span.
+        let mut ys = Vec::new();
+        let mut y = d.content.y + spacing_y;
+        for height in &heights {
+            ys.push(y);
+            y += height + spacing_y;
+        }
+        for (cell, place) in self.table_cells_mut().zip(&grid.cells) {
+            let last = place.row + place.rows - 1;
+            cell.translate(0.0, ys[place.row]);
+            let d = &mut cell.dimensions;
+            d.content.height = ys[last] + heights[last] - ys[place.row] - d.border.top - d.padding.top - d.padding.bottom - d.border.bottom;
+        }
+        let rows = Rect { x: d.content.x + spacing_x, y: d.content.y, width: (d.content.width - 2.0 * spacing_x).max(0.0), height: 0.0 };
+        for ((row, &y), &height) in self.table_rows_mut().zip(&ys).zip(&heights) {
+            row.dimensions.content = Rect { y, height, ..rows };
+        }
+        for group in self.children.iter_mut().filter(|child| matches!(child.box_type, BoxType::TableRowGroupNode(_))) {
+            let (top, bottom) = match (group.children.first(), group.children.last()) {
+                (Some(first), Some(last)) => (first.dimensions.content.y, last.dimensions.content.y + last.dimensions.content.height),
+                _ => (rows.y, rows.y),
+            };
+            group.dimensions.content = Rect { y: top, height: bottom - top, ..rows };
+        }
+
+        // Relatively positioned parts take what's in them with them.
+        for child in self.children.iter_mut().filter(|child| !matches!(child.box_type, BoxType::TableRowNode(_))) {
+            child.apply_relative_offset(&parts_ctx);
+        }
+        for row in self.table_rows_mut() {
+            row.apply_relative_offset(&parts_ctx);
+            for cell in &mut row.children {
+                cell.apply_relative_offset(&parts_ctx);
+            }
+        }
     }
 
-    fn layout_block_children(&mut self) {
+    // Lay out a table cell 'width' wide, with the left of its border box at 'x' and the top
+    // at 0. It's moved down into its row, and made as tall as the rows it spans, once their
+    // heights are known. Its contents are laid out around floats of their own.
+    fn layout_table_cell(&mut self, x: f32, width: f32, mut ctx: LengthContext, fonts: &Fonts) {
+        let mut height = None;
+        if let BoxType::TableCellNode(Some(node)) = self.box_type {
+            ctx.font_size = node.font.size;
+            let edges = self.edges(&ctx);
+            self.dimensions.border = edges.border;
+            self.dimensions.padding = edges.padding;
+            height = self.specified_height(&ctx);
+        }
         let d = &mut self.dimensions;
-        for child in &mut self.children {
-            child.layout(*d);
-            // Track the height so each child is laid out below the previous content.
-            d.content.height = d.content.height + child.dimensions.margin_box().height;
+        d.content.x = x + d.border.left + d.padding.left;
+        d.content.y = d.border.top + d.padding.top;
+        d.content.width = (width - d.border.left - d.border.right - d.padding.left - d.padding.right).max(0.0);
+        d.content.height = 0.0;
+        let mut floats = Floats::default();
+        self.layout_block_children(LengthContext { containing_height: height, ..ctx }, fonts, &mut floats, (false, false));
+        let d = &mut self.dimensions;
+        d.content.height = d.content.height.max(floats.bottom() - d.content.y).max(height.unwrap_or(0.0));
+    }
+
+    // The space between a table's cells, across and down. Anonymous tables have none.
+    fn border_spacing(&self, ctx: &LengthContext) -> (f32, f32) {
+        match self.box_type {
+            BoxType::TableNode(Some(node)) => {
+                let (across, down) = &node.computed.border_spacing;
+                (across.resolve(ctx), down.resolve(ctx))
+            }
+            _ => (0.0, 0.0),
         }
     }
 
-    fn calculate_block_height(&mut self) {
-        // If the height is set to an explicit length, use that exact length.
-        // Otherwise, just keep the value set by 'layout_block_children'.
-        if let Some(Value::Length(h, Unit::Px)) = self.get_style_node().value("height") {
-            self.dimensions.content.height = h;
+    // A table's rows, in order, whether they're in row groups or not.
+    fn table_rows(&self) -> impl Iterator<Item = &LayoutBox<'a>> {
+        self.children.iter().flat_map(|child| match child.box_type {
+            BoxType::TableRowGroupNode(_) => child.children.iter(),
+            BoxType::TableRowNode(_) => std::slice::from_ref(child).iter(),
+            _ => [].iter(),
+        })
+    }
+
+    fn table_rows_mut(&mut self) -> impl Iterator<Item = &mut LayoutBox<'a>> {
+        self.children.iter_mut().flat_map(|child| match child.box_type {
+            BoxType::TableRowGroupNode(_) => child.children.iter_mut(),
+            BoxType::TableRowNode(_) => std::slice::from_mut(child).iter_mut(),
+            _ => [].iter_mut(),
+        })
+    }
+
+    // A table's cells, row by row.
+    fn table_cells(&self) -> impl Iterator<Item = &LayoutBox<'a>> {
+        self.table_rows().flat_map(|row| row.children.iter())
+    }
+
+    fn table_cells_mut(&mut self) -> impl Iterator<Item = &mut LayoutBox<'a>> {
+        self.table_rows_mut().flat_map(|row| row.children.iter_mut())
+    }
+
+    // How many columns and rows a table cell spans, from an HTML cell's 'colspan' and
+    // 'rowspan' attributes. A 'rowspan' of 0 spans the rest of the rows.
+    fn spans(&self) -> (usize, usize) {
+        let elem = match self.box_type {
+            BoxType::TableCellNode(Some(node)) if node.pseudo_element.is_none() => match node.node().node_type {
+                NodeType::Element(ref elem) => elem,
+                _ => return (1, 1),
+            },
+            _ => return (1, 1),
+        };
+        let span = |name: &str| elem.attributes.get(name).and_then(|value| value.trim().parse::<usize>().ok());
+        (span("colspan").unwrap_or(1).clamp(1, 1000), span("rowspan").unwrap_or(1).min(65534))
+    }
+
+    // The narrowest and widest each of a table's columns can be. A column is as wide as the
+    // cells in it need, and a cell that spans columns makes them wider, in proportion to how
+    // wide they'd be, if they aren't wide enough for it between them.
+    fn column_widths(&self, grid: &TableGrid, ctx: &LengthContext, fonts: &Fonts) -> Vec<IntrinsicWidths> {
+        let spacing = self.border_spacing(ctx).0;
+        let cells: Vec<(IntrinsicWidths, GridCell)> = self.table_cells()
+            .map(|cell| cell.cell_widths(ctx, fonts))
+            .zip(grid.cells.iter().copied())
+            .collect();
+        let mut columns = vec![IntrinsicWidths::default(); grid.columns];
+        for &(widths, place) in cells.iter().filter(|(_, place)| place.columns == 1) {
+            let column = &mut columns[place.column];
+            column.min = column.min.max(widths.min);
+            column.max = column.max.max(widths.max);
+        }
+        for &(widths, place) in cells.iter().filter(|(_, place)| place.columns > 1) {
+            let spanned = &mut columns[place.column..place.column + place.columns];
+            let between = spacing * (place.columns - 1) as f32;
+            let min = spanned.iter().map(|column| column.min).sum::<f32>() + between;
+            let max = spanned.iter().map(|column| column.max).sum::<f32>() + between;
+            let weights: Vec<f32> = spanned.iter().map(|column| column.max).collect();
+            let min_extra = share((widths.min - min).max(0.0), &weights);
+            let max_extra = share((widths.max - max).max(0.0), &weights);
+            for ((column, min_extra), max_extra) in spanned.iter_mut().zip(min_extra).zip(max_extra) {
+                column.min += min_extra;
+                column.max = (column.max + max_extra).max(column.min);
+            }
         }
+        columns
     }
-}
 
-impl Dimensions {
-    // The area covered by the content area plus its padding.
-    fn padding_box(self) -> Rect {
-        self.content.expanded_by(self.padding)
+    // The narrowest and widest a table cell can be, including its borders and padding. A set
+    // width is the least it can be, if its contents can be narrower.
+    fn cell_widths(&self, ctx: &LengthContext, fonts: &Fonts) -> IntrinsicWidths {
+        let widths = self.content_widths(ctx, fonts);
+        let node = match self.box_type {
+            BoxType::TableCellNode(Some(node)) => node,
+            _ => return widths,
+        };
+        let ctx = LengthContext { font_size: node.font.size, ..*ctx };
+        let style = &node.computed;
+        let edges = style.border_width.left.resolve(&ctx) + style.border_width.right.resolve(&ctx)
+            + style.padding.left.resolve(&ctx) + style.padding.right.resolve(&ctx);
+        let min = match style.width {
+            Some(ref width @ Value::Length(..)) => widths.min.max(width.resolve(&ctx)),
+            _ => widths.min,
+        };
+        IntrinsicWidths { min: min + edges, max: widths.max.max(min) + edges, line: 0.0 }
     }
 
-    // The area covered by the content area plus its padding and borders.
-    pub fn border_box(self) -> Rect {
-        self.padding_box().expanded_by(self.border)
+    fn layout_block(&mut self, containing_block: Dimensions, ctx: LengthContext, fonts: &Fonts, floats: Option<&mut Floats>) {
+        // Styling has already made lengths absolute, so this is mostly for percentages,
+        // which are resolved against the width of the containing block. Relative lengths
+        // can still come from animations, and use the box's own font size.
+        let ctx = LengthContext {
+            font_size: self.get_style_node().font.size,
+            containing_width: containing_block.content.width,
+            ..ctx
+        };
+
+        // Child width can depend on parent width so we need to calculate
+        // this box's width before laying out its children.
+        self.calculate_block_width(containing_block, &ctx, fonts);
+
+        // Determine where the box is located within its container.
+        self.calculate_block_position(containing_block, &ctx);
+
+        self.layout_block_contents(ctx, fonts, floats);
     }
 
-    // The area covered by the content area plus its padding, borders and margin.
-    fn margin_box(self) -> Rect {
-        self.border_box().expanded_by(self.border)
+    // Lay out the block's contents, once its width and position are known, and work out its
+    // height.
+    fn layout_block_contents(&mut self, ctx: LengthContext, fonts: &Fonts, floats: Option<&mut Floats>) {
+        // Recursively lay out the children of this box, whose percentage heights are of its
+        // height if it's known now. A box with floats of its own is tall enough to hold
+        // them. Otherwise, its margins can collapse with its children's.
+        let children_ctx = LengthContext { containing_height: self.definite_height(&ctx), ..ctx };
+        match floats {
+            Some(floats) if !self.establishes_formatting_context() => {
+                let collapse = self.collapses_with_children(&ctx);
+                self.layout_block_children(children_ctx, fonts, floats, collapse)
+            }
+            _ => {
+                let mut own_floats = Floats::default();
+                self.layout_block_children(children_ctx, fonts, &mut own_floats, (false, false));
+                let d = &mut self.dimensions;
+                d.content.height = d.content.height.max(own_floats.bottom() - d.content.y);
+            }
+        }
+
+        // Parent height can depend on child height, so 'calculate_height'
+        // must be called *after* the children are laid out.
+        self.calculate_block_height(&ctx);
     }
-}
 
-impl Rect {
-    fn expanded_by(self, edge: EdgeSizes) -> Rect {
-        Rect {
-            x: self.x - edge.left,
-            y: self.y - edge.top,
-            width: self.width + edge.left + edge.right,
-            height: self.height + edge.top + edge.bottom,
+    // Lay out a flex or grid container like a block, with its children as its items.
+    fn layout_container(&mut self, containing_block: Dimensions, ctx: LengthContext, fonts: &Fonts) {
+        let ctx = LengthContext {
+            font_size: self.get_style_node().font.size,
+            containing_width: containing_block.content.width,
+            ..ctx
+        };
+        self.calculate_block_width(containing_block, &ctx, fonts);
+        self.calculate_block_position(containing_block, &ctx);
+        self.layout_items(&ctx, fonts);
+    }
+
+    // Lay out a block's contents, or a flex or grid container's items, once its width and
+    // position are known, and work out its height.
+    fn layout_items(&mut self, ctx: &LengthContext, fonts: &Fonts) {
+        match self.box_type {
+            BoxType::FlexNode(_) => self.layout_flex_items(ctx, fonts),
+            BoxType::GridNode(_) => self.layout_grid_items(ctx, fonts),
+            _ => self.layout_block_contents(*ctx, fonts, None),
+        }
+    }
+
+    // Lay out a flex container's items in lines along its main axis, across it for
+    // 'flex-direction: row' and down it for 'column', once its width and position are known,
+    // and work out its height. The items go in 'order', on one line, or with 'flex-wrap', on
+    // as many as it takes to fit them. Each item starts out as big as its 'flex-basis' along
+    // the main axis. Those on a line grow to fill the room on it in proportion to their
+    // 'flex-grow', or shrink to fit in it in proportion to their 'flex-shrink' and size, but
+    // no smaller than their contents can be. Then they're spaced out along the line by
+    // 'justify-content', and lined up across it by 'align-items', and the lines are spaced
+    // out across the container by 'align-content'. Absolutely positioned children aren't
+    // flex items, and go at the start of the content box.
+    fn layout_flex_items(&mut self, ctx: &LengthContext, fonts: &Fonts) {
+        let style = &self.get_style_node().computed;
+        let row = matches!(style.flex_direction, FlexDirection::Row | FlexDirection::RowReverse);
+        let reverse = matches!(style.flex_direction, FlexDirection::RowReverse | FlexDirection::ColumnReverse);
+        let wrap = style.flex_wrap != FlexWrap::NoWrap;
+        let content = self.dimensions.content;
+        let height = self.definite_height(ctx);
+        let items_ctx = LengthContext { containing_width: content.width, containing_height: height, ..*ctx };
+
+        // The gaps between items on a line, and between lines.
+        let (column_gap, row_gap) = self.gaps(&items_ctx);
+        let (main_gap, cross_gap) = if row { (column_gap, row_gap) } else { (row_gap, column_gap) };
+
+        for child in self.children.iter_mut().filter(|child| child.is_absolute()) {
+            child.dimensions.content = Rect { width: 0.0, height: 0.0, ..content };
+        }
+        let mut boxes: Vec<&mut LayoutBox<'a>> = self.children.iter_mut().filter(|child| !child.is_absolute()).collect();
+        let mut items: Vec<FlexItem> = boxes.iter_mut()
+            .map(|layout_box| layout_box.measure_flex_item(row, wrap, content, style.align_items, &items_ctx, fonts))
+            .collect();
+
+        // The room along the main axis, which a column only has a limit on if its height is
+        // set. Items wrap onto a new line where the next one wouldn't fit on the last.
+        let available = if row { Some(content.width) } else { height };
+        let mut lines: Vec<Range<usize>> = Vec::new();
+        let (mut start, mut used) = (0, 0.0);
+        for (i, item) in items.iter().enumerate() {
+            let next = if i == start { item.edges + item.size } else { used + main_gap + item.edges + item.size };
+            if wrap && i > start && available.is_some_and(|available| next > available) {
+                lines.push(start..i);
+                (start, used) = (i, item.edges + item.size);
+            } else {
+                used = next;
+            }
+        }
+        lines.push(start..items.len());
+        if let Some(available) = available {
+            for line in &lines {
+                let gaps = main_gap * line.len().saturating_sub(1) as f32;
+                resolve_flexible_lengths(&mut items[line.clone()], available - gaps);
+            }
+        }
+
+        // Items in a row can only be laid out now that their widths are known. Those in a
+        // column already have been, and just take their heights.
+        for (layout_box, item) in boxes.iter_mut().zip(&items) {
+            if row {
+                layout_box.layout_in_area(content.x, content.y, Some(item.size), content.width, &items_ctx, fonts);
+            } else {
+                layout_box.dimensions.content.height = item.size;
+            }
+        }
+
+        // Each line is as big across as its biggest item. The lines fill the container's
+        // content box across, which in a row is as tall as they are unless its height is
+        // set, and a single line fills it whole.
+        let main_length = |layout_box: &LayoutBox| {
+            let margin_box = layout_box.dimensions.margin_box();
+            if row { margin_box.width } else { margin_box.height }
+        };
+        let cross_length = |layout_box: &LayoutBox| {
+            let margin_box = layout_box.dimensions.margin_box();
+            if row { margin_box.height } else { margin_box.width }
+        };
+        let mut line_sizes: Vec<f32> = lines.iter()
+            .map(|line| boxes[line.clone()].iter().map(|layout_box| cross_length(layout_box)).fold(0.0, f32::max))
+            .collect();
+        let cross_size = if row { height } else { Some(content.width) };
+        if let (false, Some(cross_size)) = (wrap, cross_size) {
+            line_sizes[0] = cross_size;
+        }
+        let lines_size = line_sizes.iter().sum::<f32>() + cross_gap * (lines.len() - 1) as f32;
+        let cross_size = cross_size.unwrap_or(lines_size);
+        let cross_free = cross_size - lines_size;
+        let (mut line_position, line_gap) = match style.align_content {
+            _ if !wrap => (0.0, 0.0),
+            Alignment::Stretch => {
+                let extra = cross_free.max(0.0) / lines.len() as f32;
+                line_sizes.iter_mut().for_each(|size| *size += extra);
+                (0.0, 0.0)
+            }
+            alignment => distribute(alignment, cross_free, lines.len() as f32),
+        };
+
+        // Stretched items are as big across as their line. In a column that wraps, that's
+        // only known now, so they're laid out again at that width.
+        for (line, &line_size) in lines.iter().zip(&line_sizes) {
+            for (layout_box, item) in boxes[line.clone()].iter_mut().zip(&items[line.clone()]) {
+                if !item.stretch {
+                    continue;
+                }
+                let d = layout_box.dimensions;
+                if row {
+                    let edges = d.margin.top + d.margin.bottom + d.border.top + d.border.bottom + d.padding.top + d.padding.bottom;
+                    layout_box.dimensions.content.height = (line_size - edges).max(0.0);
+                } else if wrap {
+                    let edges = d.margin.left + d.margin.right + d.border.left + d.border.right + d.padding.left + d.padding.right;
+                    layout_box.layout_in_area(content.x, content.y, Some((line_size - edges).max(0.0)), content.width,
+                                              &items_ctx, fonts);
+                    layout_box.dimensions.content.height = item.size;
+                }
+            }
+        }
+
+        // Room left over on a line goes to its items' 'auto' margins along it if they have
+        // any, and is shared out by 'justify-content' if not. In reverse, the items start at
+        // the other end of the line, and go backwards. With 'wrap-reverse', the lines stack
+        // from the other side, and so do the items on them.
+        let mut main_size = if row { content.width } else { height.unwrap_or(0.0) };
+        if available.is_none() {
+            main_size = boxes.iter().map(|layout_box| main_length(layout_box)).sum::<f32>()
+                + main_gap * boxes.len().saturating_sub(1) as f32;
+        }
+        for (line, &line_size) in lines.iter().zip(&line_sizes) {
+            let gaps = main_gap * line.len().saturating_sub(1) as f32;
+            let mut free = main_size - gaps - boxes[line.clone()].iter().map(|layout_box| main_length(layout_box)).sum::<f32>();
+            let auto_margins: usize = boxes[line.clone()].iter().map(|layout_box| {
+                let auto = layout_box.auto_margins();
+                if row { auto.left as usize + auto.right as usize } else { auto.top as usize + auto.bottom as usize }
+            }).sum();
+            let auto_margin = if free > 0.0 && auto_margins > 0 { std::mem::take(&mut free) / auto_margins as f32 } else { 0.0 };
+            let (mut position, gap) = distribute(style.justify_content, free, line.len() as f32);
+            for (layout_box, item) in boxes[line.clone()].iter_mut().zip(&items[line.clone()]) {
+                // Across the line, 'auto' margins take up any room there is around the item
+                // before it's aligned.
+                let auto = layout_box.auto_margins();
+                let grow = |auto: bool| if auto { auto_margin } else { 0.0 };
+                let cross_free = line_size - cross_length(layout_box);
+                let (main_before, main_after) = if row { (grow(auto.left), grow(auto.right)) } else { (grow(auto.top), grow(auto.bottom)) };
+                let cross_auto = if row { (auto.top, auto.bottom) } else { (auto.left, auto.right) };
+                let (cross_before, cross_after, offset) = align_in(cross_free, cross_auto, item.align);
+                let margin = &mut layout_box.dimensions.margin;
+                let (left, top) = if row { (main_before, cross_before) } else { (cross_before, main_before) };
+                let (right, bottom) = if row { (main_after, cross_after) } else { (cross_after, main_after) };
+                margin.left += left;
+                margin.top += top;
+                margin.right += right;
+                margin.bottom += bottom;
+
+                // Each item's margin box is at the start of the content box so far.
+                let outer = main_length(layout_box);
+                let main_offset = if reverse { main_size - position - outer } else { position } + main_before;
+                let cross_start = line_position + offset;
+                let cross_offset = match style.flex_wrap {
+                    FlexWrap::WrapReverse => cross_size - cross_start - cross_length(layout_box),
+                    _ => cross_start,
+                } + cross_before;
+                if row {
+                    layout_box.translate(main_offset, cross_offset);
+                } else {
+                    layout_box.translate(cross_offset, main_offset);
+                }
+                layout_box.apply_relative_offset(&items_ctx);
+                position += outer + main_gap + gap;
+            }
+            line_position += line_size + cross_gap + line_gap;
+        }
+
+        self.dimensions.content.height = if row { cross_size } else { main_size };
+        self.calculate_block_height(ctx);
+    }
+
+    // Lay out a grid container's items in the areas of its grid they're placed in, once its
+    // width and position are known, and work out its height. The columns are sized to fit
+    // the items' widths first, then the items are laid out as wide as their areas, and the
+    // rows are sized to fit their heights. Each item fills its area unless its size or
+    // margins say otherwise, and goes at its top unless 'align-self' says otherwise.
+    // Absolutely positioned children aren't grid items, and go at the start of the content
+    // box.
+    fn layout_grid_items(&mut self, ctx: &LengthContext, fonts: &Fonts) {
+        let style = &self.get_style_node().computed;
+        let content = self.dimensions.content;
+        let height = self.definite_height(ctx);
+        let items_ctx = LengthContext { containing_width: content.width, containing_height: height, ..*ctx };
+        let (column_gap, row_gap) = self.gaps(&items_ctx);
+        let placement = GridPlacement::new(self);
+        let widths = self.grid_item_widths(&placement, &items_ctx, fonts);
+        let columns = size_tracks(&style.grid_template_columns, placement.columns, &widths, Some(content.width),
+                                  column_gap, &items_ctx);
+
+        // Where each track starts from the start of the content box, and how big an area
+        // spanning some of them is, with the gaps between them.
+        let starts = |sizes: &[f32], gap: f32| -> Vec<f32> {
+            sizes.iter().scan(0.0, |start, size| Some(std::mem::replace(start, *start + size + gap))).collect()
+        };
+        let span = |sizes: &[f32], tracks: &Range<usize>, gap: f32| {
+            sizes[tracks.clone()].iter().sum::<f32>() + gap * tracks.len().saturating_sub(1) as f32
+        };
+        let column_starts = starts(&columns, column_gap);
+
+        for child in self.children.iter_mut().filter(|child| child.is_absolute()) {
+            child.dimensions.content = Rect { width: 0.0, height: 0.0, ..content };
+        }
+        let mut boxes: Vec<&mut LayoutBox<'a>> = self.children.iter_mut().filter(|child| !child.is_absolute()).collect();
+        for (layout_box, (tracks, _)) in boxes.iter_mut().zip(&placement.areas) {
+            // Blocks are stretched across their areas if their widths and margins are 'auto'.
+            let auto = layout_box.auto_margins();
+            let area_width = span(&columns, tracks, column_gap);
+            let width = match layout_box.box_type {
+                BoxType::BlockNode(node) | BoxType::FlexNode(node) | BoxType::GridNode(node)
+                    if node.computed.width.is_none() && !auto.left && !auto.right => {
+                    let d = layout_box.edges(&items_ctx);
+                    let edges = d.margin.left + d.margin.right + d.border.left + d.border.right + d.padding.left + d.padding.right;
+                    Some((area_width - edges).max(0.0))
+                }
+                BoxType::AnonymousBlock(_) => Some(area_width),
+                _ => None,
+            };
+            layout_box.layout_in_area(content.x + column_starts[tracks.start], content.y, width, area_width, &items_ctx, fonts);
+        }
+
+        let heights: Vec<(Range<usize>, f32, f32)> = boxes.iter().zip(&placement.areas).map(|(layout_box, (_, tracks))| {
+            let height = layout_box.dimensions.margin_box().height;
+            (tracks.clone(), height, height)
+        }).collect();
+        let rows = size_tracks(&style.grid_template_rows, placement.rows, &heights, height, row_gap, &items_ctx);
+        let row_starts = starts(&rows, row_gap);
+
+        // Room left over in an area goes to the item's 'auto' margins if it has any. If not,
+        // blocks whose heights are 'auto' are stretched down to fill it, and anything else is
+        // aligned in it.
+        for (layout_box, (column_tracks, row_tracks)) in boxes.iter_mut().zip(&placement.areas) {
+            let auto = layout_box.auto_margins();
+            let align = match layout_box.box_type {
+                _ if layout_box.is_anonymous() => style.align_items,
+                _ => layout_box.get_style_node().computed.align_self.unwrap_or(style.align_items),
+            };
+            let auto_height = match layout_box.box_type {
+                BoxType::BlockNode(node) | BoxType::FlexNode(node) | BoxType::GridNode(node) => node.computed.height.is_none(),
+                BoxType::AnonymousBlock(_) => true,
+                _ => false,
+            };
+            let area_height = span(&rows, row_tracks, row_gap);
+            if align == Alignment::Stretch && auto_height && !auto.top && !auto.bottom {
+                let d = layout_box.dimensions;
+                let edges = d.margin.top + d.margin.bottom + d.border.top + d.border.bottom + d.padding.top + d.padding.bottom;
+                layout_box.dimensions.content.height = layout_box.dimensions.content.height.max(area_height - edges);
+            }
+            let margin_box = layout_box.dimensions.margin_box();
+            let (left, right, x) = align_in(span(&columns, column_tracks, column_gap) - margin_box.width,
+                                            (auto.left, auto.right), Alignment::Start);
+            let (top, bottom, y) = align_in(area_height - margin_box.height, (auto.top, auto.bottom), align);
+            let margin = &mut layout_box.dimensions.margin;
+            margin.left += left;
+            margin.right += right;
+            margin.top += top;
+            margin.bottom += bottom;
+            layout_box.translate(left + x, row_starts[row_tracks.start] + top + y);
+            layout_box.apply_relative_offset(&items_ctx);
+        }
+
+        self.dimensions.content.height = height.unwrap_or_else(|| {
+            rows.iter().sum::<f32>() + row_gap * rows.len().saturating_sub(1) as f32
+        });
+        self.calculate_block_height(ctx);
+    }
+
+    // The narrowest and widest each of a grid container's items can be, with the columns
+    // they're placed in.
+    fn grid_item_widths(&self, placement: &GridPlacement, ctx: &LengthContext, fonts: &Fonts) -> Vec<(Range<usize>, f32, f32)> {
+        let items = self.children.iter().filter(|child| !child.is_absolute());
+        items.zip(&placement.areas).map(|(child, (tracks, _))| {
+            let widths = if child.is_anonymous() { child.content_widths(ctx, fonts) } else { child.outer_widths(ctx, fonts) };
+            (tracks.clone(), widths.min, widths.max)
+        }).collect()
+    }
+
+    // Measure a flex item along the main axis of a flex container whose content box is
+    // 'content': its width in a row, and in a column, its height once it's been laid out
+    // as wide as it's going to be, or if the column 'wrap's, as wide as its contents, since
+    // the lines' widths aren't known yet. Percentage sizes are of the container's, in 'ctx'.
+    fn measure_flex_item(&mut self, row: bool, wrap: bool, content: Rect, align_items: Alignment,
+                         ctx: &LengthContext, fonts: &Fonts) -> FlexItem {
+        let node = if self.is_anonymous() { None } else { Some(self.get_style_node()) };
+        let style = node.map(|node| &*node.computed);
+        let ctx = node.map_or(*ctx, |node| LengthContext { font_size: node.font.size, ..*ctx });
+        let resolve_height = |value: &Value| match value {
+            Value::Length(..) => Some(value.resolve(&ctx)),
+            _ => ctx.containing_height.map(|height| value.resolve(&LengthContext { containing_width: height, ..ctx })),
+        };
+        let main = |value: &Value| if row { Some(value.resolve(&ctx)) } else { resolve_height(value) };
+        let width = style.and_then(|style| style.width.as_ref()).map(|width| width.resolve(&ctx))
+            .or_else(|| self.ratio_width(&ctx));
+        let height = style.and_then(|style| style.height.as_ref()).and_then(resolve_height);
+
+        // An item is stretched across the line if it's aligned that way and its size across
+        // it, and its margins, are 'auto'.
+        let auto = self.auto_margins();
+        let align = style.and_then(|style| style.align_self).unwrap_or(align_items);
+        let stretch = align == Alignment::Stretch && match row {
+            true => height.is_none() && !auto.top && !auto.bottom,
+            false => width.is_none() && !auto.left && !auto.right,
+        };
+        // The basis is the item's size along the main axis if it's 'auto', and its contents'
+        // size if that's 'auto' too, or if it's a percentage of a height that isn't known.
+        let basis = match style.map(|style| &style.flex_basis) {
+            Some(FlexBasis::Length(basis)) => main(basis),
+            Some(FlexBasis::Content) => None,
+            _ => if row { width } else { height },
+        };
+
+        // A scroll container's contents don't stop it shrinking.
+        let scrolls = !matches!(self.overflow(), Overflow::Visible | Overflow::Clip);
+        let edges = self.edges(&ctx);
+        let (base, min, edges) = if row {
+            let widths = match self.box_type {
+                BoxType::SvgNode(_) | BoxType::ImageNode(..) => {
+                    let width = self.replaced_size(&ctx).0;
+                    IntrinsicWidths { min: width, max: width, line: 0.0 }
+                }
+                _ => self.content_widths(&ctx, fonts),
+            };
+            let min = width.map_or(widths.min, |width| width.min(widths.min));
+            (basis.unwrap_or(widths.max), min, edges.margin.left + edges.margin.right + edges.border.left
+                + edges.border.right + edges.padding.left + edges.padding.right)
+        } else {
+            let horizontal = edges.margin.left + edges.margin.right + edges.border.left + edges.border.right
+                + edges.padding.left + edges.padding.right;
+            let width = if stretch && !wrap { Some((content.width - horizontal).max(0.0)) } else { None };
+            self.layout_in_area(content.x, content.y, width, content.width, &ctx, fonts);
+            let d = self.dimensions;
+            (basis.unwrap_or(d.content.height), d.content.height, d.margin.top + d.margin.bottom + d.border.top
+                + d.border.bottom + d.padding.top + d.padding.bottom)
+        };
+        let min = if scrolls { 0.0 } else { min };
+        FlexItem {
+            base,
+            min,
+            edges,
+            grow: style.map_or(0.0, |style| style.flex_grow),
+            shrink: style.map_or(1.0, |style| style.flex_shrink),
+            size: base.max(min),
+            frozen: false,
+            align,
+            stretch,
+        }
+    }
+
+    // Lay out a box in an area of its own, like a flex item, with the top left corner of its
+    // margin box at ('x', 'y') and its 'auto' margins as zero. Its content box is 'width'
+    // wide, or if that's 'None', as wide as its 'width', or as its contents fit in
+    // 'available'. Its contents are laid out around floats of their own.
+    fn layout_in_area(&mut self, x: f32, y: f32, width: Option<f32>, available: f32, ctx: &LengthContext, fonts: &Fonts) {
+        let ctx = match self.box_type {
+            _ if self.is_anonymous() => *ctx,
+            _ => LengthContext { font_size: self.get_style_node().font.size, ..*ctx },
+        };
+        let edges = self.edges(&ctx);
+        let horizontal = edges.margin.left + edges.margin.right + edges.border.left + edges.border.right
+            + edges.padding.left + edges.padding.right;
+        let width = width.or_else(|| match self.box_type {
+            BoxType::BlockNode(node) | BoxType::FlexNode(node) | BoxType::GridNode(node) => {
+                node.computed.width.as_ref().map(|width| width.resolve(&ctx))
+            }
+            _ => None,
+        }).or_else(|| self.ratio_width(&ctx)).unwrap_or_else(|| {
+            let widths = self.content_widths(&ctx, fonts);
+            widths.max.min(widths.min.max(available - horizontal))
+        });
+        match self.box_type {
+            BoxType::BlockNode(_) | BoxType::FlexNode(_) | BoxType::GridNode(_) => {
+                self.dimensions = edges;
+                let d = &mut self.dimensions;
+                d.content = Rect {
+                    x: x + d.margin.left + d.border.left + d.padding.left,
+                    y: y + d.margin.top + d.border.top + d.padding.top,
+                    width,
+                    height: 0.0,
+                };
+                self.layout_items(&ctx, fonts);
+            }
+            _ => {
+                let containing_block = Dimensions {
+                    content: Rect { x, y, width: width + horizontal, height: 0.0 },
+                    ..Default::default()
+                };
+                self.layout(containing_block, ctx, fonts, None);
+            }
+        }
+    }
+
+    // The box's margins, borders and padding, with 'auto' margins as zero. Anonymous boxes
+    // have none.
+    fn edges(&self, ctx: &LengthContext) -> Dimensions {
+        let mut d = Dimensions::default();
+        if self.is_anonymous() {
+            return d;
+        }
+        let style_node = self.get_style_node();
+        let ctx = LengthContext { font_size: style_node.font.size, ..*ctx };
+        let style = &style_node.computed;
+        let margin = |margin: &Option<Value>| margin.as_ref().map_or(0.0, |margin| margin.resolve(&ctx));
+        let edges = |sides: &Sides<Value>| EdgeSizes {
+            top: sides.top.resolve(&ctx),
+            right: sides.right.resolve(&ctx),
+            bottom: sides.bottom.resolve(&ctx),
+            left: sides.left.resolve(&ctx),
+        };
+        d.margin = EdgeSizes {
+            top: margin(&style.margin.top),
+            right: margin(&style.margin.right),
+            bottom: margin(&style.margin.bottom),
+            left: margin(&style.margin.left),
+        };
+        d.border = edges(&style.border_width);
+        d.padding = edges(&style.padding);
+        d
+    }
+
+    // Which of the box's margins are 'auto'.
+    fn auto_margins(&self) -> Sides<bool> {
+        if self.is_anonymous() {
+            return Sides { top: false, right: false, bottom: false, left: false };
+        }
+        let margin = &self.get_style_node().computed.margin;
+        Sides {
+            top: margin.top.is_none(),
+            right: margin.right.is_none(),
+            bottom: margin.bottom.is_none(),
+            left: margin.left.is_none(),
+        }
+    }
+
+    // The gaps between a flex or grid container's columns and rows. Percentages are of its
+    // content box, whose size is in 'ctx', and are zero for a height that isn't known.
+    fn gaps(&self, ctx: &LengthContext) -> (f32, f32) {
+        let style = &self.get_style_node().computed;
+        let row_gap = match style.row_gap {
+            ref gap @ Value::Length(..) => gap.resolve(ctx),
+            ref gap => ctx.containing_height.map_or(0.0, |height| gap.resolve(&LengthContext { containing_width: height, ..*ctx })),
+        };
+        (style.column_gap.resolve(ctx), row_gap)
+    }
+
+    fn calculate_block_width(&mut self, containing_block: Dimensions, ctx: &LengthContext, fonts: &Fonts) {
+        let style = &self.get_style_node().computed;
+
+        // 'width' has initial value 'auto', and margins can be 'auto' too ('None' here).
+        let resolve = |value: &Option<Value>| value.as_ref().map(|value| value.resolve(ctx));
+        let mut width = resolve(&style.width).or_else(|| self.ratio_width(ctx));
+        let mut margin_left = resolve(&style.margin.left);
+        let mut margin_right = resolve(&style.margin.right);
+
+        let border_left = style.border_width.left.resolve(ctx);
+        let border_right = style.border_width.right.resolve(ctx);
+
+        let padding_left = style.padding.left.resolve(ctx);
+        let padding_right = style.padding.right.resolve(ctx);
+
+        // A table is as wide as its columns need: never narrower than they can be, and with
+        // an 'auto' width, no wider than they'd be without breaking lines, if there's room.
+        if let BoxType::TableNode(_) = self.box_type {
+            let widths = self.content_widths(ctx, fonts);
+            let available = containing_block.content.width - margin_left.unwrap_or(0.0) - margin_right.unwrap_or(0.0)
+                - border_left - border_right - padding_left - padding_right;
+            width = Some(width.unwrap_or(widths.max.min(available)).max(widths.min));
+        }
+
+        let total: f32 = [margin_left, margin_right, width].iter().map(|v| v.unwrap_or(0.0)).sum::<f32>()
+            + border_left + border_right + padding_left + padding_right;
+
+        if width.is_some() && total > containing_block.content.width {
+            if margin_left.is_none() {
+                margin_left = Some(0.0);
+            }
+            if margin_right.is_none() {
+                margin_right = Some(0.0);
+            }
+        }
+
+        let underflow = containing_block.content.width - total;
+
+        // An inline block's or a float's 'auto' margins are zero, and an 'auto' width shrinks
+        // to fit its contents: as wide as they are unbroken, but no wider than the room there
+        // is, unless they can't be made any narrower.
+        if matches!(self.box_type, BoxType::InlineBlockNode(_)) || self.float() != Float::None || self.is_inline_table() {
+            margin_left = margin_left.or(Some(0.0));
+            margin_right = margin_right.or(Some(0.0));
+            if width.is_none() {
+                let widths = self.content_widths(ctx, fonts);
+                width = Some(widths.max.min(widths.min.max(underflow)));
+            }
+        } else {
+            match (width.is_none(), margin_left.is_none(), margin_right.is_none()) {
+                // If the values are overconstrained, calculate margin_right.
+                (false, false, false) => {
+                    margin_right = margin_right.map(|margin| margin + underflow);
+                }
+
+                (false, false, true) => { margin_right = Some(0.0); }
+                (false, true, false) => { margin_left  = Some(0.0); }
+
+                (true, _, _) => {
+                    if margin_left.is_none() { margin_left = Some(0.0); }
+                    if margin_right.is_none() { margin_right = Some(0.0); }
+
+                    if underflow >= 0.0 {
+                        // Expand width to fill the underflow.
+                        width = Some(underflow);
+                    } else {
+                        width = Some(0.0);
+                        margin_right = margin_right.map(|margin| margin + underflow);
+                    }
+                }
+
+                (false, true, true) => {
+                    margin_left = Some(underflow / 2.0);
+                    margin_right = Some(underflow / 2.0);
+                }
+            }
+        }
+
+        let d = &mut self.dimensions;
+        d.content.width = width.unwrap_or(0.0);
+
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
+
+        d.border.left = border_left;
+        d.border.right = border_right;
+
+        d.margin.left = margin_left.unwrap_or(0.0);
+        d.margin.right = margin_right.unwrap_or(0.0);
+    }
+
+    fn calculate_block_position(&mut self, containing_block: Dimensions, ctx: &LengthContext) {
+        let style = &self.get_style_node().computed;
+        let d = &mut self.dimensions;
+
+        // If margin-top or margin-bottom is 'auto', the used value is zero.
+        let zero = Value::Length(0.0, Unit::Px);
+        d.margin.top = style.margin.top.as_ref().unwrap_or(&zero).resolve(ctx);
+        d.margin.bottom = style.margin.bottom.as_ref().unwrap_or(&zero).resolve(ctx);
+
+        d.border.top = style.border_width.top.resolve(ctx);
+        d.border.bottom = style.border_width.bottom.resolve(ctx);
+
+        d.padding.top = style.padding.top.resolve(ctx);
+        d.padding.bottom = style.padding.bottom.resolve(ctx);
+
+        d.content.x = containing_block.content.x +
+            d.margin.left + d.border.left + d.padding.left;
+
+        d.content.y = containing_block.content.height + containing_block.content.y +
+            d.margin.top + d.border.top + d.padding.top;
+    }
+
+    // Lay out the block's children one below the other. Vertical margins that adjoin
+    // collapse into one: those of siblings, and the top and bottom margins of empty blocks.
+    // The block's own top and bottom margins collapse with its first and last children's
+    // too if 'collapse' says so. 'ctx.containing_height' is the block's own height, if it's
+    // known, since that's what its children's percentage heights are of.
+    fn layout_block_children(&mut self, ctx: LengthContext, fonts: &Fonts, floats: &mut Floats,
+                             (collapse_top, collapse_bottom): (bool, bool)) {
+        let d = &mut self.dimensions;
+        let child_ctx = LengthContext { containing_width: d.content.width, ..ctx };
+        #[cfg(feature = "parallel")]
+        lay_out_ahead(&mut self.children, Dimensions { content: Rect { height: 0.0, ..d.content }, ..*d }, ctx, fonts);
+        // The margins below the content so far, which go between it and the next block.
+        // Until there's some content, those that collapse with the block's own top margin
+        // have already been put above the block, along with it.
+        let mut margin = CollapsedMargin::default();
+        let mut at_top = collapse_top;
+        for child in &mut self.children {
+            let y = d.content.y + d.content.height + if at_top { 0.0 } else { margin.value() };
+            // An absolutely positioned box is laid out once the box it's positioned against
+            // is. For now, note where it would have gone.
+            if child.is_absolute() {
+                child.dimensions.content = Rect { x: d.content.x, y, width: 0.0, height: 0.0 };
+                continue;
+            }
+            // Floats go beside the content below them, and don't take up any height.
+            if child.float() != Float::None {
+                child.layout(*d, ctx, fonts, Some(floats));
+                child.place_float(floats, Rect { y, ..d.content });
+                child.apply_relative_offset(&child_ctx);
+                continue;
+            }
+            margin = margin.join(child.top_margin(&child_ctx));
+            let mut border_top = d.content.y + d.content.height + if at_top { 0.0 } else { margin.value() };
+            // A block that clears floats has its border box moved down below them, and its
+            // top margin doesn't collapse with the margins above it.
+            if let BoxType::BlockNode(node) = child.box_type {
+                if node.computed.clear != Clear::None && floats.clearance(node.computed.clear) > border_top {
+                    border_top = floats.clearance(node.computed.clear);
+                    margin = CollapsedMargin::default();
+                    at_top = false;
+                }
+            }
+            // The child works out where its border box goes from its own top margin, so the
+            // containing block it's given ends that far above where it should go.
+            let containing_block = Dimensions {
+                content: Rect { height: border_top - child.vertical_margins(&child_ctx).0 - d.content.y, ..d.content },
+                ..*d
+            };
+            child.layout(containing_block, ctx, fonts, Some(floats));
+            if child.is_empty(&child_ctx) {
+                margin = margin.join(child.bottom_margin(&child_ctx));
+            } else {
+                // Track the height so each child is laid out below the previous content.
+                let border_box = child.dimensions.border_box();
+                d.content.height = border_box.y + border_box.height - d.content.y;
+                margin = child.bottom_margin(&child_ctx);
+                at_top = false;
+            }
+            child.apply_relative_offset(&child_ctx);
+        }
+        // The margins below the last child collapse with the block's bottom margin, or go
+        // inside the block if they can't.
+        if !collapse_bottom && !at_top {
+            d.content.height += margin.value();
+        }
+    }
+
+    // The box's own top and bottom margins, with 'auto' as zero.
+    fn vertical_margins(&self, ctx: &LengthContext) -> (f32, f32) {
+        match self.box_type {
+            BoxType::BlockNode(node) | BoxType::SvgNode(node) | BoxType::ImageNode(node, _) | BoxType::TableNode(Some(node)) |
+            BoxType::FlexNode(node) | BoxType::GridNode(node) => {
+                let ctx = LengthContext { font_size: node.font.size, ..*ctx };
+                let resolve = |margin: &Option<Value>| margin.as_ref().map_or(0.0, |margin| margin.resolve(&ctx));
+                (resolve(&node.computed.margin.top), resolve(&node.computed.margin.bottom))
+            }
+            _ => (0.0, 0.0),
+        }
+    }
+
+    // Do the top and bottom margins of the block collapse with those of its first and last
+    // children? They do if there's no border or padding between them, unless it makes a
+    // block formatting context. The bottom ones don't if the block's height is set.
+    fn collapses_with_children(&self, ctx: &LengthContext) -> (bool, bool) {
+        let node = match self.box_type {
+            BoxType::BlockNode(node) if !self.establishes_formatting_context() => node,
+            _ => return (false, false),
+        };
+        let ctx = LengthContext { font_size: node.font.size, ..*ctx };
+        let style = &node.computed;
+        let separated = |border: &Value, padding: &Value| border.resolve(&ctx) != 0.0 || padding.resolve(&ctx) != 0.0;
+        let auto_height = self.definite_height(&ctx).is_none();
+        (!separated(&style.border_width.top, &style.padding.top),
+         !separated(&style.border_width.bottom, &style.padding.bottom) && auto_height)
+    }
+
+    // Is there nothing in the box that takes up height, so that its top and bottom margins
+    // collapse together? An anonymous block or line is empty if all it has is floats and
+    // absolutely positioned boxes.
+    fn is_empty(&self, ctx: &LengthContext) -> bool {
+        let empty_children = || self.children.iter().all(|child| {
+            child.float() != Float::None || child.is_absolute() || child.is_empty(ctx)
+        });
+        match self.box_type {
+            BoxType::BlockNode(_) => self.collapses_with_children(ctx) == (true, true) && empty_children(),
+            BoxType::AnonymousBlock(_) | BoxType::LineBox => empty_children(),
+            _ => false,
+        }
+    }
+
+    // The margins that collapse with the box's top margin: its own, and those of its first
+    // children, or of the empty children before its first one with content. Percentages
+    // are of the width in 'ctx' all the way down, since the children's aren't known yet.
+    fn top_margin(&self, ctx: &LengthContext) -> CollapsedMargin {
+        let mut margin = CollapsedMargin::new(self.vertical_margins(ctx).0);
+        if self.collapses_with_children(ctx).0 {
+            for child in self.children.iter().filter(|child| child.float() == Float::None && !child.is_absolute()) {
+                margin = margin.join(child.top_margin(ctx));
+                if !child.is_empty(ctx) {
+                    break;
+                }
+                margin = margin.join(child.bottom_margin(ctx));
+            }
+        }
+        margin
+    }
+
+    // The margins that collapse with the box's bottom margin, like 'top_margin'.
+    fn bottom_margin(&self, ctx: &LengthContext) -> CollapsedMargin {
+        let mut margin = CollapsedMargin::new(self.vertical_margins(ctx).1);
+        if self.collapses_with_children(ctx).1 {
+            for child in self.children.iter().rev().filter(|child| child.float() == Float::None && !child.is_absolute()) {
+                margin = margin.join(child.bottom_margin(ctx));
+                if !child.is_empty(ctx) {
+                    break;
+                }
+                margin = margin.join(child.top_margin(ctx));
+            }
+        }
+        margin
+    }
+
+    fn calculate_block_height(&mut self, ctx: &LengthContext) {
+        // If the height is set to an explicit length, use that exact length. If it's worked
+        // out from the width and 'aspect-ratio', it's still tall enough for the contents,
+        // unless they're clipped or scrolled.
+        // Otherwise, just keep the value set by 'layout_block_children'.
+        if let Some(height) = self.specified_height(ctx) {
+            self.dimensions.content.height = height;
+        } else if let Some(height) = self.ratio_height(ctx) {
+            let visible = self.overflow() == Overflow::Visible;
+            let d = &mut self.dimensions.content;
+            d.height = if visible { d.height.max(height) } else { height };
+        }
+    }
+
+    // The box's 'height', or 'None' for 'auto'. A percentage is of the containing block's
+    // height, and is taken as 'auto' when that isn't known before the box is laid out,
+    // since it depends on the box's own height then.
+    fn specified_height(&self, ctx: &LengthContext) -> Option<f32> {
+        match self.get_style_node().computed.height {
+            Some(ref height @ Value::Length(..)) => Some(height.resolve(ctx)),
+            Some(ref height) => ctx.containing_height.map(|containing_height| {
+                height.resolve(&LengthContext { containing_width: containing_height, ..*ctx })
+            }),
+            None => None,
+        }
+    }
+
+    // The box's height if it's known before its contents are laid out: its 'height', or
+    // what its width and 'aspect-ratio' make it.
+    fn definite_height(&self, ctx: &LengthContext) -> Option<f32> {
+        self.specified_height(ctx).or_else(|| self.ratio_height(ctx))
+    }
+
+    // The ratio of the box's width to its height from 'aspect-ratio', if it has one. A
+    // replaced element's ratio is part of working out its size, and inline boxes and the
+    // parts of tables don't have one.
+    fn aspect_ratio(&self) -> Option<f32> {
+        match self.box_type {
+            BoxType::BlockNode(node) | BoxType::InlineBlockNode(node) | BoxType::FlexNode(node) |
+            BoxType::GridNode(node) => node.computed.aspect_ratio.ratio,
+            _ => None,
+        }
+    }
+
+    // The box's 'auto' height, worked out from its width and 'aspect-ratio', once its width
+    // is known.
+    fn ratio_height(&self, ctx: &LengthContext) -> Option<f32> {
+        let ratio = self.aspect_ratio()?;
+        match self.specified_height(ctx) {
+            Some(_) => None,
+            None => Some(self.dimensions.content.width / ratio),
+        }
+    }
+
+    // The box's 'auto' width, worked out from its height and 'aspect-ratio', if its height
+    // is known.
+    fn ratio_width(&self, ctx: &LengthContext) -> Option<f32> {
+        let ratio = self.aspect_ratio()?;
+        match self.get_style_node().computed.width {
+            Some(_) => None,
+            None => self.specified_height(ctx).map(|height| height * ratio),
+        }
+    }
+
+    // The narrowest and widest the box's contents can be laid out, not counting its own
+    // margins, borders and padding.
+    fn content_widths(&self, ctx: &LengthContext, fonts: &Fonts) -> IntrinsicWidths {
+        let mut widths: IntrinsicWidths = Default::default();
+        if let BoxType::FlexNode(node) = self.box_type {
+            // A flex container's items are side by side in a row, on a line that only breaks if
+            // they wrap, with gaps between them. A percentage gap is of the width being worked
+            // out here, so it counts as none.
+            let style = &node.computed;
+            if matches!(style.flex_direction, FlexDirection::Row | FlexDirection::RowReverse) {
+                let gap = match style.column_gap {
+                    ref gap @ Value::Length(..) => gap.resolve(&LengthContext { font_size: node.font.size, ..*ctx }),
+                    _ => 0.0,
+                };
+                let items = self.children.iter().filter(|child| !child.is_absolute());
+                for (i, child) in items.enumerate() {
+                    let mut item: IntrinsicWidths = Default::default();
+                    child.add_widths(&mut item, ctx, fonts);
+                    let gap = if i > 0 { gap } else { 0.0 };
+                    widths.min = if style.flex_wrap == FlexWrap::NoWrap { widths.min + gap + item.min } else { widths.min.max(item.min) };
+                    widths.max += gap + item.max;
+                }
+                widths.max = widths.max.max(widths.min);
+                return widths;
+            }
+        }
+        if let BoxType::GridNode(node) = self.box_type {
+            // A grid container's columns with the gaps between them, as narrow as its items
+            // can be and as wide as they'd like. A percentage gap counts as none, like in a
+            // flex container.
+            let gap = match node.computed.column_gap {
+                ref gap @ Value::Length(..) => gap.resolve(&LengthContext { font_size: node.font.size, ..*ctx }),
+                _ => 0.0,
+            };
+            let placement = GridPlacement::new(self);
+            let items = self.grid_item_widths(&placement, ctx, fonts);
+            let narrowest: Vec<_> = items.iter().map(|(tracks, min, _)| (tracks.clone(), *min, *min)).collect();
+            let gaps = gap * placement.columns.saturating_sub(1) as f32;
+            let template = &node.computed.grid_template_columns;
+            widths.min = size_tracks(template, placement.columns, &narrowest, None, gap, ctx).iter().sum::<f32>() + gaps;
+            widths.max = size_tracks(template, placement.columns, &items, None, gap, ctx).iter().sum::<f32>() + gaps;
+            widths.max = widths.max.max(widths.min);
+            return widths;
+        }
+        if let BoxType::TableNode(_) = self.box_type {
+            // A table's columns and the spacing around them, but at least as wide as its
+            // captions can be.
+            let columns = self.column_widths(&TableGrid::new(self), ctx, fonts);
+            let spacing = if columns.is_empty() { 0.0 } else { self.border_spacing(ctx).0 * (columns.len() + 1) as f32 };
+            widths.min = columns.iter().map(|column| column.min).sum::<f32>() + spacing;
+            widths.max = columns.iter().map(|column| column.max).sum::<f32>() + spacing;
+            for caption in self.children.iter().filter(|child| matches!(child.box_type, BoxType::BlockNode(_))) {
+                widths.min = widths.min.max(caption.outer_widths(ctx, fonts).min);
+            }
+            widths.max = widths.max.max(widths.min);
+            return widths;
+        }
+        for child in &self.children {
+            child.add_widths(&mut widths, ctx, fonts);
+        }
+        widths
+    }
+
+    // The narrowest and widest the box can be, including its margins, borders and padding.
+    // A box with a set width can only be that wide.
+    fn outer_widths(&self, ctx: &LengthContext, fonts: &Fonts) -> IntrinsicWidths {
+        let style_node = match self.box_type {
+            BoxType::TableNode(None) => return self.content_widths(ctx, fonts), // An anonymous table has no edges
+            _ => self.get_style_node(),
+        };
+        let ctx = LengthContext { font_size: style_node.font.size, ..*ctx };
+        let style = &style_node.computed;
+        let edges = style.margin.left.as_ref().map_or(0.0, |margin| margin.resolve(&ctx))
+            + style.margin.right.as_ref().map_or(0.0, |margin| margin.resolve(&ctx))
+            + style.border_width.left.resolve(&ctx) + style.border_width.right.resolve(&ctx)
+            + style.padding.left.resolve(&ctx) + style.padding.right.resolve(&ctx);
+        let width = match (self.box_type, &style.width) {
+            (BoxType::SvgNode(_) | BoxType::ImageNode(..), _) => Some(self.replaced_size(&ctx).0),
+            (_, Some(width @ Value::Length(..))) => Some(width.resolve(&ctx)),
+            _ => self.ratio_width(&ctx),
+        };
+        let widths = match width {
+            Some(width) => IntrinsicWidths { min: width, max: width, line: 0.0 },
+            None => self.content_widths(&ctx, fonts),
+        };
+        IntrinsicWidths { min: widths.min + edges, max: widths.max + edges, line: 0.0 }
+    }
+
+    // Add the box to the intrinsic widths of the box it's in.
+    fn add_widths(&self, widths: &mut IntrinsicWidths, ctx: &LengthContext, fonts: &Fonts) {
+        match self.box_type {
+            // Out of the flow, or outside the box
+            _ if self.is_absolute() || self.is_outside_marker() => {}
+            BoxType::InlineNode(style_node) => match style_node.text() {
+                // Text can break after whitespace if it wraps, and at newlines that are kept,
+                // and its whitespace collapses, like 'place_text' does. A tab is taken to be
+                // 8 spaces wide.
+                Some(text) => {
+                    let (metrics, size) = (fonts.select(&style_node.font), style_node.font.size);
+                    let white_space = style_node.computed.white_space;
+                    let mut unbroken = 0.0; // The width since the text could last break
+                    let mut space = false; // Whether the text so far ends in collapsed whitespace
+                    for word in text.split_inclusive(char::is_whitespace) {
+                        let visible = word.trim_end();
+                        let whitespace = &word[visible.len()..];
+                        let width = metrics.measure(visible, size);
+                        space &= visible.is_empty();
+                        if whitespace == "\n" && white_space.keeps_newlines() {
+                            widths.add_inline(unbroken + width, width);
+                            widths.line = 0.0;
+                            unbroken = 0.0;
+                            continue;
+                        }
+                        let after = match whitespace {
+                            _ if whitespace.is_empty() || white_space.collapses() && space => 0.0,
+                            _ if white_space.collapses() => {
+                                space = true;
+                                metrics.measure(" ", size)
+                            }
+                            "\t" => 8.0 * metrics.measure(" ", size),
+                            _ => metrics.measure(whitespace, size),
+                        };
+                        widths.add_inline(unbroken + width, width + after);
+                        if !white_space.wraps() {
+                            unbroken += width + after;
+                        }
+                    }
+                }
+                None => {
+                    let style = &style_node.computed;
+                    let ctx = LengthContext { font_size: style_node.font.size, ..*ctx };
+                    widths.add_inline(0.0, style.margin.left.as_ref().map_or(0.0, |margin| margin.resolve(&ctx))
+                        + style.border_width.left.resolve(&ctx) + style.padding.left.resolve(&ctx));
+                    for child in &self.children {
+                        child.add_widths(widths, &ctx, fonts);
+                    }
+                    widths.add_inline(0.0, style.margin.right.as_ref().map_or(0.0, |margin| margin.resolve(&ctx))
+                        + style.border_width.right.resolve(&ctx) + style.padding.right.resolve(&ctx));
+                }
+            },
+            BoxType::InlineBlockNode(_) | BoxType::SvgNode(_) | BoxType::ImageNode(..) => {
+                let outer = self.outer_widths(ctx, fonts);
+                widths.add_inline(outer.min, outer.max);
+            }
+            BoxType::BlockNode(_) if self.float() != Float::None => {
+                let outer = self.outer_widths(ctx, fonts);
+                widths.add_inline(outer.min, outer.max);
+            }
+            BoxType::TableNode(_) if self.is_inline_table() => {
+                let outer = self.outer_widths(ctx, fonts);
+                widths.add_inline(outer.min, outer.max);
+            }
+            BoxType::BlockNode(_) | BoxType::TableNode(_) | BoxType::FlexNode(_) | BoxType::GridNode(_) => {
+                widths.add_block(self.outer_widths(ctx, fonts))
+            }
+            BoxType::AnonymousBlock(_) => widths.add_block(self.content_widths(ctx, fonts)),
+            BoxType::LineBox | BoxType::TextRun(..) => {}
+            // Only found in tables, which measure them with 'column_widths'
+            BoxType::TableRowGroupNode(_) | BoxType::TableRowNode(_) | BoxType::TableCellNode(_) => {}
+        }
+    }
+}
+
+// A flex item's size along its flex container's main axis, as it's worked out.
+struct FlexItem {
+    base: f32, // Its flex base size, which it grows or shrinks from
+    min: f32, // The smallest it can be, which is as small as its contents can be
+    edges: f32, // Its margins, borders and padding along the main axis
+    grow: f32,
+    shrink: f32,
+    size: f32,
+    frozen: bool, // Whether its size is settled
+    align: Alignment, // Where it goes across the line
+    stretch: bool, // Whether it's stretched across the line
+}
+
+// Where the first of 'count' boxes goes along an axis with 'free' room to spare, and the
+// gap to add between each of them, for 'justify-content' and 'align-content'. Without
+// room to spare, the ones that space boxes out centre them instead.
+fn distribute(alignment: Alignment, free: f32, count: f32) -> (f32, f32) {
+    match alignment {
+        Alignment::End => (free, 0.0),
+        Alignment::Center => (free / 2.0, 0.0),
+        Alignment::SpaceBetween if free > 0.0 && count > 1.0 => (0.0, free / (count - 1.0)),
+        Alignment::SpaceAround if free > 0.0 => (free / count / 2.0, free / count),
+        Alignment::SpaceEvenly if free > 0.0 => (free / (count + 1.0), free / (count + 1.0)),
+        Alignment::SpaceAround | Alignment::SpaceEvenly => (free / 2.0, 0.0),
+        _ => (0.0, 0.0),
+    }
+}
+
+// Where a box goes along an axis with 'free' room to spare around it, given whether its
+// margins before and after it on that axis are 'auto': as much to add to each of those
+// margins, and how far to move it on top of that. 'auto' margins take up all of the room
+// there is, and without them, the box is aligned by 'align'.
+fn align_in(free: f32, auto: (bool, bool), align: Alignment) -> (f32, f32, f32) {
+    match auto {
+        (true, true) if free > 0.0 => (free / 2.0, free / 2.0, 0.0),
+        (true, false) if free > 0.0 => (free, 0.0, 0.0),
+        (false, true) if free > 0.0 => (0.0, free, 0.0),
+        _ => (0.0, 0.0, match align {
+            Alignment::End => free,
+            Alignment::Center => free / 2.0,
+            _ => 0.0,
+        }),
+    }
+}
+
+// Grow or shrink flex items to fill the 'available' room along the main axis. Each item
+// gets a share of the room that's left over in proportion to its 'flex-grow', or gives up
+// a share of the room there isn't in proportion to its 'flex-shrink' times its base size.
+// Any that would be smaller than they can be are frozen at their smallest, and the room is
+// shared between the others again.
+fn resolve_flexible_lengths(items: &mut [FlexItem], available: f32) {
+    let growing = items.iter().map(|item| item.edges + item.size).sum::<f32>() < available;
+    for item in items.iter_mut() {
+        // Items that can't flex, or are as small as they can be already, keep their size.
+        let factor = if growing { item.grow } else { item.shrink };
+        item.frozen = factor == 0.0 || (!growing && item.base < item.min);
+    }
+    let free = |items: &[FlexItem]| {
+        available - items.iter().map(|item| item.edges + if item.frozen { item.size } else { item.base }).sum::<f32>()
+    };
+    let initial_free = free(items);
+    while items.iter().any(|item| !item.frozen) {
+        // Factors that add up to less than 1 only share out that fraction of the room.
+        let factor = |item: &FlexItem| if growing { item.grow } else { item.shrink };
+        let factors: f32 = items.iter().filter(|item| !item.frozen).map(factor).sum();
+        let mut free = free(items);
+        if factors < 1.0 && (initial_free * factors).abs() < free.abs() {
+            free = initial_free * factors;
+        }
+        let scaled: f32 = items.iter().filter(|item| !item.frozen).map(|item| item.shrink * item.base).sum();
+        let mut violated = false;
+        for item in items.iter_mut().filter(|item| !item.frozen) {
+            item.size = match growing {
+                true => item.base + free * item.grow / factors,
+                false if scaled > 0.0 => item.base + free * item.shrink * item.base / scaled,
+                false => item.base,
+            };
+            violated |= item.size < item.min;
+        }
+        for item in items.iter_mut().filter(|item| !item.frozen) {
+            if item.size < item.min {
+                item.size = item.min;
+                item.frozen = true;
+            } else if !violated {
+                item.frozen = true;
+            }
+        }
+    }
+}
+
+// How narrow some content can be laid out, with its lines broken everywhere they can be,
+// and how wide, with none broken.
+#[derive(Default, Clone, Copy)]
+struct IntrinsicWidths {
+    min: f32,
+    max: f32,
+    line: f32, // The unbroken width of the inline content on the current line
+}
+
+impl IntrinsicWidths {
+    // Add inline content, which can't be broken narrower than 'min', and is 'max' wide.
+    fn add_inline(&mut self, min: f32, max: f32) {
+        self.min = self.min.max(min);
+        self.line += max;
+        self.max = self.max.max(self.line);
+    }
+
+    // Add a block, which goes on lines of its own.
+    fn add_block(&mut self, widths: IntrinsicWidths) {
+        self.min = self.min.max(widths.min);
+        self.max = self.max.max(widths.max);
+        self.line = 0.0;
+    }
+}
+
+// Vertical margins that adjoin, which collapse into one margin: the largest positive one
+// plus the most negative one.
+#[derive(Default, Clone, Copy)]
+struct CollapsedMargin {
+    positive: f32,
+    negative: f32,
+}
+
+impl CollapsedMargin {
+    fn new(margin: f32) -> CollapsedMargin {
+        CollapsedMargin { positive: margin.max(0.0), negative: margin.min(0.0) }
+    }
+
+    fn join(self, other: CollapsedMargin) -> CollapsedMargin {
+        CollapsedMargin { positive: self.positive.max(other.positive), negative: self.negative.min(other.negative) }
+    }
+
+    fn value(self) -> f32 {
+        self.positive + self.negative
+    }
+}
+
+// Where a table's cells go in its grid of rows and columns.
+struct TableGrid {
+    columns: usize,
+    cells: Vec<GridCell>, // In the order the cells are in, row by row
+}
+
+#[derive(Clone, Copy)]
+struct GridCell {
+    row: usize,
+    column: usize,
+    rows: usize, // How many rows and columns it spans
+    columns: usize,
+}
+
+impl TableGrid {
+    // Put each of a table's cells in the first column along its row that isn't taken by a
+    // cell from a row above. A cell can't span past the last row.
+    fn new(table: &LayoutBox) -> TableGrid {
+        let rows: Vec<&LayoutBox> = table.table_rows().collect();
+        let mut cells = Vec::new();
+        // How many rows, from the current one down, each column is taken for.
+        let mut taken: Vec<usize> = Vec::new();
+        for (row, row_box) in rows.iter().enumerate() {
+            let mut column = 0;
+            for cell in &row_box.children {
+                while taken.get(column).is_some_and(|&rows| rows > 0) {
+                    column += 1;
+                }
+                let (columns, rowspan) = cell.spans();
+                let spanned_rows = if rowspan == 0 { rows.len() - row } else { rowspan.min(rows.len() - row) };
+                if taken.len() < column + columns {
+                    taken.resize(column + columns, 0);
+                }
+                for rows in &mut taken[column..column + columns] {
+                    *rows = spanned_rows;
+                }
+                cells.push(GridCell { row, column, rows: spanned_rows, columns });
+                column += columns;
+            }
+            for rows in &mut taken {
+                *rows = rows.saturating_sub(1);
+            }
+        }
+        TableGrid { columns: taken.len(), cells }
+    }
+}
+
+// Where a grid container's items go in its grid of rows and columns.
+struct GridPlacement {
+    columns: usize,
+    rows: usize,
+    areas: Vec<(Range<usize>, Range<usize>)>, // The columns and rows each item spans, in order
+}
+
+impl GridPlacement {
+    // Put each of a grid container's items between the lines it says it goes between, and
+    // those that don't say in the first area that's free for them, going along each row in
+    // turn from the last one placed. Items that give their row but not their column go
+    // first, in the first free area on that row. Rows are added below the template's for
+    // items that don't fit in them, and columns to its right for those placed past it.
+    fn new(container: &LayoutBox) -> GridPlacement {
+        let style = &container.get_style_node().computed;
+        let explicit = (style.grid_template_columns.len(), style.grid_template_rows.len());
+        let items: Vec<_> = container.children.iter().filter(|child| !child.is_absolute()).map(|child| {
+            match child.box_type {
+                _ if child.is_anonymous() => (Err(1), Err(1)),
+                _ => {
+                    let style = &child.get_style_node().computed;
+                    (resolve_grid_lines(style.grid_column, explicit.0), resolve_grid_lines(style.grid_row, explicit.1))
+                }
+            }
+        }).collect();
+        let columns = items.iter()
+            .map(|(column, _)| match column { Ok(tracks) => tracks.end, Err(span) => *span })
+            .fold(explicit.0, usize::max);
+
+        // Which cells of each row are taken so far.
+        let mut taken: Vec<Vec<bool>> = Vec::new();
+        let is_free = |taken: &[Vec<bool>], area: &(Range<usize>, Range<usize>)| {
+            area.1.clone().all(|row| taken.get(row).is_none_or(|cells| area.0.clone().all(|column| !cells[column])))
+        };
+        let take = |taken: &mut Vec<Vec<bool>>, area: &(Range<usize>, Range<usize>)| {
+            if taken.len() < area.1.end {
+                taken.resize(area.1.end, vec![false; columns]);
+            }
+            for row in area.1.clone() {
+                taken[row][area.0.clone()].fill(true);
+            }
+        };
+        let mut areas = vec![(0..0, 0..0); items.len()];
+        for (area, item) in areas.iter_mut().zip(&items) {
+            if let (Ok(column), Ok(row)) = item {
+                *area = (column.clone(), row.clone());
+                take(&mut taken, area);
+            }
+        }
+        for (area, item) in areas.iter_mut().zip(&items) {
+            if let (Err(span), Ok(row)) = item {
+                // An item too wide to fit anywhere on its row overlaps whatever is at its start.
+                let start = (0..=columns - span).find(|&start| is_free(&taken, &(start..start + span, row.clone())));
+                *area = (start.unwrap_or(0)..start.unwrap_or(0) + span, row.clone());
+                take(&mut taken, area);
+            }
+        }
+        let (mut row, mut column) = (0, 0); // Where to look for a free area from
+        for (area, item) in areas.iter_mut().zip(&items) {
+            match *item {
+                (Ok(ref tracks), Err(span)) => {
+                    if tracks.start < column {
+                        row += 1;
+                    }
+                    row = (row..).find(|&row| is_free(&taken, &(tracks.clone(), row..row + span))).unwrap_or(row);
+                    column = tracks.start;
+                    *area = (tracks.clone(), row..row + span);
+                }
+                (Err(columns_spanned), Err(rows_spanned)) => {
+                    loop {
+                        if column + columns_spanned > columns {
+                            (row, column) = (row + 1, 0);
+                        } else if is_free(&taken, &(column..column + columns_spanned, row..row + rows_spanned)) {
+                            break;
+                        } else {
+                            column += 1;
+                        }
+                    }
+                    *area = (column..column + columns_spanned, row..row + rows_spanned);
+                    column += columns_spanned;
+                }
+                _ => continue,
+            }
+            take(&mut taken, area);
+        }
+        let rows = areas.iter().map(|(_, rows)| rows.end).fold(explicit.1, usize::max);
+        GridPlacement { columns, rows, areas }
+    }
+}
+
+// The tracks a grid item goes in along one axis, going by the lines it says it starts and
+// ends at, in a grid with 'explicit' tracks from its template, or if it doesn't say where
+// it goes, how many tracks it spans. Lines count from 1, or back from the end of the
+// explicit grid if they're negative. Lines before the start of the grid are its first.
+fn resolve_grid_lines(lines: (GridLine, GridLine), explicit: usize) -> Result<Range<usize>, usize> {
+    let line = |n: i32| if n > 0 { n as usize - 1 } else { (explicit as i32 + 1 + n).max(0) as usize };
+    match lines {
+        (GridLine::Line(start), GridLine::Line(end)) => {
+            let (start, end) = (line(start).min(line(end)), line(start).max(line(end)));
+            Ok(start..end.max(start + 1))
+        }
+        (GridLine::Line(start), GridLine::Span(span)) => Ok(line(start)..line(start) + span),
+        (GridLine::Line(start), GridLine::Auto) => Ok(line(start)..line(start) + 1),
+        (GridLine::Span(span), GridLine::Line(end)) => {
+            let end = line(end).max(span);
+            Ok(end - span..end)
+        }
+        (GridLine::Auto, GridLine::Line(end)) => {
+            let end = line(end).max(1);
+            Ok(end - 1..end)
+        }
+        (GridLine::Span(span), _) | (GridLine::Auto, GridLine::Span(span)) => Err(span),
+        (GridLine::Auto, GridLine::Auto) => Err(1),
+    }
+}
+
+// The sizes of 'count' grid tracks along one axis, with 'available' room along it if it's
+// known, given the template's sizes for the first of them and the least and most room each
+// item needs across the tracks it spans. Fixed tracks are the size they say, and 'auto'
+// ones as big as their items need, growing toward as big as their items would like if
+// there's room. Then the room left over is shared between 'fr' tracks in proportion to
+// their 'fr', though each is at least as big as its items need, or without any, between
+// the 'auto' tracks. Without a limit on the room, every track is as big as its items would
+// like, and 'fr' tracks keep to their proportions.
+fn size_tracks(template: &[TrackSize], count: usize, items: &[(Range<usize>, f32, f32)], available: Option<f32>,
+               gap: f32, ctx: &LengthContext) -> Vec<f32> {
+    // Percentages of a size that isn't known are 'auto'.
+    let fixed: Vec<Option<f32>> = (0..count).map(|i| match template.get(i) {
+        Some(TrackSize::Length(length @ Value::Length(..))) => Some(length.resolve(ctx)),
+        Some(TrackSize::Length(length)) => {
+            available.map(|available| length.resolve(&LengthContext { containing_width: available, ..*ctx }))
+        }
+        _ => None,
+    }).collect();
+    let fr: Vec<Option<f32>> = (0..count).map(|i| match template.get(i) {
+        Some(&TrackSize::Flex(fr)) => Some(fr),
+        _ => None,
+    }).collect();
+    let mut base: Vec<f32> = fixed.iter().map(|size| size.unwrap_or(0.0)).collect();
+    let mut limit = base.clone();
+    for (tracks, min, max) in items.iter().filter(|(tracks, ..)| tracks.len() == 1 && fixed[tracks.start].is_none()) {
+        base[tracks.start] = base[tracks.start].max(*min);
+        limit[tracks.start] = limit[tracks.start].max(*max);
+    }
+    // Items spanning more than one track make the 'auto' tracks they span bigger, equally,
+    // if the tracks are too small for them, unless they span an 'fr' track, which can take
+    // up the room instead.
+    for (tracks, min, max) in items.iter().filter(|(tracks, ..)| tracks.len() > 1) {
+        let autos: Vec<usize> = tracks.clone().filter(|&i| fixed[i].is_none()).collect();
+        if autos.is_empty() || autos.iter().any(|&i| fr[i].is_some()) {
+            continue;
+        }
+        let gaps = gap * (tracks.len() - 1) as f32;
+        let base_needed = min - gaps - base[tracks.clone()].iter().sum::<f32>();
+        let limit_needed = max - gaps - tracks.clone().map(|i| limit[i].max(base[i])).sum::<f32>();
+        for &i in &autos {
+            base[i] += base_needed.max(0.0) / autos.len() as f32;
+            limit[i] = limit[i].max(base[i]) + limit_needed.max(0.0) / autos.len() as f32;
+        }
+    }
+    for (limit, base) in limit.iter_mut().zip(&base) {
+        *limit = limit.max(*base);
+    }
+
+    let gaps = gap * count.saturating_sub(1) as f32;
+    let Some(available) = available else {
+        // The size of '1fr' is as big as it has to be for each 'fr' track to be as big as its
+        // items would like.
+        let fraction = (0..count)
+            .filter_map(|i| fr[i].filter(|&fr| fr > 0.0).map(|fr| limit[i] / fr))
+            .fold(0.0, f32::max);
+        return (0..count).map(|i| fr[i].map_or(limit[i], |fr| (fr * fraction).max(base[i]))).collect();
+    };
+    let mut sizes = base;
+    let free = available - gaps - sizes.iter().sum::<f32>();
+    let growth: Vec<f32> = (0..count).map(|i| if fr[i].is_some() { 0.0 } else { limit[i] - sizes[i] }).collect();
+    let total: f32 = growth.iter().sum();
+    if free > 0.0 && total > 0.0 {
+        for (size, growth) in sizes.iter_mut().zip(&growth) {
+            *size += growth * (free / total).min(1.0);
+        }
+    }
+    if fr.iter().any(Option::is_some) {
+        // Tracks whose share would be smaller than their items need keep to that, and the
+        // rest is shared again between the others. 'fr's that add up to less than 1 only
+        // share out that fraction of the room.
+        let mut inflexible = vec![false; count];
+        loop {
+            let flexible = |i: usize| fr[i].is_some() && !inflexible[i];
+            let leftover = available - gaps - (0..count).filter(|&i| !flexible(i)).map(|i| sizes[i]).sum::<f32>();
+            let fraction = leftover.max(0.0) / (0..count).filter_map(|i| fr[i].filter(|_| flexible(i))).sum::<f32>().max(1.0);
+            let too_small: Vec<usize> = (0..count).filter(|&i| flexible(i) && fr[i].unwrap() * fraction < sizes[i]).collect();
+            if too_small.is_empty() {
+                for i in (0..count).filter(|&i| flexible(i)) {
+                    sizes[i] = fr[i].unwrap() * fraction;
+                }
+                break;
+            }
+            for i in too_small {
+                inflexible[i] = true;
+            }
+        }
+    } else {
+        let free = available - gaps - sizes.iter().sum::<f32>();
+        let autos: Vec<usize> = (0..count).filter(|&i| fixed[i].is_none()).collect();
+        if free > 0.0 {
+            for &i in &autos {
+                sizes[i] += free / autos.len() as f32;
+            }
+        }
+    }
+    sizes
+}
+
+// Split 'amount' up in proportion to 'weights', or equally if they're all zero.
+fn share(amount: f32, weights: &[f32]) -> Vec<f32> {
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|&weight| if total > 0.0 { amount * weight / total } else { amount / weights.len() as f32 }).collect()
+}
+
+// The fewest boxes a block's children should have between them for them to be laid out in
+// parallel.
+#[cfg(feature = "parallel")]
+const PARALLEL_LAYOUT_MIN_BOXES: usize = 2000;
+
+// Set on the threads that lay out ahead, so that they don't start threads of their own.
+#[cfg(feature = "parallel")]
+thread_local!(static LAYING_OUT_AHEAD: std::cell::Cell<bool> = const { std::cell::Cell::new(false) });
+
+// Lay out the children of a block that don't depend on anything around them ahead of time,
+// on as many threads as there are cores, as if they were at the top of the block. Each is
+// then reused when it's laid out in turn and moved to where it goes, unless it turns out
+// to be beside floats (see 'reuse'). This is only done for blocks with enough in them to
+// be worth it, and not again in the children it's done for.
+#[cfg(feature = "parallel")]
+fn lay_out_ahead(children: &mut [LayoutBox], containing_block: Dimensions, ctx: LengthContext, fonts: &Fonts) {
+    if LAYING_OUT_AHEAD.with(|ahead| ahead.get()) {
+        return;
+    }
+    let mut ahead = Vec::new();
+    let mut size = 0;
+    for child in children.iter_mut().filter(|child| child.previous.is_none()) {
+        if let Some(child_size) = child.self_contained_size() {
+            size += child_size;
+            ahead.push(child);
+        }
+    }
+    // Tests use several threads even on one core, so that this gets exercised.
+    let threads = if cfg!(test) { 4 } else { std::thread::available_parallelism().map_or(1, |threads| threads.get()) };
+    if size < PARALLEL_LAYOUT_MIN_BOXES || ahead.len() < 2 || threads < 2 {
+        return;
+    }
+    let per_thread = ahead.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        for chunk in ahead.chunks_mut(per_thread) {
+            // Layout recurses as deep as the tree, so give the threads as much stack as the
+            // main thread usually has.
+            std::thread::Builder::new().stack_size(8 << 20).spawn_scoped(scope, move || {
+                LAYING_OUT_AHEAD.with(|ahead| ahead.set(true));
+                for child in chunk {
+                    let mut previous = child.clone();
+                    previous.layout(containing_block, ctx, fonts, Some(&mut Floats::default()));
+                    child.previous = Some(Box::new(previous));
+                }
+            }).expect("couldn't start a layout thread");
+        }
+    });
+}
+
+// The floats placed so far in a block formatting context: a part of the layout, like the
+// inside of an inline block, whose lines go around its floats.
+#[derive(Default)]
+struct Floats {
+    boxes: Vec<(Float, Rect)>, // Their margin boxes, in the order they were placed
+}
+
+impl Floats {
+    // The room there is for a line at 'y' between 'left' and 'right', beside the floats.
+    fn space(&self, y: f32, left: f32, right: f32) -> (f32, f32) {
+        self.band(y, y, left, right)
+    }
+
+    // The room there is between 'left' and 'right' beside the floats that overlap the
+    // band from 'top' to 'bottom' (or the point at 'top', if they're the same).
+    fn band(&self, top: f32, bottom: f32, mut left: f32, mut right: f32) -> (f32, f32) {
+        for &(side, rect) in self.overlapping(top, bottom) {
+            match side {
+                Float::Left => left = left.max(rect.x + rect.width),
+                Float::Right => right = right.min(rect.x),
+                Float::None => {}
+            }
+        }
+        (left, right)
+    }
+
+    fn overlapping(&self, top: f32, bottom: f32) -> impl Iterator<Item = &(Float, Rect)> {
+        self.boxes.iter().filter(move |(_, rect)| {
+            (rect.y <= top && top < rect.y + rect.height) || (top < rect.y && rect.y < bottom)
+        })
+    }
+
+    // The bottom of the lowest float.
+    fn bottom(&self) -> f32 {
+        self.boxes.iter().map(|(_, rect)| rect.y + rect.height).fold(f32::NEG_INFINITY, f32::max)
+    }
+
+    // How far down something that clears floats on the 'clear' side has to go.
+    fn clearance(&self, clear: Clear) -> f32 {
+        self.boxes.iter()
+            .filter(|&&(side, _)| matches!((clear, side), (Clear::Both, _) | (Clear::Left, Float::Left) | (Clear::Right, Float::Right)))
+            .map(|(_, rect)| rect.y + rect.height)
+            .fold(f32::NEG_INFINITY, f32::max)
+    }
+
+    // Place a float whose margin box is 'width' by 'height' as high in 'area' as it can go,
+    // but no higher than the floats before it, and as far to its side as it can go, going
+    // down past other floats until there's room for it. Returns where its margin box goes.
+    fn place(&mut self, side: Float, clear: Clear, width: f32, height: f32, area: Rect) -> (f32, f32) {
+        let (left, right) = (area.x, area.x + area.width);
+        let mut y = area.y.max(self.clearance(clear));
+        if let Some(&(_, last)) = self.boxes.last() {
+            y = y.max(last.y);
+        }
+        loop {
+            let (band_left, band_right) = self.band(y, y + height, left, right);
+            let next = self.overlapping(y, y + height).map(|(_, rect)| rect.y + rect.height).fold(f32::INFINITY, f32::min);
+            if width <= band_right - band_left || next == f32::INFINITY {
+                let x = if side == Float::Right { band_right - width } else { band_left };
+                self.boxes.push((side, Rect { x, y, width, height }));
+                return (x, y);
+            }
+            y = next;
+        }
+    }
+}
+
+// Puts inline-level boxes into line boxes, starting a new line when the next box doesn't
+// fit. An inline box whose contents go onto more than one line is split into a fragment
+// on each line, like the box itself but with its left edges on the first fragment only,
+// and its right edges on the last one. Lines are made with their top left corner at (0, 0),
+// and moved into place in the area they go in when they're finished, beside any floats.
+struct LineBuilder<'a, 'f> {
+    area: Rect, // Where the lines go, from its top down
+    container: &'a StyledNode<'a>, // The block container the lines are in
+    ctx: LengthContext,
+    fonts: &'f Fonts,
+    floats: &'f mut Floats,
+    y: f32, // The top of the current line, from the top of the area
+    left: f32, // Where the current line starts, from the left of the area
+    width: f32, // The width of the current line
+    x: f32, // Where the next box goes on the current line
+    end: f32, // Where what's visible on the current line ends, before any whitespace after it
+    lines: Vec<LayoutBox<'a>>, // The lines before the current one
+    line: LayoutBox<'a>, // The current line, with its top-level fragments
+    empty: bool, // Whether nothing that takes up space has been put on the current line
+    space: bool, // Whether whitespace would collapse away here, after whitespace or at the start of a line
+    // The fragments of the inline boxes open on the current line, outermost first.
+    open: Vec<LayoutBox<'a>>,
+    placed: Vec<LayoutBox<'a>>, // The floats that have been placed
+    waiting: Vec<LayoutBox<'a>>, // Floats to place at the start of the next line
+}
+
+impl<'a, 'f> LineBuilder<'a, 'f> {
+    fn new(area: Rect, container: &'a StyledNode<'a>, ctx: LengthContext, fonts: &'f Fonts,
+           floats: &'f mut Floats) -> LineBuilder<'a, 'f> {
+        let mut builder = LineBuilder {
+            area, container, ctx, fonts, floats, y: 0.0, left: 0.0, width: area.width, x: 0.0, end: 0.0, lines: Vec::new(),
+            line: LayoutBox::new(BoxType::LineBox), empty: true, space: true, open: Vec::new(), placed: Vec::new(),
+            waiting: Vec::new(),
+        };
+        builder.fit_line();
+        builder
+    }
+
+    // Fit the current line into the room beside the floats at its top.
+    fn fit_line(&mut self) {
+        let (left, right) = self.floats.space(self.area.y + self.y, self.area.x, self.area.x + self.area.width);
+        self.left = left - self.area.x;
+        self.width = right - left;
+    }
+
+    // Place a float that's been laid out at the top of the current line, and fit the line
+    // around it.
+    fn place_float(&mut self, mut layout_box: LayoutBox<'a>) {
+        layout_box.place_float(self.floats, Rect { y: self.area.y + self.y, ..self.area });
+        self.placed.push(layout_box);
+        self.fit_line();
+    }
+
+    // Add a finished fragment or atomic box to the innermost open box, or to the line.
+    fn push(&mut self, layout_box: LayoutBox<'a>) {
+        self.open.last_mut().unwrap_or(&mut self.line).children.push(layout_box);
+    }
+
+    fn place(&mut self, mut layout_box: LayoutBox<'a>) {
+        let (ctx, fonts) = (&self.ctx, self.fonts);
+        match layout_box.box_type {
+            _ if layout_box.is_absolute() => {
+                // An absolutely positioned box is laid out later, but where it would have
+                // gone on the line is noted.
+                layout_box.dimensions.content.x = self.x;
+                self.push(layout_box);
+            }
+            BoxType::BlockNode(_) if layout_box.float() != Float::None => {
+                // A float goes at the top of the current line if it fits beside what's on
+                // it already, and at the top of the next line if not.
+                let containing_block = Dimensions { content: Rect { height: 0.0, ..self.area }, ..Default::default() };
+                layout_box.layout(containing_block, self.ctx, fonts, Some(self.floats));
+                if !self.empty && self.x + layout_box.dimensions.margin_box().width > self.width {
+                    self.waiting.push(layout_box);
+                } else {
+                    self.place_float(layout_box);
+                }
+            }
+            BoxType::InlineNode(style_node) if style_node.text().is_some() => {
+                self.place_text(style_node, &style_node.text().unwrap());
+            }
+            BoxType::InlineNode(style_node) => {
+                // Inline boxes are as wide as their contents, plus their horizontal edges.
+                let style = &style_node.computed;
+                let d = &mut layout_box.dimensions;
+                d.margin.left = style.margin.left.as_ref().map_or(0.0, |margin| margin.resolve(ctx));
+                d.margin.right = style.margin.right.as_ref().map_or(0.0, |margin| margin.resolve(ctx));
+                d.border.left = style.border_width.left.resolve(ctx);
+                d.border.right = style.border_width.right.resolve(ctx);
+                d.border.top = style.border_width.top.resolve(ctx);
+                d.border.bottom = style.border_width.bottom.resolve(ctx);
+                d.padding.left = style.padding.left.resolve(ctx);
+                d.padding.right = style.padding.right.resolve(ctx);
+                d.padding.top = style.padding.top.resolve(ctx);
+                d.padding.bottom = style.padding.bottom.resolve(ctx);
+
+                self.x += d.margin.left + d.border.left + d.padding.left;
+                if d.margin.left + d.border.left + d.padding.left != 0.0 {
+                    self.end = self.x;
+                }
+                d.content.x = self.x;
+                let children = std::mem::take(&mut layout_box.children);
+                self.open.push(layout_box);
+                for child in children {
+                    self.place(child);
+                }
+                let mut fragment = self.open.pop().unwrap();
+                let d = &mut fragment.dimensions;
+                d.content.width = self.x - d.content.x;
+                self.x += d.padding.right + d.border.right + d.margin.right;
+                if d.padding.right + d.border.right + d.margin.right != 0.0 {
+                    self.end = self.x;
+                }
+                self.push(fragment);
+            }
+            _ => {
+                // Anything else, like an SVG image or an inline block, is laid out on its own
+                // and put on the line whole. A block inside an inline box takes up a line of
+                // its own. An outside marker goes just before where the line starts, and
+                // takes up none of it.
+                let block = layout_box.is_block_in_inline();
+                let outside = layout_box.is_outside_marker();
+                let mut containing_block: Dimensions = Default::default();
+                containing_block.content.width = self.width;
+                layout_box.layout(containing_block, self.ctx, fonts, None);
+                let margin_box = layout_box.dimensions.margin_box();
+                if outside {
+                    layout_box.translate(-margin_box.width - margin_box.x, -margin_box.y);
+                    self.push(layout_box);
+                    self.empty = false;
+                    return;
+                }
+                if !self.empty && (block || self.x + margin_box.width > self.width) {
+                    self.break_line(block);
+                }
+                layout_box.translate(self.x - margin_box.x, -margin_box.y);
+                self.x += margin_box.width;
+                self.end = self.x;
+                self.push(layout_box);
+                self.empty = false;
+                self.space = false;
+                if block {
+                    self.break_line(true);
+                }
+            }
+        }
+    }
+
+    // Put a text node's text on lines in runs, as wide as the font's advance widths add up
+    // to and as tall as its ascent and descent. Lines break after whitespace if 'white-space'
+    // lets them wrap, leaving it out at the end of a line, and at newlines if it keeps them.
+    // A word too wide for a line of its own sticks out of it, unless 'overflow-wrap' lets it
+    // break between any two characters. Whitespace that collapses is as wide as a space, or
+    // takes up no room at all after other whitespace or at the start of a line, and a tab
+    // that doesn't goes on to the next tab stop, every 8 spaces along the line.
+    fn place_text(&mut self, style_node: &'a StyledNode<'a>, text: &str) {
+        let metrics = self.fonts.select(&style_node.font);
+        let size = style_node.font.size;
+        let height = metrics.ascent(size) + metrics.descent(size);
+        let white_space = style_node.computed.white_space;
+        let break_word = white_space.wraps() && style_node.computed.overflow_wrap == OverflowWrap::BreakWord;
+        let space = metrics.measure(" ", size);
+
+        // The current line's run starts at byte 'start' of the text and at 'x', and its text
+        // up to 'end', not counting whitespace after that, takes it to 'end_x'.
+        let (mut start, mut x) = (0, self.x);
+        let (mut end, mut end_x) = (0, self.x);
+        let mut word_start = 0;
+        for word in text.split_inclusive(char::is_whitespace) {
+            let visible = word.trim_end();
+            let width = metrics.measure(visible, size);
+            if white_space.wraps() && !visible.is_empty() && self.x + width > self.width && (!self.empty || end > start) {
+                self.push_run(style_node, (start, end), x, end_x - x, height);
+                self.break_line(false);
+                start = word_start;
+                x = self.x;
+            }
+            if break_word && self.x + width > self.width {
+                for (i, c) in visible.char_indices() {
+                    let advance = metrics.advance(c, size);
+                    if self.x + advance > self.width && (!self.empty || end > start) {
+                        self.push_run(style_node, (start, end), x, end_x - x, height);
+                        self.break_line(false);
+                        start = word_start + i;
+                        x = self.x;
+                    }
+                    self.x += advance;
+                    end = word_start + i + c.len_utf8();
+                    end_x = self.x;
+                    self.end = self.x;
+                }
+            } else if !visible.is_empty() {
+                self.x += width;
+                end = word_start + visible.len();
+                end_x = self.x;
+                self.end = self.x;
+            }
+            self.space &= visible.is_empty();
+
+            let (whitespace, next) = (&word[visible.len()..], word_start + word.len());
+            match whitespace {
+                "" => {}
+                "\n" if white_space.keeps_newlines() => {
+                    // The line ends here even if there's nothing on it, so it isn't empty.
+                    self.push_run(style_node, (start, next - 1), x, self.x - x, height);
+                    self.empty = false;
+                    self.break_line(true);
+                    (start, x) = (next, self.x);
+                    (end, end_x) = (next, self.x);
+                }
+                _ if white_space.collapses() && self.space => {
+                    if start == next - whitespace.len() {
+                        (start, x) = (next, self.x);
+                    }
+                }
+                _ if white_space.collapses() => {
+                    self.x += space;
+                    self.space = true;
+                }
+                _ => {
+                    self.x = match whitespace {
+                        "\t" => ((self.x / (8.0 * space)).floor() + 1.0) * 8.0 * space,
+                        _ => self.x + metrics.measure(whitespace, size),
+                    };
+                    self.space = false;
+                    // Kept whitespace only hangs off the end of a line that can wrap.
+                    if !white_space.wraps() {
+                        (end, end_x) = (next, self.x);
+                        self.end = self.x;
+                    }
+                }
+            }
+            word_start = next;
+        }
+        self.push_run(style_node, (start, text.len()), x, self.x - x, height);
+    }
+
+    // Put the text 'range' of a text node on the line, if it isn't empty.
+    fn push_run(&mut self, style_node: &'a StyledNode<'a>, range: (usize, usize), x: f32, width: f32, height: f32) {
+        if range.1 > range.0 {
+            let mut run = LayoutBox::new(BoxType::TextRun(style_node, range.0, range.1));
+            run.dimensions.content = Rect { x, y: 0.0, width, height };
+            self.push(run);
+            self.empty = false;
+        }
+    }
+
+    // Finish the current line and start a new one, continuing the open inline boxes on it.
+    // A 'forced' break is one that isn't there because the line is full.
+    fn break_line(&mut self, forced: bool) {
+        let continued: Vec<LayoutBox<'a>> = self.open.iter().map(|open| {
+            let mut fragment = LayoutBox::new(open.box_type);
+            fragment.dimensions = open.dimensions;
+            let d = &mut fragment.dimensions;
+            d.margin.left = 0.0;
+            d.border.left = 0.0;
+            d.padding.left = 0.0;
+            fragment
+        }).collect();
+        while let Some(mut fragment) = self.open.pop() {
+            let d = &mut fragment.dimensions;
+            d.content.width = self.x - d.content.x;
+            d.margin.right = 0.0;
+            d.border.right = 0.0;
+            d.padding.right = 0.0;
+            self.push(fragment);
+        }
+        let line = std::mem::replace(&mut self.line, LayoutBox::new(BoxType::LineBox));
+        self.finish_line(line, !forced);
+        self.x = 0.0;
+        self.end = 0.0;
+        self.empty = true;
+        self.space = true;
+        for float in std::mem::take(&mut self.waiting) {
+            self.place_float(float);
+        }
+        self.fit_line();
+        for mut fragment in continued {
+            fragment.dimensions.content.x = 0.0;
+            self.open.push(fragment);
+        }
+    }
+
+    // Line up what's on a finished line by 'vertical-align' and 'text-align', and size the
+    // line to fit it. Only lines that are 'full' are justified, so not the last line, or one
+    // that ends in a forced break.
+    fn finish_line(&mut self, mut line: LayoutBox<'a>, full: bool) {
+        // The whitespace in the text on the line, apart from any after its end, which
+        // justifying the line spaces out.
+        fn spaces(layout_box: &LayoutBox, end: f32) -> usize {
+            match layout_box.box_type {
+                BoxType::TextRun(node, start, stop) => {
+                    let text = node.text().unwrap_or_default();
+                    let content = layout_box.dimensions.content;
+                    let text = if content.x + content.width > end + 0.01 { text[start..stop].trim_end() } else { &text[start..stop] };
+                    // Whitespace that collapses is one space however much of it there is.
+                    let collapses = node.computed.white_space.collapses();
+                    text.char_indices()
+                        .filter(|&(i, c)| c.is_whitespace() && !(collapses && text[..i].ends_with(char::is_whitespace)))
+                        .count()
+                }
+                BoxType::InlineNode(_) => layout_box.children.iter().map(|child| spaces(child, end)).sum(),
+                _ => 0,
+            }
+        }
+        // Move each box along by the room added before it, and widen text runs and the
+        // inline boxes they're in by the room added in them.
+        fn justify(layout_box: &mut LayoutBox, per_space: f32, end: f32, added: &mut f32) {
+            match layout_box.box_type {
+                BoxType::TextRun(..) => {
+                    let extra = per_space * spaces(layout_box, end) as f32;
+                    layout_box.dimensions.content.x += *added;
+                    layout_box.dimensions.content.width += extra;
+                    *added += extra;
+                }
+                BoxType::InlineNode(_) => {
+                    let before = *added;
+                    layout_box.dimensions.content.x += before;
+                    for child in &mut layout_box.children {
+                        justify(child, per_space, end, added);
+                    }
+                    layout_box.dimensions.content.width += *added - before;
+                }
+                _ => layout_box.translate(*added, 0.0),
+            }
+        }
+        // Whether the line is one a block inside an inline box has to itself.
+        fn holds_block(layout_box: &LayoutBox) -> bool {
+            layout_box.is_block_in_inline()
+                || matches!(layout_box.box_type, BoxType::InlineNode(_)) && layout_box.children.iter().any(holds_block)
+        }
+        // The line is tall enough for what's lined up around its baseline, and for each box
+        // lined up with its top or bottom. What's around the baseline includes a 'strut', an
+        // empty inline box with the font and line height of the block the line is in, unless
+        // the line holds a block. A line with nothing on it that takes up space has no
+        // height at all.
+        let font = &self.container.font;
+        let (ascent, descent) = self.content_area(self.container);
+        let half_leading = (self.line_height(self.container) - ascent - descent) / 2.0;
+        let strut = match line.children.iter().any(holds_block) {
+            true => (0.0, 0.0),
+            false => (-ascent - half_leading, descent + half_leading),
+        };
+        let (mut bounds, mut aligned) = (strut, Vec::new());
+        for child in &line.children {
+            self.measure(child, 0.0, font, &mut bounds, &mut aligned);
+        }
+        let height = match self.empty {
+            true => 0.0,
+            false => aligned.iter().map(|(top, bottom)| bottom - top).fold(bounds.1 - bounds.0, f32::max),
+        };
+        let mut aligned = aligned.iter();
+        for child in &mut line.children {
+            self.position(child, -bounds.0, font, height, &mut aligned);
+        }
+        line.baseline = -bounds.0;
+
+        // What doesn't fit on the line sticks out of its right edge, whatever the alignment.
+        let free = (self.width - self.end).max(0.0);
+        let count: usize = line.children.iter().map(|child| spaces(child, self.end)).sum();
+        let align = self.container.computed.text_align;
+        match align {
+            TextAlign::Right | TextAlign::Center => {
+                let shift = if align == TextAlign::Right { free } else { free / 2.0 };
+                for child in &mut line.children {
+                    child.translate(shift, 0.0);
+                }
+            }
+            TextAlign::Justify if full && count > 0 => {
+                let mut added = 0.0;
+                for child in &mut line.children {
+                    justify(child, free / count as f32, self.end, &mut added);
+                }
+            }
+            TextAlign::Left | TextAlign::Justify => {}
+        }
+        line.dimensions.content.width = self.width;
+        line.dimensions.content.height = height;
+        line.translate(self.area.x + self.left, self.area.y + self.y);
+        self.y += height;
+        self.lines.push(line);
+    }
+
+    // How far the glyphs of the font of 'node' reach above and below the baseline, which is
+    // how tall its text and inline boxes are.
+    fn content_area(&self, node: &StyledNode) -> (f32, f32) {
+        let metrics = self.fonts.select(&node.font);
+        (metrics.ascent(node.font.size), metrics.descent(node.font.size))
+    }
+
+    // The 'line-height' of 'node', which is its font's ascent, descent and line gap for
+    // 'normal'.
+    fn line_height(&self, node: &StyledNode) -> f32 {
+        let (size, metrics) = (node.font.size, self.fonts.select(&node.font));
+        match node.computed.line_height {
+            LineHeight::Normal => metrics.ascent(size) + metrics.descent(size) + metrics.line_gap(size),
+            LineHeight::Number(n) => n * size,
+            LineHeight::Length(ref length) => length.resolve(&LengthContext { font_size: size, ..self.ctx }),
+        }
+    }
+
+    // How far a box on a line reaches above and below its baseline. Text and inline boxes
+    // take up their line height, around their content area with half of the difference
+    // above it and half below. Anything else takes up its margin box, whose baseline is at
+    // its bottom unless it's an inline block with a line of its own.
+    fn extents(&self, layout_box: &LayoutBox) -> (f32, f32) {
+        match layout_box.box_type {
+            BoxType::TextRun(node, ..) | BoxType::InlineNode(node) => {
+                let (ascent, descent) = self.content_area(node);
+                let half_leading = (self.line_height(node) - ascent - descent) / 2.0;
+                (ascent + half_leading, descent + half_leading)
+            }
+            _ => {
+                let margin_box = layout_box.dimensions.margin_box();
+                let bottom = margin_box.y + margin_box.height;
+                let baseline = match layout_box.box_type {
+                    BoxType::InlineBlockNode(_) if layout_box.overflow() == Overflow::Visible => layout_box.last_baseline(),
+                    _ => None,
+                };
+                let baseline = baseline.unwrap_or(bottom);
+                (baseline - margin_box.y, bottom - baseline)
+            }
+        }
+    }
+
+    // How far below the baseline of the inline box it's in, whose font is 'parent', a box on
+    // a line goes by its 'vertical-align', or 'None' if it goes at the top or bottom of the
+    // line instead. 'ex' is taken to be half an 'em'.
+    fn baseline_shift(&self, layout_box: &LayoutBox, parent: &Font) -> Option<f32> {
+        let node = match layout_box.box_type {
+            // Text is lined up by the inline box it's in, and anonymous boxes by their baselines.
+            BoxType::TextRun(..) => return Some(0.0),
+            _ if layout_box.is_anonymous() => return Some(0.0),
+            _ => layout_box.get_style_node(),
+        };
+        let (ascent, descent) = self.extents(layout_box);
+        let metrics = self.fonts.select(parent);
+        Some(match node.computed.vertical_align {
+            VerticalAlign::Baseline => 0.0,
+            VerticalAlign::Middle => (ascent - descent) / 2.0 - parent.size / 4.0,
+            VerticalAlign::TextTop => ascent - metrics.ascent(parent.size),
+            VerticalAlign::TextBottom => metrics.descent(parent.size) - descent,
+            VerticalAlign::Length(ref length) => {
+                // A percentage is of the box's line height.
+                let line_height = self.line_height(node);
+                -length.resolve(&LengthContext { font_size: node.font.size, containing_width: line_height, ..self.ctx })
+            }
+            VerticalAlign::Top | VerticalAlign::Bottom => return None,
+        })
+    }
+
+    // Work out how far above and below a line's baseline a box on it and the boxes in it
+    // reach into 'bounds', given where the baseline of the inline box it's in is, and that
+    // box's font. Boxes at the top or bottom of the line are measured around their own
+    // baselines instead, and their bounds added to 'aligned', in order.
+    fn measure(&self, layout_box: &LayoutBox, baseline: f32, parent: &Font, bounds: &mut (f32, f32),
+               aligned: &mut Vec<(f32, f32)>) {
+        if layout_box.is_absolute() {
+            return;
+        }
+        let (ascent, descent) = self.extents(layout_box);
+        let shift = self.baseline_shift(layout_box, parent);
+        let mut own = (f32::INFINITY, f32::NEG_INFINITY);
+        let (baseline, target) = match shift {
+            Some(shift) => (baseline + shift, bounds),
+            None => (0.0, &mut own),
+        };
+        // An inline box's fragment only takes up room on the line if there's something in
+        // it, or it has edges, and not if all that's in it is a block with a line of its own.
+        let d = layout_box.dimensions;
+        let empty = matches!(layout_box.box_type, BoxType::InlineNode(_))
+            && d.margin.left + d.margin.right + d.border.left + d.border.right + d.padding.left + d.padding.right == 0.0
+            && layout_box.children.iter().all(|child| child.is_absolute() || child.is_block_in_inline());
+        if !empty {
+            target.0 = target.0.min(baseline - ascent);
+            target.1 = target.1.max(baseline + descent);
+        }
+        let index = aligned.len();
+        if shift.is_none() {
+            aligned.push((0.0, 0.0));
+        }
+        if let BoxType::InlineNode(node) = layout_box.box_type {
+            for child in &layout_box.children {
+                self.measure(child, baseline, &node.font, target, aligned);
+            }
+        }
+        if shift.is_none() {
+            aligned[index] = own;
+        }
+    }
+
+    // Put a box on a line, and the boxes in it, where 'measure' worked out they go, now that
+    // the line's 'height' is known. Where the baseline of the inline box it's in is, and that
+    // box's font, are given, and 'aligned' has the bounds of the boxes at the top or bottom
+    // of the line still to be put.
+    fn position(&self, layout_box: &mut LayoutBox, baseline: f32, parent: &Font, height: f32,
+                aligned: &mut std::slice::Iter<(f32, f32)>) {
+        if layout_box.is_absolute() {
+            return;
+        }
+        let baseline = match self.baseline_shift(layout_box, parent) {
+            Some(shift) => baseline + shift,
+            None => match (aligned.next(), &layout_box.get_style_node().computed.vertical_align) {
+                (Some(&(top, _)), VerticalAlign::Top) => -top,
+                (Some(&(_, bottom)), _) => height - bottom,
+                (None, _) => baseline,
+            },
+        };
+        match layout_box.box_type {
+            BoxType::TextRun(node, ..) => layout_box.dimensions.content.y = baseline - self.content_area(node).0,
+            BoxType::InlineNode(node) => {
+                let (ascent, descent) = self.content_area(node);
+                layout_box.dimensions.content.y = baseline - ascent;
+                layout_box.dimensions.content.height = ascent + descent;
+                for child in &mut layout_box.children {
+                    self.position(child, baseline, &node.font, height, aligned);
+                }
+            }
+            _ => {
+                let ascent = self.extents(layout_box).0;
+                let top = layout_box.dimensions.margin_box().y;
+                layout_box.translate(0.0, baseline - ascent - top);
+            }
+        }
+    }
+
+    // The lines, followed by the floats.
+    fn finish(mut self) -> Vec<LayoutBox<'a>> {
+        let line = std::mem::replace(&mut self.line, LayoutBox::new(BoxType::LineBox));
+        self.finish_line(line, false);
+        for float in std::mem::take(&mut self.waiting) {
+            self.place_float(float);
+        }
+        self.lines.extend(self.placed);
+        self.lines
+    }
+}
+
+// An indented outline of a layout tree, one box per line, with its type, the node it's
+// for, its content box and any margins, borders and padding. For debugging.
+pub fn dump(layout_box: &LayoutBox) -> String {
+    let mut output = String::new();
+    dump_box(layout_box, 0, &mut output);
+    output
+}
+
+fn dump_box(layout_box: &LayoutBox, depth: usize, output: &mut String) {
+    output.push_str(&"  ".repeat(depth));
+    output.push_str(&match layout_box.box_type {
+        BoxType::BlockNode(node) => format!("block {}", node.label()),
+        BoxType::InlineNode(node) => format!("inline {}", node.label()),
+        BoxType::InlineBlockNode(node) => format!("inline-block {}", node.label()),
+        BoxType::SvgNode(node) => format!("svg {}", node.label()),
+        BoxType::ImageNode(node, _) => format!("img {}", node.label()),
+        BoxType::AnonymousBlock(_) => "anonymous block".to_string(),
+        BoxType::LineBox => "line".to_string(),
+        BoxType::TextRun(node, start, end) => format!("text {:?}", &node.text().unwrap_or_default()[start..end]),
+        BoxType::TableNode(Some(node)) => format!("table {}", node.label()),
+        BoxType::TableNode(None) => "anonymous table".to_string(),
+        BoxType::TableRowGroupNode(node) => format!("row group {}", node.label()),
+        BoxType::TableRowNode(Some(node)) => format!("row {}", node.label()),
+        BoxType::TableRowNode(None) => "anonymous row".to_string(),
+        BoxType::TableCellNode(Some(node)) => format!("cell {}", node.label()),
+        BoxType::TableCellNode(None) => "anonymous cell".to_string(),
+        BoxType::FlexNode(node) => format!("flex {}", node.label()),
+        BoxType::GridNode(node) => format!("grid {}", node.label()),
+    });
+    let d = &layout_box.dimensions;
+    output.push_str(&format!(" at ({}, {}) size {}x{}", d.content.x, d.content.y, d.content.width, d.content.height));
+    for (name, edges) in [("margin", d.margin), ("border", d.border), ("padding", d.padding)] {
+        if [edges.top, edges.right, edges.bottom, edges.left].iter().any(|&edge| edge != 0.0) {
+            output.push_str(&format!(" {} {} {} {} {}", name, edges.top, edges.right, edges.bottom, edges.left));
+        }
+    }
+    output.push('\n');
+    for child in &layout_box.children {
+        dump_box(child, depth + 1, output);
+    }
+}
+
+impl Dimensions {
+    // The area covered by the content area plus its padding.
+    pub fn padding_box(self) -> Rect {
+        self.content.expanded_by(self.padding)
+    }
+
+    // The area covered by the content area plus its padding and borders.
+    pub fn border_box(self) -> Rect {
+        self.padding_box().expanded_by(self.border)
+    }
+
+    // The area covered by the content area plus its padding, borders and margin.
+    fn margin_box(self) -> Rect {
+        self.border_box().expanded_by(self.margin)
+    }
+}
+
+impl Rect {
+    // The smallest rectangle that covers both of these.
+    pub fn union(self, other: Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    // Where these two rectangles overlap, which is empty if they don't.
+    pub fn intersection(self, other: Rect) -> Rect {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        Rect {
+            x,
+            y,
+            width: ((self.x + self.width).min(other.x + other.width) - x).max(0.0),
+            height: ((self.y + self.height).min(other.y + other.height) - y).max(0.0),
+        }
+    }
+
+    fn expanded_by(self, edge: EdgeSizes) -> Rect {
+        Rect {
+            x: self.x - edge.left,
+            y: self.y - edge.top,
+            width: self.width + edge.left + edge.right,
+            height: self.height + edge.top + edge.bottom,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::{Fonts, Metrics};
+    use crate::style::FontStyle;
+    use crate::testing::{laid_out, laid_out_with_fonts, laid_out_with_images, stylesheet, test_font, test_png};
+    use crate::css::Origin;
+    use crate::engine::Engine;
+    use crate::{html, style};
+
+    #[test]
+    fn em_and_rem_lengths() {
+        laid_out("<div><p></p></div>", "div, p { display: block; } div { font-size: 20px; width: 10em; } \
+                                        p { font-size: 10px; width: 2em; padding-left: 1rem; }", |root| {
+            assert_eq!(root.dimensions.content.width, 200.0);
+            let p = &root.children[0].dimensions;
+            assert_eq!((p.content.width, p.padding.left), (20.0, 20.0));
+        });
+    }
+
+    #[test]
+    fn percentage_widths_margins_and_padding() {
+        laid_out("<div><p></p></div>", "div, p { display: block; } div { width: 50%; } \
+                                        p { width: 50%; margin-left: 10%; padding-right: 5%; }", |root| {
+            assert_eq!(root.dimensions.content.width, 400.0);
+            let p = &root.children[0].dimensions;
+            assert_eq!((p.content.width, p.margin.left, p.padding.right), (200.0, 40.0, 20.0));
+        });
+    }
+
+    #[test]
+    fn viewport_units() {
+        laid_out("<div><p></p></div>", "div, p { display: block; } div { width: 50vw; padding-left: 10vh; } \
+                                        p { width: 10vmin; margin-left: 5vmax; }", |root| {
+            assert_eq!((root.dimensions.content.width, root.dimensions.padding.left), (400.0, 60.0));
+            let p = &root.children[0].dimensions;
+            assert_eq!((p.content.width, p.margin.left), (60.0, 40.0));
+        });
+    }
+
+    #[test]
+    fn physical_units() {
+        laid_out("<div></div>", "div { display: block; width: 1in; padding-left: 2.54cm; margin-left: 72pt; \
+                                 padding-right: 6pc; border-left-width: 25.4mm; }", |root| {
+            let d = &root.dimensions;
+            assert_eq!((d.content.width, d.padding.left, d.margin.left, d.padding.right), (96.0, 96.0, 96.0, 96.0));
+            assert!((d.border.left - 96.0).abs() < 0.001);
+        });
+    }
+
+    #[test]
+    fn calc_lengths() {
+        laid_out("<div><p></p></div>", "div, p { display: block; } div { width: calc(100% - 2 * 50px); } \
+                                        p { width: calc((50% + 1em) / 2); margin-left: calc(10px*3); }", |root| {
+            assert_eq!(root.dimensions.content.width, 700.0);
+            let p = &root.children[0].dimensions;
+            assert_eq!((p.content.width, p.margin.left), (183.0, 30.0));
+        });
+    }
+
+    #[test]
+    fn negative_margins() {
+        laid_out("<div><p class=\"a\"></p><p class=\"b\"></p></div>", "div, p { display: block; } p { height: 50px; } \
+                  .a { margin: -10px -20px 0px -.5em; } .b { margin-top: -5px; width: calc(100px - -10px); }", |root| {
+            let a = &root.children[0].dimensions;
+            assert_eq!((a.margin.left, a.margin.top), (-8.0, -10.0));
+            assert_eq!((a.content.x, a.content.y, a.content.width), (-8.0, -10.0, 828.0));
+            let b = &root.children[1].dimensions;
+            assert_eq!((b.content.y, b.content.width), (35.0, 110.0));
+        });
+    }
+
+    #[test]
+    fn root_with_display_none_is_an_empty_block() {
+        laid_out("<div><p></p></div>", "div { display: none; } p { display: block; height: 10px; }", |root| {
+            // It has at most an empty line in it.
+            assert!(root.children.iter().all(|line| line.children.is_empty()));
+            assert_eq!(root.dimensions.content.height, 0.0);
+        });
+    }
+
+    #[test]
+    fn display_contents_promotes_children() {
+        laid_out("<div><section><p></p><i></i></section><p></p></div>",
+                 "div, p { display: block; } section { display: contents; width: 10px; }", |root| {
+            assert_eq!(root.children.len(), 3);
+            assert!(matches!(root.children[1].box_type, BoxType::AnonymousBlock(_)));
+            assert!(matches!(root.children[2].box_type, BoxType::BlockNode(_)));
+        });
+    }
+
+    #[test]
+    fn dump_shows_boxes_and_edges() {
+        laid_out("<div><p></p></div>", "div { display: block; padding: 2px; } p { display: block; margin: 1px 0; height: 10px; }", |root| {
+            assert_eq!(dump(root), "block div at (2, 2) size 796x12 padding 2 2 2 2\n  \
+                                    block p at (2, 3) size 796x10 margin 1 0 1 0\n");
+        });
+    }
+
+    #[test]
+    fn inline_content_is_laid_out_in_lines() {
+        let source = "<div><svg width=30 height=10></svg><span><svg width=30 height=20></svg><svg width=30 height=5></svg></span>\
+                      <svg width=10 height=10></svg></div>";
+        laid_out(source, "div { display: block; padding: 3px; width: 70px; } span { padding-left: 2px; margin-right: 4px; }", |root| {
+            let anonymous = &root.children[0];
+            assert!(matches!(anonymous.box_type, BoxType::AnonymousBlock(_)));
+            let lines: Vec<(f32, f32)> = anonymous.children.iter()
+                .map(|line| (line.dimensions.content.y, line.dimensions.content.height)).collect();
+            // The boxes sit on the baseline, and each line's strut reaches 3.2px below it.
+            assert_eq!(lines, [(3.0, 23.2), (26.2, 16.0)]);
+            assert_eq!(root.dimensions.content.height, 39.2);
+            // The span is split between the lines, with its left edges on the first part and
+            // its right edges on the second.
+            let (first, second) = (&anonymous.children[0].children[1], &anonymous.children[1].children[0]);
+            assert_eq!((first.dimensions.content.x, first.dimensions.padding.left, first.dimensions.margin.right), (35.0, 2.0, 0.0));
+            assert_eq!((second.dimensions.content.x, second.dimensions.padding.left, second.dimensions.margin.right), (3.0, 0.0, 4.0));
+            assert_eq!(anonymous.children[1].children[1].dimensions.content.x, 37.0);
+        });
+    }
+
+    #[test]
+    fn text_is_measured_with_font_metrics() {
+        let mut fonts = Fonts::new();
+        fonts.add("Test", 400, FontStyle::Normal, Metrics::parse(test_font()).unwrap());
+        laid_out_with_fonts("<p>AAB<span>CC</span></p>", "p { display: block; font: 10px Test; width: 100px; }", &fonts, |root| {
+            let dump = dump(root);
+            // A 'normal' line height takes in the font's 1px line gap, half above the text and half below.
+            assert!(dump.contains("    line at (0, 0) size 100x13\n      text \"AAB\" at (0, 0.5) size 19x12\n"), "{}", dump);
+            assert!(dump.contains("      inline span at (19, 0.5) size 14x12\n        text \"CC\" at (19, 0.5) size 14x12\n"), "{}", dump);
+        });
+    }
+
+    // The text of each line box under 'root', as '[text@x+width]' for each run on it.
+    fn text_lines(root: &LayoutBox) -> Vec<String> {
+        fn runs(layout_box: &LayoutBox, output: &mut String) {
+            if let BoxType::TextRun(node, start, end) = layout_box.box_type {
+                if let NodeType::Text(ref text) = node.node().node_type {
+                    let d = &layout_box.dimensions;
+                    output.push_str(&format!("[{}@{}+{}]", &text[start..end], d.content.x, d.content.width));
+                }
+            }
+            for child in &layout_box.children {
+                runs(child, output);
+            }
+        }
+        fn lines(layout_box: &LayoutBox, output: &mut Vec<String>) {
+            match layout_box.box_type {
+                BoxType::LineBox => {
+                    let mut line = String::new();
+                    runs(layout_box, &mut line);
+                    output.push(line);
+                }
+                _ => for child in &layout_box.children {
+                    lines(child, output);
+                },
+            }
+        }
+        let mut output = Vec::new();
+        lines(root, &mut output);
+        output
+    }
+
+    #[test]
+    fn text_wraps_at_whitespace() {
+        let stylesheet = |width| format!("p {{ display: block; font-size: 16px; width: {}px; }}", width);
+        laid_out("<p>aaa bbb ccc</p>", &stylesheet(60), |root| {
+            assert_eq!(text_lines(root), ["[aaa bbb@0+52]", "[ccc@0+24]"]);
+        });
+        laid_out("<p>aaa bbb ccc</p>", &stylesheet(50), |root| {
+            assert_eq!(text_lines(root), ["[aaa@0+24]", "[bbb@0+24]", "[ccc@0+24]"]);
+        });
+        laid_out("<p>aaa <span>bbb ccc</span> d</p>", &stylesheet(60), |root| {
+            assert_eq!(text_lines(root), ["[aaa @0+28][bbb@28+24]", "[ccc@0+24][ d@24+12]"]);
+        });
+        // A word that's too long for the line overflows it, unless it may be broken.
+        laid_out("<p>aaaaaaaaaa b</p>", &stylesheet(50), |root| {
+            assert_eq!(text_lines(root), ["[aaaaaaaaaa@0+80]", "[b@0+8]"]);
+        });
+        laid_out("<div><p>x aaaaaaaaaa b</p></div>", "div { display: block; overflow-wrap: break-word; width: 50px; } \
+                                                       p { display: block; font-size: 16px; }", |root| {
+            assert_eq!(text_lines(root), ["[x@0+8]", "[aaaaaa@0+48]", "[aaaa b@0+44]"]);
+        });
+    }
+
+    #[test]
+    fn inline_blocks_shrink_to_fit() {
+        let stylesheet = |width| format!("p {{ display: block; width: {}px; }} b {{ display: inline-block; padding: 0 2px; }} \
+                                          i {{ display: block; }}", width);
+        laid_out("<p>aa <b>bbb cc</b> d</p>", &stylesheet(200), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("inline-block b at (22, 0) size 44x16 padding 0 2 0 2\n"), "{}", dump);
+        });
+        // It takes the room there is, but not less than its longest word.
+        laid_out("<p>aa <b>bbb cc</b></p>", &stylesheet(40), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("inline-block b at (2, 16) size 36x32 padding 0 2 0 2\n"), "{}", dump);
+        });
+        laid_out("<p><b>bbbbbbbbbb</b></p>", &stylesheet(50), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("inline-block b at (2, 0) size 80x16"), "{}", dump);
+        });
+        // Blocks inside go on lines of their own, and a width that's set is used as it is.
+        laid_out("<p><b>a<i>bb</i><svg width=40 height=5></svg></b></p>", &stylesheet(200), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("inline-block b at (2, 0) size 40x"), "{}", dump);
+        });
+        laid_out("<p><b style='width: 10px'>bbbbbbbbbb</b></p>", &stylesheet(200), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("inline-block b at (2, 0) size 10x"), "{}", dump);
+        });
+    }
+
+    #[test]
+    fn floats_and_clear() {
+        let stylesheet = |width| format!("div, p {{ display: block; }} div {{ width: {}px; }} .l {{ float: left; width: 20px; height: 20px; }} \
+                                          .r {{ float: right; height: 10px; }} .c {{ clear: both; height: 5px; }}", width);
+        // Lines go beside floats, and below them once they end.
+        laid_out("<div><b class=l></b><p>aaa bbb ccc ddd</p></div>", &stylesheet(80), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block b.l at (0, 0) size 20x20\n"), "{}", dump);
+            assert!(dump.contains("line at (20, 16) size 60x16"), "{}", dump);
+            assert_eq!(text_lines(root), ["[aaa bbb@20+52]", "[ccc ddd@20+52]"]);
+        });
+        laid_out("<div><b class=l></b><p>aaa bbb ccc ddd eee</p></div>", &stylesheet(80), |root| {
+            assert_eq!(text_lines(root), ["[aaa bbb@20+52]", "[ccc ddd@20+52]", "[eee@0+24]"]);
+        });
+        // A float in a line shrinks to fit, and goes on the right.
+        laid_out("<div><p>aaa <i class=r>xx</i>bbb ccc</p></div>", &stylesheet(80), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block i.r at (64, 0) size 16x10\n"), "{}", dump);
+            assert_eq!(text_lines(root), ["[aaa @0+28][bbb@28+24]", "[ccc@0+24]", "[xx@64+16]"]);
+        });
+        // Floats stack beside each other, then wrap below, and clearance goes below them.
+        // A float holds its own floats.
+        let source = "<div><b class=l></b><b class=l></b><b class=l></b><p class=c></p>\
+                      <b class=r style='height: auto'><b class=l style='height: 30px'></b></b></div>";
+        laid_out(source, &stylesheet(50), |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block b.l at (20, 0) size 20x20\n"), "{}", dump);
+            assert!(dump.contains("block b.l at (0, 20) size 20x20\n"), "{}", dump);
+            assert!(dump.contains("block p.c at (0, 40) size 50x5\n"), "{}", dump);
+            assert!(dump.contains("block b.r at (30, 45) size 20x30\n"), "{}", dump);
+        });
+    }
+
+    #[test]
+    fn relative_positions_offset_boxes() {
+        let stylesheet = "div, p { display: block; } p { height: 10px; } div { width: 200px; } \
+                          .r { position: relative; top: 5px; left: 10%; right: 3px; } \
+                          .b { position: relative; bottom: 2px; right: 3px; } .pc { position: relative; top: 50%; }";
+        laid_out("<div><p class=r></p><p></p><p><span class=b>aa</span>b</p><p class=pc></p></div>", stylesheet, |root| {
+            let dump = dump(root);
+            // 'left' wins over 'right', and percentages of an auto height are ignored.
+            assert!(dump.contains("block p.r at (20, 5) size 200x10\n"), "{}", dump);
+            assert!(dump.contains("block p at (0, 10) size 200x10\n"), "{}", dump);
+            assert!(dump.contains("inline span.b at (-3, 18) size 16x16\n"), "{}", dump);
+            assert!(dump.contains("block p.pc at (0, 30)"), "{}", dump);
+        });
+    }
+
+    #[test]
+    fn absolute_positions() {
+        let stylesheet = "div, p { display: block; } .rel { position: relative; width: 180px; height: 100px; padding: 10px; } \
+                          .a { position: absolute; }";
+        // Boxes without offsets go where they would have been, shrink to fit and are out
+        // of the flow.
+        laid_out("<div class=rel><p>x</p><p class=a>ab cd</p><p>y</p></div>", stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block p.a at (10, 26) size 36x16\n"), "{}", dump);
+            assert!(dump.contains("block p at (10, 26) size 180x16\n"), "{}", dump);
+        });
+        // Offsets are from the padding box of the nearest positioned ancestor.
+        let source = "<div class=rel><p><p class=a style='right: 5px; bottom: 10%; width: 20px; height: 30px; margin: 1px'></p></p></div>";
+        laid_out(source, stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block p.a at (174, 77) size 20x30 margin 1 1 1 1\n"), "{}", dump);
+        });
+        let source = "<div class=rel><p class=a style='left: 0px; right: 0px; top: 10px; bottom: 10px; width: 100px; height: 20px; margin: auto'></p></div>";
+        laid_out(source, stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block p.a at (50, 50) size 100x20 margin 40 50 40 50\n"), "{}", dump);
+        });
+        laid_out("<div class=rel><p class=a style='left: 5px; right: 5px; top: 0px'>hi</p></div>", stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block p.a at (5, 0) size 190x16\n"), "{}", dump);
+        });
+        // Fixed boxes go in the viewport, and inline ones start where they would have been.
+        let source = "<div class=rel>aa <span class=a>b</span><p style='position: fixed; bottom: 0px; right: 0px'>c</p></div>";
+        laid_out(source, stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("block span.a at (30, 10) size 8x16\n"), "{}", dump);
+            assert!(dump.contains("block p at (792, 584) size 8x16\n"), "{}", dump);
+        });
+    }
+
+    #[test]
+    fn adjoining_margins_collapse() {
+        let stylesheet = "div, p { display: block; } p { height: 10px; margin: 20px 0; } \
+                          .b { border-top-width: 1px; border-bottom-width: 1px; } .n { margin: -5px 0; }";
+        // Between siblings. The root's margins don't collapse with its children's.
+        laid_out("<div><p></p><p style='margin-top: 30px'></p><p class=n></p></div>", stylesheet, |root| {
+            let ys: Vec<f32> = root.children.iter().map(|p| p.dimensions.content.y).collect();
+            assert_eq!(ys, [20.0, 60.0, 85.0]);
+            assert_eq!(root.dimensions.content.height, 90.0);
+        });
+        // Between a parent and its first or last child, and through empty blocks.
+        let source = "<div><div style='margin-top: 5px'><div></div><p style='margin-top: 8px'></p></div>\
+                      <div><p style='margin-bottom: 40px'></p></div><p></p></div>";
+        laid_out(source, stylesheet, |root| {
+            let first = &root.children[0];
+            assert_eq!((first.dimensions.content.y, first.dimensions.content.height), (8.0, 10.0));
+            let second = &root.children[1];
+            assert_eq!((second.dimensions.content.y, second.dimensions.content.height), (38.0, 10.0));
+            assert_eq!(root.children[2].dimensions.content.y, 88.0);
+        });
+        // Not through borders.
+        laid_out("<div><div class=b><p></p></div></div>", stylesheet, |root| {
+            let inner = &root.children[0];
+            assert_eq!((inner.dimensions.content.y, inner.dimensions.content.height), (1.0, 50.0));
+        });
+        laid_out("<div><p></p><div style='margin: 30px 0'></div><p></p></div>", stylesheet, |root| {
+            assert_eq!(root.children[2].dimensions.content.y, 60.0);
+        });
+    }
+
+    #[test]
+    fn percentage_heights() {
+        // The root's containing block is the viewport, which has a definite height.
+        laid_out("<div></div>", "div { display: block; height: 50%; }", |root| {
+            assert_eq!(root.dimensions.content.height, 300.0);
+        });
+        let stylesheet = "div, p { display: block; } .h { height: 200px; } .p { height: 50%; } \
+                          .a { position: absolute; height: 25%; } .ib { display: inline-block; height: 10%; }";
+        let source = "<div><div class=h><p class=p><span class=ib></span></p></div><div><p class=p>x</p></div><p class=a></p></div>";
+        laid_out(source, stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("    block p.p at (0, 0) size 800x100\n"), "{}", dump);
+            assert!(dump.contains("size 0x10\n"), "{}", dump);
+            // Percentages of an 'auto' height are 'auto'.
+            assert!(dump.contains("block p.p at (0, 200) size 800x16\n"), "{}", dump);
+        });
+    }
+
+    #[test]
+    fn scrollable_overflow() {
+        let stylesheet = "div, p { display: block; } .o { overflow: hidden; width: 50px; height: 20px; padding: 5px; border-top-width: 1px; } \
+                          p { height: 100px; margin-left: -10px; }";
+        laid_out("<div><div class=o><p></p></div><div style='overflow: visible'><p style='width: 300px'></p></div></div>", stylesheet, |root| {
+            let rect = root.children[0].scrollable_overflow;
+            assert_eq!((rect.x, rect.y, rect.width, rect.height), (0.0, 1.0, 60.0, 105.0));
+            let rect = root.scrollable_overflow;
+            assert_eq!((rect.x, rect.y, rect.width, rect.height), (0.0, 0.0, 800.0, 131.0));
+        });
+    }
+
+    fn content_rect(layout_box: &LayoutBox) -> (f32, f32, f32, f32) {
+        let content = layout_box.dimensions.content;
+        (content.x, content.y, content.width, content.height)
+    }
+
+    const TABLE_STYLESHEET: &str = "div, caption { display: block; } table { display: table; border-spacing: 2px; } \
+                                    tr { display: table-row; } td { display: table-cell; } tbody { display: table-row-group; } \
+                                    thead { display: table-header-group; } tfoot { display: table-footer-group; } \
+                                    caption { display: table-caption; }";
+
+    #[test]
+    fn tables_size_their_columns_to_fit() {
+        let source = "<div><table><tr><td>aa</td><td>bbbb bb</td></tr><tr><td colspan=2>c</td></tr></table></div>";
+        laid_out(source, TABLE_STYLESHEET, |root| {
+            let table = &root.children[0];
+            assert_eq!(content_rect(table), (0.0, 0.0, 74.0, 38.0));
+            let body = &table.children[0];
+            assert_eq!(content_rect(body), (2.0, 2.0, 70.0, 34.0));
+            assert_eq!(content_rect(&body.children[0]), (2.0, 2.0, 70.0, 16.0));
+            assert_eq!(content_rect(&body.children[0].children[0]), (2.0, 2.0, 16.0, 16.0));
+            assert_eq!(content_rect(&body.children[0].children[1]), (20.0, 2.0, 52.0, 16.0));
+            assert_eq!(content_rect(&body.children[1].children[0]), (2.0, 20.0, 70.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 38.0);
+        });
+        // When the table is narrower than its columns would like, they get their least
+        // widths and the text wraps.
+        laid_out("<div><table style='width: 50px'><tr><td>aa</td><td>bbbb bb</td></tr></table></div>", TABLE_STYLESHEET, |root| {
+            let table = &root.children[0];
+            assert_eq!(content_rect(table), (0.0, 0.0, 54.0, 36.0));
+            let row = &table.children[0].children[0];
+            assert_eq!(content_rect(&row.children[0]), (2.0, 2.0, 16.0, 32.0));
+            assert_eq!(content_rect(&row.children[1]), (20.0, 2.0, 32.0, 32.0));
+        });
+        // When it's wider, the extra width goes to the columns in proportion to their widths.
+        let source = "<div><table style='width: 72px; border-spacing: 0px'><tr><td>aa</td><td>bbbb</td></tr></table></div>";
+        laid_out(source, TABLE_STYLESHEET, |root| {
+            let row = &root.children[0].children[0].children[0];
+            assert_eq!(content_rect(&row.children[0]), (0.0, 0.0, 24.0, 16.0));
+            assert_eq!(content_rect(&row.children[1]), (24.0, 0.0, 48.0, 16.0));
+        });
+    }
+
+    #[test]
+    fn table_parts() {
+        // Cells outside a table get an anonymous table and row.
+        laid_out("<div><span style='display: table-cell'>x</span>y</div>", TABLE_STYLESHEET, |root| {
+            let table = &root.children[0];
+            assert!(matches!(table.box_type, BoxType::TableNode(None)));
+            assert!(matches!(table.children[0].box_type, BoxType::TableRowNode(None)));
+            assert_eq!(content_rect(table), (0.0, 0.0, 8.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (0.0, 16.0, 800.0, 16.0));
+        });
+        // Header groups go first and footer groups last, and the caption goes above the
+        // table, in its top margin.
+        let source = "<div><table style='margin: 0px auto'><tfoot><tr><td>f</td></tr></tfoot>\
+                      <tr><td rowspan=2>aa aa</td><td>b</td></tr><tr><td>c</td></tr>\
+                      <thead><tr><td>h</td></tr></thead><caption>cap</caption></table></div>";
+        laid_out(source, TABLE_STYLESHEET, |root| {
+            let table = &root.children[0];
+            let labels: Vec<String> = table.children.iter()
+                .map(|child| dump(child).split(" at").next().unwrap().to_string())
+                .collect();
+            assert_eq!(labels, ["row group thead", "row group tbody", "block caption", "row group tfoot"]);
+            assert_eq!((table.dimensions.content.x, table.dimensions.content.width), (375.0, 50.0));
+            assert_eq!(table.dimensions.margin.top, 16.0);
+            assert_eq!(content_rect(&table.children[2]), (375.0, 0.0, 50.0, 16.0));
+            assert_eq!(table.dimensions.content.y, 16.0);
+            let body = &table.children[1];
+            assert_eq!(content_rect(&body.children[0].children[0]), (377.0, 36.0, 36.0, 34.0));
+            assert_eq!(content_rect(&body.children[1].children[0]), (415.0, 54.0, 8.0, 16.0));
+            assert_eq!(content_rect(&table.children[3]), (377.0, 72.0, 46.0, 16.0));
+            assert_eq!(table.dimensions.content.height, 74.0);
+        });
+        // An inline table goes on the line.
+        laid_out("<div>ab<table style='display: inline-table'><tr><td>x</td></tr></table>c</div>", TABLE_STYLESHEET, |root| {
+            let line = &root.children[0].children[0];
+            assert_eq!(line.children.len(), 3);
+            assert_eq!(content_rect(&line.children[1]), (16.0, 0.0, 12.0, 20.0));
+            assert_eq!(line.children[2].dimensions.content.x, 28.0);
+        });
+    }
+
+    // A stylesheet for flex layout tests, with flex containers 'width' wide.
+    fn flex_stylesheet(width: f32) -> String {
+        format!("div {{ display: block; }} .f {{ display: flex; width: {}px; }} .g {{ flex: 1; }} .h {{ flex: 2; }} \
+                 .w {{ width: 30px; }}", width)
+    }
+
+    #[test]
+    fn flex_items_grow_and_shrink() {
+        laid_out("<div class=f><div>aa</div><div>bbbb</div></div>", &flex_stylesheet(200.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 16.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (16.0, 0.0, 32.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 16.0);
+        });
+        // Items grow from a basis of 0 in proportion to 'flex-grow'.
+        laid_out("<div class=f><div class=g>aa</div><div class=h>bbbb</div></div>", &flex_stylesheet(300.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 100.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (100.0, 0.0, 200.0, 16.0));
+        });
+        // They shrink, but not below the least width of their contents.
+        laid_out("<div class=f style='width: 40px'><div class=w>aa</div><div class=w>bbbb</div></div>", &flex_stylesheet(300.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 16.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (16.0, 0.0, 30.0, 16.0));
+        });
+    }
+
+    #[test]
+    fn flex_items_are_justified_and_aligned() {
+        // Text directly in the container is an anonymous item.
+        let source = "<div class=f style='justify-content: space-between; align-items: center'>aa<div style='height: 40px'>b</div>\
+                      <span>cc</span></div>";
+        laid_out(source, &flex_stylesheet(100.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 12.0, 16.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (46.0, 0.0, 8.0, 40.0));
+            assert_eq!(content_rect(&root.children[2]), (84.0, 12.0, 16.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 40.0);
+        });
+        let source = "<div class=f style='flex-direction: row-reverse; justify-content: center'><div>aa</div>\
+                      <div style='margin-top: auto; align-self: flex-start'>b</div></div>";
+        laid_out(source, &flex_stylesheet(100.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (46.0, 0.0, 16.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (38.0, 0.0, 8.0, 16.0));
+        });
+        laid_out("<div class=f><div style='margin-left: auto; padding: 2px'>aa</div></div>", &flex_stylesheet(100.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (82.0, 2.0, 16.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 20.0);
+        });
+    }
+
+    #[test]
+    fn flex_columns() {
+        // Items are stretched across the column and grow down it.
+        let source = "<div class=f style='flex-direction: column; height: 100px'><div>aa</div><div class=g>b</div>\
+                      <div style='align-self: end'>cc</div></div>";
+        laid_out(source, &flex_stylesheet(100.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 100.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (0.0, 16.0, 100.0, 68.0));
+            assert_eq!(content_rect(&root.children[2]), (84.0, 84.0, 16.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 100.0);
+        });
+        laid_out("<div class=f style='flex-direction: column-reverse'><div>aa</div><div>b</div></div>", &flex_stylesheet(100.0), |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 16.0, 100.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (0.0, 0.0, 100.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 32.0);
+        });
+        // A flex container in an inline block is as wide as its items.
+        let source = "<div>x<span style='display: inline-block'><div class=f style='width: auto'><div>aa</div><div>bb</div></div></span></div>";
+        laid_out(source, &flex_stylesheet(100.0), |root| {
+            assert_eq!(root.children[0].children[0].children[1].dimensions.content.width, 32.0);
+        });
+    }
+
+    #[test]
+    fn flex_items_wrap_onto_lines() {
+        let stylesheet = "div { display: block; } .f { display: flex; flex-wrap: wrap; width: 100px; } .w { width: 40px; }";
+        // Three 40px items with 10px between them: two fit on the first line.
+        let source = "<div class=f style='gap: 4px 10px'><div class=w>a</div><div class=w style='height: 30px'>b</div><div class=w>c</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 40.0, 30.0));
+            assert_eq!(content_rect(&root.children[1]), (50.0, 0.0, 40.0, 30.0));
+            assert_eq!(content_rect(&root.children[2]), (0.0, 34.0, 40.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 50.0);
+        });
+        // Items grow line by line, and 'order' moves them.
+        let source = "<div class=f><div class=w style='flex-grow: 1'>a</div><div class=w>b</div>\
+                      <div class=w style='order: -1; flex-grow: 1'>c</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert!(dump(&root.children[0]).contains("\"c\""));
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 50.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (50.0, 0.0, 50.0, 16.0));
+            assert_eq!(content_rect(&root.children[2]), (0.0, 16.0, 40.0, 16.0));
+        });
+        // Lines are spread out by 'align-content', or stretched by default.
+        let source = "<div class=f style='height: 100px; align-content: space-between'><div class=w>a</div><div class=w>b</div><div class=w>c</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 40.0, 16.0));
+            assert_eq!(content_rect(&root.children[2]), (0.0, 84.0, 40.0, 16.0));
+        });
+        laid_out("<div class=f style='height: 100px'><div class=w>a</div><div class=w>b</div><div class=w>c</div></div>", stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 40.0, 50.0));
+            assert_eq!(content_rect(&root.children[2]), (0.0, 50.0, 40.0, 50.0));
+        });
+        // 'wrap-reverse' stacks the lines from the bottom.
+        let source = "<div class=f style='flex-flow: row wrap-reverse; align-items: flex-start'><div class=w style='height: 30px'>a</div>\
+                      <div class=w>b</div><div class=w>c</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 16.0, 40.0, 30.0));
+            assert_eq!(content_rect(&root.children[1]), (40.0, 30.0, 40.0, 16.0));
+            assert_eq!(content_rect(&root.children[2]), (0.0, 0.0, 40.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 46.0);
+        });
+        // The lines of a wrapping column go side by side.
+        let source = "<div class=f style='flex-direction: column; height: 40px; align-content: flex-start'><div>aa</div><div>b</div>\
+                      <div>cccc</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 16.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (0.0, 16.0, 16.0, 16.0));
+            assert_eq!(content_rect(&root.children[2]), (16.0, 0.0, 32.0, 16.0));
+        });
+        // A wrapping row can be as narrow as its widest item.
+        let source = "<div style='width: 10px'><span style='display: inline-block'><div class=f style='width: auto'><div>aa</div>\
+                      <div>bbb</div></div></span></div>";
+        laid_out(source, stylesheet, |root| {
+            let flex = &root.children[0].children[0].children[0].children[0];
+            assert_eq!(content_rect(flex), (0.0, 0.0, 24.0, 32.0));
+        });
+    }
+
+    #[test]
+    fn grid_items_are_placed_in_tracks() {
+        let stylesheet = "div { display: block; } .g { display: grid; width: 200px; }";
+        // px, fr and auto columns, with auto-placement and a gap: 200 - 30 - 20 - 24 leaves 126 for 1fr and 2fr.
+        let source = "<div class=g style='grid-template-columns: 20px 1fr auto 2fr; gap: 5px 10px'><div>a</div><div>b</div>\
+                      <div>ccc</div><div>d</div><div>e</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[0]), (0.0, 0.0, 20.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (30.0, 0.0, 42.0, 16.0));
+            assert_eq!(content_rect(&root.children[2]), (82.0, 0.0, 24.0, 16.0));
+            assert_eq!(content_rect(&root.children[3]), (116.0, 0.0, 84.0, 16.0));
+            assert_eq!(content_rect(&root.children[4]), (0.0, 21.0, 20.0, 16.0));
+            assert_eq!(root.dimensions.content.height, 37.0);
+        });
+        // Line placement, negative lines, spans, and auto-placement around them.
+        let source = "<div class=g style='grid-template-columns: repeat(3, 50px); grid-template-rows: 30px auto'>\
+                      <div style='grid-column: 2 / -1; grid-row: 2'>a</div><div style='grid-row: span 2'>b</div><div>c</div><div>d</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[0]), (50.0, 30.0, 100.0, 16.0));
+            assert_eq!(content_rect(&root.children[1]), (0.0, 0.0, 50.0, 46.0));
+            assert_eq!(content_rect(&root.children[2]), (50.0, 0.0, 50.0, 30.0));
+            assert_eq!(content_rect(&root.children[3]), (100.0, 0.0, 50.0, 30.0));
+            assert_eq!(root.dimensions.content.height, 46.0);
+        });
+        // Implicit rows, alignment, and auto margins.
+        let source = "<div class=g style='width: 100px; grid-template-columns: 1fr 1fr; align-items: center'><div style='height: 40px'>a</div>\
+                      <div>b</div><div style='margin: 0 auto; grid-column: span 2'>c</div></div>";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(content_rect(&root.children[1]), (50.0, 12.0, 50.0, 16.0));
+            assert_eq!(content_rect(&root.children[2]), (46.0, 40.0, 8.0, 16.0));
+        });
+        // A shrink-to-fit grid is as wide as its items would like, with 'fr' tracks in proportion:
+        // 'bbbb' needs 32px for 2fr, so 1fr is 16px.
+        let source = "<div><div style='display: inline-block'><div class=g style='width: auto; grid-template-columns: auto 2fr 1fr'>\
+                      <div>aa</div><div>bbbb</div><div>c</div></div></div></div>";
+        laid_out(source, stylesheet, |root| {
+            let grid = &root.children[0].children[0].children[0].children[0];
+            assert_eq!(content_rect(grid), (0.0, 0.0, 64.0, 16.0));
+        });
+    }
+
+    #[test]
+    fn huge_grid_lines_are_clamped() {
+        let stylesheet = "div { display: block; } .g { display: grid; grid-template-columns: 10px; }";
+        laid_out("<div class=g><div style='grid-column-start: 2000000000'>a</div><div style='grid-row: -2000000000'>b</div></div>", stylesheet, |root| {
+            assert_eq!(root.children.len(), 2);
+        });
+    }
+
+    #[test]
+    fn text_align_moves_and_justifies_lines() {
+        let stylesheet = "div { display: block; width: 100px; } .n { width: 60px; }";
+        // 'aa bb' is 36px wide.
+        laid_out("<div style='text-align: right'>aa bb</div>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aa bb@64+36]"]);
+        });
+        laid_out("<div style='text-align: center'>aa bb</div>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aa bb@32+36]"]);
+        });
+        // Inherited through a child block, and an inline box moves with its text.
+        laid_out("<div style='text-align: end'><div>aa <span style='padding-right: 10px'>bb</span></div></div>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aa @54+20][bb@74+16]"]);
+        });
+        // Justified lines but the last, where the trailing space isn't stretched:
+        // 'aa bb cc' is 56px, with 2 spaces sharing the 4px left.
+        laid_out("<div class=n style='text-align: justify'>aa bb cc dd</div>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aa bb cc@0+60]", "[dd@0+16]"]);
+        });
+        // A line too long for its width starts at the left.
+        laid_out("<div class=n style='text-align: center'>aaaaaaaaaaaaa</div>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aaaaaaaaaaaaa@0+104]"]);
+        });
+    }
+
+    #[test]
+    fn vertical_align_lines_up_boxes() {
+        let stylesheet = "div { display: block; } i { display: inline-block; width: 10px; height: 40px; }";
+        // The y position and height of the first box whose line in the dump contains 'label'.
+        fn place(root: &LayoutBox, label: &str) -> (f32, f32) {
+            let dump = dump(root);
+            let line = dump.lines().find(|line| line.contains(label)).unwrap_or_else(|| panic!("{}", dump));
+            let (position, size) = line.split(" at (").nth(1).unwrap().split_once(") size ").unwrap();
+            let y: f32 = position.split(", ").nth(1).unwrap().parse().unwrap();
+            let height: f32 = size.split(' ').next().unwrap().split('x').nth(1).unwrap().parse().unwrap();
+            ((y * 100.0).round() / 100.0, height)
+        }
+        // Text's baseline is 12.8px down, so a 40px box on the baseline pushes it down to 40.
+        laid_out("<div>a<i id=i></i></div>", stylesheet, |root| {
+            assert_eq!(place(root, "i#i"), (0.0, 40.0));
+            assert_eq!(place(root, "text \"a\""), (27.2, 16.0));
+            assert_eq!(place(root, "line"), (0.0, 43.2));
+        });
+        // 'top' and 'bottom' go to the edges of the line.
+        laid_out("<div>a<i id=t style='vertical-align: top'></i><i id=b style='vertical-align: bottom; height: 10px'></i></div>", stylesheet, |root| {
+            assert_eq!(place(root, "i#t"), (0.0, 40.0));
+            assert_eq!(place(root, "i#b"), (30.0, 10.0));
+            assert_eq!(place(root, "text \"a\""), (0.0, 16.0));
+        });
+        // 'middle' centres the box half an ex above the baseline: 4px, so it reaches 6px below.
+        laid_out("<div>a<i id=m style='vertical-align: middle; height: 20px'></i></div>", stylesheet, |root| {
+            assert_eq!(place(root, "i#m"), (0.0, 20.0));
+            assert_eq!(place(root, "text \"a\""), (1.2, 16.0));
+        });
+        // A length raises the box, and what's in an inline box moves with it.
+        laid_out("<div>a<span id=s style='vertical-align: 10px'>b</span></div>", stylesheet, |root| {
+            assert_eq!(place(root, "span#s"), (0.0, 16.0));
+            assert_eq!(place(root, "text \"b\""), (0.0, 16.0));
+            assert_eq!(place(root, "text \"a\""), (10.0, 16.0));
+        });
+        // An inline block's baseline is that of its last line.
+        laid_out("<div>a<span id=ib style='display: inline-block'>b<div>c</div></span></div>", stylesheet, |root| {
+            assert_eq!(place(root, "span#ib"), (0.0, 32.0));
+            assert_eq!(place(root, "text \"a\""), (16.0, 16.0));
+        });
+    }
+
+    #[test]
+    fn line_height_sizes_lines() {
+        fn heights(root: &LayoutBox) -> Vec<f32> {
+            root.children[0].children.iter().map(|line| line.dimensions.content.height).collect()
+        }
+        // Unitless multipliers are of each box's own font size, and inherited as numbers:
+        // the strut is 32px tall around 12.8 and 3.2, the span 64px around 25.6 and 6.4.
+        laid_out("<p>aa <span>bb</span></p>", "p { display: block; line-height: 2; } span { font-size: 32px; }", |root| {
+            assert_eq!(heights(root), [64.0]);
+            assert!(dump(root).contains("text \"aa \" at (0, 28.8) size 20x16\n"), "{}", dump(root));
+        });
+        // Lengths and percentages are computed on the element, so a percentage is inherited as a length:
+        // the span gets the paragraph's 24px, 21.6 above the baseline, and the strut's 7.2 below.
+        laid_out("<p>aa <span>bb</span></p>", "p { display: block; line-height: 150%; } span { font-size: 32px; }", |root| {
+            assert_eq!(heights(root), [28.8]);
+        });
+        let stylesheet = "p, div { display: block; line-height: 10px; } div div { line-height: 1em; font-size: 20px; }";
+        laid_out("<div><p>aa</p><div>bb</div></div>", stylesheet, |root| {
+            assert_eq!(root.children[0].dimensions.content.height, 10.0);
+            assert_eq!(root.children[1].dimensions.content.height, 20.0);
+            assert!(dump(root).contains("text \"aa\" at (0, -3) size 16x16\n"), "{}", dump(root));
+        });
+        // The strut keeps a line with only small text as tall as the block's own line height.
+        laid_out("<p><span>x</span></p>", "p { display: block; line-height: 30px; } span { font-size: 8px; line-height: 8px; }", |root| {
+            assert_eq!(heights(root).iter().map(|height| height.round()).collect::<Vec<_>>(), [30.0]);
+        });
+        assert!(!crate::style::supports("line-height", &Value::Number(-1.0)));
+    }
+
+    #[test]
+    fn white_space_collapses_preserves_or_wraps() {
+        let stylesheet = "div, p, pre { display: block; } .n { width: 40px; } .pre { white-space: pre; } .nowrap { white-space: nowrap; } \
+                          .wrap { white-space: pre-wrap; } .line { white-space: pre-line; } .break { overflow-wrap: break-word; }";
+        // Whitespace collapses, across elements too, and goes at the start of a line; indentation
+        // between blocks makes no boxes.
+        laid_out("<div>\n  <p>  aa \n\t bb <b> </b> cc</p>\n  <p>dd</p>\n</div>", stylesheet, |root| {
+            assert_eq!(root.children.len(), 2);
+            assert_eq!(text_lines(root), ["[aa \n\t bb @0+40][cc@40+16]", "[dd@0+16]"]);
+            assert_eq!(root.dimensions.content.height, 32.0);
+        });
+        // Nothing collapses in 'pre', and lines only break at newlines, with a newline straight
+        // after '<pre>' left out.
+        laid_out("<pre class='n pre'>\n aa  bb cc\n\ndd\te</pre>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[ aa  bb cc@0+64]", "", "[dd\te@0+40]"]);
+            let lines: Vec<f32> = root.children[0].children.iter().map(|line| line.dimensions.content.y).collect();
+            assert_eq!(lines, [0.0, 16.0, 32.0]);
+        });
+        laid_out("<p class='n nowrap'>aa bb  cc</p>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aa bb  cc@0+56]"]);
+        });
+        // 'pre-wrap' wraps, with spaces hanging at the ends of lines; 'pre-line' collapses spaces
+        // but keeps newlines.
+        laid_out("<p class='n wrap'>aa  bb   cc</p>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aa  bb@0+40]", "[cc@0+16]"]);
+        });
+        laid_out("<p class=line>aa   bb \n  cc</p>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aa   bb @0+40]", "[cc@0+16]"]);
+        });
+        // 'overflow-wrap' breaks a word too long for a line anywhere.
+        laid_out("<p class='n break'>aaaaaaa</p>", stylesheet, |root| {
+            assert_eq!(text_lines(root), ["[aaaaa@0+40]", "[aa@0+16]"]);
+        });
+        // Shrink-to-fit widths don't break text that doesn't wrap.
+        laid_out("<div class=n><b style='float: left' class=nowrap>aa bb cc</b></div>", stylesheet, |root| {
+            assert!(dump(root).contains("block b.nowrap at (0, 0) size 56x16"), "{}", dump(root));
+        });
+        // Whitespace between table parts makes no anonymous rows or cells.
+        laid_out("<table><tr>\n<td>a</td>\n</tr>\n<tr><td>b</td></tr></table>", TABLE_STYLESHEET, |root| {
+            let dump = dump(root);
+            assert_eq!(dump.matches("row group").count(), 1, "{}", dump);
+            assert!(!dump.contains("anonymous row") && !dump.contains("anonymous cell"), "{}", dump);
+        });
+    }
+
+    #[test]
+    fn list_markers() {
+        let stylesheet = "ul, ol, p, div { display: block; } li { display: list-item; } ol { list-style-type: decimal; }";
+        // Outside markers hang before the first line, and don't take up room in it.
+        laid_out("<ul><li>aa</li><li><p>bb</p></li></ul>", stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("inline-block li::marker at (-12, 0) size 12x16\n"), "{}", dump);
+            assert!(dump.contains("text \"aa\" at (0, 0) size 16x16\n"), "{}", dump);
+            assert!(dump.contains("inline-block li::marker at (-12, 16) size 12x16\n"), "{}", dump);
+            assert!(dump.contains("text \"bb\" at (0, 16) size 16x16\n"), "{}", dump);
+            assert_eq!(root.dimensions.content.height, 32.0);
+        });
+        // Inside markers are the first thing on the line.
+        laid_out("<ol style='list-style-position: inside'><li>aa</li><li></li></ol>", stylesheet, |root| {
+            let dump = dump(root);
+            assert!(dump.contains("text \"1. \" at (0, 0) size 20x16\n        text \"aa\" at (20, 0) size 16x16\n"), "{}", dump);
+            assert!(dump.contains("text \"2. \" at (0, 16) size 20x16\n"), "{}", dump);
+        });
+        // Markers don't count towards the widths of shrink-to-fit boxes.
+        laid_out("<div><ul style='float: left'><li>aa</li></ul></div>", stylesheet, |root| {
+            assert!(dump(root).contains("block ul at (0, 0) size 16x16"), "{}", dump(root));
+        });
+    }
+
+    #[test]
+    fn images_are_sized_by_their_files() {
+        let mut images = Images::new();
+        images.add("a.png", &test_png(200, 100)).unwrap();
+        images.add("b.png", &test_png(7, 7)).unwrap();
+        // An image keeps its aspect ratio when only one of its width and height is set, and
+        // one that isn't loaded takes no room.
+        let source = "<div><img src=a.png><img src=a.png width=50><img src=a.png style='height: 20px'>\
+                      <img src=b.png width=30 height=10><img src=missing.png></div>";
+        laid_out_with_images(source, "div { display: block; }", &images, |root| {
+            fn sizes(layout_box: &LayoutBox, output: &mut Vec<(f32, f32, f32)>) {
+                if let BoxType::ImageNode(..) = layout_box.box_type {
+                    let content = layout_box.dimensions.content;
+                    output.push((content.x, content.width, content.height));
+                }
+                for child in &layout_box.children {
+                    sizes(child, output);
+                }
+            }
+            let mut output = Vec::new();
+            sizes(root, &mut output);
+            assert_eq!(output, [(0.0, 200.0, 100.0), (200.0, 50.0, 25.0), (250.0, 40.0, 20.0), (290.0, 30.0, 10.0), (320.0, 0.0, 0.0)]);
+        });
+    }
+
+    #[test]
+    fn aspect_ratio_sizes_boxes() {
+        // The content sizes of the boxes with an 'id', in tree order.
+        fn sizes(root: &LayoutBox) -> Vec<(f32, f32)> {
+            fn walk(layout_box: &LayoutBox, output: &mut Vec<(f32, f32)>) {
+                if let BoxType::BlockNode(node) | BoxType::FlexNode(node) | BoxType::ImageNode(node, _) = layout_box.box_type {
+                    if let NodeType::Element(ref elem) = node.node().node_type {
+                        if elem.attributes.contains_key("id") {
+                            output.push((layout_box.dimensions.content.width, layout_box.dimensions.content.height));
+                        }
+                    }
+                }
+                for child in &layout_box.children {
+                    walk(child, output);
+                }
+            }
+            let mut output = Vec::new();
+            walk(root, &mut output);
+            output
+        }
+        // Blocks take their width from the containing block and their height from the ratio,
+        // or their width from a set height; content taller than the ratio allows makes the box
+        // taller, unless it overflows.
+        let source = "<div><p id=a></p><p id=b></p><p id=c>xxxxxxxxxxxxxxxxxxxx xxxxxxxxxxxxxxxxxxxx</p>\
+                      <p id=d style='overflow: hidden'>xxxxxxxxxxxxxxxxxxxx xxxxxxxxxxxxxxxxxxxx</p></div>";
+        let stylesheet = "div, p { display: block; } div { width: 400px; } #a { aspect-ratio: 4 / 1; } #b { height: 50px; aspect-ratio: 2; } \
+                          #c, #d { width: 200px; aspect-ratio: 20; }";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(sizes(root), [(400.0, 100.0), (100.0, 50.0), (200.0, 32.0), (200.0, 10.0)]);
+        });
+        // A percentage height inside a block sized by its ratio.
+        laid_out("<div id=a><p id=b></p></div>", "div, p { display: block; } div { width: 400px; aspect-ratio: 2; } p { height: 50%; }", |root| {
+            assert_eq!(sizes(root), [(400.0, 200.0), (400.0, 100.0)]);
+        });
+        // Flex items: in a row, a set height gives the base size; in a column, the width gives the height.
+        let source = "<main><div id=r><p id=a></p><p id=b></p></div><div id=c><p id=e></p></div></main>";
+        let stylesheet = "main, p { display: block; } main { width: 400px; } div { display: flex; } \
+                          #a { height: 30px; aspect-ratio: 2; } #b { width: 60px; aspect-ratio: 3; align-self: start; } \
+                          #c { flex-direction: column; width: 100px; } #e { aspect-ratio: 1 / 2; }";
+        laid_out(source, stylesheet, |root| {
+            assert_eq!(sizes(root), [(400.0, 30.0), (60.0, 30.0), (60.0, 20.0), (100.0, 200.0), (100.0, 200.0)]);
+        });
+        // An image's own ratio is overridden, unless the property is 'auto' and the image is loaded.
+        let mut images = Images::new();
+        images.add("i.png", &test_png(200, 100)).unwrap();
+        let source = "<div><img id=a src=i.png><img id=b src=i.png><img id=c src=i.png><img id=d src=none.png></div>";
+        let stylesheet = "div { display: block; } #a { aspect-ratio: 1; } #b { aspect-ratio: auto 1; width: 100px; } \
+                          #c { aspect-ratio: 4; height: 10px; } #d { aspect-ratio: auto 2; width: 50px; }";
+        laid_out_with_images(source, stylesheet, &images, |root| {
+            assert_eq!(sizes(root), [(200.0, 200.0), (100.0, 50.0), (40.0, 10.0), (50.0, 25.0)]);
+        });
+    }
+
+    #[test]
+    fn viewport_is_zoomed() {
+        let viewport = Viewport { width: 800.0, height: 600.0, dpi: 96.0, zoom: 2.0 };
+        let block = viewport.initial_containing_block();
+        assert_eq!((block.width, block.height), (400.0, 300.0));
+        let media = viewport.media("screen");
+        assert_eq!((media.media_type.as_str(), media.width, media.height), ("screen", 400.0, 300.0));
+        // Percentages, viewport units and media queries are all of the zoomed-out viewport.
+        let root = html::parse("<div><p></p></div>".to_string()).0;
+        let stylesheets = [stylesheet("div { display: block; height: 50%; } p { display: block; width: 50vw; height: 10vh; } \
+                                       @media (max-width: 500px) { p { margin-left: 10px; } }")];
+        let styled = style::style_tree(&root, &stylesheets, &media, &Default::default());
+        let root = layout_tree(&styled, &viewport, &Fonts::new(), &Images::new());
+        assert_eq!((root.dimensions.content.width, root.dimensions.content.height), (400.0, 150.0));
+        let p = root.children[0].dimensions.content;
+        assert_eq!((p.x, p.width, p.height), (10.0, 200.0, 30.0));
+    }
+
+    #[test]
+    fn relayout_matches_a_fresh_layout() {
+        let viewport = Viewport { width: 400.0, height: 300.0, dpi: 96.0, zoom: 1.0 };
+        let (fonts, images) = (Fonts::new(), Images::new());
+        let stylesheet_source = "main, div, p { display: block; } .tall { height: 50px; } .wide { width: 100px; } \
+                                 .f { float: left; width: 30px; height: 30px; } .rel { position: relative; top: 3px; } .hide { display: none; }";
+        // Documents where the element at 'path' has its class changed from 'x' to 'class'.
+        let cases = [
+            ("<main><div class=x>a b</div><p>some text here</p><div><p class=rel>deep</p></div></main>", "tall", vec![0]),
+            ("<main><div>a b</div><p class=x>some text here</p><div><p>deep</p></div></main>", "wide", vec![1]),
+            ("<main><div class=x></div><p>some text here that wraps around the float</p><div><p>deep</p></div></main>", "f", vec![0]),
+            ("<main><div class=f></div><p>text</p><div class=x><p>deep</p></div></main>", "tall", vec![2]),
+            ("<main class=x><p>a</p></main>", "hide", vec![]),
+            ("<main class=hide><p class=x>a</p></main>", "tall", vec![0]),
+        ];
+        for (source, class, path) in cases {
+            let before = html::parse(source.to_string()).0;
+            let after = html::parse(source.replace("class=x", &format!("class={}", class))).0;
+            let mut engine = Engine::new(viewport.media("screen"));
+            engine.add_stylesheet(stylesheet(stylesheet_source), Origin::Author);
+            let old_styles = engine.style(&before);
+            let old = layout_tree(&old_styles, &viewport, &fonts, &images);
+            engine.invalidate_attributes(path);
+            let styles = engine.restyle(&after, &old_styles);
+            let again = relayout(&old, &styles, &viewport, &fonts, &images);
+            let fresh = layout_tree(&styles, &viewport, &fonts, &images);
+            assert_eq!(dump(&again), dump(&fresh), "{}", source);
+            // With nothing changed, everything is reused.
+            assert_eq!(dump(&relayout(&again, &styles, &viewport, &fonts, &images)), dump(&fresh), "{}", source);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_layout_matches_serial_layout() {
+        let mut source = "<div>".to_string();
+        for i in 0..400 {
+            source.push_str(&format!("<section><p>Item {} of the list, with enough words in it to wrap \
+                                      <span>once or twice</span></p><p class=side>{}</p></section>", i, i));
+            if i % 50 == 0 {
+                source.push_str("<p class=float>A float the next sections go around</p>");
+            }
         }
+        source.push_str("</div>");
+        let stylesheet = "div, section, p { display: block; } section { margin: 4px 0; padding: 2px; width: 50%; } \
+                          .side { display: inline-block; width: 20px; } .float { float: left; width: 100px; height: 300px; }";
+        let parallel = laid_out(&source, stylesheet, dump);
+        LAYING_OUT_AHEAD.with(|ahead| ahead.set(true));
+        let serial = laid_out(&source, stylesheet, dump);
+        LAYING_OUT_AHEAD.with(|ahead| ahead.set(false));
+        assert!(parallel == serial, "parallel layout differs from serial layout");
     }
 }
@@ -1,25 +1,42 @@
 extern crate getopts;
 extern crate image;
 
-use std::default::Default;
 use std::io::{Read, BufWriter};
-use std::fs::File;
+use std::fs::{self, File};
 
 pub mod css;
+pub mod css_tokenizer;
 pub mod dom;
 pub mod html;
 pub mod layout;
 pub mod style;
 pub mod painting;
 pub mod pdf;
+pub mod shorthand;
+pub mod animation;
+pub mod engine;
+pub mod url;
+pub mod encoding;
+pub mod svg;
+pub mod font;
+pub mod images;
+
+#[cfg(test)]
+mod testing;
 
 fn main() {
     // Parse command-line options:
     let mut opts = getopts::Options::new();
     opts.optopt("h", "html", "HTML document", "FILENAME");
-    opts.optopt("c", "css", "CSS stylesheet", "FILENAME");
+    opts.optflag("x", "xml", "Parse the document as XML, like XHTML or SVG");
+    opts.optmulti("c", "css", "CSS stylesheet (repeatable, later ones win ties)", "FILENAME");
+    opts.optmulti("u", "user-css", "User stylesheet, cascaded below the author's (repeatable)", "FILENAME");
     opts.optopt("o", "output", "Output file", "FILENAME");
     opts.optopt("f", "format", "Output file format", "png | pdf");
+    opts.optopt("d", "dpi", "Pixels per inch for physical CSS units", "DPI");
+    opts.optopt("z", "zoom", "How far to zoom in, as a scale factor", "FACTOR");
+    opts.optopt("t", "time", "How far into CSS animations to render", "SECONDS");
+    opts.optflag("D", "dump", "Print the DOM, style and layout trees");
 
     let matches = opts.parse(std::env::args().skip(1)).unwrap();
     let str_arg = |flag: &str, default: &str| -> String {
@@ -34,19 +51,71 @@ fn main() {
     };
 
     // Read input files:
-    let html = read_source(str_arg("h", "examples/test.html"));
-    let css  = read_source(str_arg("c", "examples/test.css"));
+    let html_filename = str_arg("h", "examples/test.html");
+    let html = fs::read(&html_filename).unwrap();
+    let mut css_filenames = matches.opt_strs("c");
+    if css_filenames.is_empty() {
+        css_filenames.push("examples/test.css".to_string());
+    }
+
+    let dpi = matches.opt_str("d").map(|s| s.parse().unwrap()).unwrap_or(css::DEFAULT_DPI);
+    let zoom = matches.opt_str("z").map(|s| s.parse().unwrap()).unwrap_or(1.0);
 
     // Since we don't have an actual window, hard-code the "viewport" size.
-    let mut viewport: layout::Dimensions = Default::default();
-    viewport.content.width  = 800.0;
-    viewport.content.height = 600.0;
+    let viewport = layout::Viewport { width: 800.0, height: 600.0, dpi, zoom };
 
     // Parsing and rendering:
-    let root_node = html::parse(html);
-    let stylesheet = css::parse(css);
-    let style_root = style::style_tree(&root_node, &stylesheet);
-    let layout_root = layout::layout_tree(&style_root, viewport.clone());
+    let (root_node, html_errors) = if matches.opt_present("x") {
+        // XML that isn't well-formed isn't rendered at all.
+        match html::parse_xml(html::sniff_encoding(&html).decode(&html)) {
+            Ok(root_node) => (root_node, Vec::new()),
+            Err(error) => {
+                eprintln!("{}:{}", html_filename, error);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        html::parse_bytes(&html)
+    };
+    // Malformed HTML is repaired, like browsers do, but report it.
+    for error in &html_errors {
+        eprintln!("{}:{}", html_filename, error);
+    }
+    let media = viewport.media(if png { "screen" } else { "print" });
+    let mut engine = engine::Engine::new(media);
+    for user_css_filename in matches.opt_strs("u") {
+        engine.add_stylesheet(load_stylesheet(&user_css_filename), css::Origin::User);
+    }
+    // The document's own stylesheets are loaded relative to it (or to its '<base href>').
+    let document_errors = engine.add_document_stylesheets(&root_node, &html_filename, &mut |url| fs::read_to_string(url).ok());
+    for (url, error) in &document_errors {
+        match url {
+            Some(url) => eprintln!("{}:{}", url, error),
+            None => eprintln!("{}: <style>:{}", html_filename, error),
+        }
+    }
+    for css_filename in &css_filenames {
+        engine.add_stylesheet(load_stylesheet(css_filename), css::Origin::Author);
+    }
+
+    let mut style_root = engine.style(&root_node);
+    if let Some(time) = matches.opt_str("t") {
+        animation::animate(&mut style_root, engine.stylesheets(), time.parse().unwrap());
+    }
+    // Text is measured with the fonts from '@font-face' rules, where they can be loaded.
+    let mut fonts = font::Fonts::new();
+    for (url, error) in fonts.load_font_faces(engine.stylesheets(), &mut |url| fs::read(url).ok()) {
+        eprintln!("{}: {}", url, error);
+    }
+    // Images are loaded relative to the document, like its stylesheets.
+    let mut images = images::Images::new();
+    for (url, error) in images.load_images(&root_node, &html_filename, &mut |url| fs::read(url).ok()) {
+        eprintln!("{}: {}", url, error);
+    }
+    let layout_root = layout::layout_tree(&style_root, &viewport, &fonts, &images);
+    if matches.opt_present("D") {
+        print!("{}\n{}\n{}", dom::dump(&root_node), style::dump(&style_root), layout::dump(&layout_root));
+    }
 
     // Create the output file:
     let filename = str_arg("o", if png { "output.png" } else { "output.pdf" });
@@ -54,7 +123,7 @@ fn main() {
 
     // Write to the file:
     let ok = if png {
-        let canvas = painting::paint(&layout_root, viewport.content);
+        let canvas = painting::paint(&layout_root, &viewport);
         let (w, h) = (canvas.width as u32, canvas.height as u32);
         let img = image::ImageBuffer::from_fn(w, h, move |x, y| {
             let color = canvas.pixels[(y * w + x) as usize];
@@ -62,7 +131,7 @@ fn main() {
         });
         image::ImageRgba8(img).save(&mut file, image::PNG).is_ok()
     } else {
-        pdf::render(&layout_root, viewport.content, &mut file).is_ok()
+        pdf::render(&layout_root, viewport.initial_containing_block(), &mut file).is_ok()
     };
     if ok {
         println!("Saved output as {}", filename)
@@ -71,6 +140,23 @@ fn main() {
     }
 }
 
+// Read and parse a stylesheet, along with the stylesheets it imports.
+fn load_stylesheet(filename: &str) -> css::Stylesheet {
+    let (mut stylesheet, errors) = css::parse(read_source(filename.to_string()));
+
+    // Imported stylesheets, fonts and images are loaded relative to the stylesheet.
+    stylesheet.resolve_urls(filename);
+    let import_errors = stylesheet.resolve_imports(&mut |url| fs::read_to_string(url).ok());
+    // Malformed CSS is skipped, like browsers do, but report it.
+    for error in &errors {
+        eprintln!("{}:{}", filename, error);
+    }
+    for (url, error) in &import_errors {
+        eprintln!("{}:{}", url, error);
+    }
+    stylesheet
+}
+
 fn read_source(filename: String) -> String {
     let mut str = String::new();
     File::open(filename).unwrap().read_to_string(&mut str).unwrap();
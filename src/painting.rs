@@ -1,85 +1,348 @@
-use crate::css::{Color, Value};
-use crate::layout::{Rect, LayoutBox, BoxType};
+use crate::css::{Color, PseudoElement};
+use crate::layout::{Rect, LayoutBox, BoxType, Viewport};
+use crate::style::{ComputedStyle, Visibility, Float, Position, Overflow, ListStyleType};
+use crate::svg;
 
 type DisplayList = Vec<DisplayCommand>;
 
 pub enum DisplayCommand {
     SolidColor(Color, Rect),
+    // Polygons to fill with the nonzero winding rule, like the shapes of an SVG image.
+    FillPath(Color, Vec<Vec<(f32, f32)>>),
+}
+
+impl DisplayCommand {
+    // The command with everything it paints made 'zoom' times as big, from the top left.
+    fn scale(self, zoom: f32) -> DisplayCommand {
+        match self {
+            DisplayCommand::SolidColor(color, rect) => DisplayCommand::SolidColor(color, Rect {
+                x: rect.x * zoom,
+                y: rect.y * zoom,
+                width: rect.width * zoom,
+                height: rect.height * zoom,
+            }),
+            DisplayCommand::FillPath(color, subpaths) => DisplayCommand::FillPath(color, subpaths.into_iter()
+                .map(|subpath| subpath.into_iter().map(|(x, y)| (x * zoom, y * zoom)).collect())
+                .collect()),
+        }
+    }
 }
 
 pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
     let mut list = Vec::new();
-    render_layout_box(&mut list, layout_root);
+    render_stacking_context(&mut list, layout_root, None);
     return list;
 }
 
-fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
-    render_background(list, layout_box);
-    render_borders(list, layout_box);
-    
+// The area that painting is clipped to, by the boxes that clip their overflow, if any.
+type Clip = Option<Rect>;
+
+// The positioned boxes in a stacking context, which are painted over the rest of it: the
+// stacking contexts in it with negative 'z-index' go behind its in-flow contents, and the
+// rest go in front, ordered by 'z-index' and then by where they are in the tree. Each is
+// painted clipped by the boxes it's in that clip it.
+#[derive(Default)]
+struct StackingContext<'b, 'a> {
+    negative: Vec<(&'b LayoutBox<'a>, Clip)>,
+    positioned: Vec<(&'b LayoutBox<'a>, Clip)>, // 'z-index' 'auto' or 0
+    positive: Vec<(&'b LayoutBox<'a>, Clip)>,
+}
+
+// The boxes in the normal flow of a stacking context (or of a float or inline block, which
+// are painted like one), in the order they're painted: block backgrounds, then floats,
+// then inline content.
+#[derive(Default)]
+struct Flow<'b, 'a> {
+    blocks: Vec<(&'b LayoutBox<'a>, Clip)>,
+    floats: Vec<(&'b LayoutBox<'a>, Clip)>,
+    inlines: Vec<(&'b LayoutBox<'a>, Clip)>,
+}
+
+// Paint the stacking context 'layout_box' makes (the root box always makes one), in the
+// order CSS gives.
+fn render_stacking_context(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    let contents_clip = clip_contents(layout_box, clip);
+    let mut context = StackingContext::default();
+    collect_positioned(layout_box, &mut context, contents_clip, contents_clip);
+    context.negative.sort_by_key(|(layout_box, _)| layout_box.z_index());
+    context.positive.sort_by_key(|(layout_box, _)| layout_box.z_index());
+
+    render_box(list, layout_box, clip);
+    for (layout_box, clip) in context.negative {
+        render_stacking_context(list, layout_box, clip);
+    }
+    render_flow(list, layout_box, contents_clip);
+    for (layout_box, clip) in context.positioned {
+        // A positioned box with 'z-index: auto' is painted like a stacking context, except
+        // that the positioned boxes in it belong to the one it's in.
+        match layout_box.z_index() {
+            Some(_) => render_stacking_context(list, layout_box, clip),
+            None => render_atomically(list, layout_box, clip),
+        }
+    }
+    for (layout_box, clip) in context.positive {
+        render_stacking_context(list, layout_box, clip);
+    }
+}
+
+// The clip for what's inside 'layout_box', which is painted clipped to 'clip': its padding
+// box as well, if it clips its overflow.
+fn clip_contents(layout_box: &LayoutBox, clip: Clip) -> Clip {
+    if layout_box.overflow() == Overflow::Visible {
+        return clip;
+    }
+    let padding_box = layout_box.dimensions.padding_box();
+    Some(clip.map_or(padding_box, |clip| clip.intersection(padding_box)))
+}
+
+// Sort the positioned boxes in 'layout_box' into 'context', looking inside those that
+// don't make stacking contexts of their own. Absolutely positioned boxes are only clipped
+// by the boxes inside the box they're positioned against, so they get 'absolute_clip',
+// rather than 'clip', and fixed boxes aren't clipped at all.
+fn collect_positioned<'b, 'a>(layout_box: &'b LayoutBox<'a>, context: &mut StackingContext<'b, 'a>,
+                              clip: Clip, absolute_clip: Clip) {
+    for child in &layout_box.children {
+        let child_clip = match child.position() {
+            Position::Absolute => absolute_clip,
+            Position::Fixed => None,
+            Position::Static | Position::Relative => clip,
+        };
+        match child.z_index() {
+            Some(z_index) if z_index < 0 => context.negative.push((child, child_clip)),
+            Some(z_index) if z_index > 0 => context.positive.push((child, child_clip)),
+            Some(_) => context.positioned.push((child, child_clip)),
+            None if child.position() != Position::Static => {
+                context.positioned.push((child, child_clip));
+                let contents_clip = clip_contents(child, child_clip);
+                collect_positioned(child, context, contents_clip, contents_clip);
+            }
+            None => collect_positioned(child, context, clip_contents(child, clip), absolute_clip),
+        }
+    }
+}
+
+// Paint a box and what's in its normal flow, as a unit.
+fn render_atomically(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    render_box(list, layout_box, clip);
+    render_flow(list, layout_box, clip_contents(layout_box, clip));
+}
+
+// Paint the normal flow inside 'layout_box', leaving out positioned boxes, which are
+// painted with their stacking contexts.
+fn render_flow(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    let mut flow = Flow::default();
+    collect_flow(layout_box, &mut flow, false, clip);
+    for (layout_box, clip) in flow.blocks {
+        render_box(list, layout_box, clip);
+    }
+    for (layout_box, clip) in flow.floats {
+        render_atomically(list, layout_box, clip);
+    }
+    for (layout_box, clip) in flow.inlines {
+        match layout_box.box_type {
+            BoxType::InlineBlockNode(_) => render_atomically(list, layout_box, clip),
+            _ => render_box(list, layout_box, clip),
+        }
+    }
+}
+
+// Sort the boxes in the normal flow inside 'layout_box' into 'flow'. Everything inside a
+// line is inline content. Floats and inline blocks are painted whole, so what's inside
+// them isn't sorted.
+fn collect_flow<'b, 'a>(layout_box: &'b LayoutBox<'a>, flow: &mut Flow<'b, 'a>, inline: bool, clip: Clip) {
     for child in &layout_box.children {
-        render_layout_box(list, child);
+        if child.position() != Position::Static {
+            continue;
+        }
+        if child.float() != Float::None {
+            flow.floats.push((child, clip));
+            continue;
+        }
+        match child.box_type {
+            BoxType::InlineBlockNode(_) => flow.inlines.push((child, clip)),
+            BoxType::LineBox => collect_flow(child, flow, true, clip),
+            _ => {
+                if inline { flow.inlines.push((child, clip)) } else { flow.blocks.push((child, clip)) }
+                collect_flow(child, flow, inline, clip_contents(child, clip));
+            }
+        }
+    }
+}
+
+// Paint a box's own background, borders and image, but not its children.
+fn render_box(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    render_background(list, layout_box, clip);
+    render_borders(list, layout_box, clip);
+    render_svg(list, layout_box, clip);
+    render_marker(list, layout_box, clip);
+}
+
+// Add 'command' to the list, cut down to 'clip'. Nothing is added if none of it is left.
+fn push_clipped(list: &mut DisplayList, command: DisplayCommand, clip: Clip) {
+    let clip = match clip {
+        Some(clip) => clip,
+        None => return list.push(command),
+    };
+    match command {
+        DisplayCommand::SolidColor(color, rect) => {
+            let rect = rect.intersection(clip);
+            if rect.width > 0.0 && rect.height > 0.0 {
+                list.push(DisplayCommand::SolidColor(color, rect));
+            }
+        }
+        DisplayCommand::FillPath(color, subpaths) => {
+            let subpaths: Vec<_> = subpaths.iter()
+                .map(|subpath| clip_polygon(subpath, clip))
+                .filter(|subpath| subpath.len() >= 3)
+                .collect();
+            if !subpaths.is_empty() {
+                list.push(DisplayCommand::FillPath(color, subpaths));
+            }
+        }
     }
 }
 
-fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color|
-        list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box())));
+// The part of a polygon inside 'clip', found by cutting it along each of the rectangle's
+// edges in turn (the Sutherland-Hodgman algorithm). Since the rectangle is convex, the
+// winding number of each point inside it stays the same.
+fn clip_polygon(polygon: &[(f32, f32)], clip: Rect) -> Vec<(f32, f32)> {
+    // Each edge is the axis it's across (0 for x, 1 for y), where it is, and which side of
+    // it is inside.
+    let edges = [(0, clip.x, 1.0), (0, clip.x + clip.width, -1.0), (1, clip.y, 1.0), (1, clip.y + clip.height, -1.0)];
+    let mut points = polygon.to_vec();
+    for &(axis, position, direction) in &edges {
+        let coordinate = |point: (f32, f32)| if axis == 0 { point.0 } else { point.1 };
+        let inside = |point: (f32, f32)| (coordinate(point) - position) * direction >= 0.0;
+        let mut clipped = Vec::new();
+        for (i, &point) in points.iter().enumerate() {
+            let previous = points[(i + points.len() - 1) % points.len()];
+            if inside(point) != inside(previous) {
+                // Where the side from the previous point crosses the edge.
+                let t = (position - coordinate(previous)) / (coordinate(point) - coordinate(previous));
+                clipped.push((previous.0 + t * (point.0 - previous.0), previous.1 + t * (point.1 - previous.1)));
+            }
+            if inside(point) {
+                clipped.push(point);
+            }
+        }
+        points = clipped;
+    }
+    points
+}
+
+fn render_background(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    get_style(layout_box).and_then(|style| style.background_color).map(|color|
+        push_clipped(list, DisplayCommand::SolidColor(color, layout_box.dimensions.border_box()), clip));
 }
 
-fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+// The style of the box, or 'None' if it's anonymous or hidden, and so has nothing to paint.
+// The children of a hidden box can still be visible, and are painted as usual. Text runs
+// have nothing to paint either, since glyphs aren't drawn yet.
+fn get_style<'a>(layout_box: &LayoutBox<'a>) -> Option<&'a ComputedStyle> {
     match layout_box.box_type {
-        BoxType::BlockNode(style) | BoxType::InlineNode(style) => match style.value(name) {
-            Some(Value::ColorValue(color)) => Some(color),
-            _ => None
-        },
-        BoxType::AnonymousBlock => None,
+        BoxType::BlockNode(style) | BoxType::InlineNode(style) | BoxType::InlineBlockNode(style) |
+        BoxType::SvgNode(style) | BoxType::ImageNode(style, _) | BoxType::TableNode(Some(style)) |
+        BoxType::TableRowGroupNode(style) | BoxType::TableRowNode(Some(style)) | BoxType::TableCellNode(Some(style)) |
+        BoxType::FlexNode(style) | BoxType::GridNode(style) => {
+            Some(&*style.computed).filter(|style| style.visibility == Visibility::Visible)
+        }
+        BoxType::AnonymousBlock(_) | BoxType::LineBox | BoxType::TextRun(..) | BoxType::TableNode(None) |
+        BoxType::TableRowNode(None) | BoxType::TableCellNode(None) => None,
+    }
+}
+
+fn render_svg(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    if let BoxType::SvgNode(style_node) = layout_box.box_type {
+        if get_style(layout_box).is_some() {
+            for shape in svg::shapes(style_node, layout_box.dimensions.content) {
+                push_clipped(list, DisplayCommand::FillPath(shape.color, shape.subpaths), clip);
+            }
+        }
     }
 }
 
-fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
-    let color = match get_color(layout_box, "border-color") {
-        Some(color) => color,
-        _ => return, // bail out of no border-color is specified
+// Paint the bullet of a list item's marker over its text: a disc, circle or square a third
+// of an 'em' across, in the middle of where the bullet's glyph would be. Numbers are text,
+// so they aren't drawn yet.
+fn render_marker(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    let node = match layout_box.box_type {
+        BoxType::TextRun(node, ..) if node.pseudo_element == Some(PseudoElement::Marker) => node,
+        _ => return,
+    };
+    if node.computed.visibility != Visibility::Visible {
+        return;
+    }
+    let color = node.computed.color;
+    let content = layout_box.dimensions.content;
+    let radius = node.font.size / 6.0;
+    let (x, y) = (content.x + node.font.size / 4.0, content.y + content.height / 2.0);
+    let circle = |radius: f32| (0..24).map(|i| {
+        let angle = i as f32 * std::f32::consts::TAU / 24.0;
+        (x + radius * angle.cos(), y + radius * angle.sin())
+    }).collect::<Vec<_>>();
+    let command = match node.computed.list_style_type {
+        ListStyleType::Disc => DisplayCommand::FillPath(color, vec![circle(radius)]),
+        // The inside of the ring goes round the other way, so it isn't filled.
+        ListStyleType::Circle => DisplayCommand::FillPath(color, vec![circle(radius), circle(radius * 2.0 / 3.0).into_iter().rev().collect()]),
+        ListStyleType::Square => DisplayCommand::SolidColor(color, Rect {
+            x: x - radius, y: y - radius, width: 2.0 * radius, height: 2.0 * radius,
+        }),
+        ListStyleType::Decimal | ListStyleType::None => return,
     };
+    push_clipped(list, command, clip);
+}
 
+fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox, clip: Clip) {
+    let colors = match get_style(layout_box) {
+        Some(style) => &style.border_color,
+        None => return,
+    };
     let d = &layout_box.dimensions;
     let border_box = d.border_box();
 
     // Left border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y,
-        width: d.border.left,
-        height: border_box.height,
-    }));
+    if let Some(color) = colors.left {
+        push_clipped(list, DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: d.border.left,
+            height: border_box.height,
+        }), clip);
+    }
 
     // Right border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x + border_box.width - d.border.right,
-        y: border_box.y,
-        width: d.border.right,
-        height: border_box.height,
-    }));
+    if let Some(color) = colors.right {
+        push_clipped(list, DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x + border_box.width - d.border.right,
+            y: border_box.y,
+            width: d.border.right,
+            height: border_box.height,
+        }), clip);
+    }
 
     // Top border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y,
-        width: border_box.width,
-        height: d.border.top,
-    }));
+    if let Some(color) = colors.top {
+        push_clipped(list, DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: border_box.width,
+            height: d.border.top,
+        }), clip);
+    }
 
     // Bottom border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y + border_box.height - d.border.bottom,
-        width: border_box.width,
-        height: d.border.bottom,
-    }));
+    if let Some(color) = colors.bottom {
+        push_clipped(list, DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x,
+            y: border_box.y + border_box.height - d.border.bottom,
+            width: border_box.width,
+            height: d.border.bottom,
+        }), clip);
+    }
 }
 
-struct Canvas {
+// The pixels a layout tree is painted onto, row by row from the top left.
+pub struct Canvas {
     pub pixels: Vec<Color>,
     pub width: usize,
     pub height: usize,
@@ -111,15 +374,168 @@ impl Canvas {
                     }
                 }
             }
+            &DisplayCommand::FillPath(color, subpaths) => {
+                // Fill the pixels whose centers are inside the path, a row at a time: find
+                // where the row crosses the edges, and fill between crossings where the
+                // edges crossed so far don't cancel out.
+                let points = subpaths.iter().flatten();
+                let top = points.clone().map(|point| point.1).fold(f32::INFINITY, f32::min);
+                let bottom = points.map(|point| point.1).fold(f32::NEG_INFINITY, f32::max);
+                let y0 = top.clamp(0.0, self.height as f32) as usize;
+                let y1 = bottom.ceil().clamp(0.0, self.height as f32) as usize;
+                for y in y0..y1 {
+                    let sample_y = y as f32 + 0.5;
+                    let mut crossings: Vec<(f32, i32)> = Vec::new();
+                    for subpath in subpaths {
+                        for (i, &(x0, y0)) in subpath.iter().enumerate() {
+                            let (x1, y1) = subpath[(i + 1) % subpath.len()];
+                            if (y0 <= sample_y) != (y1 <= sample_y) {
+                                let x = x0 + (sample_y - y0) / (y1 - y0) * (x1 - x0);
+                                crossings.push((x, if y1 > y0 { 1 } else { -1 }));
+                            }
+                        }
+                    }
+                    crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    let mut winding = 0;
+                    for (i, &(x, direction)) in crossings.iter().enumerate() {
+                        winding += direction;
+                        if winding == 0 || i + 1 == crossings.len() {
+                            continue;
+                        }
+                        // Fill the pixels with centers between this crossing and the next.
+                        let x0 = (x - 0.5).ceil().clamp(0.0, self.width as f32) as usize;
+                        let x1 = (crossings[i + 1].0 - 0.5).ceil().clamp(0.0, self.width as f32) as usize;
+                        for x in x0..x1 {
+                            self.pixels[x + y * self.width] = *color;
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
-pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
+// Paint the layout tree onto a canvas of the viewport's device pixels, zoomed in by its
+// 'zoom'.
+pub fn paint(layout_root: &LayoutBox, viewport: &Viewport) -> Canvas {
     let display_list = build_display_list(layout_root);
-    let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
+    let mut canvas = Canvas::new(viewport.width as usize, viewport.height as usize);
     for item in display_list {
-        canvas.paint_item(&item);
+        canvas.paint_item(&item.scale(viewport.zoom));
     }
     return canvas;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::Fonts;
+    use crate::images::Images;
+    use crate::testing::{laid_out, stylesheet};
+    use crate::{html, layout, style};
+
+    #[test]
+    fn hidden_boxes_are_not_painted() {
+        laid_out("<div><p></p><p class=\"v\"></p></div>", "div, p { display: block; height: 10px; background-color: #ff0000; } \
+                                                          div { visibility: hidden; } .v { visibility: visible; }", |root| {
+            // Hidden boxes still take up space.
+            assert_eq!(root.children[1].dimensions.content.y, 10.0);
+            assert_eq!(build_display_list(root).len(), 1);
+        });
+    }
+
+    #[test]
+    fn stacking_order() {
+        // Each box's background has a red channel giving the order it should be painted in.
+        let source = "<div style='background: rgb(1, 0, 0)'>\
+                      <p class=r style='background: rgb(6, 0, 0)'></p>\
+                      <p style='background: rgb(3, 0, 0)'></p>\
+                      <p class=f style='background: rgb(4, 0, 0)'><p class=r style='background: rgb(7, 0, 0)'></p></p>\
+                      <p class=r style='z-index: -1; background: rgb(2, 0, 0)'></p>\
+                      <p class=r style='z-index: 2; background: rgb(9, 0, 0)'></p>\
+                      <p class=r style='z-index: 1; background: rgb(8, 0, 0)'></p>\
+                      <p>x<span class=ib style='background: rgb(5, 0, 0)'>y</span></p>\
+                      </div>";
+        let stylesheet = "div, p { display: block; height: 10px; } .f { float: left; width: 10px; } \
+                          .r { position: relative; } .ib { display: inline-block; }";
+        laid_out(source, stylesheet, |root| {
+            let order: Vec<u8> = build_display_list(root).iter().filter_map(|command| match *command {
+                DisplayCommand::SolidColor(color, _) => Some(color.r),
+                _ => None,
+            }).collect();
+            assert_eq!(order, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        });
+    }
+
+    // The solid color rectangles painted for the document, with the red channel of each one's color.
+    fn painted_rects(source: &str, stylesheet: &str) -> Vec<(u8, f32, f32, f32, f32)> {
+        laid_out(source, stylesheet, |root| build_display_list(root).iter().filter_map(|command| match *command {
+            DisplayCommand::SolidColor(color, rect) => Some((color.r, rect.x, rect.y, rect.width, rect.height)),
+            _ => None,
+        }).collect())
+    }
+
+    #[test]
+    fn overflow_is_clipped() {
+        let stylesheet = "div, p { display: block; } \
+                          .o { overflow: hidden; width: 50px; height: 20px; padding: 5px; border-top-width: 1px; border-top-color: rgb(9, 0, 0); } \
+                          p { height: 100px; margin-left: -10px; background: rgb(1, 0, 0); } \
+                          .a { position: absolute; width: 300px; height: 10px; background: rgb(2, 0, 0); } .rel { position: relative; }";
+        let rects = painted_rects("<div><div class=o><p></p><p class=a></p></div></div>", stylesheet);
+        assert!(rects.contains(&(9, 0.0, 0.0, 60.0, 1.0)), "{:?}", rects);
+        assert!(rects.contains(&(1, 0.0, 6.0, 55.0, 25.0)), "{:?}", rects);
+        // The absolute box's containing block is the viewport, outside the clipping box.
+        assert!(rects.contains(&(2, -5.0, 106.0, 300.0, 10.0)), "{:?}", rects);
+        let rects = painted_rects("<div><div class='o rel'><p class=a></p></div></div>", stylesheet);
+        assert!(rects.contains(&(2, 0.0, 6.0, 60.0, 10.0)), "{:?}", rects);
+
+        let source = "<div><div class=o><svg viewBox='0 0 100 100' width=100><rect width=100 height=100 fill=red /></svg></div></div>";
+        laid_out(source, "div, svg { display: block; } .o { overflow: hidden; width: 50px; height: 20px; }", |root| {
+            let paths: Vec<Vec<Vec<(f32, f32)>>> = build_display_list(root).into_iter().filter_map(|command| match command {
+                DisplayCommand::FillPath(_, subpaths) => Some(subpaths),
+                _ => None,
+            }).collect();
+            assert_eq!(paths.len(), 1);
+            assert_eq!(paths[0].len(), 1);
+            assert!(paths[0][0].iter().all(|&(x, y)| (0.0..=50.0).contains(&x) && (0.0..=20.0).contains(&y)));
+        });
+    }
+
+    #[test]
+    fn table_backgrounds_are_painted_table_then_rows_then_cells() {
+        let source = "<table style='background: rgb(1, 0, 0)'><tr style='background: rgb(2, 0, 0)'>\
+                      <td style='background: rgb(3, 0, 0)'>x</td></tr></table>";
+        let rects = painted_rects(source, "table { display: table; } tr { display: table-row; } td { display: table-cell; }");
+        let reds: Vec<u8> = rects.iter().map(|rect| rect.0).collect();
+        assert_eq!(reds, [1, 2, 3]);
+    }
+
+    #[test]
+    fn list_markers_are_painted_in_their_color() {
+        let stylesheet = "ul { display: block; color: rgb(3, 0, 0); } li { display: list-item; } .s { list-style-type: square; }";
+        // A disc is a path, and a square a rectangle a third of the font size across.
+        laid_out("<ul><li>a</li></ul>", stylesheet, |root| {
+            let list = build_display_list(root);
+            assert!(matches!(list[0], DisplayCommand::FillPath(color, _) if color.r == 3));
+        });
+        let rects = painted_rects("<ul class=s><li>a</li></ul>", stylesheet);
+        assert_eq!(rects.len(), 1);
+        assert_eq!(rects[0].0, 3);
+        assert!((rects[0].3 - 16.0 / 3.0).abs() < 0.01, "{:?}", rects);
+        // Hidden markers aren't painted.
+        assert!(painted_rects("<ul class=s style='visibility: hidden'><li>a</li></ul>", stylesheet).is_empty());
+    }
+
+    #[test]
+    fn canvas_is_in_zoomed_device_pixels() {
+        let viewport = Viewport { width: 40.0, height: 40.0, dpi: 96.0, zoom: 2.0 };
+        let root = html::parse("<div></div>".to_string()).0;
+        let stylesheets = [stylesheet("div { display: block; background-color: red; width: 10px; height: 10px; }")];
+        let styled = style::style_tree(&root, &stylesheets, &viewport.media("screen"), &Default::default());
+        let root = layout::layout_tree(&styled, &viewport, &Fonts::new(), &Images::new());
+        let canvas = paint(&root, &viewport);
+        assert_eq!((canvas.width, canvas.height), (40, 40));
+        assert_eq!(canvas.pixels[19 * 40 + 19].g, 0);
+        assert_eq!(canvas.pixels[20 * 40 + 20].g, 255);
+    }
+}
@@ -41,6 +41,16 @@ fn render_item<W: Write>(item: &DisplayCommand, output: &mut W) -> io::Result<()
                 rect.height
             )
         }
+        DisplayCommand::FillPath(color, subpaths) => {
+            writeln!(output, "{} {} {} sc", color.r, color.g, color.b)?;
+            for subpath in subpaths {
+                for (i, &(x, y)) in subpath.iter().enumerate() {
+                    writeln!(output, "{} {} {}", x, y, if i == 0 { "m" } else { "l" })?;
+                }
+                writeln!(output, "h")?;
+            }
+            writeln!(output, "f")
+        }
     }
 }
 
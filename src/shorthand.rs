@@ -0,0 +1,612 @@
+use crate::css::{list_or_single, Declaration, Separator, Unit, Value};
+
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+// Expand a declaration into the longhand declarations it stands for.
+// Properties that aren't shorthands pass through unchanged. Returns an error
+// message if the value isn't valid for the shorthand.
+pub fn expand(name: String, value: Value) -> Result<Vec<Declaration>, String> {
+    // The CSS-wide keywords, like 'inherit', apply to each longhand.
+    if value.is_css_wide_keyword() {
+        return Ok(longhands(&name).into_iter().map(|longhand| declaration(longhand, value.clone())).collect());
+    }
+    let values = components(&value);
+    Ok(match &*name {
+        "margin" | "padding" => expand_box(&name, values, |side| format!("{}-{}", name, side))?,
+        "border-width" | "border-style" | "border-color" => {
+            // 'border-width' expands to 'border-top-width' etc.
+            let suffix = &name["border-".len()..];
+            expand_box(&name, values, |side| format!("border-{}-{}", side, suffix))?
+        }
+        "border" => {
+            let mut longhands = Vec::new();
+            for side in &SIDES {
+                longhands.extend(expand_border(side, &values)?);
+            }
+            longhands
+        }
+        "border-top" | "border-right" | "border-bottom" | "border-left" =>
+            expand_border(&name["border-".len()..], &values)?,
+        "font" => expand_font(&value)?,
+        "background" => expand_background(&value)?,
+        "transition" => expand_transition(&value)?,
+        "flex" => expand_flex(&value)?,
+        "flex-flow" => expand_flex_flow(&values)?,
+        "list-style" => expand_list_style(&values)?,
+        "grid-column" | "grid-row" => {
+            let mut lines = split_at_slash(&value).into_iter().map(|line| list_or_single(line, Separator::Space));
+            let start = lines.next().unwrap();
+            let end = lines.next().unwrap_or(Value::Keyword("auto".to_string()));
+            if lines.next().is_some() {
+                return Err(format!("Expected 1 or 2 lines for {}", name));
+            }
+            vec![declaration(format!("{}-start", name), start), declaration(format!("{}-end", name), end)]
+        }
+        "gap" => match values.len() {
+            1 | 2 => vec![
+                declaration("row-gap".to_string(), values[0].clone()),
+                declaration("column-gap".to_string(), values[values.len() - 1].clone()),
+            ],
+            n => return Err(format!("Expected 1 or 2 values for gap, found {}", n)),
+        },
+        "font-family" => vec![declaration(name, font_family_list(&value)?)],
+        _ => vec![declaration(name, value)]
+    })
+}
+
+// The names of the longhands that a property expands to.
+pub fn longhands(name: &str) -> Vec<String> {
+    let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    match name {
+        "margin" | "padding" => SIDES.iter().map(|side| format!("{}-{}", name, side)).collect(),
+        "border-width" | "border-style" | "border-color" => {
+            SIDES.iter().map(|side| format!("border-{}-{}", side, &name["border-".len()..])).collect()
+        }
+        "border" => SIDES.iter().flat_map(|side| longhands(&format!("border-{}", side))).collect(),
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            ["width", "style", "color"].iter().map(|part| format!("{}-{}", name, part)).collect()
+        }
+        "font" => names(&["font-style", "font-variant", "font-weight", "font-stretch",
+                          "font-size", "line-height", "font-family"]),
+        "background" => names(&["background-color", "background-image", "background-position", "background-size",
+                                "background-repeat", "background-attachment", "background-origin", "background-clip"]),
+        "transition" => names(&["transition-property", "transition-duration",
+                                "transition-timing-function", "transition-delay"]),
+        "flex" => names(&["flex-grow", "flex-shrink", "flex-basis"]),
+        "flex-flow" => names(&["flex-direction", "flex-wrap"]),
+        "list-style" => names(&["list-style-type", "list-style-position"]),
+        "gap" => names(&["row-gap", "column-gap"]),
+        "grid-column" | "grid-row" => vec![format!("{}-start", name), format!("{}-end", name)],
+        _ => vec![name.to_string()],
+    }
+}
+
+// The whitespace-separated components of a value.
+fn components(value: &Value) -> Vec<Value> {
+    match *value {
+        Value::List(ref items, Separator::Space) => items.clone(),
+        _ => vec![value.clone()],
+    }
+}
+
+// The parts of a value between its '/'s, each a list of whitespace-separated components.
+// '/' binds tighter than whitespace when values are parsed, so 'span 2 / 3' is parsed as
+// 'span [2 / 3]', and this splits it into 'span 2' and '3'.
+fn split_at_slash(value: &Value) -> Vec<Vec<Value>> {
+    let mut parts = vec![Vec::new()];
+    for component in components(value) {
+        match component {
+            Value::List(items, Separator::Slash) => {
+                for (i, item) in items.into_iter().enumerate() {
+                    if i > 0 {
+                        parts.push(Vec::new());
+                    }
+                    parts.last_mut().unwrap().push(item);
+                }
+            }
+            component => parts.last_mut().unwrap().push(component),
+        }
+    }
+    parts
+}
+
+// Expand a 1 to 4 value box shorthand like 'margin: 10px 20px' into one
+// declaration per side, named by 'longhand'.
+fn expand_box<F>(name: &str, values: Vec<Value>, longhand: F) -> Result<Vec<Declaration>, String>
+        where F: Fn(&str) -> String {
+    let indices = match values.len() {
+        1 => [0, 0, 0, 0],
+        2 => [0, 1, 0, 1],
+        3 => [0, 1, 2, 1],
+        4 => [0, 1, 2, 3],
+        n => return Err(format!("Expected 1 to 4 values for {}, found {}", name, n))
+    };
+    Ok(SIDES.iter().zip(indices.iter())
+        .map(|(side, &i)| declaration(longhand(side), values[i].clone()))
+        .collect())
+}
+
+// Expand the components of 'border' or 'border-<side>' ('1px solid black', in
+// any order) into the width, style and color longhands for one side. Omitted
+// components are reset to their initial values.
+fn expand_border(side: &str, values: &[Value]) -> Result<Vec<Declaration>, String> {
+    let (mut width, mut style, mut color) = (None, None, None);
+    for value in values {
+        let component = match *value {
+            Value::Keyword(ref k) if is_border_style(k) => &mut style,
+            Value::Keyword(ref k) if is_border_width(k) => &mut width,
+            Value::Length(..) => &mut width,
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("currentcolor") => &mut color,
+            _ if value.to_color().is_some() => &mut color,
+            _ => return Err("Invalid component in border shorthand".to_string()),
+        };
+        if component.is_some() {
+            return Err("Repeated component in border shorthand".to_string());
+        }
+        *component = Some(value.clone());
+    }
+    let initial = |keyword: &str| Value::Keyword(keyword.to_string());
+    Ok(vec![
+        declaration(format!("border-{}-width", side), width.unwrap_or_else(|| initial("medium"))),
+        declaration(format!("border-{}-style", side), style.unwrap_or_else(|| initial("none"))),
+        declaration(format!("border-{}-color", side), color.unwrap_or_else(|| initial("currentcolor"))),
+    ])
+}
+
+fn is_border_style(keyword: &str) -> bool {
+    ["none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset"]
+        .iter().any(|style| keyword.eq_ignore_ascii_case(style))
+}
+
+fn is_border_width(keyword: &str) -> bool {
+    ["thin", "medium", "thick"].iter().any(|width| keyword.eq_ignore_ascii_case(width))
+}
+
+// Expand 'font: [<style> || <variant> || <weight> || <stretch>]? <size>[/<line-height>]? <family>#'.
+// Omitted components are reset to their initial values.
+fn expand_font(value: &Value) -> Result<Vec<Declaration>, String> {
+    // Everything after the first comma belongs to the family fallback list.
+    let (first, fallbacks) = match *value {
+        Value::List(ref groups, Separator::Comma) => (&groups[0], &groups[1..]),
+        _ => (value, &[][..]),
+    };
+    let parts = components(first);
+
+    let normal = Value::Keyword("normal".to_string());
+    let (mut style, mut variant, mut weight, mut stretch) =
+        (normal.clone(), normal.clone(), normal.clone(), normal.clone());
+    let mut i = 0;
+    while i < parts.len() {
+        match parts[i] {
+            Value::Keyword(ref k) if k == "normal" => {}
+            Value::Keyword(ref k) if k == "italic" || k == "oblique" => style = parts[i].clone(),
+            Value::Keyword(ref k) if k == "small-caps" => variant = parts[i].clone(),
+            Value::Keyword(ref k) if is_font_weight(k) => weight = parts[i].clone(),
+            Value::Keyword(ref k) if is_font_stretch(k) => stretch = parts[i].clone(),
+            Value::Number(_) => weight = parts[i].clone(),
+            _ => break, // The font size starts here.
+        }
+        i += 1;
+    }
+    if i + 1 >= parts.len() {
+        return Err("font shorthand requires a size and a family".to_string());
+    }
+
+    let (size, line_height) = match parts[i] {
+        Value::List(ref items, Separator::Slash) if items.len() == 2 => (items[0].clone(), items[1].clone()),
+        ref size => (size.clone(), normal.clone()),
+    };
+
+    // The first family name is made of the remaining space-separated components.
+    let mut families = vec![list_or_single(parts[i + 1..].to_vec(), Separator::Space)];
+    families.extend(fallbacks.iter().cloned());
+
+    Ok(vec![
+        declaration("font-style".to_string(), style),
+        declaration("font-variant".to_string(), variant),
+        declaration("font-weight".to_string(), weight),
+        declaration("font-stretch".to_string(), stretch),
+        declaration("font-size".to_string(), size),
+        declaration("line-height".to_string(), line_height),
+        declaration("font-family".to_string(), font_family_list(&Value::List(families, Separator::Comma))?),
+    ])
+}
+
+// Expand 'background', which may have several comma-separated layers. Each layer
+// contributes one entry to the comma-separated list of each longhand, except for
+// the color, which may only be given in the final layer.
+fn expand_background(value: &Value) -> Result<Vec<Declaration>, String> {
+    let layers = match *value {
+        Value::List(ref layers, Separator::Comma) => layers.clone(),
+        _ => vec![value.clone()],
+    };
+    let mut color = Value::Keyword("transparent".to_string());
+    let mut longhands: Vec<(&str, Vec<Value>)> = vec![
+        ("background-image", vec![]),
+        ("background-position", vec![]),
+        ("background-size", vec![]),
+        ("background-repeat", vec![]),
+        ("background-attachment", vec![]),
+        ("background-origin", vec![]),
+        ("background-clip", vec![]),
+    ];
+    for (i, layer) in layers.iter().enumerate() {
+        let layer = BackgroundLayer::parse(&components(layer))?;
+        if let Some(layer_color) = layer.color {
+            if i != layers.len() - 1 {
+                return Err("Only the final background layer may have a color".to_string());
+            }
+            color = layer_color;
+        }
+        let values = [layer.image, layer.position, layer.size, layer.repeat,
+                      layer.attachment, layer.origin, layer.clip];
+        for ((_, list), value) in longhands.iter_mut().zip(values.iter()) {
+            list.push(value.clone());
+        }
+    }
+
+    let mut declarations = vec![declaration("background-color".to_string(), color)];
+    declarations.extend(longhands.into_iter().map(|(name, values)| {
+        declaration(name.to_string(), list_or_single(values, Separator::Comma))
+    }));
+    Ok(declarations)
+}
+
+// The components of one layer of the 'background' shorthand, with omitted ones
+// set to their initial values.
+struct BackgroundLayer {
+    color: Option<Value>,
+    image: Value,
+    position: Value,
+    size: Value,
+    repeat: Value,
+    attachment: Value,
+    origin: Value,
+    clip: Value,
+}
+
+impl BackgroundLayer {
+    fn parse(parts: &[Value]) -> Result<BackgroundLayer, String> {
+        let keyword = |k: &str| Value::Keyword(k.to_string());
+        let mut color = None;
+        let mut image = keyword("none");
+        let mut position = Vec::new();
+        let mut size = Vec::new();
+        let mut repeat = Vec::new();
+        let mut attachment = keyword("scroll");
+        let mut boxes = Vec::new();
+
+        let mut in_size = false;
+        for part in parts {
+            // The size follows the position, separated by a '/'.
+            if let Value::List(ref items, Separator::Slash) = *part {
+                if items.len() != 2 {
+                    return Err("Invalid background position / size".to_string());
+                }
+                position.push(items[0].clone());
+                size.push(items[1].clone());
+                in_size = true;
+                continue;
+            }
+            if in_size && size.len() < 2 && is_background_size(part) {
+                size.push(part.clone());
+                continue;
+            }
+            in_size = false;
+
+            match *part {
+                Value::Url(_) => image = part.clone(),
+                Value::Keyword(ref k) if k == "none" => image = part.clone(),
+                Value::Keyword(ref k) if is_background_repeat(k) => repeat.push(part.clone()),
+                Value::Keyword(ref k) if k == "scroll" || k == "fixed" || k == "local" => attachment = part.clone(),
+                Value::Keyword(ref k) if k.ends_with("-box") => boxes.push(part.clone()),
+                Value::Keyword(ref k) if is_background_position(k) => position.push(part.clone()),
+                Value::Length(..) | Value::Percentage(_) | Value::Number(_) => position.push(part.clone()),
+                _ => color = Some(part.clone()),
+            }
+        }
+
+        if position.is_empty() {
+            position = vec![Value::Percentage(0.0), Value::Percentage(0.0)];
+        }
+        if repeat.is_empty() {
+            repeat.push(keyword("repeat"));
+        }
+        // A single box keyword sets both the origin and the clip.
+        let (origin, clip) = match boxes.len() {
+            0 => (keyword("padding-box"), keyword("border-box")),
+            1 => (boxes[0].clone(), boxes[0].clone()),
+            _ => (boxes[0].clone(), boxes[1].clone()),
+        };
+
+        Ok(BackgroundLayer {
+            color,
+            image,
+            position: list_or_single(position, Separator::Space),
+            size: if size.is_empty() { keyword("auto") } else { list_or_single(size, Separator::Space) },
+            repeat: list_or_single(repeat, Separator::Space),
+            attachment,
+            origin,
+            clip,
+        })
+    }
+}
+
+fn is_background_size(value: &Value) -> bool {
+    match *value {
+        Value::Length(..) | Value::Percentage(_) | Value::Number(_) => true,
+        Value::Keyword(ref k) => k == "auto" || k == "cover" || k == "contain",
+        _ => false,
+    }
+}
+
+fn is_background_repeat(keyword: &str) -> bool {
+    matches!(keyword, "repeat" | "repeat-x" | "repeat-y" | "no-repeat" | "space" | "round")
+}
+
+fn is_background_position(keyword: &str) -> bool {
+    matches!(keyword, "left" | "right" | "top" | "bottom" | "center")
+}
+
+// Expand 'transition', a comma-separated list of '<property> <duration> <timing-function> <delay>'
+// (in any order, except that the duration comes before the delay) into comma-separated longhands.
+fn expand_transition(value: &Value) -> Result<Vec<Declaration>, String> {
+    let transitions = match *value {
+        Value::List(ref items, Separator::Comma) => items.clone(),
+        _ => vec![value.clone()],
+    };
+    let keyword = |k: &str| Value::Keyword(k.to_string());
+    let (mut properties, mut durations, mut timing_functions, mut delays) = (vec![], vec![], vec![], vec![]);
+    for transition in &transitions {
+        let mut property = keyword("all");
+        let mut times = Vec::new();
+        let mut timing_function = keyword("ease");
+        for part in components(transition) {
+            match part {
+                Value::Time(_) => times.push(part),
+                Value::Keyword(ref k) if is_timing_function(k) => timing_function = part.clone(),
+                Value::Keyword(_) => property = part,
+                _ => return Err("Invalid component in transition shorthand".to_string()),
+            }
+        }
+        if times.len() > 2 {
+            return Err("Too many times in transition shorthand".to_string());
+        }
+        properties.push(property);
+        durations.push(times.first().cloned().unwrap_or(Value::Time(0.0)));
+        delays.push(times.get(1).cloned().unwrap_or(Value::Time(0.0)));
+        timing_functions.push(timing_function);
+    }
+    Ok(vec![
+        declaration("transition-property".to_string(), list_or_single(properties, Separator::Comma)),
+        declaration("transition-duration".to_string(), list_or_single(durations, Separator::Comma)),
+        declaration("transition-timing-function".to_string(), list_or_single(timing_functions, Separator::Comma)),
+        declaration("transition-delay".to_string(), list_or_single(delays, Separator::Comma)),
+    ])
+}
+
+fn is_timing_function(keyword: &str) -> bool {
+    matches!(keyword, "linear" | "ease" | "ease-in" | "ease-out" | "ease-in-out" |
+                      "step-start" | "step-end")
+}
+
+// Expand 'flex: none | [<grow> <shrink>?] || <basis>'. 'none' is '0 0 auto' and 'auto' is
+// '1 1 auto'. An omitted grow or shrink factor is 1, and an omitted basis is zero, which
+// makes the items' sizes depend only on their factors.
+fn expand_flex(value: &Value) -> Result<Vec<Declaration>, String> {
+    let auto = Value::Keyword("auto".to_string());
+    let (grow, shrink, basis) = match *value {
+        Value::Keyword(ref k) if k == "none" => (0.0, 0.0, auto),
+        Value::Keyword(ref k) if k == "auto" => (1.0, 1.0, auto),
+        _ => {
+            let mut factors = Vec::new();
+            let mut basis = None;
+            for part in components(value) {
+                match part {
+                    Value::Number(n) if factors.len() < 2 => factors.push(n),
+                    Value::Number(n) if n == 0.0 && basis.is_none() => basis = Some(Value::Length(0.0, Unit::Px)),
+                    Value::Length(..) | Value::Percentage(_) | Value::Calc(_) if basis.is_none() => basis = Some(part),
+                    Value::Keyword(ref k) if (k == "auto" || k == "content") && basis.is_none() => basis = Some(part),
+                    _ => return Err("Invalid component in flex shorthand".to_string()),
+                }
+            }
+            let factor = |i: usize| factors.get(i).copied().unwrap_or(1.0);
+            (factor(0), factor(1), basis.unwrap_or(Value::Length(0.0, Unit::Px)))
+        }
+    };
+    Ok(vec![
+        declaration("flex-grow".to_string(), Value::Number(grow)),
+        declaration("flex-shrink".to_string(), Value::Number(shrink)),
+        declaration("flex-basis".to_string(), basis),
+    ])
+}
+
+// Expand 'flex-flow: <direction> || <wrap>'. An omitted component is reset to its initial
+// value.
+fn expand_flex_flow(values: &[Value]) -> Result<Vec<Declaration>, String> {
+    let mut direction = Value::Keyword("row".to_string());
+    let mut wrap = Value::Keyword("nowrap".to_string());
+    for value in values {
+        match *value {
+            Value::Keyword(ref k) if matches!(&**k, "row" | "row-reverse" | "column" | "column-reverse") => {
+                direction = value.clone()
+            }
+            Value::Keyword(ref k) if matches!(&**k, "nowrap" | "wrap" | "wrap-reverse") => wrap = value.clone(),
+            _ => return Err("Invalid component in flex-flow shorthand".to_string()),
+        }
+    }
+    Ok(vec![
+        declaration("flex-direction".to_string(), direction),
+        declaration("flex-wrap".to_string(), wrap),
+    ])
+}
+
+// Expand 'list-style' into its type and position, in either order. Marker images aren't
+// supported, so there's no 'list-style-image'.
+fn expand_list_style(values: &[Value]) -> Result<Vec<Declaration>, String> {
+    let mut list_type = Value::Keyword("disc".to_string());
+    let mut position = Value::Keyword("outside".to_string());
+    for value in values {
+        match *value {
+            Value::Keyword(ref k) if matches!(&**k, "disc" | "circle" | "square" | "decimal" | "none") => {
+                list_type = value.clone()
+            }
+            Value::Keyword(ref k) if matches!(&**k, "outside" | "inside") => position = value.clone(),
+            _ => return Err("Invalid component in list-style shorthand".to_string()),
+        }
+    }
+    Ok(vec![
+        declaration("list-style-type".to_string(), list_type),
+        declaration("list-style-position".to_string(), position),
+    ])
+}
+
+// Normalize a 'font-family' value into a comma-separated list in which each family is
+// either a generic family keyword or a family name string. Unquoted names made of
+// several identifiers ('Times New Roman') are joined into a single string.
+fn font_family_list(value: &Value) -> Result<Value, String> {
+    let families = match *value {
+        Value::List(ref items, Separator::Comma) => items.clone(),
+        _ => vec![value.clone()],
+    };
+    let families = families.iter().map(|family| match *family {
+        Value::List(ref words, Separator::Space) => {
+            let words = words.iter().map(|word| match *word {
+                Value::Keyword(ref s) | Value::String(ref s) => Ok(s.clone()),
+                _ => Err("Invalid font family name".to_string()),
+            }).collect::<Result<Vec<String>, String>>()?;
+            Ok(Value::String(words.join(" ")))
+        }
+        ref family => Ok(family.clone()),
+    }).collect::<Result<Vec<Value>, String>>()?;
+    Ok(Value::List(families, Separator::Comma))
+}
+
+fn is_font_weight(keyword: &str) -> bool {
+    matches!(keyword, "bold" | "bolder" | "lighter")
+}
+
+fn is_font_stretch(keyword: &str) -> bool {
+    matches!(keyword, "ultra-condensed" | "extra-condensed" | "condensed" | "semi-condensed" |
+                      "semi-expanded" | "expanded" | "extra-expanded" | "ultra-expanded")
+}
+
+// A declaration that isn't (yet) marked '!important'.
+fn declaration(name: String, value: Value) -> Declaration {
+    Declaration { name, value, important: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::{self, Unit};
+
+    // The longhand declarations in 'name: value', as (name, value) pairs.
+    fn expanded(name: &str, value: &str) -> Vec<(String, Value)> {
+        let (stylesheet, errors) = css::parse(format!("a {{ {}: {}; }}", name, value));
+        assert!(errors.is_empty(), "{:?}", errors);
+        stylesheet.rules[0].declarations.iter()
+            .map(|declaration| (declaration.name.clone(), declaration.value.clone()))
+            .collect()
+    }
+
+    // The message of the error that drops the invalid declaration 'name: value'.
+    fn rejected(name: &str, value: &str) -> String {
+        let (stylesheet, mut errors) = css::parse(format!("a {{ {}: {}; }}", name, value));
+        assert!(stylesheet.rules[0].declarations.is_empty());
+        assert_eq!(errors.len(), 1);
+        errors.remove(0).message
+    }
+
+    #[test]
+    fn box_shorthands_repeat_missing_sides() {
+        let px = |n| Value::Length(n, Unit::Px);
+        assert!(expanded("margin", "1px 2px") == vec![
+            ("margin-top".to_string(), px(1.0)), ("margin-right".to_string(), px(2.0)),
+            ("margin-bottom".to_string(), px(1.0)), ("margin-left".to_string(), px(2.0)),
+        ]);
+        assert!(expanded("padding", "1px 2px 3px") == vec![
+            ("padding-top".to_string(), px(1.0)), ("padding-right".to_string(), px(2.0)),
+            ("padding-bottom".to_string(), px(3.0)), ("padding-left".to_string(), px(2.0)),
+        ]);
+    }
+
+    #[test]
+    fn box_shorthands_take_at_most_4_values() {
+        assert_eq!(rejected("padding", "1px 2px 3px 4px 5px"), "Expected 1 to 4 values for padding, found 5");
+    }
+
+    #[test]
+    fn border_shorthand_components_in_any_order() {
+        let keyword = |k: &str| Value::Keyword(k.to_string());
+        assert!(expanded("border-top", "red 1PX SOLID") == vec![
+            ("border-top-width".to_string(), Value::Length(1.0, Unit::Px)),
+            ("border-top-style".to_string(), keyword("SOLID")),
+            ("border-top-color".to_string(), keyword("red")),
+        ]);
+        assert!(expanded("border-left", "Thick") == vec![
+            ("border-left-width".to_string(), keyword("Thick")),
+            ("border-left-style".to_string(), keyword("none")),
+            ("border-left-color".to_string(), keyword("currentcolor")),
+        ]);
+        assert_eq!(expanded("border", "1px solid red").len(), 12);
+    }
+
+    #[test]
+    fn border_shorthand_color_must_be_a_color() {
+        assert_eq!(rejected("border", "1px solid bogus"), "Invalid component in border shorthand");
+    }
+
+    #[test]
+    fn border_shorthand_components_are_not_repeated() {
+        assert_eq!(rejected("border", "1px 2px solid"), "Repeated component in border shorthand");
+    }
+
+    #[test]
+    fn font_shorthand() {
+        let keyword = |k: &str| Value::Keyword(k.to_string());
+        assert!(expanded("font", "italic bold 12px/18px Times New Roman, serif") == vec![
+            ("font-style".to_string(), keyword("italic")),
+            ("font-variant".to_string(), keyword("normal")),
+            ("font-weight".to_string(), keyword("bold")),
+            ("font-stretch".to_string(), keyword("normal")),
+            ("font-size".to_string(), Value::Length(12.0, Unit::Px)),
+            ("line-height".to_string(), Value::Length(18.0, Unit::Px)),
+            ("font-family".to_string(), Value::List(vec![
+                Value::String("Times New Roman".to_string()), keyword("serif"),
+            ], Separator::Comma)),
+        ]);
+    }
+
+    #[test]
+    fn font_shorthand_requires_a_size_and_a_family() {
+        assert_eq!(rejected("font", "bold 12px"), "font shorthand requires a size and a family");
+    }
+
+    #[test]
+    fn background_shorthand() {
+        let keyword = |k: &str| Value::Keyword(k.to_string());
+        let longhands = expanded("background", "url(\"a.png\") no-repeat right 10px #fff content-box");
+        assert!(longhands == vec![
+            ("background-color".to_string(), Value::ColorValue(css::Color { r: 255, g: 255, b: 255, a: 255 })),
+            ("background-image".to_string(), Value::Url("a.png".to_string())),
+            ("background-position".to_string(), Value::List(vec![keyword("right"), Value::Length(10.0, Unit::Px)], css::Separator::Space)),
+            ("background-size".to_string(), keyword("auto")),
+            ("background-repeat".to_string(), keyword("no-repeat")),
+            ("background-attachment".to_string(), keyword("scroll")),
+            ("background-origin".to_string(), keyword("content-box")),
+            ("background-clip".to_string(), keyword("content-box")),
+        ]);
+    }
+
+    #[test]
+    fn transition_shorthand() {
+        let keyword = |k: &str| Value::Keyword(k.to_string());
+        assert!(expanded("transition", "ease-in 2s width 500ms") == vec![
+            ("transition-property".to_string(), keyword("width")),
+            ("transition-duration".to_string(), Value::Time(2.0)),
+            ("transition-timing-function".to_string(), keyword("ease-in")),
+            ("transition-delay".to_string(), Value::Time(0.5)),
+        ]);
+    }
+}
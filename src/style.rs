@@ -1,21 +1,397 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+// Styles are shared with the threads that lay out in parallel, if there are any.
+use std::sync::Arc;
 
-use crate::css::{Value, Selector, SimpleSelector, Specificity, Rule, Stylesheet};
-use crate::dom::{Node, NodeType, ElementData};
+use crate::css::{self, Value, Calc, Declaration, Separator, Selector, SimpleSelector, AttributeSelector, AttributeOperator};
+use crate::css::{PseudoClass, PseudoElement, Combinator, Specificity, Rule, Stylesheet, Origin};
+use crate::css::{MediaQuery, MediaFeature, LengthContext, Unit, Color, TransformFunction};
+use crate::dom::{Node, NodeType, ElementData, Namespace};
+use crate::shorthand;
 
 // Map from CSS property names to values
 type PropertyMap = HashMap<String, Value>;
 
+// The initial value of 'font-size' (the 'medium' keyword).
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+// An indented outline of a style tree, one node per line with the values set on it,
+// ordered by property name. For debugging.
+pub fn dump(node: &StyledNode) -> String {
+    let mut output = String::new();
+    dump_node(node, 0, &mut output);
+    output
+}
+
+fn dump_node(node: &StyledNode, depth: usize, output: &mut String) {
+    output.push_str(&"  ".repeat(depth));
+    output.push_str(&node.label());
+    let values: Vec<String> = node.computed_values().into_iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+    if !values.is_empty() {
+        output.push_str(" { ");
+        output.push_str(&values.join("; "));
+        output.push_str(" }");
+    }
+    output.push('\n');
+    for child in &node.children {
+        dump_node(child, depth + 1, output);
+    }
+}
+
+// The SVG attributes that set the CSS property with the same name.
+const PRESENTATION_ATTRIBUTES: [&str; 4] = ["color", "display", "fill", "visibility"];
+
 // A node with associated style data
 pub struct StyledNode<'a> {
-    node: &'a Node, // Reference to a DOM node
-    pub specified_values: PropertyMap,
+    node: &'a Node, // Reference to a DOM node (the originating element, for pseudo-elements)
+    pub pseudo_element: Option<PseudoElement>, // Set for generated '::before', '::after' and '::marker' nodes
+    // Computed values, apart from percentages (see 'compute_values'). Siblings with the same
+    // style share these.
+    pub specified_values: Arc<PropertyMap>,
+    pub computed: Arc<ComputedStyle>, // What layout and painting use out of 'specified_values'
+    pub font: Font, // Computed font properties, inherited from the parent
     pub children: Vec<StyledNode<'a>>,
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+// The computed values of the font properties.
+#[derive(Clone)]
+pub struct Font {
+    pub size: f32, // In px
+    pub style: FontStyle,
+    pub weight: u16, // From 1 to 1000; 400 is 'normal' and 700 is 'bold'
+    pub family: Vec<String>, // Family names and generic families, in order of preference
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl Font {
+    fn initial() -> Font {
+        Font {
+            size: DEFAULT_FONT_SIZE,
+            style: FontStyle::Normal,
+            weight: 400,
+            family: vec!["serif".to_string()],
+        }
+    }
+}
+
+// The media that a document is styled for. '@media' rules are evaluated against it.
+pub struct Media {
+    pub media_type: String, // Such as 'screen' or 'print'
+    pub width: f32,         // Viewport size in px
+    pub height: f32,
+    pub dpi: f32,
+}
+
+impl Media {
+    // Does 'queries' match? An empty list matches all media.
+    fn matches(&self, queries: &[MediaQuery]) -> bool {
+        queries.is_empty() || queries.iter().any(|query| self.matches_query(query))
+    }
+
+    fn matches_query(&self, query: &MediaQuery) -> bool {
+        let type_matches = match query.media_type {
+            Some(ref media_type) => media_type == "all" || *media_type == self.media_type,
+            None => true,
+        };
+        let matches = type_matches && query.features.iter().all(|feature| self.matches_feature(feature));
+        matches != query.negated
+    }
+
+    // Unknown features never match.
+    fn matches_feature(&self, feature: &MediaFeature) -> bool {
+        let (range, name) = match feature.name.split_once('-') {
+            Some((range @ ("min" | "max"), name)) => (Some(range), name),
+            _ => (None, &*feature.name),
+        };
+        let actual = match name {
+            "width" => self.width,
+            "height" => self.height,
+            "orientation" => {
+                let orientation = if self.height >= self.width { "portrait" } else { "landscape" };
+                return match feature.value {
+                    Some(Value::Keyword(ref value)) => value.eq_ignore_ascii_case(orientation),
+                    None => true,
+                    _ => false,
+                };
+            }
+            _ => return false,
+        };
+        // Relative lengths in media queries refer to the initial font size.
+        let expected = match feature.value {
+            Some(ref value) => value.resolve(&self.length_context()),
+            None => return range.is_none() && actual != 0.0,
+        };
+        match range {
+            Some("min") => actual >= expected,
+            Some(_) => actual <= expected,
+            None => actual == expected,
+        }
+    }
+
+    fn length_context(&self) -> LengthContext {
+        LengthContext {
+            font_size: DEFAULT_FONT_SIZE,
+            root_font_size: DEFAULT_FONT_SIZE,
+            containing_width: 0.0,
+            containing_height: None,
+            viewport_width: self.width,
+            viewport_height: self.height,
+            dpi: self.dpi,
+        }
+    }
+}
+
+// The state a node inherits from its parent.
+struct Inherited {
+    lengths: LengthContext, // Font sizes for resolving 'em' and 'rem'
+    font: Font,
+    custom_properties: PropertyMap, // Properties named '--*', with any 'var()' substituted
+    values: Arc<PropertyMap>, // The parent's computed values, for 'inherit'
+    color: Color, // The parent's computed 'color'
+}
+
+// The elements the user is interacting with, each given as the child indices leading to
+// it from the root of the DOM tree. ':hover' and ':active' match these elements and
+// their ancestors, and ':focus' only the focused element itself.
+#[derive(Clone, Default)]
+pub struct ElementStates {
+    pub hover: Option<Vec<usize>>,
+    pub focus: Option<Vec<usize>>,
+    pub active: Option<Vec<usize>>,
+}
+
+// The rest of the paths in 'ElementStates' below a node, for the paths the node is on.
+#[derive(Clone, Copy, Default)]
+struct StatePaths<'b> {
+    hover: Option<&'b [usize]>,
+    focus: Option<&'b [usize]>,
+    active: Option<&'b [usize]>,
+}
+
+impl<'b> StatePaths<'b> {
+    fn new(states: &'b ElementStates) -> StatePaths<'b> {
+        StatePaths {
+            hover: states.hover.as_deref(),
+            focus: states.focus.as_deref(),
+            active: states.active.as_deref(),
+        }
+    }
+
+    // The paths below the node's 'index'th child.
+    fn child(&self, index: usize) -> StatePaths<'b> {
+        let below = |path: Option<&'b [usize]>| match path?.split_first() {
+            Some((&first, rest)) if first == index => Some(rest),
+            _ => None,
+        };
+        StatePaths { hover: below(self.hover), focus: below(self.focus), active: below(self.active) }
+    }
+}
+
+impl Inherited {
+    // What the root element inherits.
+    fn initial(media: &Media) -> Inherited {
+        Inherited {
+            lengths: media.length_context(),
+            font: Font::initial(),
+            custom_properties: HashMap::new(),
+            values: Arc::new(HashMap::new()),
+            color: Color { r: 0, g: 0, b: 0, a: 255 },
+        }
+    }
+
+    // What lengths are resolved against in a node that inherits this and has 'font'.
+    // The root element's font size is what 'rem' units refer to everywhere else.
+    fn lengths(&self, font: &Font, root: bool) -> LengthContext {
+        LengthContext {
+            font_size: font.size,
+            root_font_size: if root { font.size } else { self.lengths.root_font_size },
+            ..self.lengths
+        }
+    }
+
+    // What the children of a node that inherits this inherit, given the node's computed
+    // values and font.
+    fn for_children(&self, values: &Arc<PropertyMap>, font: &Font, root: bool) -> Inherited {
+        Inherited {
+            lengths: self.lengths(font, root),
+            font: font.clone(),
+            custom_properties: values.iter()
+                .filter(|&(name, _)| name.starts_with("--"))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            values: values.clone(),
+            color: computed_color(values, self),
+        }
+    }
+}
+
+// A Bloom filter of the tag names, ids and classes of a node's ancestors. Selectors that
+// need an ancestor with a feature the filter doesn't contain can't match the node, which
+// is much quicker to find out than walking up the tree. It can give false positives, but
+// never false negatives.
+#[derive(Clone, Copy, Default)]
+struct AncestorFilter {
+    bits: [u64; 4],
+}
+
+impl AncestorFilter {
+    // This filter with the features of 'node' added.
+    fn with(mut self, node: &Node) -> AncestorFilter {
+        if let NodeType::Element(ref elem) = node.node_type {
+            self.insert(b't', &elem.tag_name);
+            if let Some(id) = elem.id() {
+                self.insert(b'#', id);
+            }
+            for class in elem.classes() {
+                self.insert(b'.', class);
+            }
+        }
+        self
+    }
+
+    fn insert(&mut self, kind: u8, name: &str) {
+        for bit in AncestorFilter::bits(kind, name) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    fn may_contain(&self, kind: u8, name: &str) -> bool {
+        AncestorFilter::bits(kind, name).iter().all(|&bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // Could some ancestor match 'selector', as far as its tag name, id and classes go?
+    fn may_match(&self, selector: &SimpleSelector) -> bool {
+        selector.tag_name.iter().all(|tag_name| self.may_contain(b't', tag_name))
+            && selector.id.iter().all(|id| self.may_contain(b'#', id))
+            && selector.class.iter().all(|class| self.may_contain(b'.', class))
+    }
+
+    // The two bits a feature sets, from an FNV-1a hash of it.
+    fn bits(kind: u8, name: &str) -> [usize; 2] {
+        let hash = name.bytes().fold((0x811c9dc5 ^ kind as u32).wrapping_mul(0x01000193), |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+        [(hash & 0xff) as usize, ((hash >> 8) & 0xff) as usize]
+    }
+}
+
+// A DOM node and where it is in the tree, so that combinators can look at its
+// ancestors and siblings. These form a linked list up to the root.
+#[derive(Clone, Copy)]
+struct TreePosition<'a, 'b> {
+    node: &'a Node,
+    index: usize, // Position among the parent's children
+    parent: Option<&'b TreePosition<'a, 'b>>,
+    states: StatePaths<'b>,
+    ancestors: AncestorFilter, // The node's ancestors
+}
+
+impl<'a, 'b> TreePosition<'a, 'b> {
+    fn root(node: &'a Node, states: &'b ElementStates) -> TreePosition<'a, 'b> {
+        TreePosition { node, index: 0, parent: None, states: StatePaths::new(states), ancestors: AncestorFilter::default() }
+    }
+
+    // The position of this node's 'index'th child.
+    fn child<'c>(&'c self, index: usize) -> TreePosition<'a, 'c> {
+        TreePosition {
+            node: &self.node.children[index],
+            index,
+            parent: Some(self),
+            states: self.states.child(index),
+            ancestors: self.ancestors.with(self.node),
+        }
+    }
+
+    // The elements before this node that share its parent, nearest first.
+    fn preceding_siblings(&self) -> impl Iterator<Item = TreePosition<'a, 'b>> {
+        let (parent, ancestors) = (self.parent, self.ancestors);
+        let siblings = match parent {
+            Some(p) => &p.node.children[..self.index],
+            None => &[],
+        };
+        siblings.iter().enumerate().rev()
+            .filter(|&(_, node)| matches!(node.node_type, NodeType::Element(_)))
+            .map(move |(index, node)| {
+                let states = parent.map(|p| p.states.child(index)).unwrap_or_default();
+                TreePosition { node, index, parent, states, ancestors }
+            })
+    }
+
+    // The elements after this node that share its parent.
+    fn following_siblings(&self) -> impl Iterator<Item = &'a Node> {
+        let siblings = match self.parent {
+            Some(p) => &p.node.children[self.index + 1..],
+            None => &[],
+        };
+        siblings.iter().filter(|node| matches!(node.node_type, NodeType::Element(_)))
+    }
+}
+
+// Does 'elem', found at 'position' in the tree, match 'selector'? If 'pseudo_element'
+// is set, the selector has to select that pseudo-element of 'elem' instead.
+fn matches(elem: &ElementData, position: &TreePosition, selector: &Selector,
+           pseudo_element: Option<PseudoElement>) -> bool {
+    let subject = match *selector {
+        Selector::Simple(ref simple_selector) => simple_selector,
+        Selector::Complex(ref complex) => &complex.subject,
+    };
+    if subject.pseudo_element != pseudo_element || !matches_simple_selector(elem, position, subject) {
+        return false;
+    }
     match *selector {
-        Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector)
+        Selector::Simple(_) => true,
+        Selector::Complex(ref complex) => {
+            // The parts before a descendant or child combinator have to match ancestors of
+            // the element, wherever they are in the selector.
+            let ancestors_may_match = complex.combinators.iter()
+                .filter(|&&(combinator, _)| matches!(combinator, Combinator::Descendant | Combinator::Child))
+                .all(|(_, simple)| position.ancestors.may_match(simple));
+            ancestors_may_match && matches_combinators(position, &complex.combinators)
+        }
+    }
+}
+
+// Match the remaining parts of a complex selector, from right to left, relative to
+// the position of the element matched by the previous part.
+fn matches_combinators(position: &TreePosition, combinators: &[(Combinator, SimpleSelector)]) -> bool {
+    let (&(combinator, ref selector), rest) = match combinators.split_first() {
+        Some(first) => first,
+        None => return true,
+    };
+    // Candidates are tried nearest first, backtracking if the rest doesn't match.
+    let matches_at = |candidate: &TreePosition| {
+        node_matches(candidate, selector) && matches_combinators(candidate, rest)
+    };
+    match combinator {
+        Combinator::Descendant => {
+            let mut ancestor = position.parent;
+            while let Some(candidate) = ancestor {
+                if matches_at(candidate) {
+                    return true;
+                }
+                ancestor = candidate.parent;
+            }
+            false
+        }
+        Combinator::Child => position.parent.is_some_and(matches_at),
+        Combinator::NextSibling => position.preceding_siblings().next().is_some_and(|s| matches_at(&s)),
+        Combinator::SubsequentSibling => position.preceding_siblings().any(|s| matches_at(&s)),
+    }
+}
+
+fn node_matches(position: &TreePosition, selector: &SimpleSelector) -> bool {
+    // Pseudo-elements can only appear in the subject of a selector.
+    if selector.pseudo_element.is_some() {
+        return false;
+    }
+    match position.node.node_type {
+        NodeType::Element(ref elem) => matches_simple_selector(elem, position, selector),
+        NodeType::Text(_) | NodeType::Comment(_) => false,
     }
 }
 
@@ -32,7 +408,39 @@ impl ElementData {
     }
 }
 
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
+impl Node {
+    // The path to the first element in this subtree (in document order) that matches
+    // 'selectors', a comma separated list, like 'querySelector' in the DOM. This node is
+    // matched as the root of the document, and no element is hovered, focused or active.
+    pub fn query_selector(&self, selectors: &str) -> Result<Option<Vec<usize>>, css::ParseError> {
+        Ok(self.query_selector_all(selectors)?.into_iter().next())
+    }
+
+    // Like 'query_selector', but the paths to all the elements that match.
+    pub fn query_selector_all(&self, selectors: &str) -> Result<Vec<Vec<usize>>, css::ParseError> {
+        let selectors = css::parse_selector_list(selectors.to_string())?;
+        let states = ElementStates::default();
+        let mut found = Vec::new();
+        query(TreePosition::root(self, &states), &selectors, &mut Vec::new(), &mut found);
+        Ok(found)
+    }
+}
+
+// Add the paths to the elements at and below 'position' that match 'selectors' to 'found'.
+fn query(position: TreePosition, selectors: &[Selector], path: &mut Vec<usize>, found: &mut Vec<Vec<usize>>) {
+    if let NodeType::Element(ref elem) = position.node.node_type {
+        if selectors.iter().any(|selector| matches(elem, &position, selector, None)) {
+            found.push(path.clone());
+        }
+    }
+    for index in 0..position.node.children.len() {
+        path.push(index);
+        query(position.child(index), selectors, path, found);
+        path.pop();
+    }
+}
+
+fn matches_simple_selector(elem: &ElementData, position: &TreePosition, selector: &SimpleSelector) -> bool {
     // Check type selector
     if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
         return false;
@@ -49,78 +457,2148 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
         return false;
     }
 
+    // Check attribute selectors
+    if selector.attributes.iter().any(|attribute| !matches_attribute(elem, attribute)) {
+        return false;
+    }
+
+    // Check pseudo-classes
+    if selector.pseudo_classes.iter().any(|pseudo_class| !matches_pseudo_class(position, pseudo_class)) {
+        return false;
+    }
+
     // We didn't find any non-matching selector components
     return true;
 }
 
-type MatchedRule<'a> = (Specificity, &'a Rule);
+fn matches_attribute(elem: &ElementData, selector: &AttributeSelector) -> bool {
+    let value = match elem.attributes.get(&selector.name) {
+        Some(value) => value,
+        None => return false,
+    };
+    match selector.operator {
+        AttributeOperator::Exists => true,
+        AttributeOperator::Equals(ref v) => value == v,
+        AttributeOperator::Includes(ref v) => value.split_whitespace().any(|word| word == v),
+        AttributeOperator::DashMatch(ref v) => value == v || value.starts_with(&format!("{}-", v)),
+        // An empty string never matches the substring operators.
+        AttributeOperator::Prefix(ref v) => !v.is_empty() && value.starts_with(&**v),
+        AttributeOperator::Suffix(ref v) => !v.is_empty() && value.ends_with(&**v),
+        AttributeOperator::Substring(ref v) => !v.is_empty() && value.contains(&**v),
+    }
+}
+
+fn matches_pseudo_class(position: &TreePosition, pseudo_class: &PseudoClass) -> bool {
+    match *pseudo_class {
+        PseudoClass::Hover => return position.states.hover.is_some(),
+        PseudoClass::Focus => return position.states.focus.is_some_and(|rest| rest.is_empty()),
+        PseudoClass::Active => return position.states.active.is_some(),
+        _ => {}
+    }
+    // The root element isn't anyone's child.
+    if position.parent.is_none() {
+        return false;
+    }
+    match *pseudo_class {
+        PseudoClass::FirstChild => position.preceding_siblings().next().is_none(),
+        PseudoClass::LastChild => position.following_siblings().next().is_none(),
+        PseudoClass::OnlyChild => position.preceding_siblings().next().is_none()
+            && position.following_siblings().next().is_none(),
+        PseudoClass::NthChild(a, b) => matches_an_plus_b(a, b, position.preceding_siblings().count() + 1),
+        PseudoClass::NthLastChild(a, b) => matches_an_plus_b(a, b, position.following_siblings().count() + 1),
+        PseudoClass::Hover | PseudoClass::Focus | PseudoClass::Active => unreachable!(),
+    }
+}
 
-// If 'rule' matches 'elem', return a 'MatchedRule'. Otherwise return 'None'.
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+// Is 'index' (counting from 1) equal to 'an+b' for some integer n >= 0?
+fn matches_an_plus_b(a: i32, b: i32, index: usize) -> bool {
+    let offset = index as i32 - b;
+    if a == 0 {
+        offset == 0
+    } else {
+        offset % a == 0 && offset / a >= 0
+    }
+}
+
+// The specificity of the selector that matched, the rule's position in source
+// order (counting imported rules first), and the rule.
+type MatchedRule<'a> = (Specificity, usize, &'a Rule);
+
+// If 'rule', the 'order'th rule in the stylesheets, matches 'elem', return a 'MatchedRule'.
+// Otherwise return 'None'.
+fn match_rule<'a>(elem: &ElementData, position: &TreePosition, order: usize, rule: &'a Rule,
+                  pseudo_element: Option<PseudoElement>) -> Option<MatchedRule<'a>> {
     // Find the first (highest-specificity) matching selector.
     rule.selectors.iter()
-        .find(|selector| matches(elem, *selector))
-        .map(|selector| (selector.specificity(), rule))
+        .find(|selector| matches(elem, position, *selector, pseudo_element))
+        .map(|selector| (selector.specificity(), order, rule))
 }
 
-//Find all CSS rules that match the given element.
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
-    stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule)).collect()
+//Find all CSS rules that match the given element (or one of its pseudo-elements).
+// Rules inside '@media' rules only count if their queries match 'media'.
+fn matching_rules<'a>(elem: &ElementData, position: &TreePosition, stylesheets: &'a [Stylesheet],
+                      media: &Media, pseudo_element: Option<PseudoElement>) -> Vec<MatchedRule<'a>> {
+    stylesheets.iter().flat_map(|stylesheet| &stylesheet.rules).enumerate()
+        .filter(|&(_, rule)| rule.media.iter().all(|queries| media.matches(queries)))
+        .filter_map(|(order, rule)| match_rule(elem, position, order, rule, pseudo_element))
+        .collect()
 }
 
-// Apply styles to a single element, returning the specified value.
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
-    let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
+// Apply the matching rules to a single element (or one of its pseudo-elements), returning the specified value.
+// The values include the element's custom properties, which 'var()' references are replaced with.
+fn specified_values(elem: &ElementData, rules: &[MatchedRule], inherited: &Inherited,
+                    pseudo_element: Option<PseudoElement>) -> PropertyMap {
+    let (inline, presentation) = match pseudo_element {
+        None => (inline_declarations(elem), presentation_attributes(elem)),
+        Some(_) => (Vec::new(), Vec::new()),
+    };
 
-    // Go through the rules from lowest to highest specificity.
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
+    // Go through the declarations by cascade level (origin and importance), then from
+    // lowest to highest specificity, then in source order, so that later ones win.
+    // Within a rule, declarations are applied in the order they were written.
+    // The 'style' attribute counts as an author rule more specific than any selector,
+    // and presentation attributes as one less specific than any.
+    let mut cascaded: Vec<(usize, Specificity, usize, Origin, &Declaration)> = presentation.iter()
+        .map(|declaration| (cascade_level(Origin::Author, false), (0, 0, 0), 0, Origin::Author, declaration))
+        .chain(rules.iter().flat_map(|&(specificity, order, rule)| rule.declarations.iter().map(move |declaration| {
+            (cascade_level(rule.origin, declaration.important), specificity, order, rule.origin, declaration)
+        })))
+        .chain(inline.iter().map(|declaration| {
+            let specificity = (usize::MAX, usize::MAX, usize::MAX);
+            (cascade_level(Origin::Author, declaration.important), specificity, usize::MAX, Origin::Author, declaration)
+        }))
+        .collect();
+    cascaded.sort_by_key(|&(level, specificity, order, _, _)| (level, specificity, order));
+    let declarations: Vec<&Declaration> = cascaded.iter().map(|&(.., declaration)| declaration).collect();
+
+    // Custom properties come first, since any of the other declarations can refer to them.
+    let declared_custom = declarations.iter()
+        .filter(|d| d.name.starts_with("--"))
+        .map(|d| (d.name.clone(), d.value.clone()))
+        .collect();
+    let mut values = custom_properties(&declared_custom, &inherited.custom_properties);
+
+    for (i, declaration) in declarations.iter().enumerate().filter(|(_, d)| !d.name.starts_with("--")) {
+        if is_revert(&declaration.value) {
+            // Roll back to the value from the earlier origins, if they set one.
+            let origin = cascaded[i].3;
+            let reverted = cascaded[..i].iter().rev()
+                .find(|&&(_, _, _, earlier, d)| earlier < origin && !d.important && d.name == declaration.name);
+            if let Some(&(.., reverted)) = reverted {
+                if !reverted.value.contains_var() {
+                    values.insert(declaration.name.clone(), reverted.value.clone());
+                    continue;
+                }
+            }
+        }
+        if !declaration.value.contains_var() {
             values.insert(declaration.name.clone(), declaration.value.clone());
+            continue;
+        }
+        // Shorthands containing 'var()' are only expanded now. If a variable is missing,
+        // or the substituted value is invalid, the property is treated as if it had not been set.
+        let expanded = substitute(&declaration.value, &values)
+            .and_then(|value| shorthand::expand(declaration.name.clone(), value).ok());
+        match expanded {
+            Some(longhands) => {
+                for longhand in longhands {
+                    values.insert(longhand.name, longhand.value);
+                }
+            }
+            None => { values.remove(&declaration.name); }
         }
     }
+    apply_css_wide_keywords(&mut values, inherited);
+    resolve_current_color(&mut values, inherited);
+    inherit_values(&mut values, inherited);
     return values;
 }
 
-// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+// Layout and painting read these off each box, text run or SVG shape, so they're inherited
+// through the values rather than through 'Inherited' like 'color' is.
+const INHERITED_VALUES: [&str; 8] = ["visibility", "fill", "overflow-wrap", "text-align", "line-height", "white-space",
+                                     "list-style-type", "list-style-position"];
+
+fn inherit_values(values: &mut PropertyMap, inherited: &Inherited) {
+    for name in INHERITED_VALUES {
+        if !values.contains_key(name) {
+            if let Some(value) = inherited.values.get(name) {
+                values.insert(name.to_string(), value.clone());
+            }
+        }
+    }
+}
+
+// Declarations for the presentation attributes of an SVG element, like 'fill="red"',
+// which set the CSS property with the same name, and for an '<img>'s 'width' and 'height',
+// which are in px unless they're percentages. Invalid ones are ignored.
+fn presentation_attributes(elem: &ElementData) -> Vec<Declaration> {
+    let sources: Vec<String> = match elem.namespace {
+        Namespace::Svg => PRESENTATION_ATTRIBUTES.iter()
+            .filter_map(|&name| elem.attributes.get(name).map(|value| format!("{}: {}", name, value)))
+            .collect(),
+        Namespace::Html if elem.tag_name == "img" => ["width", "height"].iter()
+            .filter_map(|&name| elem.attributes.get(name).map(|value| match value.trim() {
+                value if value.ends_with('%') => format!("{}: {}", name, value),
+                value => format!("{}: {}px", name, value.trim_end_matches("px")),
+            }))
+            .collect(),
+        Namespace::Html => return Vec::new(),
+    };
+    sources.into_iter()
+        .flat_map(|source| css::parse_style_attribute(source).0)
+        .filter(|declaration| !declaration.important)
+        .collect()
+}
+
+// The declarations in the element's 'style' attribute, if it has one. Errors in it are
+// ignored, like errors in stylesheets.
+fn inline_declarations(elem: &ElementData) -> Vec<Declaration> {
+    match elem.attributes.get("style") {
+        Some(source) => css::parse_style_attribute(source.clone()).0,
+        None => Vec::new(),
+    }
+}
+
+// Replace 'currentColor' with the element's computed 'color'. In 'color' itself, it
+// stands for the inherited color.
+fn resolve_current_color(values: &mut PropertyMap, inherited: &Inherited) {
+    if let Some(color) = values.get_mut("color") {
+        *color = replace_current_color(color, inherited.color);
+    }
+    let color = computed_color(values, inherited);
+    for value in values.values_mut() {
+        *value = replace_current_color(value, color);
+    }
+}
+
+fn replace_current_color(value: &Value, color: Color) -> Value {
+    match *value {
+        Value::Keyword(ref k) if k.eq_ignore_ascii_case("currentcolor") => Value::ColorValue(color),
+        Value::List(ref items, separator) => {
+            Value::List(items.iter().map(|item| replace_current_color(item, color)).collect(), separator)
+        }
+        _ => value.clone(),
+    }
+}
+
+// The computed value of 'color', which is inherited if it isn't set.
+fn computed_color(values: &PropertyMap, inherited: &Inherited) -> Color {
+    values.get("color").and_then(|value| value.to_color()).unwrap_or(inherited.color)
+}
+
+// Where declarations from 'origin' come in the cascade, from lowest to highest.
+// '!important' declarations override normal ones, and reverse the order of the origins.
+fn cascade_level(origin: Origin, important: bool) -> usize {
+    let level = origin as usize;
+    if important { 5 - level } else { level }
+}
+
+fn is_revert(value: &Value) -> bool {
+    matches!(*value, Value::Keyword(ref k) if k.eq_ignore_ascii_case("revert"))
+}
+
+// Replace the CSS-wide keywords 'inherit', 'initial', 'unset' and 'revert' with the values
+// they stand for. Properties are at their initial value when they have no value at all.
+fn apply_css_wide_keywords(values: &mut PropertyMap, inherited: &Inherited) {
+    let keywords: Vec<(String, String)> = values.iter()
+        .filter(|&(_, value)| value.is_css_wide_keyword())
+        .map(|(name, value)| (name.clone(), match *value {
+            Value::Keyword(ref k) => k.to_ascii_lowercase(),
+            _ => unreachable!(),
+        }))
+        .collect();
+    for (name, keyword) in keywords {
+        let inherit = match &*keyword {
+            "inherit" => true,
+            "initial" => false,
+            // 'revert' is the same as 'unset' when the earlier origins don't set the property.
+            _ => is_inherited(&name),
+        };
+        let value = if inherit { inherited_value(&name, inherited) } else { initial_value(&name) };
+        match value {
+            Some(value) => { values.insert(name, value); }
+            None => { values.remove(&name); }
+        }
+    }
+}
+
+// The value that 'name' inherits from the parent. The font properties are left
+// unset, since 'compute_font' inherits their computed values when they are.
+fn inherited_value(name: &str, inherited: &Inherited) -> Option<Value> {
+    if matches!(name, "font-size" | "font-style" | "font-weight" | "font-family") {
+        return None;
+    }
+    // The parent's values are computed, so relative lengths already mean the same as there.
+    inherited.values.get(name).cloned()
+}
+
+// The initial value of 'name', for the properties that don't take it when they have no value.
+fn initial_value(name: &str) -> Option<Value> {
+    match name {
+        "font-size" => Some(Value::Length(DEFAULT_FONT_SIZE, Unit::Px)),
+        "font-style" | "font-weight" => Some(Value::Keyword("normal".to_string())),
+        "font-family" => Some(Value::List(vec![Value::Keyword("serif".to_string())], Separator::Comma)),
+        _ => None,
+    }
+}
+
+// Is 'name' an inherited property, which 'unset' makes inherit?
+fn is_inherited(name: &str) -> bool {
+    name.starts_with("--") || name.starts_with("font") || name.starts_with("list-style") || matches!(name,
+        "color" | "line-height" | "letter-spacing" | "word-spacing" | "white-space" | "text-align" |
+        "text-indent" | "text-transform" | "visibility" | "quotes" | "cursor" | "direction" | "fill" |
+        "overflow-wrap" | "border-spacing")
+}
+
+// Does the engine implement the declaration 'name: value'? This is what '@supports' tests.
+// A shorthand is supported if the longhands it sets that the engine uses (it ignores some,
+// like 'font-variant') accept their values.
+pub fn supports(name: &str, value: &Value) -> bool {
+    if name.starts_with("--") {
+        return true;
+    }
+    // Every property accepts the CSS-wide keywords, and 'var()' could stand for anything.
+    if value.is_css_wide_keyword() || value.contains_var() {
+        return shorthand::longhands(name).iter().any(|longhand| accepts(longhand, value).is_some());
+    }
+    let accepted: Vec<bool> = match shorthand::expand(name.to_string(), value.clone()) {
+        Ok(declarations) => declarations.iter().filter_map(|d| accepts(&d.name, &d.value)).collect(),
+        Err(_) => return false,
+    };
+    !accepted.is_empty() && accepted.iter().all(|&accepted| accepted)
+}
+
+// The registry of properties the engine implements: whether it can use 'value' for the
+// longhand 'name', or 'None' if it doesn't implement 'name' at all.
+fn accepts(name: &str, value: &Value) -> Option<bool> {
+    // The animation and transition properties take a comma separated value per animation.
+    if let Value::List(ref items, Separator::Comma) = *value {
+        if name.starts_with("animation-") || name.starts_with("transition-") {
+            return accepts(name, &items[0]).map(|_| items.iter().all(|item| accepts(name, item) == Some(true)));
+        }
+    }
+    let keyword = |keywords: &[&str]| match *value {
+        Value::Keyword(ref k) => keywords.iter().any(|keyword| k.eq_ignore_ascii_case(keyword)),
+        _ => false,
+    };
+    let length = matches!(*value, Value::Length(..) | Value::Percentage(_) | Value::Calc(_));
+    Some(match name {
+        "visibility" => keyword(&["visible", "hidden", "collapse"]),
+        "fill" => keyword(&["none"]) || value.to_color().is_some(),
+        "overflow-wrap" => keyword(&["normal", "break-word", "anywhere"]),
+        "float" => keyword(&["none", "left", "right"]),
+        "clear" => keyword(&["none", "left", "right", "both"]),
+        "position" => keyword(&["static", "relative", "absolute", "fixed"]),
+        "top" | "right" | "bottom" | "left" => length || keyword(&["auto"]),
+        "overflow" => keyword(&["visible", "hidden", "clip", "scroll", "auto"]),
+        "z-index" => matches!(*value, Value::Number(n) if n.fract() == 0.0) || keyword(&["auto"]),
+        "display" => matches!(*value, Value::Keyword(ref k) if Display::from_keyword(k).is_some()),
+        "flex-direction" => keyword(&["row", "row-reverse", "column", "column-reverse"]),
+        "justify-content" => keyword(&["normal", "flex-start", "flex-end", "start", "end", "left", "right", "center",
+                                       "space-between", "space-around", "space-evenly", "stretch"]),
+        "align-items" | "align-self" => keyword(&["normal", "stretch", "flex-start", "flex-end", "start", "end", "self-start",
+                                                  "self-end", "center", "baseline"]) || (name == "align-self" && keyword(&["auto"])),
+        "flex-grow" | "flex-shrink" => matches!(*value, Value::Number(n) if n >= 0.0),
+        "flex-basis" => length || keyword(&["auto", "content"]),
+        "flex-wrap" => keyword(&["nowrap", "wrap", "wrap-reverse"]),
+        "align-content" => keyword(&["normal", "flex-start", "flex-end", "start", "end", "center", "space-between",
+                                     "space-around", "space-evenly", "stretch"]),
+        "row-gap" | "column-gap" => length || keyword(&["normal"]),
+        "order" => matches!(*value, Value::Number(n) if n.fract() == 0.0),
+        "text-align" => keyword(&["left", "right", "center", "justify", "start", "end"]),
+        "white-space" => keyword(&["normal", "pre", "nowrap", "pre-wrap", "pre-line"]),
+        "list-style-type" => keyword(&["disc", "circle", "square", "decimal", "none"]),
+        "list-style-position" => keyword(&["outside", "inside"]),
+        "vertical-align" => length || keyword(&["baseline", "middle", "top", "bottom", "text-top", "text-bottom"]),
+        "line-height" => matches!(*value, Value::Number(n) | Value::Length(n, _) | Value::Percentage(n) if n >= 0.0)
+            || matches!(*value, Value::Calc(_)) || keyword(&["normal"]),
+        "grid-template-columns" | "grid-template-rows" => TrackSize::list_from_value(value).is_some(),
+        "grid-column-start" | "grid-column-end" | "grid-row-start" | "grid-row-end" => GridLine::from_value(value).is_some(),
+        "border-spacing" => match *value {
+            Value::List(ref items, Separator::Space) => items.len() == 2 && items.iter().all(|item| matches!(*item, Value::Length(n, _) if n >= 0.0)),
+            _ => matches!(*value, Value::Length(n, _) if n >= 0.0),
+        },
+        "aspect-ratio" => AspectRatio::from_value(value).is_some(),
+        "width" | "height" | "margin-top" | "margin-right" | "margin-bottom" | "margin-left" => length || keyword(&["auto"]),
+        "padding-top" | "padding-right" | "padding-bottom" | "padding-left" |
+        "border-top-width" | "border-right-width" | "border-bottom-width" | "border-left-width" => length,
+        "background-color" | "border-top-color" | "border-right-color" | "border-bottom-color" |
+        "border-left-color" => value.to_color().is_some(),
+        "font-size" => matches!(*value, Value::Length(..) | Value::Percentage(_)),
+        "font-style" => keyword(&["normal", "italic", "oblique"]),
+        "font-weight" => matches!(*value, Value::Number(n) if (1.0..=1000.0).contains(&n)) ||
+                         keyword(&["normal", "bold", "bolder", "lighter"]),
+        "font-family" => true, // Checked by 'shorthand::expand'
+        "content" => keyword(&["none", "normal"]) || match *value {
+            Value::String(_) => true,
+            Value::List(ref items, Separator::Space) => items.iter().all(|item| matches!(*item, Value::String(_))),
+            _ => false,
+        },
+        "animation-name" | "transition-property" => matches!(*value, Value::Keyword(_) | Value::String(_)),
+        "animation-duration" | "animation-delay" | "transition-duration" | "transition-delay" => {
+            matches!(*value, Value::Time(_))
+        }
+        "animation-iteration-count" => matches!(*value, Value::Number(_)) || keyword(&["infinite"]),
+        "animation-direction" => keyword(&["normal", "reverse", "alternate", "alternate-reverse"]),
+        "animation-fill-mode" => keyword(&["none", "forwards", "backwards", "both"]),
+        "animation-timing-function" | "transition-timing-function" => {
+            keyword(&["linear", "ease", "ease-in", "ease-out", "ease-in-out", "step-start", "step-end"])
+        }
+        _ => return None,
+    })
+}
+
+// The custom properties of an element: the ones it inherits, overridden by the ones it
+// declares, with 'var()' references between them substituted. Properties that refer
+// to themselves, directly or indirectly, are invalid and left out.
+fn custom_properties(declared: &PropertyMap, inherited: &PropertyMap) -> PropertyMap {
+    let mut values: PropertyMap = inherited.iter()
+        .filter(|&(name, _)| !declared.contains_key(name))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    for name in declared.keys() {
+        resolve_custom_property(name, declared, &mut values, &mut Vec::new());
+    }
+    values
+}
+
+// Resolve the declared custom property 'name' into 'values', after the ones it refers to.
+// 'resolving' holds the properties being resolved further up, to detect cycles.
+fn resolve_custom_property(name: &str, declared: &PropertyMap, values: &mut PropertyMap,
+                           resolving: &mut Vec<String>) {
+    if values.contains_key(name) || resolving.iter().any(|n| n == name) {
+        return;
+    }
+    let value = match declared.get(name) {
+        Some(value) => value,
+        None => return,
+    };
+    resolving.push(name.to_string());
+    let mut references = Vec::new();
+    var_references(value, &mut references);
+    for reference in references {
+        resolve_custom_property(&reference, declared, values, resolving);
+    }
+    resolving.pop();
+
+    if let Some(value) = substitute(value, values) {
+        values.insert(name.to_string(), value);
+    }
+}
+
+// Collect the names of the custom properties that 'value' refers to.
+fn var_references(value: &Value, references: &mut Vec<String>) {
+    match *value {
+        Value::Var(ref name, ref fallback) => {
+            references.push(name.clone());
+            if let Some(ref fallback) = *fallback {
+                var_references(fallback, references);
+            }
+        }
+        Value::List(ref items, _) => for item in items {
+            var_references(item, references);
+        },
+        Value::Calc(ref calc) => calc_var_references(calc, references),
+        _ => {}
+    }
+}
+
+fn calc_var_references(calc: &Calc, references: &mut Vec<String>) {
+    match *calc {
+        Calc::Leaf(ref value) => var_references(value, references),
+        Calc::Sum(ref a, ref b) | Calc::Difference(ref a, ref b) |
+        Calc::Product(ref a, ref b) | Calc::Quotient(ref a, ref b) => {
+            calc_var_references(a, references);
+            calc_var_references(b, references);
+        }
+    }
+}
+
+// Replace the 'var()' references in 'value' with the values of the custom properties in
+// 'custom', or their fallbacks. Returns 'None' if a reference can't be resolved.
+fn substitute(value: &Value, custom: &PropertyMap) -> Option<Value> {
+    match *value {
+        Value::Var(ref name, ref fallback) => match custom.get(name) {
+            Some(value) => Some(value.clone()),
+            None => substitute(fallback.as_ref()?, custom),
+        },
+        Value::List(ref items, separator) => {
+            let mut result = Vec::new();
+            for item in items {
+                // A variable holding a list of the same kind adds its items to this one.
+                match substitute(item, custom)? {
+                    Value::List(inner, inner_separator) if inner_separator == separator => result.extend(inner),
+                    item => result.push(item),
+                }
+            }
+            Some(Value::List(result, separator))
+        }
+        Value::Calc(ref calc) => Some(Value::Calc(Box::new(substitute_calc(calc, custom)?))),
+        _ => Some(value.clone()),
+    }
+}
+
+fn substitute_calc(calc: &Calc, custom: &PropertyMap) -> Option<Calc> {
+    let both = |a: &Calc, b: &Calc| Some((Box::new(substitute_calc(a, custom)?), Box::new(substitute_calc(b, custom)?)));
+    Some(match *calc {
+        Calc::Leaf(ref value) => match substitute(value, custom)? {
+            Value::Calc(calc) => *calc,
+            value => Calc::Leaf(value),
+        },
+        Calc::Sum(ref a, ref b) => { let (a, b) = both(a, b)?; Calc::Sum(a, b) }
+        Calc::Difference(ref a, ref b) => { let (a, b) = both(a, b)?; Calc::Difference(a, b) }
+        Calc::Product(ref a, ref b) => { let (a, b) = both(a, b)?; Calc::Product(a, b) }
+        Calc::Quotient(ref a, ref b) => { let (a, b) = both(a, b)?; Calc::Quotient(a, b) }
+    })
+}
+
+// Apply stylesheets to an entire DOM tree for the given media, returning a StyledNode tree.
+// Each rule cascades by its own origin; among rules from the same origin, ones in later
+// stylesheets win ties, as if the stylesheets were concatenated.
+pub fn style_tree<'a>(root: &'a Node, stylesheets: &[Stylesheet], media: &Media,
+                      states: &ElementStates) -> StyledNode<'a> {
+    let position = TreePosition::root(root, states);
+    style_subtree(position, stylesheets, media, None, &mut Vec::new())
+}
+
+// Style the subtree at 'path' (child indices from the root) of a tree styled by 'style_tree'
+// again, in place, as when the state of an element in it changes. The rest of the tree
+// keeps its style.
+pub fn restyle_subtree<'a>(root: &'a Node, styled_root: &mut StyledNode<'a>, path: &[usize],
+                           stylesheets: &[Stylesheet], media: &Media, states: &ElementStates) {
+    let position = TreePosition::root(root, states);
+    restyle_path(position, styled_root, path, stylesheets, media, None);
+}
+
+fn restyle_path<'a>(position: TreePosition<'a, '_>, styled: &mut StyledNode<'a>, path: &[usize],
+                    stylesheets: &[Stylesheet], media: &Media, parent: Option<&Inherited>) {
+    let (&index, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *styled = style_subtree(position, stylesheets, media, parent, &mut Vec::new());
+            return;
+        }
+    };
+    let initial = Inherited::initial(media);
+    let inherited = parent.unwrap_or(&initial);
+    let own = inherited.for_children(&styled.specified_values, &styled.font, parent.is_none());
+    // Skip generated content, which has no DOM node of its own.
+    let styled_child = styled.children.iter_mut()
+        .filter(|child| child.pseudo_element.is_none())
+        .nth(index)
+        .expect("Path to restyle isn't in the styled tree");
+    restyle_path(position.child(index), styled_child, rest, stylesheets, media, Some(&own));
+}
+
+// What changed in a document or its stylesheets since it was styled, for 'restyle_tree'.
+// Elements are given as child indices from the root of the DOM tree.
+#[derive(Default)]
+pub struct Invalidations {
+    // Elements whose attributes (including 'class' and 'id') changed.
+    pub attributes: Vec<Vec<usize>>,
+    // Elements whose children changed. These are restyled along with all their descendants.
+    pub subtrees: Vec<Vec<usize>>,
+    // The selectors of rules that were added or removed. Elements they match are restyled.
+    pub selectors: Vec<Selector>,
+}
+
+// Style a DOM tree again after the changes in 'invalidations', reusing the styles in 'old'
+// (the tree styled before them) for the elements they can't affect. The DOM tree can be a
+// modified copy of the one 'old' was styled from, but apart from the elements in
+// 'invalidations.subtrees', the two must have the same shape.
+pub fn restyle_tree<'a>(root: &'a Node, old: &StyledNode, invalidations: &Invalidations,
+                        stylesheets: &[Stylesheet], media: &Media, states: &ElementStates) -> StyledNode<'a> {
+    let restyle = Restyle { stylesheets, media, invalidations, dependencies: Dependencies::new(stylesheets) };
+    let position = TreePosition::root(root, states);
+    restyle.node(position, old, None, &mut Vec::new(), false).0
+}
+
+// The features of selectors (tag names, '#id's, '.class'es and '[attribute's) that can make
+// a change to one element affect others: those matched against ancestors, which affect
+// the element's descendants, and those matched against preceding siblings, which affect
+// its later siblings.
+struct Dependencies {
+    ancestors: HashSet<String>,
+    siblings: HashSet<String>,
+}
+
+impl Dependencies {
+    fn new(stylesheets: &[Stylesheet]) -> Dependencies {
+        let mut dependencies = Dependencies { ancestors: HashSet::new(), siblings: HashSet::new() };
+        for selector in stylesheets.iter().flat_map(|stylesheet| &stylesheet.rules).flat_map(|rule| &rule.selectors) {
+            if let Selector::Complex(ref complex) = *selector {
+                for &(combinator, ref simple) in &complex.combinators {
+                    let features = match combinator {
+                        Combinator::Descendant | Combinator::Child => &mut dependencies.ancestors,
+                        Combinator::NextSibling | Combinator::SubsequentSibling => &mut dependencies.siblings,
+                    };
+                    features.extend(selector_features(simple));
+                }
+            }
+        }
+        dependencies
+    }
+}
+
+fn selector_features(selector: &SimpleSelector) -> Vec<String> {
+    selector.tag_name.iter().cloned()
+        .chain(selector.id.iter().map(|id| format!("#{}", id)))
+        .chain(selector.class.iter().map(|class| format!(".{}", class)))
+        .chain(selector.attributes.iter().map(|attribute| format!("[{}", attribute.name)))
+        .collect()
+}
+
+// The selector features that differ between two versions of an element, or 'None' if
+// either isn't an element, in which case anything could have changed.
+fn changed_features(old: &Node, new: &Node) -> Option<Vec<String>> {
+    let (old, new) = match (&old.node_type, &new.node_type) {
+        (NodeType::Element(old), NodeType::Element(new)) => (old, new),
+        _ => return None,
+    };
+    let mut changed = Vec::new();
+    if old.tag_name != new.tag_name {
+        changed.extend([old.tag_name.clone(), new.tag_name.clone()]);
+    }
+    if old.id() != new.id() {
+        changed.extend(old.id().into_iter().chain(new.id()).map(|id| format!("#{}", id)));
+    }
+    changed.extend(old.classes().symmetric_difference(&new.classes()).map(|class| format!(".{}", class)));
+    let names: HashSet<&String> = old.attributes.keys().chain(new.attributes.keys()).collect();
+    changed.extend(names.into_iter()
+        .filter(|&name| old.attributes.get(name) != new.attributes.get(name))
+        .map(|name| format!("[{}", name)));
+    Some(changed)
+}
+
+struct Restyle<'s> {
+    stylesheets: &'s [Stylesheet],
+    media: &'s Media,
+    invalidations: &'s Invalidations,
+    dependencies: Dependencies,
+}
+
+impl<'s> Restyle<'s> {
+    // Restyle the node at 'position' (and 'path'), which was styled as 'old', and its
+    // descendants. If 'force' is set, or it's invalidated, its whole subtree is styled from
+    // scratch. Also returns whether the node's later siblings have to be restyled.
+    fn node<'a>(&self, position: TreePosition<'a, '_>, old: &StyledNode, parent: Option<&Inherited>,
+                path: &mut Vec<usize>, force: bool) -> (StyledNode<'a>, bool) {
+        let (stylesheets, media) = (self.stylesheets, self.media);
+        let old_children: Vec<&StyledNode> = old.children.iter().filter(|child| child.pseudo_element.is_none()).collect();
+        let matches_changed_rule = match position.node.node_type {
+            NodeType::Element(ref elem) => self.invalidations.selectors.iter().any(|selector| {
+                let subject = match *selector {
+                    Selector::Simple(ref simple) => simple,
+                    Selector::Complex(ref complex) => &complex.subject,
+                };
+                matches(elem, &position, selector, subject.pseudo_element)
+            }),
+            NodeType::Text(_) | NodeType::Comment(_) => false,
+        };
+        if force || matches_changed_rule || self.invalidations.subtrees.contains(path)
+            || old_children.len() != position.node.children.len() {
+            return (style_subtree(position, stylesheets, media, parent, &mut Vec::new()), false);
+        }
+
+        let initial = Inherited::initial(media);
+        let inherited = parent.unwrap_or(&initial);
+        let root = parent.is_none();
+        let attributes_changed = self.invalidations.attributes.contains(path);
+        let (specified_values, computed, font, restyle_children, restyle_siblings) = if attributes_changed {
+            let (values, computed, font) = style_node(&position, stylesheets, media, inherited, root, &mut Vec::new());
+            let (descendants, siblings) = match changed_features(old.node, position.node) {
+                Some(changed) => (changed.iter().any(|feature| self.dependencies.ancestors.contains(feature)),
+                                  changed.iter().any(|feature| self.dependencies.siblings.contains(feature))),
+                None => (true, true),
+            };
+            // The children inherit from this element, so they change if its values do.
+            let restyle_children = descendants || *values != *old.specified_values;
+            (values, computed, font, restyle_children, siblings)
+        } else {
+            (old.specified_values.clone(), old.computed.clone(), old.font.clone(), false, false)
+        };
+        let own = inherited.for_children(&specified_values, &font, root);
+
+        let children = if restyle_children {
+            style_children(&position, stylesheets, media, &own)
+        } else {
+            let mut children = Vec::new();
+            let mut force_siblings = false;
+            for (index, old_child) in old_children.into_iter().enumerate() {
+                path.push(index);
+                let (child, restyle_siblings) = self.node(position.child(index), old_child, Some(&own), path, force_siblings);
+                path.pop();
+                force_siblings |= restyle_siblings;
+                children.push(child);
+            }
+            add_markers(&mut children, &position, stylesheets, media, &own);
+            // Generated content can depend on the element's attributes, but not on its children.
+            for pseudo_element in [PseudoElement::Before, PseudoElement::After] {
+                let generated = match position.node.node_type {
+                    NodeType::Element(ref elem) if attributes_changed => {
+                        style_pseudo_element(elem, &position, stylesheets, media, &own, pseudo_element)
+                    }
+                    _ => old.children.iter()
+                        .find(|child| child.pseudo_element == Some(pseudo_element))
+                        .map(|child| child.reused(position.node)),
+                };
+                match (generated, pseudo_element) {
+                    (Some(before), PseudoElement::Before) => children.insert(0, before),
+                    (Some(after), _) => children.push(after),
+                    (None, _) => {}
+                }
+            }
+            children
+        };
+
+        (StyledNode { node: position.node, pseudo_element: None, font, children, computed, specified_values }, restyle_siblings)
+    }
+}
+
+// Style the node at 'position' and its descendants. 'parent' is what the node
+// inherits, or 'None' at the root. 'siblings' holds the styles of the siblings styled
+// before it, which it can share.
+fn style_subtree<'a>(position: TreePosition<'a, '_>, stylesheets: &[Stylesheet], media: &Media,
+                     parent: Option<&Inherited>, siblings: &mut Vec<SharedStyle>) -> StyledNode<'a> {
+    let initial = Inherited::initial(media);
+    let inherited = parent.unwrap_or(&initial);
+    let (specified_values, computed, font) = style_node(&position, stylesheets, media, inherited, parent.is_none(), siblings);
+    let own = inherited.for_children(&specified_values, &font, parent.is_none());
     StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            NodeType::Text(_) => HashMap::new()
+        node: position.node,
+        pseudo_element: None,
+        font,
+        children: style_children(&position, stylesheets, media, &own),
+        computed,
+        specified_values,
+    }
+}
+
+// The computed values of the node at 'position' on its own, which inherits 'inherited'.
+fn style_node(position: &TreePosition, stylesheets: &[Stylesheet], media: &Media, inherited: &Inherited,
+              root: bool, siblings: &mut Vec<SharedStyle>) -> (Arc<PropertyMap>, Arc<ComputedStyle>, Font) {
+    match position.node.node_type {
+        NodeType::Element(ref elem) => {
+            let rules = matching_rules(elem, position, stylesheets, media, None);
+            let key = sharing_key(elem, &rules);
+            if let Some(shared) = key.as_ref().and_then(|key| siblings.iter().find(|shared| shared.key == *key)) {
+                return (shared.values.clone(), shared.computed.clone(), shared.font.clone());
+            }
+            let mut values = specified_values(elem, &rules, inherited, None);
+            let font = compute_font(&values, inherited);
+            compute_values(&mut values, &font, &inherited.lengths(&font, root));
+            let computed = Arc::new(ComputedStyle::new(&values, inherited.color));
+            let values = Arc::new(values);
+            if let Some(key) = key {
+                siblings.insert(0, SharedStyle { key, values: values.clone(), computed: computed.clone(), font: font.clone() });
+                siblings.truncate(STYLE_SHARING_CACHE_SIZE);
+            }
+            (values, computed, font)
+        }
+        NodeType::Text(_) | NodeType::Comment(_) => {
+            let mut values = HashMap::new();
+            inherit_values(&mut values, inherited);
+            // Comments stay in the style tree, so that its nodes line up with the DOM's,
+            // but they don't generate boxes.
+            if let NodeType::Comment(_) = position.node.node_type {
+                values.insert("display".to_string(), Value::Keyword("none".to_string()));
+            }
+            let font = compute_font(&values, inherited);
+            let computed = Arc::new(ComputedStyle::new(&values, inherited.color));
+            (Arc::new(values), computed, font)
+        }
+    }
+}
+
+// Style the children of the node at 'position', which inherit 'own', along with its
+// generated content.
+fn style_children<'a>(position: &TreePosition<'a, '_>, stylesheets: &[Stylesheet], media: &Media,
+                      own: &Inherited) -> Vec<StyledNode<'a>> {
+    let mut shared_styles = Vec::new();
+    let mut children: Vec<StyledNode> = (0..position.node.children.len()).map(|index| {
+        style_subtree(position.child(index), stylesheets, media, Some(own), &mut shared_styles)
+    }).collect();
+    add_markers(&mut children, position, stylesheets, media, own);
+
+    // Generated content goes before the first child and after the last one.
+    if let NodeType::Element(ref elem) = position.node.node_type {
+        if let Some(before) = style_pseudo_element(elem, position, stylesheets, media, own, PseudoElement::Before) {
+            children.insert(0, before);
+        }
+        if let Some(after) = style_pseudo_element(elem, position, stylesheets, media, own, PseudoElement::After) {
+            children.push(after);
+        }
+    }
+    children
+}
+
+// Siblings with the same tag, classes and matching rules, and no 'style' attribute, end up
+// with the same style, since they inherit from the same parent. They share one copy of it.
+#[derive(PartialEq)]
+struct SharingKey {
+    tag_name: String,
+    classes: Vec<String>, // Sorted
+    rules: Vec<(Specificity, usize)>, // The specificity and order of each matching rule
+}
+
+// The style of an element, kept for its later siblings to share.
+struct SharedStyle {
+    key: SharingKey,
+    values: Arc<PropertyMap>,
+    computed: Arc<ComputedStyle>,
+    font: Font,
+}
+
+// How many of the most recently styled siblings' styles are kept for sharing.
+const STYLE_SHARING_CACHE_SIZE: usize = 8;
+
+// The key 'elem' can share its style under, or 'None' if it can't share it.
+fn sharing_key(elem: &ElementData, rules: &[MatchedRule]) -> Option<SharingKey> {
+    if elem.attributes.contains_key("style") || !presentation_attributes(elem).is_empty() {
+        return None;
+    }
+    let mut classes: Vec<String> = elem.classes().into_iter().map(|class| class.to_string()).collect();
+    classes.sort();
+    Some(SharingKey {
+        tag_name: elem.tag_name.clone(),
+        classes,
+        rules: rules.iter().map(|&(specificity, order, _)| (specificity, order)).collect(),
+    })
+}
+
+// Style the '::before' or '::after' pseudo-element of the element at 'position', which
+// inherits from the element itself. Returns 'None' if it doesn't generate any content.
+fn style_pseudo_element<'a>(elem: &ElementData, position: &TreePosition<'a, '_>, stylesheets: &[Stylesheet],
+                            media: &Media, inherited: &Inherited,
+                            pseudo_element: PseudoElement) -> Option<StyledNode<'a>> {
+    let rules = matching_rules(elem, position, stylesheets, media, Some(pseudo_element));
+    let mut specified_values = specified_values(elem, &rules, inherited, Some(pseudo_element));
+    let font = compute_font(&specified_values, inherited);
+    compute_values(&mut specified_values, &font, &LengthContext { font_size: font.size, ..inherited.lengths });
+    let styled = StyledNode {
+        node: position.node,
+        pseudo_element: Some(pseudo_element),
+        font,
+        children: Vec::new(),
+        computed: Arc::new(ComputedStyle::new(&specified_values, inherited.color)),
+        specified_values: Arc::new(specified_values),
+    };
+    styled.generated_text().map(|_| styled)
+}
+
+// Give each list item among 'children', the styled children of the node at 'position', its
+// '::marker'. Items are numbered from the 'start' attribute of an 'ol', or from 1, going up
+// by one from each item to the next, and an item's 'value' attribute sets its number.
+fn add_markers<'a>(children: &mut [StyledNode<'a>], position: &TreePosition<'a, '_>, stylesheets: &[Stylesheet],
+                   media: &Media, own: &Inherited) {
+    let number = |elem: &ElementData, name: &str| elem.attributes.get(name).and_then(|value| value.trim().parse::<i32>().ok());
+    let mut ordinal = match position.node.node_type {
+        NodeType::Element(ref elem) if elem.tag_name == "ol" => number(elem, "start").unwrap_or(1),
+        _ => 1,
+    };
+    for (index, child) in children.iter_mut().enumerate() {
+        let elem = match child.node.node_type {
+            NodeType::Element(ref elem) if child.display() == Display::ListItem => elem,
+            _ => continue,
+        };
+        ordinal = number(elem, "value").unwrap_or(ordinal);
+        let inherited = own.for_children(&child.specified_values, &child.font, false);
+        if let Some(marker) = style_marker(elem, &position.child(index), stylesheets, media, &inherited, ordinal) {
+            child.children.insert(0, marker);
+        }
+        ordinal += 1;
+    }
+}
+
+// Style the '::marker' of the list item at 'position', numbered 'ordinal', which inherits
+// 'inherited' from the item. Its text is its 'content', or else a bullet or number for its
+// 'list-style-type', and it's 'None' for 'none'. Its color is kept for painting bullets.
+fn style_marker<'a>(elem: &ElementData, position: &TreePosition<'a, '_>, stylesheets: &[Stylesheet],
+                    media: &Media, inherited: &Inherited, ordinal: i32) -> Option<StyledNode<'a>> {
+    let rules = matching_rules(elem, position, stylesheets, media, Some(PseudoElement::Marker));
+    let mut specified_values = specified_values(elem, &rules, inherited, Some(PseudoElement::Marker));
+    let font = compute_font(&specified_values, inherited);
+    compute_values(&mut specified_values, &font, &LengthContext { font_size: font.size, ..inherited.lengths });
+    let text = match ListStyleType::from_values(&specified_values) {
+        ListStyleType::Disc => "\u{2022} ".to_string(),
+        ListStyleType::Circle => "\u{25e6} ".to_string(),
+        ListStyleType::Square => "\u{25aa} ".to_string(),
+        ListStyleType::Decimal => format!("{}. ", ordinal),
+        ListStyleType::None => return None,
+    };
+    if !matches!(specified_values.get("content"), Some(Value::String(_) | Value::List(..))) {
+        specified_values.insert("content".to_string(), Value::String(text));
+    }
+    specified_values.insert("color".to_string(), Value::ColorValue(computed_color(&specified_values, inherited)));
+    Some(StyledNode {
+        node: position.node,
+        pseudo_element: Some(PseudoElement::Marker),
+        font,
+        children: Vec::new(),
+        computed: Arc::new(ComputedStyle::new(&specified_values, inherited.color)),
+        specified_values: Arc::new(specified_values),
+    })
+}
+
+// Turn the specified values of a node into computed values, given its computed font and
+// the lengths it's resolved against. Lengths become 'px', including inside 'calc()', and
+// keywords for lengths are replaced with them. Percentages are left for layout, since
+// they depend on the containing block.
+fn compute_values(values: &mut PropertyMap, font: &Font, lengths: &LengthContext) {
+    for (name, value) in values.iter_mut().filter(|&(name, _)| !name.starts_with("--")) {
+        *value = match *value {
+            _ if name == "font-size" => Value::Length(font.size, Unit::Px),
+            // A percentage line height is of the font size, and inherited as a length.
+            Value::Percentage(p) if name == "line-height" => Value::Length(p / 100.0 * font.size, Unit::Px),
+            Value::Keyword(ref keyword) if name.starts_with("border-") && name.ends_with("-width") => {
+                match &*keyword.to_ascii_lowercase() {
+                    "thin" => Value::Length(1.0, Unit::Px),
+                    "medium" => Value::Length(3.0, Unit::Px),
+                    "thick" => Value::Length(5.0, Unit::Px),
+                    _ => continue,
+                }
+            }
+            ref value => compute_lengths(value, lengths),
+        };
+    }
+}
+
+// 'value' with its lengths in 'px'. A 'calc()' without percentages becomes a single length.
+fn compute_lengths(value: &Value, lengths: &LengthContext) -> Value {
+    match *value {
+        Value::Length(_, Unit::Px) => value.clone(),
+        Value::Length(..) => Value::Length(value.resolve(lengths), Unit::Px),
+        Value::Calc(ref calc) if !calc_has_percentage(calc) => Value::Length(calc.resolve(lengths), Unit::Px),
+        Value::Calc(ref calc) => Value::Calc(Box::new(compute_calc(calc, lengths))),
+        Value::List(ref items, separator) => {
+            Value::List(items.iter().map(|item| compute_lengths(item, lengths)).collect(), separator)
+        }
+        Value::Transform(ref functions) => Value::Transform(functions.iter().map(|function| match *function {
+            TransformFunction::Translate(ref x, ref y) => {
+                TransformFunction::Translate(compute_lengths(x, lengths), compute_lengths(y, lengths))
+            }
+            ref function => function.clone(),
+        }).collect()),
+        _ => value.clone(),
+    }
+}
+
+fn compute_calc(calc: &Calc, lengths: &LengthContext) -> Calc {
+    let both = |a: &Calc, b: &Calc| (Box::new(compute_calc(a, lengths)), Box::new(compute_calc(b, lengths)));
+    match *calc {
+        Calc::Leaf(ref value) => Calc::Leaf(compute_lengths(value, lengths)),
+        Calc::Sum(ref a, ref b) => { let (a, b) = both(a, b); Calc::Sum(a, b) }
+        Calc::Difference(ref a, ref b) => { let (a, b) = both(a, b); Calc::Difference(a, b) }
+        Calc::Product(ref a, ref b) => { let (a, b) = both(a, b); Calc::Product(a, b) }
+        Calc::Quotient(ref a, ref b) => { let (a, b) = both(a, b); Calc::Quotient(a, b) }
+    }
+}
+
+fn calc_has_percentage(calc: &Calc) -> bool {
+    match *calc {
+        Calc::Leaf(ref value) => matches!(*value, Value::Percentage(_)),
+        Calc::Sum(ref a, ref b) | Calc::Difference(ref a, ref b) |
+        Calc::Product(ref a, ref b) | Calc::Quotient(ref a, ref b) => calc_has_percentage(a) || calc_has_percentage(b),
+    }
+}
+
+// Compute the font properties of a node from its specified values and what it inherits.
+fn compute_font(values: &PropertyMap, inherited: &Inherited) -> Font {
+    let parent = &inherited.font;
+    Font {
+        size: match values.get("font-size") {
+            // 'em' units in 'font-size' refer to the parent's font size.
+            Some(value @ Value::Length(..)) => value.resolve(&inherited.lengths),
+            // So do percentages.
+            Some(&Value::Percentage(p)) => p / 100.0 * parent.size,
+            _ => parent.size,
+        },
+        style: match values.get("font-style") {
+            Some(Value::Keyword(k)) => match &**k {
+                "italic" => FontStyle::Italic,
+                "oblique" => FontStyle::Oblique,
+                _ => FontStyle::Normal,
+            },
+            _ => parent.style,
+        },
+        weight: match values.get("font-weight") {
+            Some(&Value::Number(n)) => n.clamp(1.0, 1000.0) as u16,
+            Some(Value::Keyword(k)) => match &**k {
+                "bold" => 700,
+                "bolder" => bolder(parent.weight),
+                "lighter" => lighter(parent.weight),
+                _ => 400,
+            },
+            _ => parent.weight,
+        },
+        family: match values.get("font-family") {
+            Some(Value::List(families, _)) => families.iter().filter_map(|family| match family {
+                Value::Keyword(name) | Value::String(name) => Some(name.clone()),
+                _ => None,
+            }).collect(),
+            _ => parent.family.clone(),
         },
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect(),
     }
 }
 
-enum Display {
+// The weight 'bolder' resolves to, relative to the parent's weight.
+fn bolder(weight: u16) -> u16 {
+    match weight {
+        0..=349 => 400,
+        350..=549 => 700,
+        550..=899 => 900,
+        _ => weight,
+    }
+}
+
+// The weight 'lighter' resolves to, relative to the parent's weight.
+fn lighter(weight: u16) -> u16 {
+    match weight {
+        0..=99 => weight,
+        100..=549 => 100,
+        550..=749 => 400,
+        _ => 700,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Display {
     Inline,
     Block,
+    InlineBlock,
+    ListItem,
+    Flex,
+    Grid,
+    Table,
+    InlineTable,
+    TableRowGroup,
+    TableHeaderGroup,
+    TableFooterGroup,
+    TableRow,
+    TableCell,
+    TableColumnGroup,
+    TableColumn,
+    TableCaption,
+    Contents,
+    None,
+}
+
+impl Display {
+    // The value of a 'display' keyword, or 'None' if it isn't one.
+    pub fn from_keyword(keyword: &str) -> Option<Display> {
+        Some(match &*keyword.to_ascii_lowercase() {
+            "inline" => Display::Inline,
+            "block" => Display::Block,
+            "inline-block" => Display::InlineBlock,
+            "list-item" => Display::ListItem,
+            "flex" => Display::Flex,
+            "grid" => Display::Grid,
+            "table" => Display::Table,
+            "inline-table" => Display::InlineTable,
+            "table-row-group" => Display::TableRowGroup,
+            "table-header-group" => Display::TableHeaderGroup,
+            "table-footer-group" => Display::TableFooterGroup,
+            "table-row" => Display::TableRow,
+            "table-cell" => Display::TableCell,
+            "table-column-group" => Display::TableColumnGroup,
+            "table-column" => Display::TableColumn,
+            "table-caption" => Display::TableCaption,
+            "contents" => Display::Contents,
+            "none" => Display::None,
+            _ => return None,
+        })
+    }
+
+    // Does this generate a box that sits in a block formatting context, rather than in a line?
+    pub fn is_block_level(self) -> bool {
+        matches!(self, Display::Block | Display::ListItem | Display::Flex | Display::Grid | Display::Table)
+    }
+
+    // Does this generate a box that belongs inside a table, or a part of one?
+    pub fn is_table_part(self) -> bool {
+        matches!(self, Display::TableRowGroup | Display::TableHeaderGroup | Display::TableFooterGroup |
+                 Display::TableRow | Display::TableCell | Display::TableColumnGroup | Display::TableColumn |
+                 Display::TableCaption)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Visibility {
+    Visible,
+    Hidden,
+    Collapse, // The same as 'Hidden': collapsed table rows and columns still take up room
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Float {
     None,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Position {
+    Static,
+    Relative,
+    Absolute,
+    Fixed,
+}
+
+// What's done with a box's contents where they go outside it. Anything but 'Visible' clips
+// them to its padding box. Nothing scrolls, so 'Scroll' and 'Auto' are like 'Hidden'.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Visible,
+    Hidden,
+    Clip, // Like 'Hidden', but the box doesn't get a block formatting context of its own
+    Scroll,
+    Auto,
+}
+
+// Where the contents of a block container's lines go across them. Lines always go left to
+// right, so 'start' is 'Left' and 'end' is 'Right'.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Right,
+    Center,
+    Justify, // Between the ends of each line but the last, by spacing its words out
+}
+
+// What happens to whitespace in text: whether runs of spaces and tabs collapse into one
+// space, whether newlines break lines or are spaces too, and whether lines wrap.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Nowrap, // Like 'Normal', but lines only break at blocks and '<br>'s
+    Pre, // Nothing collapses, and lines only break at newlines
+    PreWrap, // Like 'Pre', but lines wrap too
+    PreLine, // Like 'Normal', but lines break at newlines too
+}
+
+impl WhiteSpace {
+    // Do runs of spaces and tabs collapse into a single space?
+    pub fn collapses(self) -> bool {
+        matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine)
+    }
+
+    // Do newlines break lines, rather than being spaces?
+    pub fn keeps_newlines(self) -> bool {
+        !matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap)
+    }
+
+    // Can lines break after whitespace when they're full?
+    pub fn wraps(self) -> bool {
+        !matches!(self, WhiteSpace::Nowrap | WhiteSpace::Pre)
+    }
+}
+
+// Whether a word too wide for a line of its own can break between any two characters.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OverflowWrap {
+    Normal,
+    BreakWord, // Also for 'anywhere', which lays out the same here
+}
+
+// What a list item's marker shows.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ListStyleType {
+    Disc,
+    Circle,
+    Square,
+    Decimal,
+    None,
+}
+
+impl ListStyleType {
+    fn from_values(values: &PropertyMap) -> ListStyleType {
+        match values.get("list-style-type") {
+            Some(Value::Keyword(keyword)) => match &*keyword.to_ascii_lowercase() {
+                "circle" => ListStyleType::Circle,
+                "square" => ListStyleType::Square,
+                "decimal" => ListStyleType::Decimal,
+                "none" => ListStyleType::None,
+                _ => ListStyleType::Disc,
+            },
+            _ => ListStyleType::Disc,
+        }
+    }
+}
+
+// Where a list item's marker goes: before the start of its first line, outside its box, or
+// at the start of that line, as the first thing on it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ListStylePosition {
+    Outside,
+    Inside,
+}
+
+// Where an inline-level box goes up and down on its line: with its baseline on that of the
+// inline box it's in, or moved from there, or at the top or bottom of the line.
+#[derive(Clone, PartialEq)]
+pub enum VerticalAlign {
+    Baseline,
+    Middle, // Its middle half an 'ex' above the baseline
+    TextTop, // Its top at the top of the inline box's font
+    TextBottom,
+    Top,
+    Bottom,
+    Length(Value), // Raised by a length, or a percentage of its line height
+}
+
+// How tall the lines an inline box is on are around it: as its font says for 'normal', a
+// multiple of its font size, or a length.
+#[derive(Clone)]
+pub enum LineHeight {
+    Normal,
+    Number(f32),
+    Length(Value),
+}
+
+// Which way a flex container's main axis goes, which its items are laid out along.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlexDirection {
+    Row,
+    RowReverse,
+    Column,
+    ColumnReverse,
+}
+
+// Whether a flex container's items go on one line, or wrap onto more, and which way the
+// lines stack if they do.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlexWrap {
+    NoWrap,
+    Wrap,
+    WrapReverse,
+}
+
+// Where boxes go in the room there is for them along an axis, for properties like
+// 'justify-content' and 'align-items'. Baselines aren't lined up, so 'baseline' is like
+// 'flex-start', and 'start' and 'end' don't follow the writing mode.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Alignment {
+    Start,
+    End,
+    Center,
+    Stretch,
+    SpaceBetween,
+    SpaceAround,
+    SpaceEvenly,
+}
+
+impl Alignment {
+    // The value of an alignment keyword, or 'None' if it isn't one. What 'normal' and 'auto'
+    // mean depends on the property.
+    fn from_keyword(keyword: &str) -> Option<Alignment> {
+        Some(match &*keyword.to_ascii_lowercase() {
+            "flex-start" | "start" | "self-start" | "left" | "baseline" => Alignment::Start,
+            "flex-end" | "end" | "self-end" | "right" => Alignment::End,
+            "center" => Alignment::Center,
+            "stretch" => Alignment::Stretch,
+            "space-between" => Alignment::SpaceBetween,
+            "space-around" => Alignment::SpaceAround,
+            "space-evenly" => Alignment::SpaceEvenly,
+            _ => return None,
+        })
+    }
+}
+
+// The size a flex item starts out at along the main axis, before it grows or shrinks.
+#[derive(Clone)]
+pub enum FlexBasis {
+    Auto, // Its 'width' or 'height', or its contents' size if that's 'auto'
+    Content, // Its contents' size
+    Length(Value),
+}
+
+// The ratio of a box's width to its height that 'aspect-ratio' gives, which an 'auto' width
+// or height is worked out from. With 'auto', a replaced element's own ratio is used if it
+// has one, and 'ratio' is only for when it doesn't. A ratio with a zero in it is none.
+#[derive(Clone, Copy, PartialEq)]
+pub struct AspectRatio {
+    pub auto: bool,
+    pub ratio: Option<f32>,
+}
+
+impl AspectRatio {
+    // The 'aspect-ratio' value, like 'auto', '16 / 9', '1.5' or 'auto 4 / 3', or 'None'
+    // if it isn't one.
+    fn from_value(value: &Value) -> Option<AspectRatio> {
+        let auto = |value: &Value| matches!(*value, Value::Keyword(ref k) if k.eq_ignore_ascii_case("auto"));
+        let ratio = |value: &Value| match *value {
+            Value::Number(width) if width >= 0.0 => Some((width, 1.0)),
+            Value::List(ref items, Separator::Slash) => match items[..] {
+                [Value::Number(width), Value::Number(height)] if width >= 0.0 && height >= 0.0 => Some((width, height)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let (auto, (width, height)) = match *value {
+            ref value if auto(value) => return Some(AspectRatio { auto: true, ratio: None }),
+            Value::List(ref items, Separator::Space) => match items[..] {
+                [ref first, ref second] if auto(first) => (true, ratio(second)?),
+                [ref first, ref second] if auto(second) => (true, ratio(first)?),
+                _ => return None,
+            },
+            ref value => (false, ratio(value)?),
+        };
+        let ratio = Some(width / height).filter(|_| width > 0.0 && height > 0.0 && width.is_finite() && height.is_finite());
+        Some(AspectRatio { auto, ratio })
+    }
+}
+
+// How big a grid track is: a length or percentage, a share of the room left over, or as big
+// as what's in it.
+#[derive(Clone)]
+pub enum TrackSize {
+    Length(Value),
+    Flex(f32),
+    Auto,
+}
+
+impl TrackSize {
+    // The tracks in a grid template like '100px 1fr auto', or 'None' if it isn't one. 'none'
+    // has no tracks, and 'repeat()' has already been written out by the parser.
+    fn list_from_value(value: &Value) -> Option<Vec<TrackSize>> {
+        Some(match *value {
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("none") => Vec::new(),
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("auto") => vec![TrackSize::Auto],
+            Value::Length(n, _) | Value::Percentage(n) if n >= 0.0 => vec![TrackSize::Length(value.clone())],
+            Value::Calc(_) => vec![TrackSize::Length(value.clone())],
+            Value::Flex(n) if n >= 0.0 => vec![TrackSize::Flex(n)],
+            Value::List(ref items, Separator::Space) => {
+                let mut tracks = Vec::new();
+                for item in items {
+                    match *item {
+                        Value::Keyword(ref k) if k.eq_ignore_ascii_case("none") => return None,
+                        _ => tracks.extend(TrackSize::list_from_value(item)?),
+                    }
+                }
+                tracks
+            }
+            _ => return None,
+        })
+    }
+}
+
+// Where a grid item starts or ends: at a numbered grid line, counting back from the end if
+// it's negative, a number of tracks from its other edge, or wherever it's placed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum GridLine {
+    Auto,
+    Line(i32),
+    Span(usize),
+}
+
+impl GridLine {
+    // The grid line in a value like '2', '-1' or 'span 2', or 'None' if it isn't one.
+    fn from_value(value: &Value) -> Option<GridLine> {
+        let is_span = |k: &str| k.eq_ignore_ascii_case("span");
+        match *value {
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("auto") => Some(GridLine::Auto),
+            // Clamped like spans, so a huge line number can't ask for billions of tracks.
+            Value::Number(n) if n.fract() == 0.0 && n != 0.0 => Some(GridLine::Line(n.clamp(-10000.0, 10000.0) as i32)),
+            Value::List(ref items, Separator::Space) => match items[..] {
+                [Value::Keyword(ref k), Value::Number(n)] | [Value::Number(n), Value::Keyword(ref k)]
+                    if is_span(k) && n.fract() == 0.0 && n >= 1.0 => Some(GridLine::Span(n.min(10000.0) as usize)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// Which floats a block goes below.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Clear {
+    None,
+    Left,
+    Right,
+    Both,
+}
+
+// A value for each side of a box, like the 'margin-*' properties.
+#[derive(Clone)]
+pub struct Sides<T> {
+    pub top: T,
+    pub right: T,
+    pub bottom: T,
+    pub left: T,
+}
+
+impl<T> Sides<T> {
+    fn new(value: impl Fn(&str) -> T) -> Sides<T> {
+        Sides { top: value("top"), right: value("right"), bottom: value("bottom"), left: value("left") }
+    }
+}
+
+// The computed values of the properties that layout and painting use, read out of
+// the property map once per node rather than looked up by name for every box.
+#[derive(Clone)]
+pub struct ComputedStyle {
+    pub color: Color,
+    pub display: Display,
+    pub visibility: Visibility,
+    pub float: Float,
+    pub clear: Clear,
+    pub position: Position,
+    pub offsets: Sides<Option<Value>>, // 'top', 'right', 'bottom' and 'left'; 'None' for 'auto'
+    pub z_index: Option<i32>, // 'None' for 'auto'
+    pub overflow: Overflow,
+    pub text_align: TextAlign,
+    pub white_space: WhiteSpace,
+    pub overflow_wrap: OverflowWrap,
+    pub list_style_type: ListStyleType,
+    pub list_style_position: ListStylePosition,
+    pub vertical_align: VerticalAlign,
+    pub line_height: LineHeight,
+    pub width: Option<Value>, // 'None' for 'auto'
+    pub height: Option<Value>, // 'None' for 'auto'
+    pub aspect_ratio: AspectRatio,
+    pub margin: Sides<Option<Value>>, // 'None' for 'auto'
+    pub padding: Sides<Value>,
+    pub border_width: Sides<Value>,
+    pub border_color: Sides<Option<Color>>,
+    pub background_color: Option<Color>,
+    pub fill: Option<Color>, // The color SVG shapes are filled with, 'None' for 'none'
+    pub border_spacing: (Value, Value), // Between a table's cells, across and down
+    pub flex_direction: FlexDirection,
+    pub justify_content: Alignment,
+    pub align_items: Alignment,
+    pub align_self: Option<Alignment>, // 'None' for 'auto', which takes the container's 'align-items'
+    pub flex_grow: f32,
+    pub flex_shrink: f32,
+    pub flex_basis: FlexBasis,
+    pub flex_wrap: FlexWrap,
+    pub align_content: Alignment,
+    pub row_gap: Value, // Between flex lines in a row, and items in a column
+    pub column_gap: Value, // Between items in a row, and flex lines in a column
+    pub order: i32, // Where the box goes among the flex or grid items it's laid out with
+    pub grid_template_columns: Vec<TrackSize>,
+    pub grid_template_rows: Vec<TrackSize>,
+    pub grid_column: (GridLine, GridLine), // Where a grid item starts and ends
+    pub grid_row: (GridLine, GridLine),
+}
+
+impl ComputedStyle {
+    // 'inherited_color' is the 'color' of the parent, which is what the node has if
+    // 'values' don't set it.
+    fn new(values: &PropertyMap, inherited_color: Color) -> ComputedStyle {
+        let zero = Value::Length(0.0, Unit::Px);
+        // A length or percentage, 'None' for 'auto', or 'default' if the property isn't set to either.
+        let length = |name: String, default: Option<Value>| match values.get(&name) {
+            Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("auto") => None,
+            Some(value @ &Value::Length(..)) | Some(value @ &Value::Percentage(_)) |
+            Some(value @ &Value::Calc(_)) => Some(value.clone()),
+            _ => default,
+        };
+        let color = |name: String| values.get(&name).and_then(|value| value.to_color());
+        let alignment = |name: &str| match values.get(name) {
+            Some(Value::Keyword(keyword)) => Alignment::from_keyword(keyword),
+            _ => None,
+        };
+        let tracks = |name: &str| values.get(name).and_then(TrackSize::list_from_value).unwrap_or_default();
+        let grid_line = |name: &str| values.get(name).and_then(GridLine::from_value).unwrap_or(GridLine::Auto);
+        let number = |name: &str, default: f32| match values.get(name) {
+            Some(&Value::Number(n)) => n,
+            _ => default,
+        };
+        ComputedStyle {
+            color: color("color".to_string()).unwrap_or(inherited_color),
+            display: match values.get("display") {
+                Some(Value::Keyword(keyword)) => Display::from_keyword(keyword).unwrap_or(Display::Inline),
+                _ => Display::Inline
+            },
+            visibility: match values.get("visibility") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("hidden") => Visibility::Hidden,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("collapse") => Visibility::Collapse,
+                _ => Visibility::Visible,
+            },
+            float: match values.get("float") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("left") => Float::Left,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("right") => Float::Right,
+                _ => Float::None,
+            },
+            clear: match values.get("clear") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("left") => Clear::Left,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("right") => Clear::Right,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("both") => Clear::Both,
+                _ => Clear::None,
+            },
+            position: match values.get("position") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("relative") => Position::Relative,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("absolute") => Position::Absolute,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("fixed") => Position::Fixed,
+                _ => Position::Static,
+            },
+            offsets: Sides::new(|side| length(side.to_string(), None)),
+            z_index: match values.get("z-index") {
+                Some(&Value::Number(n)) => Some(n as i32),
+                _ => None,
+            },
+            overflow: match values.get("overflow") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("hidden") => Overflow::Hidden,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("clip") => Overflow::Clip,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("scroll") => Overflow::Scroll,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("auto") => Overflow::Auto,
+                _ => Overflow::Visible,
+            },
+            text_align: match values.get("text-align") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("right") || keyword.eq_ignore_ascii_case("end") => {
+                    TextAlign::Right
+                }
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("center") => TextAlign::Center,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("justify") => TextAlign::Justify,
+                _ => TextAlign::Left,
+            },
+            white_space: match values.get("white-space") {
+                Some(Value::Keyword(keyword)) => match &*keyword.to_ascii_lowercase() {
+                    "nowrap" => WhiteSpace::Nowrap,
+                    "pre" => WhiteSpace::Pre,
+                    "pre-wrap" => WhiteSpace::PreWrap,
+                    "pre-line" => WhiteSpace::PreLine,
+                    _ => WhiteSpace::Normal,
+                },
+                _ => WhiteSpace::Normal,
+            },
+            overflow_wrap: match values.get("overflow-wrap") {
+                Some(Value::Keyword(keyword))
+                    if keyword.eq_ignore_ascii_case("break-word") || keyword.eq_ignore_ascii_case("anywhere") => {
+                    OverflowWrap::BreakWord
+                }
+                _ => OverflowWrap::Normal,
+            },
+            list_style_type: ListStyleType::from_values(values),
+            list_style_position: match values.get("list-style-position") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("inside") => ListStylePosition::Inside,
+                _ => ListStylePosition::Outside,
+            },
+            vertical_align: match values.get("vertical-align") {
+                Some(Value::Keyword(keyword)) => match &*keyword.to_ascii_lowercase() {
+                    "middle" => VerticalAlign::Middle,
+                    "text-top" => VerticalAlign::TextTop,
+                    "text-bottom" => VerticalAlign::TextBottom,
+                    "top" => VerticalAlign::Top,
+                    "bottom" => VerticalAlign::Bottom,
+                    _ => VerticalAlign::Baseline,
+                },
+                _ => length("vertical-align".to_string(), None).map_or(VerticalAlign::Baseline, VerticalAlign::Length),
+            },
+            line_height: match values.get("line-height") {
+                Some(&Value::Number(n)) => LineHeight::Number(n),
+                Some(value @ (Value::Length(..) | Value::Calc(_))) => LineHeight::Length(value.clone()),
+                _ => LineHeight::Normal,
+            },
+            width: length("width".to_string(), None),
+            height: length("height".to_string(), None),
+            aspect_ratio: values.get("aspect-ratio").and_then(AspectRatio::from_value)
+                .unwrap_or(AspectRatio { auto: true, ratio: None }),
+            margin: Sides::new(|side| length(format!("margin-{}", side), Some(zero.clone()))),
+            padding: Sides::new(|side| length(format!("padding-{}", side), None).unwrap_or(zero.clone())),
+            border_width: Sides::new(|side| length(format!("border-{}-width", side), None).unwrap_or(zero.clone())),
+            border_color: Sides::new(|side| color(format!("border-{}-color", side))),
+            background_color: color("background-color".to_string()),
+            // 'fill' is inherited, and black by default.
+            fill: match values.get("fill") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("none") => None,
+                Some(value) => value.to_color(),
+                None => Some(Color { r: 0, g: 0, b: 0, a: 255 }),
+            },
+            border_spacing: match values.get("border-spacing") {
+                Some(Value::List(items, Separator::Space)) if items.len() == 2 => (items[0].clone(), items[1].clone()),
+                Some(spacing @ Value::Length(..)) => (spacing.clone(), spacing.clone()),
+                _ => (zero.clone(), zero.clone()),
+            },
+            flex_direction: match values.get("flex-direction") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("row-reverse") => FlexDirection::RowReverse,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("column") => FlexDirection::Column,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("column-reverse") => FlexDirection::ColumnReverse,
+                _ => FlexDirection::Row,
+            },
+            justify_content: alignment("justify-content").unwrap_or(Alignment::Start),
+            align_items: alignment("align-items").unwrap_or(Alignment::Stretch),
+            align_self: alignment("align-self"),
+            flex_grow: number("flex-grow", 0.0),
+            flex_shrink: number("flex-shrink", 1.0),
+            flex_basis: match values.get("flex-basis") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("content") => FlexBasis::Content,
+                _ => length("flex-basis".to_string(), None).map_or(FlexBasis::Auto, FlexBasis::Length),
+            },
+            flex_wrap: match values.get("flex-wrap") {
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("wrap") => FlexWrap::Wrap,
+                Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("wrap-reverse") => FlexWrap::WrapReverse,
+                _ => FlexWrap::NoWrap,
+            },
+            align_content: alignment("align-content").unwrap_or(Alignment::Stretch),
+            row_gap: length("row-gap".to_string(), None).unwrap_or(zero.clone()),
+            column_gap: length("column-gap".to_string(), None).unwrap_or(zero.clone()),
+            order: number("order", 0.0) as i32,
+            grid_template_columns: tracks("grid-template-columns"),
+            grid_template_rows: tracks("grid-template-rows"),
+            grid_column: (grid_line("grid-column-start"), grid_line("grid-column-end")),
+            grid_row: (grid_line("grid-row-start"), grid_line("grid-row-end")),
+        }
+    }
 }
 
 impl<'a> StyledNode<'a> {
-    // Return the specified value of a property if it exists, otherwise 'None'.
+    // Return the computed value of a property if it exists, otherwise 'None'.
+    // Property names are case-insensitive, except for custom properties.
     pub fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).map(|v| v.clone())
+        if name.starts_with("--") {
+            self.specified_values.get(name).cloned()
+        } else {
+            self.specified_values.get(&name.to_ascii_lowercase()).cloned()
+        }
+    }
+
+    // A copy of this childless node (generated content) for 'node' in another DOM tree.
+    fn reused<'b>(&self, node: &'b Node) -> StyledNode<'b> {
+        StyledNode {
+            node,
+            pseudo_element: self.pseudo_element,
+            specified_values: self.specified_values.clone(),
+            computed: self.computed.clone(),
+            font: self.font.clone(),
+            children: Vec::new(),
+        }
+    }
+
+    // Set the computed value of a property, as animations do, keeping 'computed' up to date.
+    pub fn set_value(&mut self, name: String, value: Value) {
+        // Without a 'color' of its own, the node's computed color is the one it inherited.
+        let inherited_color = self.computed.color;
+        Arc::make_mut(&mut self.specified_values).insert(name, value);
+        self.computed = Arc::new(ComputedStyle::new(&self.specified_values, inherited_color));
     }
 
     // The value of the 'display' property (defaults to inline).
     pub fn display(&self) -> Display {
-        match self.value("display") {
-            Some(Value::Keyword(s)) => match &*s {
-                "block" => Display::Block,
-                "none" => Display::None,
-                _ => Display::Inline
-            },
-            _ => Display::Inline
+        self.computed.display
+    }
+
+    // The DOM node this is the style of (the originating element, for pseudo-elements).
+    pub fn node(&self) -> &'a Node {
+        self.node
+    }
+
+    // A short description of the node for debugging output, like 'p.note::before'.
+    pub fn label(&self) -> String {
+        match self.pseudo_element {
+            Some(pseudo_element) => format!("{}{}", self.node.label(), pseudo_element),
+            None => self.node.label(),
+        }
+    }
+
+    // The styled node for the DOM node at 'path' (child indices from this node's DOM node),
+    // or for its '::before', '::after' or '::marker' pseudo-element. 'None' if there isn't
+    // one, like for a pseudo-element without content.
+    pub fn descendant(&self, path: &[usize], pseudo_element: Option<PseudoElement>) -> Option<&StyledNode<'a>> {
+        let node = match path.split_first() {
+            Some((&index, rest)) => {
+                // Skip generated content, which has no DOM node of its own.
+                let child = self.children.iter().filter(|child| child.pseudo_element.is_none()).nth(index)?;
+                return child.descendant(rest, pseudo_element);
+            }
+            None => self,
+        };
+        match pseudo_element {
+            None => Some(node),
+            Some(_) => node.children.iter().find(|child| child.pseudo_element == pseudo_element),
+        }
+    }
+
+    // The computed values of all the properties set on this node, written as CSS and
+    // ordered by property name. Properties that aren't here have their initial values.
+    pub fn computed_values(&self) -> BTreeMap<String, String> {
+        self.specified_values.iter().map(|(name, value)| (name.clone(), value.to_string())).collect()
+    }
+
+    // The text of a '::before', '::after' or '::marker' node, from its 'content' property.
+    // This is 'None' for other nodes, and when 'content' is 'none' or 'normal'.
+    pub fn generated_text(&self) -> Option<String> {
+        self.pseudo_element?;
+        match self.value("content") {
+            Some(Value::String(text)) => Some(text),
+            Some(Value::List(items, Separator::Space)) => items.iter().map(|item| match *item {
+                Value::String(ref text) => Some(text.clone()),
+                _ => None,
+            }).collect(),
+            _ => None,
+        }
+    }
+
+    // The text a text node or a generated node shows, or 'None' for elements.
+    pub fn text(&self) -> Option<String> {
+        match self.node().node_type {
+            _ if self.pseudo_element.is_some() => self.generated_text(),
+            NodeType::Text(ref text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::Unit;
+    use crate::testing::{media, same_styles, style_tree, styled, stylesheet};
+
+    #[test]
+    fn font_properties_are_computed_and_inherited() {
+        styled("<div><p></p></div>", "div { font: italic bold 12px Georgia; } p { font-weight: bolder; }", |root| {
+            assert_eq!(root.font.size, 12.0);
+            assert!(root.font.style == FontStyle::Italic);
+            assert_eq!(root.font.weight, 700);
+            assert_eq!(root.font.family, vec!["Georgia".to_string()]);
+
+            let p = &root.children[0].font;
+            assert_eq!((p.size, p.weight), (12.0, 900));
+            assert!(p.style == FontStyle::Italic);
+        });
+    }
+
+    #[test]
+    fn important_declarations_override_specificity() {
+        styled("<p id=\"x\" class=\"a\"></p>", "#x { width: 1px; height: 1px; } p.a { height: 2px; } \
+                                               p { width: 3px !important; } p { height: 4px ! IMPORTANT; }", |p| {
+            assert!(p.value("width") == Some(Value::Length(3.0, Unit::Px)));
+            assert!(p.value("height") == Some(Value::Length(4.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn descendant_selectors() {
+        styled("<div class=\"a\"><p><i></i></p></div>", ".a i { width: 1px; } p  i { height: 2px; } \
+                                                       .a .a i, b i { width: 3px; }", |div| {
+            let i = &div.children[0].children[0];
+            assert!(i.value("width") == Some(Value::Length(1.0, Unit::Px)));
+            assert!(i.value("height") == Some(Value::Length(2.0, Unit::Px)));
+            assert!(div.children[0].value("width").is_none());
+        });
+    }
+
+    #[test]
+    fn child_selectors() {
+        styled("<div><p><i></i></p></div>", "div > i { width: 1px; } div>p > i { height: 2px; }", |div| {
+            let i = &div.children[0].children[0];
+            assert!(i.value("width").is_none());
+            assert!(i.value("height") == Some(Value::Length(2.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn sibling_selectors() {
+        styled("<div><p></p>text<i></i><b></b></div>", "p + i { width: 1px; } p + b { width: 2px; } p ~ b { height: 3px; }", |div| {
+            let (i, b) = (&div.children[2], &div.children[3]);
+            assert!(i.value("width") == Some(Value::Length(1.0, Unit::Px)));
+            assert!(b.value("width").is_none());
+            assert!(b.value("height") == Some(Value::Length(3.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn attribute_selectors() {
+        let stylesheet = "[hidden] { width: 1px; } [lang|=en] { height: 1px; } [class~=b] { top: 1px; } \
+                          [href^='http'][href$=\".org\"] { left: 1px; } [href*=\"\"] { right: 1px; }";
+        styled("<p hidden=\"\" lang=\"en-GB\" class=\"a b\" href=\"https://example.org\"></p>", stylesheet, |p| {
+            for name in &["width", "height", "top", "left"] {
+                assert!(p.value(name) == Some(Value::Length(1.0, Unit::Px)), "{}", name);
+            }
+            assert!(p.value("right").is_none());
+        });
+    }
+
+    #[test]
+    fn structural_pseudo_classes() {
+        let stylesheet = "i:first-child { top: 1px; } i:last-child { bottom: 1px; } b:only-child { left: 1px; } \
+                          i:nth-child(2n+1) { width: 1px; } i:nth-last-child(-n+2) { height: 1px; }";
+        styled("<div><i></i>x<i></i><i><b></b></i><i></i></div>", stylesheet, |div| {
+            let has = |i: usize, name: &str| div.children[i].value(name).is_some();
+            let elements = [0, 2, 3, 4];
+            let matching = |name: &str| elements.iter().filter(|&&i| has(i, name)).cloned().collect::<Vec<_>>();
+            assert_eq!(matching("top"), vec![0]);
+            assert_eq!(matching("bottom"), vec![4]);
+            assert_eq!(matching("width"), vec![0, 3]);
+            assert_eq!(matching("height"), vec![3, 4]);
+            assert!(div.children[3].children[0].value("left").is_some());
+            assert!(div.value("top").is_none());
+        });
+    }
+
+    #[test]
+    fn before_and_after_pseudo_elements() {
+        styled("<p><i></i></p>", "p::before { content: \"<\"; width: 1px; } p:after { content: '>'; } \
+                                  i::before { content: none; } p { width: 2px; }", |p| {
+            assert_eq!(p.children.len(), 3);
+            let (before, after) = (&p.children[0], &p.children[2]);
+            assert!(before.pseudo_element == Some(PseudoElement::Before));
+            assert_eq!(before.generated_text(), Some("<".to_string()));
+            assert!(before.value("width") == Some(Value::Length(1.0, Unit::Px)));
+            assert!(after.pseudo_element == Some(PseudoElement::After));
+            assert_eq!(after.generated_text(), Some(">".to_string()));
+            assert!(p.value("width") == Some(Value::Length(2.0, Unit::Px)));
+            assert!(p.children[1].children.is_empty());
+        });
+    }
+
+    #[test]
+    fn media_rules() {
+        let stylesheet = "@media screen and (min-width: 50em) { p { width: 1px; } } \
+                          @media print, (max-width: 700px) { p { height: 1px; } } \
+                          @media not print { @media (orientation: landscape) { p { top: 1px; } } } \
+                          @media (color), (width: 800px) { p { left: 1px; } }";
+        styled("<p></p>", stylesheet, |p| {
+            assert!(p.value("width").is_some());
+            assert!(p.value("height").is_none());
+            assert!(p.value("top").is_some());
+            assert!(p.value("left").is_some());
+        });
+    }
+
+    #[test]
+    fn custom_properties_and_var() {
+        let stylesheet = "div { --gap: 4px; --loop: var(--loop); } \
+                          p { --wide: calc(var(--gap) * 2); margin: var(--gap) var(--wide); \
+                              width: var(--missing, 7px); height: var(--loop); top: var(--nothing); }";
+        styled("<div><p></p></div>", stylesheet, |div| {
+            let p = &div.children[0];
+            assert!(p.value("margin-top") == Some(Value::Length(4.0, Unit::Px)));
+            assert_eq!(p.value("margin-right").unwrap().resolve(&media().length_context()), 8.0);
+            assert!(p.value("width") == Some(Value::Length(7.0, Unit::Px)));
+            assert!(p.value("--gap") == Some(Value::Length(4.0, Unit::Px)));
+            assert!(p.value("--loop").is_none());
+            assert!(p.value("height").is_none());
+            assert!(p.value("top").is_none());
+        });
+    }
+
+    #[test]
+    fn css_wide_keywords() {
+        let stylesheet = "div { font-size: 20px; font-weight: bold; color: red; margin: 2em; width: 50%; } \
+                          p { font-size: 10px; font-weight: initial; color: inherit; margin: inherit; width: unset; \
+                              border: initial; height: revert; padding: 1px; } \
+                          div > p { font: inherit; padding-left: initial; }";
+        styled("<div><p></p></div>", stylesheet, |div| {
+            let p = &div.children[0];
+            assert_eq!((p.font.size, p.font.weight), (20.0, 700));
+            assert!(p.value("color") == Some(Value::Keyword("red".to_string())));
+            assert!(p.value("margin-left") == Some(Value::Length(40.0, Unit::Px)));
+            assert!(p.value("width").is_none() && p.value("height").is_none());
+            assert!(p.value("border-top-width").is_none() && p.value("padding-left").is_none());
+            assert!(p.value("padding-top") == Some(Value::Length(1.0, Unit::Px)));
+        });
+        styled("<div><p></p></div>", "div { font-size: 20px; font-weight: bold; } \
+                                      p { font-size: initial; font-weight: initial; }", |div| {
+            assert_eq!((div.children[0].font.size, div.children[0].font.weight), (16.0, 400));
+        });
+    }
+
+    #[test]
+    fn later_declarations_win_ties() {
+        styled("<div class=\"a\"></div>", "div.a { width: 1px; height: 1px !important; } .a { width: 9px; } \
+                                           div.a { width: 2px; margin: 1px; margin: 3px; height: 2px !important; } \
+                                           div { width: 8px; }", |div| {
+            assert!(div.value("width") == Some(Value::Length(2.0, Unit::Px)));
+            assert!(div.value("height") == Some(Value::Length(2.0, Unit::Px)));
+            assert!(div.value("margin-top") == Some(Value::Length(3.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        styled("<DIV Data=\"1\"><p></P></div>", "div[DATA] { Color: red; --Foo: 1px; MARGIN: 2px; } DIV > P { WIDTH: 3px; }", |div| {
+            assert!(div.value("color") == Some(Value::Keyword("red".to_string())));
+            assert!(div.value("COLOR") == Some(Value::Keyword("red".to_string())));
+            assert!(div.value("Margin-Left") == Some(Value::Length(2.0, Unit::Px)));
+            // Custom property names are case-sensitive.
+            assert!(div.value("--Foo") == Some(Value::Length(1.0, Unit::Px)));
+            assert!(div.value("--foo").is_none());
+            assert!(div.children[0].value("width") == Some(Value::Length(3.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn nested_rules() {
+        let stylesheet = ".card, #x { width: 1px; & > p { height: 2px; .big & { margin-top: 3px; } } \
+                          &.wide { padding-top: 4px; } span { border-top-width: 5px; } + div { margin-left: 6px; } \
+                          p:first-child { width: 7px; } }";
+        styled("<main><div class=\"big\"><div class=\"card wide\"><p></p><span></span></div><div></div></div></main>", stylesheet, |main| {
+            let px = |n| Some(Value::Length(n, Unit::Px));
+            let card = &main.children[0].children[0];
+            assert!(card.value("width") == px(1.0) && card.value("padding-top") == px(4.0));
+            let p = &card.children[0];
+            assert!(p.value("height") == px(2.0) && p.value("margin-top") == px(3.0) && p.value("width") == px(7.0));
+            assert!(card.children[1].value("border-top-width") == px(5.0));
+            assert!(main.children[0].children[1].value("margin-left") == px(6.0));
+        });
+    }
+
+    #[test]
+    fn current_color_is_the_computed_color() {
+        let stylesheet = "div { color: red; border-left: 1px solid; } \
+                          p { border-color: CurrentColor; background-color: currentcolor; color: currentColor; } \
+                          span { color: blue; border-top-color: currentcolor; box-shadow: 1px 1px currentcolor; }";
+        styled("<div><p><span></span></p></div>", stylesheet, |div| {
+            let red = Some(Value::ColorValue(Color { r: 255, g: 0, b: 0, a: 255 }));
+            assert!(div.value("border-left-color") == red);
+            let p = &div.children[0];
+            assert!(p.value("color") == red && p.value("border-top-color") == red && p.value("background-color") == red);
+            let span = &p.children[0];
+            assert!(span.value("border-top-color").unwrap().to_color().unwrap().b == 255);
+            assert_eq!(span.value("box-shadow").unwrap().to_string(), "1px 1px #0000ff");
+        });
+    }
+
+    #[test]
+    fn escaped_class_and_id_selectors() {
+        styled("<p id=\"123\" class=\"a:b\"></p>", ".a\\:b { width: 1px; } #\\31 23 { height: 2px; }", |p| {
+            assert!(p.value("width") == Some(Value::Length(1.0, Unit::Px)));
+            assert!(p.value("height") == Some(Value::Length(2.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn style_attribute_overrides_author_rules() {
+        styled("<div id=\"x\" style=\"color: #f00; width: 3px; height: 4px !important\"><p style=\"color: inherit\"></p></div>",
+               "#x#x#x { color: blue; width: 9px !important; height: 9px !important; } div::before { content: 'a'; }", |div| {
+            let red = Some(Value::ColorValue(Color { r: 255, g: 0, b: 0, a: 255 }));
+            assert!(div.value("color") == red);
+            assert!(div.value("width") == Some(Value::Length(9.0, Unit::Px)));
+            assert!(div.value("height") == Some(Value::Length(4.0, Unit::Px)));
+            assert!(div.children[1].value("color") == red);
+            // The '::before' pseudo-element doesn't get its element's style attribute.
+            assert!(div.children[0].value("width").is_none());
+        });
+    }
+
+    #[test]
+    fn several_stylesheets_cascade_together() {
+        let root = crate::html::parse("<div id=\"x\" class=\"y\"></div>".to_string()).0;
+        let mut user_agent = stylesheet("div { width: 1px; height: 1px !important; }");
+        for rule in &mut user_agent.rules {
+            rule.origin = crate::css::Origin::UserAgent;
+        }
+        let stylesheets = [
+            user_agent,
+            stylesheet("#x { width: 2px; } .y { margin-top: 2px; }"),
+            stylesheet(".y { width: 3px; margin-top: 3px; height: 3px; }"),
+        ];
+        let div = style_tree(&root, &stylesheets);
+        assert!(div.value("width") == Some(Value::Length(2.0, Unit::Px)));
+        assert!(div.value("margin-top") == Some(Value::Length(3.0, Unit::Px)));
+        assert!(div.value("height") == Some(Value::Length(1.0, Unit::Px)));
+    }
+
+    #[test]
+    fn lengths_are_computed_to_px() {
+        let stylesheet = "div { font-size: 20px; width: 2em; margin-left: calc(1em + 10px); margin-right: calc(50% - 1em); \
+                                border-top-width: thick; border-left: thin solid; padding-left: 1in; transform: translate(1em, 10%); } \
+                          p { font-size: 1.5em; width: inherit; height: 2rem; padding-left: inherit; }";
+        styled("<div><p></p></div>", stylesheet, |div| {
+            let px = |n| Some(Value::Length(n, Unit::Px));
+            assert!(div.value("width") == px(40.0) && div.value("font-size") == px(20.0));
+            assert!(div.value("margin-left") == px(30.0));
+            // Percentages are left for layout.
+            assert_eq!(div.value("margin-right").unwrap().to_string(), "calc(50% - 20px)");
+            assert!(div.value("border-top-width") == px(5.0) && div.value("border-left-width") == px(1.0));
+            assert!(div.value("padding-left") == px(96.0));
+            assert_eq!(div.value("transform").unwrap().to_string(), "translate(20px, 10%)");
+            let p = &div.children[0];
+            assert!(p.value("font-size") == px(30.0) && p.value("width") == px(40.0) && p.value("height") == px(40.0));
+        });
+    }
+
+    #[test]
+    fn computed_style() {
+        styled("<div></div>", "div { display: block; width: auto; margin: 1px auto 2px 10%; padding-top: auto; \
+                                     border-left: 2px solid red; background: blue; }", |div| {
+            let computed = &div.computed;
+            let px = |n| Some(Value::Length(n, Unit::Px));
+            assert!(computed.display == Display::Block);
+            assert!(computed.width.is_none() && computed.height.is_none());
+            assert!(computed.margin.top == px(1.0) && computed.margin.right.is_none());
+            assert!(computed.margin.left == Some(Value::Percentage(10.0)));
+            assert!(Some(computed.padding.top.clone()) == px(0.0));
+            assert!(Some(computed.border_width.left.clone()) == px(2.0));
+            assert_eq!(computed.border_color.left.unwrap().r, 255);
+            assert_eq!(computed.background_color.unwrap().b, 255);
+        });
+        let root = crate::html::parse("<div></div>".to_string()).0;
+        let stylesheets = [stylesheet("div { display: block; }")];
+        let mut div = style_tree(&root, &stylesheets);
+        div.set_value("width".to_string(), Value::Length(5.0, Unit::Px));
+        assert!(div.computed.width == Some(Value::Length(5.0, Unit::Px)));
+    }
+
+    #[test]
+    fn siblings_share_styles() {
+        let root = crate::html::parse("<ul><li class=\"a b\"></li><li class=\"b a\"></li><li class=\"a b\" style=\"width: 1px\"></li>\
+                                       <li class=\"a b\"></li><li class=\"a\"></li></ul>".to_string()).0;
+        let stylesheets = [stylesheet(".a { width: 2em; } li:nth-child(4) { height: 1px; }")];
+        let mut ul = style_tree(&root, &stylesheets);
+        let items = &ul.children;
+        assert!(Arc::ptr_eq(&items[0].specified_values, &items[1].specified_values));
+        assert!(Arc::ptr_eq(&items[0].computed, &items[1].computed));
+        // Not with a style attribute, different rules, or different classes.
+        assert!(!Arc::ptr_eq(&items[0].specified_values, &items[2].specified_values));
+        assert!(items[2].value("width") == Some(Value::Length(1.0, Unit::Px)));
+        assert!(items[3].value("height") == Some(Value::Length(1.0, Unit::Px)) && items[0].value("height").is_none());
+        assert!(!Arc::ptr_eq(&items[0].specified_values, &items[4].specified_values));
+        // Changing a shared style copies it.
+        ul.children[1].set_value("width".to_string(), Value::Length(5.0, Unit::Px));
+        assert!(ul.children[0].value("width") == Some(Value::Length(32.0, Unit::Px)));
+        assert!(ul.children[1].value("width") == Some(Value::Length(5.0, Unit::Px)));
+    }
+
+    #[test]
+    fn restyle_after_attribute_change_matches_fresh_style() {
+        let stylesheets = [stylesheet(".a { width: 1px; } .b { width: 2px; } .b + p { height: 3px; } .c span { height: 4px; } \
+                                       span::before { content: 'x'; } [title=t] i { color: red; }")];
+        let source = "<div><p class=\"a\"></p><p></p><i></i><span><i></i></span></div>";
+        let cases = [
+            ("<p class=\"a\">", "<p class=\"b\">", vec![0]),
+            ("<div>", "<div class=\"c\">", vec![]),
+            ("<span>", "<span title=\"t\">", vec![3]),
+        ];
+        for (old_tag, new_tag, path) in cases.iter() {
+            let old_root = crate::html::parse(source.to_string()).0;
+            let new_root = crate::html::parse(source.replace(old_tag, new_tag)).0;
+            let old = style_tree(&old_root, &stylesheets);
+            let invalidations = Invalidations { attributes: vec![path.clone()], ..Default::default() };
+            let restyled = restyle_tree(&new_root, &old, &invalidations, &stylesheets, &media(), &Default::default());
+            assert!(same_styles(&restyled, &style_tree(&new_root, &stylesheets)), "{}", new_tag);
         }
     }
 
-    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
-        self.value(name).unwrap_or_else(|| self.value(fallback_name)
-                        .unwrap_or_else(|| default.clone()))
+    #[test]
+    fn restyle_reuses_styles_the_change_cant_affect() {
+        let stylesheets = [stylesheet(".a { width: 1px; } .b { width: 2px; } i { height: 3px; }")];
+        let old_root = crate::html::parse("<div><p class=\"a\"></p><i></i></div>".to_string()).0;
+        let new_root = crate::html::parse("<div><p class=\"b\"></p><i></i></div>".to_string()).0;
+        let old = style_tree(&old_root, &stylesheets);
+        let invalidations = Invalidations { attributes: vec![vec![0]], ..Default::default() };
+        let restyled = restyle_tree(&new_root, &old, &invalidations, &stylesheets, &media(), &Default::default());
+        assert!(restyled.children[0].value("width") == Some(Value::Length(2.0, Unit::Px)));
+        assert!(Arc::ptr_eq(&old.specified_values, &restyled.specified_values));
+        assert!(Arc::ptr_eq(&old.children[1].specified_values, &restyled.children[1].specified_values));
+    }
+
+    #[test]
+    fn ancestor_filter_only_rejects_selectors_that_cant_match() {
+        styled("<div class=\"a\" id=\"x\"><section><p class=\"q\"></p></section></div>",
+               ".a p { width: 1px; } .b p { height: 2px; } #x > section > .q { color: red; } span p { margin-top: 5px; } \
+                div section .q { padding-top: 3px; }", |div| {
+            let p = &div.children[0].children[0];
+            assert!(p.value("width") == Some(Value::Length(1.0, Unit::Px)));
+            assert!(p.value("height").is_none() && p.value("margin-top").is_none());
+            assert!(p.value("padding-top") == Some(Value::Length(3.0, Unit::Px)));
+            assert!(p.value("color").is_some());
+        });
+    }
+
+    #[test]
+    fn display_values() {
+        styled("<div><p></p><i></i><b></b><s></s><u></u></div>",
+               "p { display: LIST-ITEM; } i { display: inline-block; } b { display: flex; } s { display: bogus; } u { display: none; }", |div| {
+            let displays: Vec<Display> = div.children.iter().map(StyledNode::display).collect();
+            assert!(displays == vec![Display::ListItem, Display::InlineBlock, Display::Flex, Display::Inline, Display::None]);
+        });
+    }
+
+    #[test]
+    fn visibility_is_inherited() {
+        styled("<div><p></p><p class=\"v\"></p></div>", "div { visibility: hidden; } .v { visibility: visible; }", |div| {
+            assert!(div.children[0].computed.visibility == Visibility::Hidden);
+            assert!(div.children[1].computed.visibility == Visibility::Visible);
+        });
+    }
+
+    #[test]
+    fn query_selector() {
+        let (root, _) = crate::html::parse("<div><p class=a>x</p><ul><li class=a></li><li id=b></li></ul></div>".to_string());
+        assert_eq!(root.query_selector(".a").unwrap(), Some(vec![0]));
+        assert_eq!(root.query_selector_all("ul > .a, #b, div").unwrap(), [vec![], vec![1, 0], vec![1, 1]]);
+        assert_eq!(root.query_selector("span").unwrap(), None);
+        assert!(root.query_selector("p >").is_err());
+        assert!(root.query_selector("p,").is_err());
+    }
+
+    #[test]
+    fn dump_lists_values_by_name() {
+        styled("<div class=a><p></p></div>", "div { width: 10px; color: red; } p::before { content: 'x'; }", |div| {
+            let dump = dump(div);
+            let lines: Vec<&str> = dump.lines().collect();
+            assert!(lines[0].starts_with("div.a { color: "), "{}", lines[0]);
+            assert!(lines[0].contains("; width: 10px"), "{}", lines[0]);
+            assert!(lines[1].starts_with("  p"));
+            assert!(lines[2].starts_with("    p::before { "), "{}", lines[2]);
+        });
+    }
+
+    #[test]
+    fn position_and_offsets() {
+        styled("<p style='position: absolute; top: auto; left: 3em'></p>", "", |p| {
+            assert!(p.computed.position == Position::Absolute);
+            assert!(p.computed.offsets.top.is_none());
+            assert!(p.computed.offsets.left == Some(Value::Length(48.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn z_index_is_an_integer() {
+        assert!(supports("z-index", &Value::Number(-3.0)));
+        assert!(!supports("z-index", &Value::Number(1.5)));
+    }
+
+    #[test]
+    fn height_can_be_a_percentage() {
+        assert!(supports("height", &Value::Percentage(50.0)));
+    }
+
+    #[test]
+    fn flex_shorthand() {
+        styled("<div></div>", "div { flex: 2 3; }", |div| {
+            assert_eq!((div.computed.flex_grow, div.computed.flex_shrink), (2.0, 3.0));
+            assert!(matches!(div.computed.flex_basis, FlexBasis::Length(Value::Length(n, Unit::Px)) if n == 0.0));
+        });
+        styled("<div></div>", "div { flex: none; }", |div| {
+            assert_eq!((div.computed.flex_grow, div.computed.flex_shrink), (0.0, 0.0));
+            assert!(matches!(div.computed.flex_basis, FlexBasis::Auto));
+        });
+    }
+
+    #[test]
+    fn list_items_get_markers() {
+        let stylesheet = "ul, ol, p { display: block; } li { display: list-item; } ol { list-style-type: decimal; }";
+        // Items are numbered from 'start', and 'value' sets an item's number; bullets are for 'ul'.
+        let source = "<div><ol start=3><li>a</li><li value=7>b</li><li>c</li><p>x</p></ol><ul><li>d</li></ul></div>";
+        styled(source, stylesheet, |div| {
+            let markers: Vec<Option<String>> = div.children[0].children.iter()
+                .map(|item| item.children.first().and_then(|marker| marker.generated_text())).collect();
+            assert_eq!(markers, [Some("3. ".to_string()), Some("7. ".to_string()), Some("8. ".to_string()), None]);
+            let bullet = &div.children[1].children[0].children[0];
+            assert!(bullet.pseudo_element == Some(PseudoElement::Marker));
+            assert_eq!(bullet.generated_text(), Some("\u{2022} ".to_string()));
+        });
+        let stylesheet = "li { display: list-item; } .n { list-style: none; } li::marker { content: '-'; }";
+        styled("<ul><li>a</li><li class=n>b</li></ul>", stylesheet, |ul| {
+            assert_eq!(ul.children[0].children[0].generated_text(), Some("-".to_string()));
+            assert_eq!(ul.children[1].children.len(), 1);
+        });
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        let aspect_ratio = |stylesheet| styled("<a></a>", stylesheet, |a| (a.computed.aspect_ratio.auto, a.computed.aspect_ratio.ratio));
+        let (auto, ratio) = aspect_ratio("a { aspect-ratio: 16 / 9; }");
+        assert!(!auto && (ratio.unwrap() - 16.0 / 9.0).abs() < 1e-6);
+        assert_eq!(aspect_ratio("a { aspect-ratio: 4/3 auto; }"), (true, Some(4.0 / 3.0)));
+        assert_eq!(aspect_ratio("a { aspect-ratio: 2; }"), (false, Some(2.0)));
+        // A ratio with a zero in it is degenerate, and treated as no ratio.
+        assert_eq!(aspect_ratio("a { aspect-ratio: 0 / 1; }"), (false, None));
+        assert_eq!(aspect_ratio("a { aspect-ratio: 2 3; }"), (true, None));
     }
 }
@@ -0,0 +1,490 @@
+use std::f32::consts::PI;
+use crate::css::Color;
+use crate::dom::{ElementData, Namespace, Node, NodeType};
+use crate::layout::Rect;
+use crate::style::{Display, StyledNode, Visibility};
+
+// The size of an SVG image that doesn't have one, like for other replaced elements.
+pub const DEFAULT_WIDTH: f32 = 300.0;
+pub const DEFAULT_HEIGHT: f32 = 150.0;
+
+// The area of an image's coordinate system that's fitted into its box, from 'viewBox'.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+// The size an SVG image has of its own, from its 'width' and 'height' attributes, and
+// the ratio of its width to its height, which its 'viewBox' gives when they don't.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IntrinsicSize {
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+    pub ratio: Option<f32>,
+}
+
+// An outline to fill: polygons in page coordinates, filled with the nonzero winding
+// rule, so a subpath going the other way around cuts a hole.
+pub struct Shape {
+    pub color: Color,
+    pub subpaths: Vec<Vec<(f32, f32)>>,
+}
+
+// The SVG element 'node' is, with its name without any namespace prefix.
+fn svg_element(node: &Node) -> Option<(&str, &ElementData)> {
+    match node.node_type {
+        NodeType::Element(ref elem) if elem.namespace == Namespace::Svg => {
+            Some((elem.tag_name.rsplit(':').next().unwrap(), elem))
+        }
+        _ => None,
+    }
+}
+
+// Is 'node' the root of an SVG image, which is drawn in a box of its own?
+pub fn is_svg_root(node: &Node) -> bool {
+    matches!(svg_element(node), Some(("svg", _)))
+}
+
+pub fn view_box(elem: &ElementData) -> Option<ViewBox> {
+    match parse_numbers(elem.attributes.get("viewBox")?)[..] {
+        [x, y, width, height] if width > 0.0 && height > 0.0 => Some(ViewBox { x, y, width, height }),
+        _ => None,
+    }
+}
+
+pub fn intrinsic_size(elem: &ElementData) -> IntrinsicSize {
+    // Lengths are in px, with or without the unit. Percentages are of the box the image
+    // is in, so they don't give it a size of its own.
+    let length = |name: &str| elem.attributes.get(name)
+        .and_then(|value| value.trim().trim_end_matches("px").parse::<f32>().ok())
+        .filter(|length| length.is_finite() && *length >= 0.0);
+    let (width, height) = (length("width"), length("height"));
+    let ratio = match (width, height) {
+        (Some(width), Some(height)) if height > 0.0 => Some(width / height),
+        _ => view_box(elem).map(|view_box| view_box.width / view_box.height),
+    };
+    IntrinsicSize { width, height, ratio }
+}
+
+// Maps the image's coordinates to the page.
+struct Transform {
+    scale: f32,
+    x: f32,
+    y: f32,
+}
+
+impl Transform {
+    fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (x * self.scale + self.x, y * self.scale + self.y)
+    }
+}
+
+// The shapes to draw for the SVG image 'svg', fitted into 'rect', its content box. The
+// view box is scaled to fit and centered, as for 'preserveAspectRatio="xMidYMid meet"'.
+pub fn shapes(svg: &StyledNode, rect: Rect) -> Vec<Shape> {
+    let transform = match svg_element(svg.node()).and_then(|(_, elem)| view_box(elem)) {
+        Some(view_box) => {
+            let scale = (rect.width / view_box.width).min(rect.height / view_box.height);
+            Transform {
+                scale,
+                x: rect.x + (rect.width - view_box.width * scale) / 2.0 - view_box.x * scale,
+                y: rect.y + (rect.height - view_box.height * scale) / 2.0 - view_box.y * scale,
+            }
+        }
+        None => Transform { scale: 1.0, x: rect.x, y: rect.y },
+    };
+    let mut shapes = Vec::new();
+    add_shapes(svg, &transform, &mut shapes);
+    shapes
+}
+
+fn add_shapes(node: &StyledNode, transform: &Transform, shapes: &mut Vec<Shape>) {
+    for child in &node.children {
+        let (name, elem) = match svg_element(child.node()) {
+            Some(element) if child.pseudo_element.is_none() => element,
+            _ => continue,
+        };
+        if child.display() == Display::None {
+            continue;
+        }
+        // 'parse' accepts 'NaN' and 'inf', and overflows to infinity, so those are rejected too.
+        let attribute = |name: &str| elem.attributes.get(name)
+            .and_then(|value| value.trim().parse::<f32>().ok())
+            .filter(|n| n.is_finite())
+            .unwrap_or(0.0);
+        let subpaths = match name {
+            "g" | "a" | "svg" => {
+                add_shapes(child, transform, shapes);
+                continue;
+            }
+            "rect" => {
+                let (x, y) = (attribute("x"), attribute("y"));
+                let (width, height) = (attribute("width"), attribute("height"));
+                if width <= 0.0 || height <= 0.0 {
+                    continue;
+                }
+                vec![vec![(x, y), (x + width, y), (x + width, y + height), (x, y + height)]]
+            }
+            "circle" => vec![ellipse(attribute("cx"), attribute("cy"), attribute("r"), attribute("r"))],
+            "ellipse" => vec![ellipse(attribute("cx"), attribute("cy"), attribute("rx"), attribute("ry"))],
+            "polygon" => {
+                let numbers = parse_numbers(elem.attributes.get("points").map_or("", |points| points));
+                vec![numbers.chunks_exact(2).map(|point| (point[0], point[1])).collect()]
+            }
+            "path" => parse_path(elem.attributes.get("d").map_or("", |d| d)),
+            _ => continue, // Things that aren't drawn, like 'title' and 'defs', or aren't supported yet
+        };
+        if let Some(color) = child.computed.fill.filter(|_| child.computed.visibility == Visibility::Visible) {
+            let subpaths = subpaths.into_iter()
+                .filter(|subpath| subpath.len() > 2)
+                .map(|subpath| subpath.into_iter().map(|point| transform.apply(point)).collect())
+                .collect();
+            shapes.push(Shape { color, subpaths });
+        }
+    }
+}
+
+fn ellipse(cx: f32, cy: f32, rx: f32, ry: f32) -> Vec<(f32, f32)> {
+    if rx <= 0.0 || ry <= 0.0 {
+        return Vec::new();
+    }
+    const SEGMENTS: usize = 64;
+    (0..SEGMENTS).map(|i| {
+        let angle = 2.0 * PI * i as f32 / SEGMENTS as f32;
+        (cx + rx * angle.cos(), cy + ry * angle.sin())
+    }).collect()
+}
+
+// Parse a list of numbers separated by whitespace and/or commas, like a 'viewBox'. The
+// list stops at anything that isn't a number.
+fn parse_numbers(source: &str) -> Vec<f32> {
+    let mut parser = PathParser { source: source.as_bytes(), pos: 0 };
+    let mut numbers = Vec::new();
+    while let Some(number) = parser.number() {
+        numbers.push(number);
+    }
+    numbers
+}
+
+// Reads the numbers and commands of path data, like 'M 0 0 L 10,10 z'.
+struct PathParser<'a> {
+    source: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PathParser<'a> {
+    fn skip_separators(&mut self) {
+        while self.pos < self.source.len() && (self.source[self.pos].is_ascii_whitespace() || self.source[self.pos] == b',') {
+            self.pos += 1;
+        }
+    }
+
+    // The next command letter, if there is one.
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        let c = *self.source.get(self.pos)?;
+        if c.is_ascii_alphabetic() && c != b'e' && c != b'E' {
+            self.pos += 1;
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    // Is the next thing a number, for another set of arguments to the last command?
+    fn at_number(&mut self) -> bool {
+        self.skip_separators();
+        matches!(self.source.get(self.pos), Some(c) if c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.'))
+    }
+
+    // Parse a number like '-1.5e3'. Numbers don't need anything between them if they
+    // can't be read as one, like '1-2' or '.5.5'.
+    fn number(&mut self) -> Option<f32> {
+        self.skip_separators();
+        let start = self.pos;
+        let digits = |parser: &mut PathParser| {
+            let start = parser.pos;
+            while parser.pos < parser.source.len() && parser.source[parser.pos].is_ascii_digit() {
+                parser.pos += 1;
+            }
+            parser.pos > start
+        };
+        if matches!(self.source.get(self.pos), Some(b'-' | b'+')) {
+            self.pos += 1;
+        }
+        let mut any = digits(self);
+        if self.source.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            any |= digits(self);
+        }
+        if any && matches!(self.source.get(self.pos), Some(b'e' | b'E')) {
+            let mantissa_end = self.pos;
+            self.pos += 1;
+            if matches!(self.source.get(self.pos), Some(b'-' | b'+')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                self.pos = mantissa_end;
+            }
+        }
+        // Exponents like '1e39' overflow to infinity, which isn't a usable coordinate.
+        let number = std::str::from_utf8(&self.source[start..self.pos]).ok()?.parse::<f32>().ok()
+            .filter(|n| n.is_finite());
+        if number.is_none() {
+            self.pos = start;
+        }
+        number
+    }
+
+    // An arc flag, which is a single '0' or '1' that doesn't need a separator after it.
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_separators();
+        let flag = match self.source.get(self.pos)? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(flag)
+    }
+
+    fn point(&mut self) -> Option<(f32, f32)> {
+        Some((self.number()?, self.number()?))
+    }
+}
+
+// Parse SVG path data into polygons, with curves and arcs broken into line segments.
+// Like browsers, this draws everything up to the first error in the data.
+fn parse_path(source: &str) -> Vec<Vec<(f32, f32)>> {
+    let mut parser = PathParser { source: source.as_bytes(), pos: 0 };
+    let mut path = Path { subpaths: Vec::new(), current: (0.0, 0.0), start: (0.0, 0.0), last_control: None };
+    let mut command = match parser.command() {
+        Some(c @ (b'M' | b'm')) => c,
+        _ => return path.subpaths,
+    };
+    loop {
+        if path.segment(command, &mut parser).is_none() {
+            break;
+        }
+        // Coordinates after the first pair of a move are lines.
+        match command {
+            b'M' => command = b'L',
+            b'm' => command = b'l',
+            _ => {}
+        }
+        // Otherwise, more numbers are more arguments for the same command.
+        if command == b'Z' || command == b'z' || !parser.at_number() {
+            command = match parser.command() {
+                Some(c) => c,
+                None => break,
+            };
+        }
+    }
+    path.subpaths
+}
+
+// A path, as it's being parsed.
+struct Path {
+    subpaths: Vec<Vec<(f32, f32)>>,
+    current: (f32, f32),
+    start: (f32, f32), // Where the current subpath started
+    // The kind of the last segment and its last control point, if it was a curve, for
+    // 'S' and 'T' to reflect.
+    last_control: Option<(u8, (f32, f32))>,
+}
+
+impl Path {
+    // Read the arguments of one 'command' (lowercase for relative coordinates) from
+    // 'parser', and add its segment.
+    fn segment(&mut self, command: u8, parser: &mut PathParser) -> Option<()> {
+        let relative = command.is_ascii_lowercase();
+        let current = self.current;
+        let offset = |(x, y): (f32, f32)| if relative { (x + current.0, y + current.1) } else { (x, y) };
+        let mut control = None;
+        let end = match command.to_ascii_uppercase() {
+            b'M' => {
+                let point = offset(parser.point()?);
+                self.start = point;
+                self.subpaths.push(Vec::new());
+                point
+            }
+            b'L' => offset(parser.point()?),
+            b'H' => (parser.number()? + if relative { current.0 } else { 0.0 }, current.1),
+            b'V' => (current.0, parser.number()? + if relative { current.1 } else { 0.0 }),
+            b'C' | b'S' => {
+                let control1 = if command.eq_ignore_ascii_case(&b'C') {
+                    offset(parser.point()?)
+                } else {
+                    reflect(self.last_control, b'C', current)
+                };
+                let control2 = offset(parser.point()?);
+                let end = offset(parser.point()?);
+                flatten(self.subpaths.last_mut()?, 16, |t| {
+                    let u = 1.0 - t;
+                    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+                    (a * current.0 + b * control1.0 + c * control2.0 + d * end.0,
+                     a * current.1 + b * control1.1 + c * control2.1 + d * end.1)
+                });
+                control = Some((b'C', control2));
+                self.current = end;
+                self.last_control = control;
+                return Some(());
+            }
+            b'Q' | b'T' => {
+                let control1 = if command.eq_ignore_ascii_case(&b'Q') {
+                    offset(parser.point()?)
+                } else {
+                    reflect(self.last_control, b'Q', current)
+                };
+                let end = offset(parser.point()?);
+                flatten(self.subpaths.last_mut()?, 12, |t| {
+                    let u = 1.0 - t;
+                    (u * u * current.0 + 2.0 * u * t * control1.0 + t * t * end.0,
+                     u * u * current.1 + 2.0 * u * t * control1.1 + t * t * end.1)
+                });
+                control = Some((b'Q', control1));
+                self.current = end;
+                self.last_control = control;
+                return Some(());
+            }
+            b'A' => {
+                let shape = ArcShape {
+                    rx: parser.number()?,
+                    ry: parser.number()?,
+                    rotation: parser.number()?,
+                    large_arc: parser.flag()?,
+                    sweep: parser.flag()?,
+                };
+                let end = offset(parser.point()?);
+                arc(self.subpaths.last_mut()?, current, &shape, end);
+                self.current = end;
+                self.last_control = control;
+                return Some(());
+            }
+            b'Z' => {
+                // Drawing carries on from the start of the closed subpath, in a new one.
+                self.subpaths.push(Vec::new());
+                self.start
+            }
+            _ => return None,
+        };
+        self.subpaths.last_mut()?.push(end);
+        self.current = end;
+        self.last_control = control;
+        Some(())
+    }
+}
+
+// The first control point of a smooth curve: the last curve's second control point
+// reflected through the current point, if the last segment was that kind of curve.
+fn reflect(last_control: Option<(u8, (f32, f32))>, kind: u8, current: (f32, f32)) -> (f32, f32) {
+    match last_control {
+        Some((last_kind, (x, y))) if last_kind == kind => (2.0 * current.0 - x, 2.0 * current.1 - y),
+        _ => current,
+    }
+}
+
+// Add 'segments' line segments along the curve 'point', which goes from the current
+// point at 't = 0' to its end at 't = 1'.
+fn flatten<F: Fn(f32) -> (f32, f32)>(subpath: &mut Vec<(f32, f32)>, segments: usize, point: F) {
+    for i in 1..=segments {
+        subpath.push(point(i as f32 / segments as f32));
+    }
+}
+
+// The arguments of an 'A' command other than its end point: the radii of an ellipse, how
+// far it's rotated (in degrees), and which of the four arcs of it between the end points
+// to draw.
+struct ArcShape {
+    rx: f32,
+    ry: f32,
+    rotation: f32,
+    large_arc: bool,
+    sweep: bool,
+}
+
+// Add line segments along an elliptical arc from 'from' to 'to', as SVG gives them, by
+// working out the ellipse's center (SVG 1.1, appendix F.6.5).
+fn arc(subpath: &mut Vec<(f32, f32)>, from: (f32, f32), shape: &ArcShape, to: (f32, f32)) {
+    let ArcShape { rx, ry, rotation, large_arc, sweep } = *shape;
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if rx == 0.0 || ry == 0.0 || from == to {
+        subpath.push(to);
+        return;
+    }
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let (dx, dy) = ((from.0 - to.0) / 2.0, (from.1 - to.1) / 2.0);
+    let x1 = cos * dx + sin * dy;
+    let y1 = -sin * dx + cos * dy;
+    // Radii too small to reach the end point are scaled up until they just do.
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let numerator = (rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1).max(0.0);
+    let mut coefficient = (numerator / (rx * rx * y1 * y1 + ry * ry * x1 * x1)).sqrt();
+    if large_arc == sweep {
+        coefficient = -coefficient;
+    }
+    let cx1 = coefficient * rx * y1 / ry;
+    let cy1 = -coefficient * ry * x1 / rx;
+    let center = (cos * cx1 - sin * cy1 + (from.0 + to.0) / 2.0, sin * cx1 + cos * cy1 + (from.1 + to.1) / 2.0);
+    let angle = |x: f32, y: f32| y.atan2(x);
+    let start = angle((x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut sweep_angle = angle((-x1 - cx1) / rx, (-y1 - cy1) / ry) - start;
+    if sweep && sweep_angle < 0.0 {
+        sweep_angle += 2.0 * PI;
+    } else if !sweep && sweep_angle > 0.0 {
+        sweep_angle -= 2.0 * PI;
+    }
+    let segments = ((sweep_angle.abs() / (PI / 16.0)).ceil() as usize).max(1);
+    flatten(subpath, segments, |t| {
+        let (sin_t, cos_t) = (start + sweep_angle * t).sin_cos();
+        (center.0 + cos * rx * cos_t - sin * ry * sin_t, center.1 + sin * rx * cos_t + cos * ry * sin_t)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::styled;
+
+    #[test]
+    fn intrinsic_size_from_attributes_or_view_box() {
+        let (root, _) = crate::html::parse("<svg width=40px height='20' viewBox='0 0 4 1'></svg>".to_string());
+        let elem = match root.node_type {
+            NodeType::Element(ref elem) => elem,
+            _ => panic!("expected an element"),
+        };
+        assert_eq!(intrinsic_size(elem), IntrinsicSize { width: Some(40.0), height: Some(20.0), ratio: Some(2.0) });
+        let (root, _) = crate::html::parse("<svg width=50% viewBox='0 0 4 1'></svg>".to_string());
+        match root.node_type {
+            NodeType::Element(ref elem) => assert_eq!(intrinsic_size(elem), IntrinsicSize { width: None, height: None, ratio: Some(4.0) }),
+            _ => panic!("expected an element"),
+        }
+    }
+
+    #[test]
+    fn shapes_are_fitted_into_the_box() {
+        let source = "<svg viewBox='0 0 10 10'><rect width=10 height=5 /><circle fill=none r=1 />\
+                      <g style='display: none'><rect width=1 height=1 /></g><path d='M 0 0 H 10 V 10 Z' fill=red /></svg>";
+        styled(source, "svg { display: block; }", |svg| {
+            let shapes = shapes(svg, Rect { x: 0.0, y: 0.0, width: 100.0, height: 200.0 });
+            assert_eq!(shapes.len(), 2);
+            assert!(shapes[0].color == Color { r: 0, g: 0, b: 0, a: 255 });
+            assert_eq!(shapes[0].subpaths, vec![vec![(0.0, 50.0), (100.0, 50.0), (100.0, 100.0), (0.0, 100.0)]]);
+            assert!(shapes[1].color == Color { r: 255, g: 0, b: 0, a: 255 });
+            assert_eq!(shapes[1].subpaths, vec![vec![(0.0, 50.0), (100.0, 50.0), (100.0, 150.0)]]);
+        });
+    }
+
+    #[test]
+    fn non_finite_numbers_end_path_data() {
+        assert_eq!(parse_numbers("1 2 1e39 3"), vec![1.0, 2.0]);
+        assert_eq!(parse_path("M 0 0 L 10 0 L 1e39 10 z"), vec![vec![(0.0, 0.0), (10.0, 0.0)]]);
+    }
+}
@@ -0,0 +1,145 @@
+// Helpers shared by the unit tests.
+
+use crate::{css, dom, font, html, images, layout, style};
+
+// An 800x600 screen, the media the tests are styled for.
+pub fn media() -> style::Media {
+    style::Media { media_type: "screen".to_string(), width: 800.0, height: 600.0, dpi: css::DEFAULT_DPI }
+}
+
+// Parse 'source', which has to be valid CSS.
+pub fn stylesheet(source: &str) -> css::Stylesheet {
+    let (stylesheet, errors) = css::parse(source.to_string());
+    assert!(errors.is_empty(), "{:?}", errors);
+    stylesheet
+}
+
+// Style the tree at 'root' with 'stylesheets' for 'media()'.
+pub fn style_tree<'a>(root: &'a dom::Node, stylesheets: &'a [css::Stylesheet]) -> style::StyledNode<'a> {
+    style::style_tree(root, stylesheets, &media(), &Default::default())
+}
+
+// Style the HTML 'source' with 'stylesheet', and pass the styled tree to 'f'.
+pub fn styled<R>(source: &str, stylesheet: &str, f: impl FnOnce(&style::StyledNode) -> R) -> R {
+    let root = html::parse(source.to_string()).0;
+    let stylesheets = [self::stylesheet(stylesheet)];
+    f(&style_tree(&root, &stylesheets))
+}
+
+// Lay out the HTML 'source' styled with 'stylesheet' in an 800x600 viewport, and pass the
+// root box to 'f'.
+pub fn laid_out<R>(source: &str, stylesheet: &str, f: impl FnOnce(&layout::LayoutBox) -> R) -> R {
+    laid_out_with_fonts(source, stylesheet, &font::Fonts::new(), f)
+}
+
+// Like 'laid_out', measuring text with 'fonts'.
+pub fn laid_out_with_fonts<R>(source: &str, stylesheet: &str, fonts: &font::Fonts, f: impl FnOnce(&layout::LayoutBox) -> R) -> R {
+    lay_out(source, stylesheet, fonts, &images::Images::new(), f)
+}
+
+// Like 'laid_out', sizing '<img>' elements by 'images'.
+pub fn laid_out_with_images<R>(source: &str, stylesheet: &str, images: &images::Images,
+                               f: impl FnOnce(&layout::LayoutBox) -> R) -> R {
+    lay_out(source, stylesheet, &font::Fonts::new(), images, f)
+}
+
+fn lay_out<R>(source: &str, stylesheet: &str, fonts: &font::Fonts, images: &images::Images,
+              f: impl FnOnce(&layout::LayoutBox) -> R) -> R {
+    let viewport = layout::Viewport { width: 800.0, height: 600.0, dpi: css::DEFAULT_DPI, zoom: 1.0 };
+    styled(source, stylesheet, |styled| f(&layout::layout_tree(styled, &viewport, fonts, images)))
+}
+
+// A tiny TrueType font: 1000 units to the em, with an ascent of 900, a descent of 300 and
+// a line gap of 100. 'A' to 'C' are glyphs 1 to 3, whose advances are 600, 700 and 700;
+// other characters get the missing glyph, 500 wide.
+pub fn test_font() -> Vec<u8> {
+    fn be16(data: &mut Vec<u8>, n: u16) {
+        data.extend_from_slice(&n.to_be_bytes());
+    }
+    let mut head = vec![0; 54];
+    head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+    let mut hhea = vec![0; 36];
+    hhea[4..6].copy_from_slice(&900i16.to_be_bytes());
+    hhea[6..8].copy_from_slice(&(-300i16).to_be_bytes());
+    hhea[8..10].copy_from_slice(&100i16.to_be_bytes());
+    hhea[34..36].copy_from_slice(&3u16.to_be_bytes());
+    let mut hmtx = Vec::new();
+    for advance in [500, 600, 700] {
+        be16(&mut hmtx, advance);
+        be16(&mut hmtx, 0);
+    }
+    // A format 4 character map with a segment for 'A' to 'C' and the final 0xFFFF one.
+    let mut cmap = Vec::new();
+    for n in [0, 1, 3, 1, 0, 12] {
+        be16(&mut cmap, n);
+    }
+    for n in [4, 32, 0, 4, 4, 1, 0, 67, 0xFFFF, 0, 65, 0xFFFF, 1u16.wrapping_sub(65), 1, 0, 0] {
+        be16(&mut cmap, n);
+    }
+    let tables = [(b"cmap", cmap), (b"head", head), (b"hhea", hhea), (b"hmtx", hmtx)];
+    let mut font = Vec::new();
+    font.extend_from_slice(&0x00010000u32.to_be_bytes());
+    be16(&mut font, tables.len() as u16);
+    font.extend_from_slice(&[0; 6]);
+    let mut offset = 12 + 16 * tables.len();
+    for (tag, table) in &tables {
+        font.extend_from_slice(*tag);
+        font.extend_from_slice(&[0; 4]);
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += table.len();
+    }
+    for (_, table) in tables {
+        font.extend(table);
+    }
+    font
+}
+
+// A 'width' by 'height' PNG of black pixels in 8-bit grey, stored without compression.
+pub fn test_png(width: u32, height: u32) -> Vec<u8> {
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+    fn chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    // Each row is a filter type byte and a byte per pixel, in one stored deflate block.
+    let pixels = vec![0; (height * (width + 1)) as usize];
+    assert!(pixels.len() <= 0xFFFF);
+    let mut zlib = vec![0x78, 0x01, 1];
+    zlib.extend_from_slice(&(pixels.len() as u16).to_le_bytes());
+    zlib.extend_from_slice(&(!(pixels.len() as u16)).to_le_bytes());
+    zlib.extend_from_slice(&pixels);
+    let (a, b) = pixels.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    zlib.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Do the trees at 'a' and 'b' have the same shape and the same specified values throughout?
+pub fn same_styles(a: &style::StyledNode, b: &style::StyledNode) -> bool {
+    a.specified_values == b.specified_values && a.children.len() == b.children.len() &&
+        a.children.iter().zip(&b.children).all(|(a, b)| same_styles(a, b))
+}
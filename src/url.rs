@@ -0,0 +1,160 @@
+// The parts of a URL, as in RFC 3986: 'scheme://authority/path?query#fragment'. File
+// paths like 'styles/main.css' are URLs with only a path.
+struct Parts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+fn split(url: &str) -> Parts<'_> {
+    let (url, fragment) = match url.find('#') {
+        Some(i) => (&url[..i], Some(&url[i + 1..])),
+        None => (url, None),
+    };
+    let (url, query) = match url.find('?') {
+        Some(i) => (&url[..i], Some(&url[i + 1..])),
+        None => (url, None),
+    };
+    // A scheme is a letter followed by letters, digits, '+', '-' or '.', and then a ':'.
+    let scheme_end = url.find(':').filter(|&i| {
+        let scheme = &url[..i];
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    let (scheme, url) = match scheme_end {
+        Some(i) => (Some(&url[..i]), &url[i + 1..]),
+        None => (None, url),
+    };
+    let (authority, path) = match url.strip_prefix("//") {
+        Some(rest) => {
+            let end = rest.find('/').unwrap_or(rest.len());
+            (Some(&rest[..end]), &rest[end..])
+        }
+        None => (None, url),
+    };
+    Parts { scheme, authority, path, query, fragment }
+}
+
+// Resolve the URL 'reference' against 'base', the URL of the document or stylesheet it's
+// in, like a browser does: 'images/a.png' in 'http://a.com/css/main.css' is
+// 'http://a.com/css/images/a.png'.
+pub fn resolve(base: &str, reference: &str) -> String {
+    let base = split(base);
+    let r = split(reference);
+
+    let (scheme, authority, path, query);
+    if r.scheme.is_some() {
+        scheme = r.scheme;
+        authority = r.authority;
+        path = remove_dot_segments(r.path);
+        query = r.query;
+    } else {
+        scheme = base.scheme;
+        if r.authority.is_some() {
+            authority = r.authority;
+            path = remove_dot_segments(r.path);
+            query = r.query;
+        } else {
+            authority = base.authority;
+            if r.path.is_empty() {
+                path = base.path.to_string();
+                query = r.query.or(base.query);
+            } else {
+                path = if r.path.starts_with('/') {
+                    remove_dot_segments(r.path)
+                } else {
+                    remove_dot_segments(&merge(&base, r.path))
+                };
+                query = r.query;
+            }
+        }
+    }
+
+    let mut url = String::new();
+    if let Some(scheme) = scheme {
+        url.push_str(scheme);
+        url.push(':');
+    }
+    if let Some(authority) = authority {
+        url.push_str("//");
+        url.push_str(authority);
+    }
+    url.push_str(&path);
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+    }
+    if let Some(fragment) = r.fragment {
+        url.push('#');
+        url.push_str(fragment);
+    }
+    url
+}
+
+// Put the relative path 'path' in the directory of the base URL's path.
+fn merge(base: &Parts, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        return format!("/{}", path);
+    }
+    match base.path.rfind('/') {
+        Some(i) => format!("{}{}", &base.path[..i + 1], path),
+        None => path.to_string(),
+    }
+}
+
+// Take the '.' and '..' segments out of a path.
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut segments: Vec<&str> = Vec::new();
+    let parts: Vec<&str> = path.split('/').collect();
+    for (i, &segment) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        match segment {
+            "" if i == 0 => {} // Before the leading '/'
+            "." if !last => {}
+            ".." => {
+                // Going up from a relative path's first directory goes nowhere.
+                segments.pop();
+                if last {
+                    segments.push("");
+                }
+            }
+            "." => segments.push(""),
+            _ => segments.push(segment),
+        }
+    }
+    let joined = segments.join("/");
+    if absolute { format!("/{}", joined) } else { joined }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_relative_references() {
+        let base = "http://a.com/b/c/d;p?q";
+        let cases = [
+            ("g", "http://a.com/b/c/g"),
+            ("./g/", "http://a.com/b/c/g/"),
+            ("/g", "http://a.com/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a.com/b/c/d;p?y"),
+            ("#s", "http://a.com/b/c/d;p?q#s"),
+            ("../../../g", "http://a.com/g"),
+            ("g/./h/../i", "http://a.com/b/c/g/i"),
+            ("https:x", "https:x"),
+        ];
+        for &(reference, expected) in cases.iter() {
+            assert_eq!(resolve(base, reference), expected, "{}", reference);
+        }
+    }
+
+    #[test]
+    fn resolve_against_file_paths() {
+        assert_eq!(resolve("styles/main.css", "../images/a.png"), "images/a.png");
+        assert_eq!(resolve("index.html", "a.css"), "a.css");
+    }
+}